@@ -0,0 +1,94 @@
+//! Full renders of a fixture world through the binary. Needs
+//! MCANVIL_BENCH_WORLD (a region directory) and MCANVIL_BENCH_PALETTE;
+//! without them the benchmarks are skipped.
+use std::path::PathBuf;
+use std::process::Command;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn fixture() -> Option<(PathBuf, PathBuf)> {
+    Some((std::env::var_os("MCANVIL_BENCH_WORLD")?.into(), std::env::var_os("MCANVIL_BENCH_PALETTE")?.into()))
+}
+
+fn render(c: &mut Criterion) {
+    let (world, palette) = match fixture() {
+        Some(fixture) => fixture,
+        None => {
+            eprintln!("MCANVIL_BENCH_WORLD and MCANVIL_BENCH_PALETTE are not set, skipping");
+            return;
+        }
+    };
+    let work = std::env::temp_dir().join("mcanvilrenderer-bench");
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for mode in ["map", "heightmap", "isometric"] {
+        for encoding in [&[][..], &["--indexed-png"][..]] {
+            for threads in [1, 4] {
+                let name = format!("{}{}/{}", mode, if encoding.is_empty() { "" } else { "-indexed" }, threads);
+                group.bench_function(&name, |b| b.iter(|| {
+                    let _ = std::fs::remove_dir_all(&work);
+                    std::fs::create_dir_all(work.join("cache")).unwrap();
+                    std::fs::create_dir_all(work.join("images")).unwrap();
+                    let status = Command::new(env!("CARGO_BIN_EXE_mcanvilrenderer"))
+                        .args(["render", "--progress-format", "json", "--cache-mode", "no-cache", "--mode", mode])
+                        .args(["--threads", &threads.to_string()])
+                        .args(encoding)
+                        .arg("-d").arg(&world)
+                        .arg("-p").arg(&palette)
+                        .arg("-c").arg(work.join("cache"))
+                        .arg("-i").arg(work.join("images"))
+                        .stdout(std::process::Stdio::null())
+                        .status()
+                        .unwrap();
+                    assert!(status.success());
+                }));
+            }
+        }
+    }
+    group.finish();
+}
+
+fn run(args: &[&str], world: &PathBuf, palette: &PathBuf, work: &PathBuf) {
+    let status = Command::new(env!("CARGO_BIN_EXE_mcanvilrenderer"))
+        .args(["render", "--progress-format", "json"])
+        .args(args)
+        .arg("-d").arg(world)
+        .arg("-p").arg(palette)
+        .arg("-c").arg(work.join("cache"))
+        .arg("-i").arg(work.join("images"))
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Every chunk drawn over the previous images, as after a palette change
+/// on a world with many regions: loading, compositing and saving the
+/// region buffers weigh more there than in a first render.
+fn redraw(c: &mut Criterion) {
+    let (world, palette) = match fixture() {
+        Some(fixture) => fixture,
+        None => return,
+    };
+    let work = std::env::temp_dir().join("mcanvilrenderer-bench-redraw");
+    let _ = std::fs::remove_dir_all(&work);
+    std::fs::create_dir_all(work.join("cache")).unwrap();
+    std::fs::create_dir_all(work.join("images")).unwrap();
+    run(&[], &world, &palette, &work);
+    let mut group = c.benchmark_group("redraw");
+    group.sample_size(10);
+    for scale in ["1", "2"] {
+        if scale != "1" {
+            // Start from images of that size, not from a blank one each time.
+            run(&["--scale", scale, "--force-all"], &world, &palette, &work);
+        }
+        for threads in [1, 4] {
+            group.bench_function(&format!("map-x{}/{}", scale, threads), |b| b.iter(|| {
+                run(&["--force-all", "--scale", scale, "--threads", &threads.to_string()], &world, &palette, &work);
+            }));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render, redraw);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+use std::str::FromStr;
+use image::RgbaImage;
+
+use crate::coords::BlockPos;
+
+/// Side of a checkerboard square, in pixels.
+const CHECKER_SIZE: i32 = 8;
+
+/// `--background`: what the published images show where no chunk was
+/// rendered (never generated, deleted, or outside the selection).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Background {
+    Transparent,
+    Colour([u8; 3]),
+    /// Light and dark grey squares, as image editors show transparency.
+    Checkerboard,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Transparent
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// "transparent", "checkerboard" or "RRGGBB" (a leading '#' is allowed).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transparent" => Ok(Background::Transparent),
+            "checkerboard" => Ok(Background::Checkerboard),
+            colour => {
+                let colour = colour.trim_start_matches('#');
+                let rgb = u32::from_str_radix(colour, 16).ok().filter(|_| colour.len() == 6)
+                    .ok_or_else(|| format!("invalid background \"{}\" (transparent, checkerboard or RRGGBB)", s))?;
+                Ok(Background::Colour([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]))
+            },
+        }
+    }
+}
+
+impl Background {
+    /// Put the background under the transparent and translucent pixels of
+    /// `image`, whose top-left pixel is at block `origin` and whose pixels
+    /// each cover `scale` blocks. Checkerboard squares follow the world's
+    /// pixel grid, so they line up across tiles.
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        if *self == Background::Transparent {
+            return;
+        }
+        let (left, top) = (origin.x.div_euclid(scale), origin.z.div_euclid(scale));
+        let (width, height) = (image.width(), image.height());
+        for y in 0..height {
+            for x in 0..width {
+                let px = image.get_pixel_mut(x, y);
+                if px[3] == 255 {
+                    continue;
+                }
+                let colour = match self {
+                    Background::Colour(colour) => *colour,
+                    _ if ((left + x as i32).div_euclid(CHECKER_SIZE) + (top + y as i32).div_euclid(CHECKER_SIZE)) % 2 == 0 => [204, 204, 204],
+                    _ => [255, 255, 255],
+                };
+                let alpha = px[3] as f32 / 255.0;
+                for (c, b) in px.0.iter_mut().zip(colour.iter()) {
+                    *c = (*c as f32 * alpha + *b as f32 * (1.0 - alpha)).round() as u8;
+                }
+                px[3] = 255;
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use fastanvil::Rgba;
+
+/// Blocks renamed between Minecraft versions, as (old name, new name).
+/// A palette built for either version colors worlds saved by the other.
+pub const BLOCK_ALIASES: &[(&str, &str)] = &[
+    ("minecraft:grass_path", "minecraft:dirt_path"),
+    ("minecraft:grass", "minecraft:short_grass"),
+    ("minecraft:sign", "minecraft:oak_sign"),
+    ("minecraft:wall_sign", "minecraft:oak_wall_sign"),
+];
+
+/// Alias that was missing from the palette and copied from its counterpart.
+#[derive(Debug)]
+pub struct AppliedAlias {
+    pub from: &'static str,
+    pub to: &'static str,
+    pub states: usize,
+}
+
+/// Palette keys are the block name, optionally followed by "|<properties>".
+fn block_states<'a>(blockstates: &'a HashMap<String, Rgba>, name: &'a str) -> impl Iterator<Item = (&'a String, &'a Rgba)> {
+    blockstates.iter().filter(move |(key, _)| {
+        key.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with('|'))
+    })
+}
+
+/// Fill in the blockstates of whichever name of each alias the palette lacks.
+pub fn apply(blockstates: &mut HashMap<String, Rgba>) -> Vec<AppliedAlias> {
+    let mut applied = vec![];
+    for (old, new) in BLOCK_ALIASES {
+        for (from, to) in [(*new, *old), (*old, *new)] {
+            if block_states(blockstates, to).next().is_some() {
+                continue;
+            }
+            let copies: Vec<(String, Rgba)> = block_states(blockstates, from)
+                .map(|(key, colour)| (format!("{}{}", to, &key[from.len()..]), *colour))
+                .collect();
+            if copies.is_empty() {
+                continue;
+            }
+            applied.push(AppliedAlias { from, to, states: copies.len() });
+            blockstates.extend(copies);
+        }
+    }
+    applied
+}
@@ -0,0 +1,85 @@
+use fastanvil::Rgba;
+use serde::Deserialize;
+
+use crate::coords::CHUNK_SIZE;
+
+/// Block entities in a chunk at which its tint is fully red.
+const SATURATION: f32 = 128.0;
+
+#[derive(Deserialize)]
+struct BlockEntityNbt {
+    x: i32,
+    z: i32,
+}
+
+#[derive(Deserialize)]
+struct LevelNbt {
+    #[serde(rename = "TileEntities", default)]
+    tile_entities: Vec<BlockEntityNbt>,
+}
+
+/// Only the block entities of a chunk; `block_entities` since 1.18,
+/// `Level.TileEntities` before.
+#[derive(Deserialize)]
+struct ChunkNbt {
+    #[serde(default)]
+    block_entities: Vec<BlockEntityNbt>,
+    #[serde(rename = "Level")]
+    level: Option<LevelNbt>,
+}
+
+/// Number of block entities (chests, hoppers, furnaces, ...) in each column of a chunk.
+pub struct BlockEntityCounts {
+    columns: Vec<u16>,
+    total: u32,
+}
+
+impl BlockEntityCounts {
+    /// Count the block entities of uncompressed chunk NBT, as returned by `Region::read_chunk`.
+    pub fn from_bytes(data: &[u8]) -> fastnbt::error::Result<Self> {
+        let chunk: ChunkNbt = fastnbt::from_bytes(data)?;
+        let entities = match chunk.level {
+            Some(level) if chunk.block_entities.is_empty() => level.tile_entities,
+            _ => chunk.block_entities,
+        };
+        let chunk_size = CHUNK_SIZE as usize;
+        let mut columns = vec![0u16; chunk_size * chunk_size];
+        for entity in &entities {
+            let (x, z) = (entity.x.rem_euclid(CHUNK_SIZE) as usize, entity.z.rem_euclid(CHUNK_SIZE) as usize);
+            columns[z * chunk_size + x] = columns[z * chunk_size + x].saturating_add(1);
+        }
+        Ok(BlockEntityCounts { columns, total: entities.len() as u32 })
+    }
+}
+
+/// Green (none) through yellow to red (`SATURATION` or more).
+fn heat(t: f32) -> [f32; 3] {
+    if t < 0.5 {
+        [t * 2.0 * 255.0, 255.0, 0.0]
+    } else {
+        [255.0, (1.0 - t) * 2.0 * 255.0, 0.0]
+    }
+}
+
+/// Grey out a rendered chunk and tint it by its block entity count, so
+/// chunks dense with hoppers and chests stand out as lag hotspots. Columns
+/// holding a block entity get the full tint colour.
+pub fn tint_block_entities(buf: &mut [Rgba], counts: &BlockEntityCounts) {
+    if counts.total == 0 {
+        for px in buf.iter_mut() {
+            let luma = ((px[0] as u32 * 3 + px[1] as u32 * 6 + px[2] as u32) / 20) as u8;
+            px[0] = luma;
+            px[1] = luma;
+            px[2] = luma;
+        }
+        return;
+    }
+    let colour = heat((counts.total as f32 / SATURATION).min(1.0));
+    for (px, count) in buf.iter_mut().zip(counts.columns.iter()) {
+        let luma = (px[0] as f32 * 0.3 + px[1] as f32 * 0.6 + px[2] as f32 * 0.1) / 2.0;
+        let strength = if *count > 0 { 1.0 } else { 0.5 };
+        for (c, tint) in px.iter_mut().zip(colour.iter()) {
+            *c = (luma + (tint - luma) * strength) as u8;
+        }
+    }
+}
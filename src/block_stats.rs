@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+use fastanvil::{Chunk, HeightMode, JavaChunk};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::debug;
+
+use crate::coords::{ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+use crate::renderer::CeilingChunk;
+
+const SURFACE_MAGIC: &[u8; 4] = b"MCSF";
+
+pub fn to_surface_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.surface", rloc.x, rloc.z)
+}
+
+/// Top block name of every column of a region, as of the last render.
+/// Stored gzipped next to the timestamp cache: a name table followed by
+/// one u16 per column (0 = unknown, otherwise index + 1).
+pub struct SurfaceCache {
+    names: Vec<String>,
+    lookup: HashMap<String, u16>,
+    columns: Vec<u16>,
+}
+
+impl SurfaceCache {
+    fn empty() -> Self {
+        SurfaceCache {
+            names: vec![],
+            lookup: Default::default(),
+            columns: vec![0; (REGION_SIZE * REGION_SIZE) as usize],
+        }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut reader = GzDecoder::new(BufReader::new(File::open(path)?));
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SURFACE_MAGIC {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a surface cache"));
+        }
+        let mut cache = Self::empty();
+        let count = read_u16(&mut reader)?;
+        for _ in 0..count {
+            let len = read_u16(&mut reader)? as usize;
+            let mut name = vec![0u8; len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            cache.intern(&name);
+        }
+        for column in cache.columns.iter_mut() {
+            *column = read_u16(&mut reader)?;
+        }
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::fast());
+        writer.write_all(SURFACE_MAGIC)?;
+        writer.write_all(&(self.names.len() as u16).to_le_bytes())?;
+        for name in &self.names {
+            writer.write_all(&(name.len() as u16).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+        for column in &self.columns {
+            writer.write_all(&column.to_le_bytes())?;
+        }
+        writer.finish()?.flush()
+    }
+
+    fn intern(&mut self, name: &str) -> u16 {
+        if let Some(index) = self.lookup.get(name) {
+            return *index;
+        }
+        self.names.push(name.to_string());
+        let index = self.names.len() as u16;
+        self.lookup.insert(name.to_string(), index);
+        index
+    }
+
+    /// Set the block of a column, returning the previous one if it was known.
+    fn replace(&mut self, x: usize, z: usize, name: &str) -> Option<String> {
+        let index = self.intern(name);
+        let slot = &mut self.columns[z * REGION_SIZE as usize + x];
+        let previous = std::mem::replace(slot, index);
+        if previous == 0 { None } else { Some(self.names[previous as usize - 1].clone()) }
+    }
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> std::io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BlockChange {
+    pub added: u64,
+    pub removed: u64,
+}
+
+/// Counts of surface block changes between the previous and the current run.
+pub struct BlockStats {
+    load_previous: bool,
+    changed_columns: Mutex<u64>,
+    changes: Mutex<BTreeMap<String, BlockChange>>,
+    /// Changed columns of each chunk that changed, for --grief-alert.
+    changed_chunks: Mutex<HashMap<ChunkPos, u64>>,
+}
+
+impl BlockStats {
+    /// With `load_previous` false (no-cache runs), surfaces are recorded but not compared.
+    pub fn new(load_previous: bool) -> Self {
+        BlockStats {
+            load_previous,
+            changed_columns: Mutex::new(0),
+            changes: Default::default(),
+            changed_chunks: Default::default(),
+        }
+    }
+
+    pub fn load_surface(&self, cache_path: &Path, rloc: &RegionPos) -> SurfaceCache {
+        if !self.load_previous {
+            return SurfaceCache::empty();
+        }
+        SurfaceCache::load(&cache_path.join(to_surface_name(rloc))).unwrap_or_else(|e| {
+            debug!("no surface cache for {}: {}", rloc, e);
+            SurfaceCache::empty()
+        })
+    }
+
+    /// Compare the top blocks of a freshly rendered chunk with `surface` and update it.
+    pub fn record_chunk(&self, surface: &mut SurfaceCache, cloc: &ChunkPos, chunk: &JavaChunk, ceiling_y: Option<isize>) {
+        let names = match ceiling_y {
+            Some(cut_y) => surface_names(&CeilingChunk::new(chunk, cut_y)),
+            None => surface_names(chunk),
+        };
+        let (cx, cz) = cloc.local();
+        let chunk_size = CHUNK_SIZE as usize;
+        let mut changed_columns = 0;
+        let mut region_changes: Vec<(String, String)> = vec![];
+        for (index, name) in names.iter().enumerate() {
+            let name = match name {
+                Some(name) => name,
+                None => continue,
+            };
+            let x = cx * chunk_size + index % chunk_size;
+            let z = cz * chunk_size + index / chunk_size;
+            if let Some(previous) = surface.replace(x, z, name) {
+                if &previous != name {
+                    changed_columns += 1;
+                    region_changes.push((previous, name.clone()));
+                }
+            }
+        }
+        if region_changes.is_empty() {
+            return;
+        }
+        *self.changed_columns.lock().unwrap() += changed_columns;
+        self.changed_chunks.lock().unwrap().insert(*cloc, changed_columns);
+        let mut changes = self.changes.lock().unwrap();
+        for (previous, name) in region_changes {
+            changes.entry(previous).or_default().removed += 1;
+            changes.entry(name).or_default().added += 1;
+        }
+    }
+
+    pub fn changed_chunks(&self) -> HashMap<ChunkPos, u64> {
+        self.changed_chunks.lock().unwrap().clone()
+    }
+
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        let changes = self.changes.lock().unwrap();
+        let blocks: serde_json::Map<String, serde_json::Value> = changes.iter()
+            .map(|(name, change)| (name.clone(), serde_json::json!({
+                "added": change.added,
+                "removed": change.removed,
+                "net": change.added as i64 - change.removed as i64,
+            })))
+            .collect();
+        let json = serde_json::json!({
+            "changed_columns": *self.changed_columns.lock().unwrap(),
+            "blocks": blocks,
+        });
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, &json)?;
+        Ok(())
+    }
+
+    /// Stats of a run that found nothing to render (--skip-if-unchanged).
+    pub fn write_noop_json(path: &Path) -> std::io::Result<()> {
+        let json = serde_json::json!({
+            "noop": true,
+            "changed_columns": 0,
+            "blocks": {},
+        });
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, &json)?;
+        Ok(())
+    }
+}
+
+/// Name of the top block of each column, row by row.
+fn surface_names<C: Chunk>(chunk: &C) -> Vec<Option<String>> {
+    let chunk_size = CHUNK_SIZE as usize;
+    let mut names = Vec::with_capacity(chunk_size * chunk_size);
+    for z in 0..chunk_size {
+        for x in 0..chunk_size {
+            let y = chunk.surface_height(x, z, HeightMode::Trust);
+            names.push(chunk.block(x, y - 1, z).map(|block| block.name().to_string()));
+        }
+    }
+    names
+}
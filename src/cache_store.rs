@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use clap::ArgEnum;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::coords::RegionPos;
+use crate::dimension::to_cache_name;
+use crate::durability::Durability;
+
+/// The database of the sqlite backend, in the cache path.
+pub const CACHE_DB_NAME: &str = "caches.sqlite";
+
+/// `--cache-backend`: where the region caches (chunk timestamps) are kept.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum CacheBackend {
+    /// One `r.X.Z.cache` file per region.
+    #[default]
+    Files,
+    /// One database for every region, for network filesystems where
+    /// thousands of small files are slow to list, open and rename.
+    Sqlite,
+}
+
+/// The region caches of a cache path, as files or database rows. Both hold
+/// the same bytes (see `RegionTimestamps::save_cache`).
+pub enum CacheStore {
+    Files(PathBuf),
+    /// Regions without a row yet are read from their cache file, so caches
+    /// written by the file backend carry over and move in as they are saved.
+    Sqlite { dir: PathBuf, conn: Mutex<Connection> },
+}
+
+fn to_io(e: rusqlite::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e)
+}
+
+fn read_file(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl CacheStore {
+    /// Open the caches of `cache_path`. The database syncs every commit with
+    /// `Durability::Safe`, and only at WAL checkpoints otherwise.
+    pub fn open(backend: CacheBackend, cache_path: &Path, durability: Durability) -> std::io::Result<Self> {
+        match backend {
+            CacheBackend::Files => Ok(CacheStore::Files(cache_path.to_path_buf())),
+            CacheBackend::Sqlite => {
+                std::fs::create_dir_all(cache_path)?;
+                let conn = Connection::open(cache_path.join(CACHE_DB_NAME)).map_err(to_io)?;
+                // WAL: a `serve` or second render reading caches doesn't block saves.
+                conn.execute_batch("
+                    PRAGMA journal_mode = WAL;
+                    CREATE TABLE IF NOT EXISTS caches (x INTEGER, z INTEGER, data BLOB NOT NULL, PRIMARY KEY (x, z));
+                ").map_err(to_io)?;
+                let synchronous = if durability == Durability::Safe { "FULL" } else { "NORMAL" };
+                conn.execute_batch(&format!("PRAGMA synchronous = {}", synchronous)).map_err(to_io)?;
+                Ok(CacheStore::Sqlite { dir: cache_path.to_path_buf(), conn: Mutex::new(conn) })
+            },
+        }
+    }
+
+    /// The cache of `rloc`, None when there is none.
+    pub fn read(&self, rloc: &RegionPos) -> std::io::Result<Option<Vec<u8>>> {
+        match self {
+            CacheStore::Files(dir) => read_file(&dir.join(to_cache_name(rloc))),
+            CacheStore::Sqlite { dir, conn } => {
+                let row = conn.lock().unwrap()
+                    .query_row("SELECT data FROM caches WHERE x = ?1 AND z = ?2", params![rloc.x, rloc.z], |row| row.get(0))
+                    .optional().map_err(to_io)?;
+                match row {
+                    Some(data) => Ok(Some(data)),
+                    None => read_file(&dir.join(to_cache_name(rloc))),
+                }
+            },
+        }
+    }
+
+    /// Replace the cache of `rloc`. A row is replaced in one transaction of
+    /// its own, so a killed run leaves either cache, never half of one;
+    /// how it is synced was set by `open`, `durability` is for files.
+    pub fn write(&self, rloc: &RegionPos, data: &[u8], durability: Durability) -> std::io::Result<()> {
+        match self {
+            CacheStore::Files(dir) => durability.write(&dir.join(to_cache_name(rloc)), data),
+            CacheStore::Sqlite { conn, .. } => {
+                conn.lock().unwrap().execute("INSERT OR REPLACE INTO caches (x, z, data) VALUES (?1, ?2, ?3)", params![rloc.x, rloc.z, data]).map_err(to_io)?;
+                Ok(())
+            },
+        }
+    }
+
+    /// Drop the rows of regions that no longer exist, returning how many
+    /// were dropped. Cache files are pruned with the other per-region files.
+    pub fn remove(&self, rlocs: &[RegionPos]) -> std::io::Result<usize> {
+        match self {
+            CacheStore::Files(_) => Ok(0),
+            CacheStore::Sqlite { conn, .. } => {
+                let mut conn = conn.lock().unwrap();
+                let tx = conn.transaction().map_err(to_io)?;
+                let mut removed = 0;
+                for rloc in rlocs {
+                    removed += tx.execute("DELETE FROM caches WHERE x = ?1 AND z = ?2", params![rloc.x, rloc.z]).map_err(to_io)?;
+                }
+                tx.commit().map_err(to_io)?;
+                Ok(removed)
+            },
+        }
+    }
+
+    /// Where the cache of `rloc` is, for messages.
+    pub fn describe(&self, rloc: &RegionPos) -> String {
+        match self {
+            CacheStore::Files(dir) => dir.join(to_cache_name(rloc)).display().to_string(),
+            CacheStore::Sqlite { dir, .. } => format!("{} ({})", dir.join(CACHE_DB_NAME).display(), rloc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlite_reads_file_caches_and_saves_rows() {
+        let work = tempfile::tempdir().unwrap();
+        let dir = work.path();
+        let rloc = RegionPos::new(-1, 2);
+        let files = CacheStore::open(CacheBackend::Files, dir, Durability::Fast).unwrap();
+        files.write(&rloc, b"from files", Durability::Fast).unwrap();
+        assert_eq!(files.read(&rloc).unwrap().as_deref(), Some(&b"from files"[..]));
+
+        let sqlite = CacheStore::open(CacheBackend::Sqlite, dir, Durability::Safe).unwrap();
+        assert_eq!(sqlite.read(&rloc).unwrap().as_deref(), Some(&b"from files"[..]));
+        assert_eq!(sqlite.read(&RegionPos::new(0, 0)).unwrap(), None);
+        sqlite.write(&rloc, b"from sqlite", Durability::Safe).unwrap();
+        assert_eq!(sqlite.read(&rloc).unwrap().as_deref(), Some(&b"from sqlite"[..]));
+        // The row takes precedence over the file it moved in from.
+        assert_eq!(files.read(&rloc).unwrap().as_deref(), Some(&b"from files"[..]));
+        drop(sqlite);
+
+        let reopened = CacheStore::open(CacheBackend::Sqlite, dir, Durability::Fast).unwrap();
+        assert_eq!(reopened.read(&rloc).unwrap().as_deref(), Some(&b"from sqlite"[..]));
+        assert_eq!(reopened.remove(&[rloc]).unwrap(), 1);
+        assert_eq!(reopened.read(&rloc).unwrap().as_deref(), Some(&b"from files"[..]));
+    }
+}
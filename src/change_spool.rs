@@ -0,0 +1,131 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use tiny_http::{Method, Response, Server};
+
+use crate::coords::{ChunkPos, RegionPos};
+use crate::durability::Durability;
+use crate::error::{Error, Result};
+
+/// Changed chunks reported by a server plugin: files in a spool directory,
+/// each with one "chunk_x,chunk_z" line per chunk (more columns, like the
+/// ones of changed-chunks.csv, and unparsable lines are ignored). Files
+/// whose name starts with '.' or ends with ".tmp" are being written and are
+/// left alone: write a report under such a name and rename it into place.
+pub struct ChangeSpool {
+    files: Vec<PathBuf>,
+    pub chunks: HashMap<RegionPos, HashSet<ChunkPos>>,
+}
+
+impl ChangeSpool {
+    /// Read every report in `dir`; a missing directory is an empty spool.
+    pub fn read(dir: &Path) -> std::io::Result<Self> {
+        let mut spool = ChangeSpool { files: vec![], chunks: Default::default() };
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(spool),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() || name.starts_with('.') || name.ends_with(".tmp") {
+                continue;
+            }
+            let text = std::fs::read_to_string(entry.path())?;
+            for cloc in parse(&text) {
+                spool.chunks.entry(cloc.region()).or_default().insert(cloc);
+            }
+            spool.files.push(entry.path());
+        }
+        info!("change spool {}: {} reports, {} regions", dir.display(), spool.files.len(), spool.chunks.len());
+        Ok(spool)
+    }
+
+    /// The reports were rendered: remove them. Reports added meanwhile stay.
+    pub fn consume(self) {
+        for path in &self.files {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("cannot remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn parse(text: &str) -> Vec<ChunkPos> {
+    text.lines().filter_map(|line| {
+        let mut fields = line.split(',').map(str::trim);
+        Some(ChunkPos::new(fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+    }).collect()
+}
+
+/// Largest report body accepted over HTTP.
+const MAX_BODY_BYTES: u64 = 4 << 20;
+/// Most lines of a report accepted over HTTP.
+const MAX_BODY_LINES: usize = 1 << 18;
+
+/// Accept reports over HTTP: `POST /changes` with "chunk_x,chunk_z" lines
+/// adds them to the spool at `dir`, `POST /changes/<dimension>` to its
+/// subdirectory (`--world-path`). Runs until the process ends.
+///
+/// There is no authentication, so only loopback addresses are accepted:
+/// the plugin runs on the same host, or reaches it through a proxy that
+/// checks who it is. Bodies over `MAX_BODY_BYTES` or `MAX_BODY_LINES` are
+/// refused.
+pub fn listen(addr: &str, dir: PathBuf) -> Result<()> {
+    let listen_error = |message: String| Error::Listen { addr: addr.to_string(), message };
+    let resolved: Vec<SocketAddr> = addr.to_socket_addrs().map_err(|e| listen_error(e.to_string()))?.collect();
+    if resolved.is_empty() || resolved.iter().any(|resolved| !resolved.ip().is_loopback()) {
+        return Err(listen_error("only loopback addresses are accepted (reports are not authenticated)".to_string()));
+    }
+    let server = Server::http(addr).map_err(|e| listen_error(e.to_string()))?;
+    info!("accepting changed chunks on http://{}/changes", addr);
+    std::thread::spawn(move || {
+        let mut count = 0;
+        for mut request in server.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or_default().to_string();
+            let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+            let target = match segments.as_slice() {
+                ["changes"] => Some(dir.clone()),
+                ["changes", dimension] if ["overworld", "nether", "end"].contains(dimension) => Some(dir.join(dimension)),
+                _ => None,
+            };
+            let post = *request.method() == Method::Post;
+            let response = match (post, target) {
+                (true, Some(target)) => {
+                    let mut body = String::new();
+                    match request.as_reader().take(MAX_BODY_BYTES + 1).read_to_string(&mut body) {
+                        Ok(_) if body.len() as u64 > MAX_BODY_BYTES || body.lines().count() > MAX_BODY_LINES => {
+                            Response::from_string("report too large, split it\n").with_status_code(413)
+                        },
+                        Ok(_) => {
+                            let chunks = parse(&body).len();
+                            count += 1;
+                            let name = format!("http-{}-{}.txt", chrono::Local::now().format("%Y%m%d%H%M%S%3f"), count);
+                            match write_report(&target, &name, &body) {
+                                Ok(()) => Response::from_string(format!("{} chunks queued\n", chunks)),
+                                Err(e) => {
+                                    warn!("cannot write change report to {}: {}", target.display(), e);
+                                    Response::from_string("cannot queue\n").with_status_code(500)
+                                }
+                            }
+                        },
+                        Err(_) => Response::from_string("bad body\n").with_status_code(400),
+                    }
+                },
+                (false, Some(_)) => Response::from_string("POST chunk_x,chunk_z lines\n").with_status_code(405),
+                (_, None) => Response::from_string("not found\n").with_status_code(404),
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+/// Write a report so the renderer never reads it half written.
+fn write_report(dir: &Path, name: &str, body: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    Durability::Fast.write(&dir.join(name), body.as_bytes())
+}
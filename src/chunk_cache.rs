@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use clap::ArgEnum;
+
+use crate::coords::{ChunkPos, RegionPos};
+
+/// `--chunk-eviction`: which decoded chunks are dropped, and when.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum Eviction {
+    /// The least recently used, once over the budget.
+    #[default]
+    Lru,
+    /// Also a region's chunks as soon as it is drawn, but for the edges the
+    /// regions south (and east, with --shade full) of it are shaded against,
+    /// until those are drawn too. Suits many threads, where the LRU order
+    /// keeps chunks no one needs.
+    Edges,
+    /// None, ignoring the budget: for small worlds rendered several times in one process.
+    None,
+}
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    last_used: u64,
+}
+
+/// Decoded chunks shared by the render workers, bounded by an approximate
+/// byte budget. The least recently used chunks are dropped first; a dropped
+/// chunk is simply read from its region file again when needed.
+pub struct ChunkCache<V: Clone> {
+    budget: usize,
+    eviction: Eviction,
+    used: usize,
+    clock: u64,
+    entries: HashMap<ChunkPos, Entry<V>>,
+    by_use: BTreeMap<u64, ChunkPos>,
+    /// With `Eviction::Edges`: regions still to be drawn.
+    pending: HashSet<RegionPos>,
+}
+
+impl<V: Clone> ChunkCache<V> {
+    pub fn new(budget: usize, eviction: Eviction) -> Self {
+        ChunkCache {
+            budget,
+            eviction,
+            used: 0,
+            clock: 0,
+            entries: Default::default(),
+            by_use: Default::default(),
+            pending: Default::default(),
+        }
+    }
+
+    fn touch(&mut self, cloc: &ChunkPos) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(cloc) {
+            self.by_use.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.by_use.insert(clock, *cloc);
+        }
+    }
+
+    pub fn get(&mut self, cloc: &ChunkPos) -> Option<V> {
+        self.touch(cloc);
+        self.entries.get(cloc).map(|entry| entry.value.clone())
+    }
+
+    /// Whether `cloc` is cached, without counting as a use.
+    pub fn contains(&self, cloc: &ChunkPos) -> bool {
+        self.entries.contains_key(cloc)
+    }
+
+    /// Insert a chunk of about `size` bytes. If another worker inserted the
+    /// same chunk in the meantime, that one is kept and returned.
+    pub fn insert(&mut self, cloc: ChunkPos, value: V, size: usize) -> V {
+        if let Some(existing) = self.get(&cloc) {
+            return existing;
+        }
+        self.clock += 1;
+        self.entries.insert(cloc, Entry { value: value.clone(), size, last_used: self.clock });
+        self.by_use.insert(self.clock, cloc);
+        self.used += size;
+        self.evict();
+        value
+    }
+
+    /// The regions about to be drawn, whose neighbours keep the edges they
+    /// are shaded against with `Eviction::Edges`.
+    pub fn plan<I: IntoIterator<Item = RegionPos>>(&mut self, regions: I) {
+        self.pending.extend(regions);
+    }
+
+    /// `rloc` is drawn; with `Eviction::Edges` drop its chunks, and the edges
+    /// its north (and west, when `keep_east`) neighbours kept for it.
+    pub fn region_done(&mut self, rloc: &RegionPos, keep_east: bool) {
+        if self.eviction != Eviction::Edges {
+            return;
+        }
+        self.pending.remove(rloc);
+        let mut released = vec![*rloc, rloc.offset(0, -1)];
+        if keep_east {
+            released.push(rloc.offset(-1, 0));
+        }
+        for rloc in released {
+            if !self.pending.contains(&rloc) {
+                self.release(&rloc, keep_east);
+            }
+        }
+    }
+
+    /// Drop the chunks of `rloc`, which is not to be drawn (again), but its
+    /// south row while the region south of it is, and likewise its east
+    /// column when `keep_east`.
+    fn release(&mut self, rloc: &RegionPos, keep_east: bool) {
+        let south = self.pending.contains(&rloc.offset(0, 1));
+        let east = keep_east && self.pending.contains(&rloc.offset(1, 0));
+        for cloc in rloc.chunks() {
+            let (x, z) = cloc.local();
+            if (south && z == 31) || (east && x == 31) {
+                continue;
+            }
+            if let Some(entry) = self.entries.remove(&cloc) {
+                self.by_use.remove(&entry.last_used);
+                self.used -= entry.size;
+            }
+        }
+    }
+
+    fn evict(&mut self) {
+        if self.eviction == Eviction::None {
+            return;
+        }
+        while self.used > self.budget && self.entries.len() > 1 {
+            let (_, cloc) = match self.by_use.pop_first() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some(entry) = self.entries.remove(&cloc) {
+                self.used -= entry.size;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(eviction: Eviction, clocs: &[ChunkPos]) -> ChunkCache<()> {
+        let mut cache = ChunkCache::new(1 << 20, eviction);
+        for cloc in clocs {
+            cache.insert(*cloc, (), 10);
+        }
+        cache
+    }
+
+    #[test]
+    fn lru_drops_the_least_recently_used_first() {
+        let (a, b, c, d) = (ChunkPos::new(0, 0), ChunkPos::new(1, 0), ChunkPos::new(2, 0), ChunkPos::new(3, 0));
+        let mut cache = ChunkCache::new(30, Eviction::Lru);
+        for cloc in [a, b, c] {
+            cache.insert(cloc, (), 10);
+        }
+        assert!(cache.get(&a).is_some());
+        cache.insert(d, (), 10);
+        assert!(!cache.contains(&b));
+        assert!(cache.contains(&a) && cache.contains(&c) && cache.contains(&d));
+        cache.insert(b, (), 20);
+        assert!(!cache.contains(&c) && !cache.contains(&a));
+        assert!(cache.contains(&d) && cache.contains(&b));
+    }
+
+    #[test]
+    fn none_ignores_the_budget() {
+        let mut cache = ChunkCache::new(10, Eviction::None);
+        for x in 0..5 {
+            cache.insert(ChunkPos::new(x, 0), (), 10);
+        }
+        assert!((0..5).all(|x| cache.contains(&ChunkPos::new(x, 0))));
+    }
+
+    #[test]
+    fn edges_keep_the_south_row_until_the_south_region_is_drawn() {
+        let (north, south) = (RegionPos::new(0, 0), RegionPos::new(0, 1));
+        let (inner, south_row, east_column) = (north.chunk(5, 5), north.chunk(5, 31), north.chunk(31, 5));
+        let mut cache = filled(Eviction::Edges, &[inner, south_row, east_column]);
+        cache.plan([north, south]);
+        cache.region_done(&north, false);
+        assert!(!cache.contains(&inner) && !cache.contains(&east_column));
+        assert!(cache.contains(&south_row));
+        cache.region_done(&south, false);
+        assert!(!cache.contains(&south_row));
+    }
+
+    #[test]
+    fn edges_drop_the_south_row_no_region_needs() {
+        let rloc = RegionPos::new(0, 0);
+        let south_row = rloc.chunk(5, 31);
+        let mut cache = filled(Eviction::Edges, &[south_row]);
+        cache.plan([rloc]);
+        cache.region_done(&rloc, false);
+        assert!(!cache.contains(&south_row));
+    }
+
+    #[test]
+    fn edges_keep_the_east_column_with_full_shading() {
+        let (west, east) = (RegionPos::new(0, 0), RegionPos::new(1, 0));
+        let east_column = west.chunk(31, 5);
+        let mut cache = filled(Eviction::Edges, &[east_column]);
+        cache.plan([west, east]);
+        cache.region_done(&west, true);
+        assert!(cache.contains(&east_column));
+        cache.region_done(&east, true);
+        assert!(!cache.contains(&east_column));
+    }
+
+    #[test]
+    fn edges_release_the_chunks_read_from_a_region_not_drawn() {
+        // Read for the shading of the region south of it.
+        let (north, south) = (RegionPos::new(0, -1), RegionPos::new(0, 0));
+        let south_row = north.chunk(5, 31);
+        let mut cache = filled(Eviction::Edges, &[south_row]);
+        cache.plan([south]);
+        cache.region_done(&south, false);
+        assert!(!cache.contains(&south_row));
+    }
+}
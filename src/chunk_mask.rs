@@ -0,0 +1,60 @@
+use clap::ArgEnum;
+
+use crate::coords::{RegionPos, CHUNK_SIZE, REGION_CHUNKS};
+use crate::error::Result;
+use crate::scale::Scale;
+use crate::selection::Selection;
+
+/// Pixel depth of the `--chunk-mask` images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum MaskDepth {
+    /// 1-bit grayscale: black ungenerated, white generated.
+    #[clap(name = "1bit")]
+    OneBit,
+    /// 8-bit grayscale, 0 or 255, for tools that don't read 1-bit PNGs.
+    #[clap(name = "8bit")]
+    EightBit,
+}
+
+pub fn to_mask_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.mask.png", rloc.x, rloc.z)
+}
+
+/// Mask of the generated pixels, the size of the region image at `scale`:
+/// those of chunks stored in the region file (`generated`, region-local
+/// chunks, z-major) and, with a selection, inside it. Lets viewers tell
+/// ungenerated land from empty terrain.
+pub fn encode(rloc: &RegionPos, generated: &[bool], selection: Option<&Selection>, depth: MaskDepth, scale: Scale) -> Result<Vec<u8>> {
+    let size = scale.region_pixels();
+    let origin = rloc.block_origin();
+    let pixel = |x: usize, z: usize| -> bool {
+        let (x, z) = scale.block_at(x, z);
+        let chunk = (z / CHUNK_SIZE) * REGION_CHUNKS + x / CHUNK_SIZE;
+        generated[chunk as usize] && selection.map_or(true, |selection| selection.contains(&origin.offset(x, z)))
+    };
+    let (bit_depth, row_bytes) = match depth {
+        MaskDepth::OneBit => (png::BitDepth::One, size / 8),
+        MaskDepth::EightBit => (png::BitDepth::Eight, size),
+    };
+    let mut rows = vec![0u8; row_bytes * size];
+    for (z, row) in rows.chunks_mut(row_bytes).enumerate() {
+        for x in 0..size {
+            if !pixel(x, z) {
+                continue;
+            }
+            match depth {
+                MaskDepth::OneBit => row[x / 8] |= 0x80 >> (x % 8),
+                MaskDepth::EightBit => row[x] = 255,
+            }
+        }
+    }
+    let mut data = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut data, size as u32, size as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&rows)?;
+    }
+    Ok(data)
+}
@@ -0,0 +1,1083 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use lazy_static::lazy_static;
+use clap::{Args, Parser, Subcommand, ArgEnum};
+
+use crate::coords::{region_bounds, BlockPos, ChunkPos, RegionPos, RegionBounds, CHUNK_SIZE};
+use crate::selection::{Selection, Shape};
+use crate::dimension::DimensionKind;
+use crate::durability::Durability;
+use crate::cache_store::CacheBackend;
+use crate::config::DimensionConfig;
+use crate::events::EventBus;
+use crate::renderer::{ErrorStyle, HeightSource, ShadeHeight, ShadeMode, WaterStyle};
+use crate::heightmap::{Gradient, HeightmapFormat};
+use crate::watermark::{WatermarkSpec, WatermarkTarget};
+use crate::chunk_cache::Eviction;
+use crate::chunk_mask::MaskDepth;
+use crate::grid::GridKind;
+use crate::overlay::OverlayKind;
+use crate::background::Background;
+use crate::indexed_png::PngEncoding;
+use crate::isometric::ViewDirection;
+use crate::i18n::Lang;
+use crate::mbtiles::TileOutput;
+use crate::upload::UploadTarget;
+use crate::tile_import::TileLayout;
+use crate::scale::Scale;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Command,
+
+    /// Read options from a TOML file: long option names as keys, and [overworld], [nether]
+    /// and [end] sections for --world-path; options on the command line take precedence
+    #[clap(long, value_name="FILE", global = true, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Use the [profile.NAME] section of --config over its top level; several names, or
+    /// "all", run each profile in turn
+    #[clap(long, value_name="NAME,...|all", global = true, requires = "config")]
+    pub profile: Option<String>,
+
+    /// Language of progress and summary messages (default: from MCANVIL_LANG or LANG)
+    #[clap(long, arg_enum, global = true)]
+    pub lang: Option<Lang>,
+
+    /// Only errors and the final summary line: no progress, notes or warnings (for cron)
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// More logs: -v info, -vv debug, -vvv trace (overrides RUST_LOG's default level);
+    /// progress bars turn into text lines
+    #[clap(short, long, global = true, parse(from_occurrences))]
+    pub verbose: u64,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render the regions that changed since the last run
+    Render(RenderArgs),
+    /// Keep running and re-render regions whenever their chunk timestamps change
+    Watch(WatchArgs),
+    /// Serve the tile pyramid over HTTP with a map viewer, optionally re-rendering on a timer
+    Serve(ServeArgs),
+    /// Palette utilities
+    Palette(PaletteArgs),
+    /// Time full renders of a fixture dimension across modes, encodings and thread counts into a CSV
+    BenchMatrix(BenchMatrixArgs),
+    /// Write a synthetic region directory (flat terrain, checkerboard biomes) for fixtures
+    SynthWorld(SynthWorldArgs),
+    /// Seed the region images and caches from the top-down tiles of another renderer
+    ImportTiles(ImportTilesArgs),
+    /// Count the chunks of a dimension by DataVersion and list the regions an upgrade will convert
+    DataVersions(DataVersionsArgs),
+    /// List the regions and chunks the next render would draw, without rendering
+    Diff(DiffArgs),
+    /// Render every profile and dimension of --config in one process, with one progress bar
+    Batch(BatchArgs),
+    /// Open a window to pick the world, palette and output folders and render with a progress bar
+    #[cfg(feature = "gui")]
+    Gui,
+}
+
+impl Command {
+    /// Render options of the subcommands that render.
+    pub fn render_args(&self) -> Option<&RenderArgs> {
+        match self {
+            Command::Render(args) => Some(args),
+            Command::Watch(args) => Some(&args.render),
+            Command::Serve(args) => Some(&args.render),
+            Command::Palette(_) | Command::BenchMatrix(_) | Command::SynthWorld(_) | Command::ImportTiles(_) | Command::DataVersions(_) | Command::Diff(_) | Command::Batch(_) => None,
+            #[cfg(feature = "gui")]
+            Command::Gui => None,
+        }
+    }
+
+    pub fn render_args_mut(&mut self) -> Option<&mut RenderArgs> {
+        match self {
+            Command::Render(args) => Some(args),
+            Command::Watch(args) => Some(&mut args.render),
+            Command::Serve(args) => Some(&mut args.render),
+            Command::Palette(_) | Command::BenchMatrix(_) | Command::SynthWorld(_) | Command::ImportTiles(_) | Command::DataVersions(_) | Command::Diff(_) | Command::Batch(_) => None,
+            #[cfg(feature = "gui")]
+            Command::Gui => None,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// World path
+    #[clap(short, long, value_name="DIR", parse(from_os_str), required_unless_present = "world-path")]
+    pub dimension_path: Option<PathBuf>,
+
+    /// World root: render the overworld, the Nether and the End in one run, each into
+    /// its own subdirectory (overworld, nether, end) of the image and cache paths
+    #[clap(long, value_name="DIR", parse(from_os_str), conflicts_with_all = &["dimension-path", "dimension", "compare-path"])]
+    pub world_path: Option<PathBuf>,
+
+    /// Cache path
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub cache_path: PathBuf,
+
+    /// Image path
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub image_path: PathBuf,
+
+    /// Palette file, the http(s) URL of a palette.tar.gz to download into the cache path
+    /// (with --palette-sha256), or "auto" for the palette of the world's Minecraft version,
+    /// generated once from Mojang's client jar into the cache path
+    #[clap(short, long, value_name="FILE|URL|auto", parse(from_os_str))]
+    pub palette_path: PathBuf,
+
+    /// SHA-256 the palette must have, checked before it is used; required for a palette
+    /// URL, whose download is kept in the cache path and reused offline while it matches
+    #[clap(long, value_name="HEX", parse(try_from_str = parse_sha256))]
+    pub palette_sha256: Option<String>,
+
+    // Render location range.(Set one or two locations. example: "L-1,10" or "L-10,10" "L10,20")
+    #[clap(short='R', long, parse(try_from_str = parse_location_val), multiple_occurrences(true), max_occurrences(2))]
+    pub range: Option<Vec<(i32, i32)>>,
+
+    /// Only render the block rectangle "x1,z1:x2,z2": the regions it touches are rendered
+    /// and their blocks outside it are left transparent
+    #[clap(long, value_name="X1,Z1:X2,Z2", parse(try_from_str = parse_range_val), conflicts_with = "range")]
+    pub block_range: Option<((i32, i32), (i32, i32))>,
+
+    /// Like --block-range, in chunk coordinates
+    #[clap(long, value_name="X1,Z1:X2,Z2", parse(try_from_str = parse_range_val), conflicts_with = "range")]
+    pub chunk_range: Option<((i32, i32), (i32, i32))>,
+
+    /// Add a shape to the rendered area, in blocks: a rectangle "x1,z1:x2,z2" or a
+    /// circle "C<x>,<z>,<radius>" ("C<x>,<z>,<radius>r" around region x,z, radius in
+    /// regions). Repeat to render their union, with --block-range and --chunk-range
+    #[clap(long, value_name="SHAPE", multiple_occurrences(true), conflicts_with = "range")]
+    pub select: Vec<Shape>,
+
+    /// Only render regions within this many blocks of a player's last position
+    /// (from playerdata/ and level.dat, or the --player-pos list)
+    #[clap(long, value_name="BLOCKS")]
+    pub around_players: Option<u32>,
+
+    /// Skip the regions entirely outside the world border of level.dat (an eighth of it
+    /// in the nether)
+    #[clap(long)]
+    pub limit_to_border: bool,
+
+    /// Player position "x,z" in blocks for --around-players, instead of reading playerdata
+    #[clap(long, value_name="X,Z", parse(try_from_str = parse_location_val), multiple_occurrences(true))]
+    pub player_pos: Vec<(i32, i32)>,
+
+    // Log mode
+    #[clap(short, long)]
+    pub bgmode: bool,
+
+    /// Progress output: bars, text (same as --bgmode), or newline-delimited JSON events
+    #[clap(long, arg_enum, default_value_t = ProgressFormat::Bars)]
+    pub progress_format: ProgressFormat,
+
+    /// Also stream the JSON progress events to every client of a Unix domain socket at
+    /// this path, for dashboards that attach and detach while the process runs
+    #[clap(long, value_name="PATH", parse(from_os_str))]
+    pub progress_socket: Option<PathBuf>,
+
+    // cache mode
+    #[clap(long, arg_enum, default_value_t = CacheMode::Default)]
+    pub cache_mode: CacheMode,
+
+    /// Keep the region caches in one file per region, or in one sqlite database of the cache path
+    #[clap(long, arg_enum, default_value_t = CacheBackend::Files)]
+    pub cache_backend: CacheBackend,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    /// Only render chunks with a new timestamp whose content changed too, hashing them against the cache
+    #[clap(long)]
+    pub verify_chunk_hash: bool,
+
+    /// Render region "x,z" in full whatever its cache says, e.g. after changing the palette
+    #[clap(long, value_name="X,Z", parse(try_from_str = parse_location_val), multiple_occurrences(true))]
+    pub force_region: Vec<(i32, i32)>,
+
+    /// Render every region in full whatever the caches say; caches are still saved
+    #[clap(long)]
+    pub force_all: bool,
+
+    /// Older copy of the dimension (e.g. a backup) to compare against.
+    /// Renders a "what changed" map: unchanged chunks are dimmed, and the
+    /// changed chunks are listed in changed-chunks.csv in the image path,
+    /// which must not already hold rendered regions.
+    #[clap(long, value_name="DIR", parse(from_os_str))]
+    pub compare_path: Option<PathBuf>,
+
+    /// Spool directory of changed chunks reported by a server plugin ("chunk_x,chunk_z"
+    /// lines, one file per report). Only those chunks are rendered, without scanning
+    /// region timestamps; the reports are removed once rendered
+    #[clap(long, value_name="DIR", parse(from_os_str), conflicts_with = "compare-path")]
+    pub changes_from: Option<PathBuf>,
+
+    /// Write markers.json (players from playerdata/ and level.dat, world spawn) to the image path
+    #[clap(long)]
+    pub markers: bool,
+
+    /// Also draw the markers onto the tile pyramid (with --tiles)
+    #[clap(long, requires = "markers")]
+    pub markers_draw: bool,
+
+    /// JSON file of marker labels by language, keyed by the label markers.json has, e.g.
+    /// {"spawn": {"ja": "スポーン", "de": "Spawnpunkt"}}; the viewer picks the browser's
+    #[clap(long, value_name="FILE", parse(from_os_str), requires = "markers")]
+    pub marker_labels: Option<PathBuf>,
+
+    /// Also write markers.LANG.json with the labels in each of these languages, one
+    /// localized variant of the map per language from the same render
+    #[clap(long, value_name="LANG,...", use_value_delimiter = true, requires = "markers")]
+    pub marker_lang: Vec<String>,
+
+    /// Write r.X.Z.json next to each region image: the chunks rendered this run, their timestamps and errors
+    #[clap(long)]
+    pub sidecar: bool,
+
+    /// Write r.X.Z.mask.png next to each region image, white where the chunk is generated
+    /// (and inside the selection), black elsewhere, for styling the ungenerated area
+    #[clap(long, arg_enum, value_name="DEPTH")]
+    pub chunk_mask: Option<MaskDepth>,
+
+    /// What the map shows for a chunk that cannot be read: keep (the previous image's
+    /// pixels), blank (transparent) or pattern (magenta checkerboard). Such chunks are
+    /// listed in render-report.json either way
+    #[clap(long, arg_enum, default_value_t = ErrorStyle::Keep)]
+    pub error_style: ErrorStyle,
+
+    #[clap(flatten)]
+    pub tile: TileArgs,
+
+    /// Write region images and tiles as 8-bit palette PNGs, each with its own 256 colours.
+    /// Much smaller, at some loss of colour; redrawn regions start from the reduced colours
+    #[clap(long)]
+    pub indexed_png: bool,
+
+    /// Dither --indexed-png images instead of mapping each pixel to the nearest colour
+    #[clap(long, requires = "indexed-png")]
+    pub dither: bool,
+
+    /// Pixels per block of the region images: 2 or 4 for 1024 or 2048 pixel regions,
+    /// 1/2 or 1/4 to average blocks together. The tiles and --stitch expect 1
+    #[clap(long, value_name="SCALE", default_value = "1", conflicts_with_all = &["tiles", "stitch"])]
+    pub scale: Scale,
+
+    /// Draw regions farther than --far-distance from the spawn and every player at 1/2 or
+    /// 1/4 of a pixel per block; the tiles and --stitch scale them back up
+    #[clap(long, value_name="SCALE", parse(try_from_str = parse_far_scale), conflicts_with = "scale")]
+    pub far_scale: Option<Scale>,
+
+    /// Distance in blocks from the spawn and the players (--player-pos, or playerdata/)
+    /// beyond which --far-scale applies
+    #[clap(long, value_name="BLOCKS", default_value_t = 2048, requires = "far-scale")]
+    pub far_distance: u32,
+
+    /// Also composite every region image into one overview image
+    #[clap(long, value_name="FILE", parse(from_os_str))]
+    pub stitch: Option<PathBuf>,
+
+    /// Downscale factor of the --stitch image (power of two)
+    #[clap(long, default_value_t = 1)]
+    pub stitch_scale: u32,
+
+    #[clap(flatten)]
+    pub overlays: OverlayArgs,
+
+    /// Dimension type (detected from DIM-1 / DIM1 in the path when omitted)
+    #[clap(long, arg_enum)]
+    pub dimension: Option<DimensionKind>,
+
+    /// Nether: render the terrain below this Y instead of the bedrock roof
+    #[clap(long, default_value_t = 127)]
+    pub ceiling_y: isize,
+
+    /// What to draw: the shaded map, the map at night (lit by torches and moonlight),
+    /// the surface Y of every column (r.X.Z.height.png), the map greyed out with
+    /// chunks tinted by their block entity count (lag hotspots), or a 2:1 isometric
+    /// view of each region (r.X.Z.iso.png).
+    /// Use a separate cache path per mode, they track changes independently.
+    #[clap(long, arg_enum, default_value_t = RenderMode::Map)]
+    pub mode: RenderMode,
+
+    /// Side the camera of --mode isometric looks towards (at the top of the images)
+    #[clap(long, arg_enum, default_value_t = ViewDirection::North)]
+    pub iso_direction: ViewDirection,
+
+    /// Pixel format of --mode heightmap
+    #[clap(long, arg_enum, default_value_t = HeightmapFormat::Gray16)]
+    pub heightmap_format: HeightmapFormat,
+
+    /// Colour stops of --heightmap-format tint, as "Y:RRGGBB,Y:RRGGBB,..."
+    #[clap(long, value_name="STOPS")]
+    pub heightmap_gradient: Option<Gradient>,
+
+    /// Height that shades water columns: the flat water surface, or the seabed under a water tint
+    #[clap(long, arg_enum, default_value_t = ShadeHeight::Water)]
+    pub shade_height: ShadeHeight,
+
+    /// Water colour: flat (the surface block), depth (darker the deeper) or transparent
+    /// (the floor through shallow water, fading out by 8 blocks deep)
+    #[clap(long, arg_enum, default_value_t = WaterStyle::Flat)]
+    pub water: WaterStyle,
+
+    /// Relief shading of the map: off (flat palette colours, one per block), north (by the
+    /// column to the north) or full (also by the column to the west). Images drawn with
+    /// another shading are not redrawn: use --cache-mode refresh or another cache path
+    #[clap(long, arg_enum, default_value_t = ShadeMode::North)]
+    pub shade: ShadeMode,
+
+    /// Column surfaces from the chunks' heightmaps (trust; chunks whose heightmap is missing
+    /// or doesn't match the blocks are calculated anyway) or calculated from the blocks
+    /// for every chunk (slower, for worlds upgraded from old versions)
+    #[clap(long, arg_enum, default_value_t = HeightSource::Trust)]
+    pub height_mode: HeightSource,
+
+    /// How much of the --shade relief to apply, from 0 (flat) to 1
+    #[clap(long, value_name="0..1", default_value_t = 1.0, parse(try_from_str = parse_strength))]
+    pub shade_strength: f32,
+
+    /// Write counts of surface block changes since the previous run to this JSON file
+    #[clap(long, value_name="FILE", parse(from_os_str))]
+    pub block_stats: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub grief: GriefArgs,
+
+    /// Also draw r.X.Z.highlight.png of the blocks listed in this JSON file, e.g.
+    /// {"min_y": -64, "max_y": 16, "blocks": {"minecraft:diamond_ore": "00ffff"}}
+    #[clap(long, value_name="FILE", parse(from_os_str))]
+    pub highlight: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub upload: UploadArgs,
+
+    /// When the scan finds nothing to render, stop right there (no tiles, stitch or
+    /// markers) and exit with code 3, so scripts can skip uploading
+    #[clap(long)]
+    pub skip_if_unchanged: bool,
+
+    /// Continue a run that was interrupted, skipping the regions it finished
+    #[clap(long)]
+    pub resume: bool,
+
+    /// After the render, remove images and caches of regions whose region file is gone
+    #[clap(long)]
+    pub prune: bool,
+
+    /// With --prune, only list the orphaned files instead of removing them
+    #[clap(long, requires = "prune")]
+    pub prune_dry_run: bool,
+
+    /// Forget the list of chunks that failed to decode in earlier runs and retry them
+    #[clap(long)]
+    pub retry_broken: bool,
+
+    /// Log every decision (cache diff, chunks selected, neighbours, evictions) for one region "x,z"
+    #[clap(long, value_name="X,Z", parse(try_from_str = parse_location_val))]
+    pub trace_region: Option<(i32, i32)>,
+
+    /// fast: write images and caches in place; safe: fsync them and swap them in
+    /// atomically, images before caches, so a crash never leaves a cache newer than its image
+    #[clap(long, arg_enum, default_value_t = Durability::Fast)]
+    pub durability: Durability,
+
+    /// Progress events buffered for the display; when it falls behind, chunk steps
+    /// are merged instead of holding up the render
+    #[clap(long, value_name="EVENTS", default_value_t = crate::events::DEFAULT_BUFFER)]
+    pub progress_buffer: usize,
+
+    /// Number of regions rendered in parallel
+    #[clap(short, long, default_value_t = num_cpus::get())]
+    pub threads: usize,
+
+    /// Which regions are rendered first: dirty-first (most chunks to draw), size (biggest
+    /// region files), spiral (outwards from the world spawn) or nearest:X,Z (to a block)
+    #[clap(long, value_name="ORDER", default_value = "dirty-first")]
+    pub order: crate::render_order::RenderOrder,
+
+    /// Seeds the order of the regions --order ranks the same, the same on every run and
+    /// machine given the same seed
+    #[clap(long, value_name="N", default_value_t = 0)]
+    pub work_seed: u64,
+
+    /// Threads that read and decode chunks ahead of the --threads workers, so those don't
+    /// wait on the disk; 0 has the workers read their own chunks
+    #[clap(long, value_name="N", default_value_t = 0)]
+    pub io_threads: usize,
+
+    /// Regions the --io-threads may prepare ahead of the workers
+    #[clap(long, value_name="REGIONS", default_value_t = 4)]
+    pub readahead: usize,
+
+    /// Pause every worker this long after each chunk, to leave CPU time to a server on the
+    /// same machine (with fewer --threads for the same effect on fewer cores)
+    #[clap(long, value_name="MS-PER-CHUNK", default_value_t = 0)]
+    pub throttle: u64,
+
+    /// Read region files at most this fast, shared by every worker, to leave disk
+    /// bandwidth to a server on the same machine
+    #[clap(long, value_name="MB/S")]
+    pub io_limit: Option<f64>,
+
+    /// Approximate memory for decoded chunks; the least recently used are dropped and re-read when needed
+    #[clap(long, value_name="MB", default_value_t = crate::dim_renderer::DEFAULT_CHUNK_CACHE_MB)]
+    pub max_chunk_cache_mb: usize,
+
+    /// Which decoded chunks are dropped: lru once over --max-chunk-cache-mb, edges also
+    /// drops a drawn region's chunks but its south (and east) edge, none keeps them all
+    #[clap(long, arg_enum, value_name="POLICY", default_value_t = Eviction::Lru)]
+    pub chunk_eviction: Eviction,
+
+    /// The [overworld], [nether] and [end] sections of --config.
+    #[clap(skip)]
+    pub dimension_config: Vec<(DimensionKind, DimensionConfig)>,
+
+    /// In a batch: the bus of its progress display, which the pass publishes to
+    /// instead of showing its own.
+    #[clap(skip)]
+    pub progress_relay: Option<EventBus>,
+
+    /// In serve's interactive lane: the run journal it keeps apart from the
+    /// batch lane's.
+    #[clap(skip)]
+    pub journal_name: Option<&'static str>,
+}
+
+impl RenderArgs {
+    pub fn dim_path(&self) -> &PathBuf {
+        self.dimension_path.as_ref().expect("--dimension-path or --world-path")
+    }
+
+    /// The path given on the command line: --world-path, else --dimension-path.
+    pub fn given_path(&self) -> &Path {
+        self.world_path.as_ref().or(self.dimension_path.as_ref()).expect("--dimension-path or --world-path")
+    }
+
+    /// The passes to render: these arguments, or with --world-path one per
+    /// dimension of the world that has region files, with its own image and
+    /// cache subdirectories. Output files are suffixed with the dimension.
+    pub fn dimensions(&self) -> Vec<RenderArgs> {
+        let world_path = match &self.world_path {
+            Some(world_path) => world_path,
+            None => return vec![self.clone()],
+        };
+        let suffixed = |path: &Option<PathBuf>, id: &str| path.as_ref().map(|path| {
+            let stem = path.file_stem().unwrap_or_default().to_str().unwrap();
+            let name = match path.extension() {
+                Some(ext) => format!("{}-{}.{}", stem, id, ext.to_str().unwrap()),
+                None => format!("{}-{}", stem, id),
+            };
+            path.with_file_name(name)
+        });
+        [DimensionKind::Overworld, DimensionKind::Nether, DimensionKind::End].iter()
+            .filter_map(|kind| {
+                let dim_path = world_path.join(kind.region_dir());
+                if !dim_path.is_dir() {
+                    return None;
+                }
+                let id = kind.id();
+                let mut args = self.clone();
+                args.world_path = None;
+                args.dimension_path = Some(dim_path);
+                args.dimension = Some(*kind);
+                args.cache_path = self.cache_path.join(id);
+                args.image_path = self.image_path.join(id);
+                args.stitch = suffixed(&self.stitch, id);
+                args.block_stats = suffixed(&self.block_stats, id);
+                args.grief.grief_json = suffixed(&self.grief.grief_json, id);
+                args.upload.upload = self.upload.upload.as_ref().map(|target| target.join(id));
+                args.changes_from = self.changes_from.as_ref().map(|dir| dir.join(id));
+                if let TileOutput::MbTiles(path) = &self.tile.output {
+                    args.tile.output = TileOutput::MbTiles(suffixed(&Some(path.clone()), id).unwrap());
+                }
+                if let Some((_, config)) = self.dimension_config.iter().find(|(config_kind, _)| config_kind == kind) {
+                    config.apply(&mut args);
+                }
+                Some(args)
+            })
+            .collect()
+    }
+
+    pub fn png_encoding(&self) -> PngEncoding {
+        if self.indexed_png { PngEncoding::Indexed { dither: self.dither } } else { PngEncoding::Plain }
+    }
+
+    pub fn progress_format(&self) -> ProgressFormat {
+        match self.progress_format {
+            ProgressFormat::Bars if self.bgmode || crate::verbosity::get() >= crate::verbosity::Verbosity::Verbose => ProgressFormat::Text,
+            format => format,
+        }
+    }
+
+    /// Area given with --block-range, --chunk-range and --select.
+    pub fn selection(&self) -> Option<Selection> {
+        let normalize = |(a, b): ((i32, i32), (i32, i32))| ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1)));
+        let mut shapes = self.select.clone();
+        if let Some(range) = self.block_range {
+            let (min, max) = normalize(range);
+            shapes.push(Shape::Rect((BlockPos::from(min), BlockPos::from(max))));
+        }
+        if let Some(range) = self.chunk_range {
+            let (min, max) = normalize(range);
+            let last = CHUNK_SIZE - 1;
+            shapes.push(Shape::Rect((ChunkPos::from(min).block_origin(), ChunkPos::from(max).block_origin().offset(last, last))));
+        }
+        Selection::new(shapes)
+    }
+
+    /// Region bounds given with `-R`, or the regions around `selection`, normalized to (min, max).
+    pub fn bounds(&self) -> Option<RegionBounds> {
+        if let Some(selection) = self.selection() {
+            return Some(region_bounds(&selection.bounds()));
+        }
+        let range = self.range.as_ref()?;
+        match range.len() {
+            1 => {
+                let rloc = RegionPos::from(range[0]);
+                Some((rloc, rloc))
+            },
+            2 => {
+                let range1 = range[0];
+                let range2 = range[1];
+                Some((
+                    RegionPos::new(range1.0.min(range2.0), range1.1.min(range2.1)),
+                    RegionPos::new(range1.0.max(range2.0), range1.1.max(range2.1)),
+                ))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The z/x/y tile pyramid built from the region images.
+#[derive(Args, Debug, Clone)]
+pub struct TileArgs {
+    /// Also write a z/x/y tile pyramid (256x256 PNGs) under <image-path>/tiles
+    #[clap(long)]
+    pub tiles: bool,
+
+    /// Where --tiles go: "dir" (z/x/y.png under <image-path>/tiles) or "mbtiles:<file>",
+    /// one MBTiles archive updated in place
+    #[clap(long, value_name="OUTPUT", default_value = "dir", requires = "tiles")]
+    pub output: TileOutput,
+
+    /// Zoom levels of the tile pyramid
+    #[clap(long, default_value_t = 5)]
+    pub tile_levels: u32,
+
+    /// Lowest tile zoom written (0 = the most zoomed out)
+    #[clap(long, default_value_t = 0)]
+    pub tile_min_zoom: u32,
+
+    /// Highest tile zoom written (defaults to tile-levels - 1, 1 block per pixel)
+    #[clap(long)]
+    pub tile_max_zoom: Option<u32>,
+
+    /// Don't write fully transparent tiles, and remove existing ones
+    #[clap(long)]
+    pub tile_prune: bool,
+
+    /// Write a UTFGrid (z/x/y.grid.json) next to each tile, mapping its pixels to
+    /// chunk and block coordinates and region files for hover info in web viewers
+    #[clap(long)]
+    pub tile_grid: bool,
+}
+
+/// What is drawn onto the tiles and the --stitch image, leaving the region images as they are.
+#[derive(Args, Debug, Clone)]
+pub struct OverlayArgs {
+    /// Composite a logo onto the published images, "logo.png:corner:alpha"
+    /// (corner: top-left, top-right, bottom-left, bottom-right; alpha 0-1)
+    #[clap(long, value_name="LOGO:CORNER:ALPHA")]
+    pub watermark: Option<WatermarkSpec>,
+
+    /// Draw faint chunk and/or region boundaries onto the tiles and the --stitch image.
+    /// Region images are left clean, so this can change from run to run
+    #[clap(long, arg_enum)]
+    pub grid: Option<GridKind>,
+
+    /// Extra layers drawn onto the tiles and the --stitch image: slime (tint slime chunks),
+    /// contours (elevation isolines; map renders then also keep r.X.Z.heights in the
+    /// cache path, and regions get lines once rendered so), spawn (mark the world spawn
+    /// of level.dat). Like --grid, the region images are left as they are
+    #[clap(long, arg_enum, value_name="LAYER,...", use_value_delimiter = true)]
+    pub overlay: Vec<OverlayKind>,
+
+    /// What the tiles and the --stitch image show where there is no chunk: transparent,
+    /// checkerboard or a colour RRGGBB. Region images stay transparent there
+    #[clap(long, value_name="COLOUR", default_value = "transparent")]
+    pub background: Background,
+
+    /// Blocks between the lines of --overlay contours (every 5th is darker)
+    #[clap(long, value_name="BLOCKS", default_value_t = 8)]
+    pub contour_interval: i32,
+
+    /// World seed of --overlay slime (read from level.dat when omitted)
+    #[clap(long, allow_hyphen_values = true)]
+    pub seed: Option<i64>,
+
+    /// Also write region coordinates in the corner of each region with --grid
+    #[clap(long, requires = "grid")]
+    pub grid_labels: bool,
+
+    /// Images that get the --watermark: the tiles, the --stitch image, or all
+    #[clap(long, arg_enum, default_value_t = WatermarkTarget::All, requires = "watermark")]
+    pub watermark_on: WatermarkTarget,
+}
+
+/// --grief-alert and its options.
+#[derive(Args, Debug, Clone)]
+pub struct GriefArgs {
+    /// Alert when more than N chunks within --grief-radius of each other changed their
+    /// surface this run, outside --build-areas (written to --grief-json, posted to --grief-webhook)
+    #[clap(long, value_name="N")]
+    pub grief_alert: Option<usize>,
+
+    /// Chunks at most this far apart count as changed together
+    #[clap(long, value_name="CHUNKS", default_value_t = 4, requires = "grief-alert")]
+    pub grief_radius: i32,
+
+    /// Surface columns (of 256) that must change for a chunk to count
+    #[clap(long, value_name="COLUMNS", default_value_t = 16, requires = "grief-alert")]
+    pub grief_min_columns: u64,
+
+    /// JSON list of areas where big changes are expected, in blocks, e.g.
+    /// [{"name": "spawn", "dimension": "overworld", "x1": -200, "z1": -200, "x2": 200, "z2": 200}]
+    #[clap(long, value_name="FILE", parse(from_os_str), requires = "grief-alert")]
+    pub build_areas: Option<PathBuf>,
+
+    /// Write the alerts of the run to this JSON file (an empty list when there are none)
+    #[clap(long, value_name="FILE", parse(from_os_str), requires = "grief-alert")]
+    pub grief_json: Option<PathBuf>,
+
+    /// POST the alerts of the run as JSON to this URL when there are any
+    #[clap(long, value_name="URL", requires = "grief-alert")]
+    pub grief_webhook: Option<String>,
+}
+
+/// Where the files a run wrote are uploaded.
+#[derive(Args, Debug, Clone)]
+pub struct UploadArgs {
+    /// Upload the region images, tiles and stitched image written by this run to
+    /// "s3://bucket/prefix" (credentials from AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY)
+    #[clap(long, value_name="S3_URL")]
+    pub upload: Option<UploadTarget>,
+
+    /// S3-compatible endpoint for --upload, e.g. MinIO or Cloudflare R2
+    #[clap(long, value_name="URL", requires = "upload")]
+    pub upload_endpoint: Option<String>,
+
+    /// Region of the --upload bucket ("auto" for R2)
+    #[clap(long, value_name="REGION", default_value = "us-east-1", requires = "upload")]
+    pub upload_region: String,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[clap(flatten)]
+    pub render: RenderArgs,
+
+    /// Seconds between scans
+    #[clap(long, value_name="SECS", default_value_t = 60)]
+    pub interval: u64,
+
+    /// Read commands from stdin: "map" draws the regions, "X,Z" re-renders a region before the next scan
+    #[clap(long)]
+    pub console: bool,
+
+    /// Accept changed chunk reports as `POST /changes` on this loopback address (they are
+    /// not authenticated), into --changes-from
+    #[clap(long, value_name="ADDR", requires = "changes-from")]
+    pub changes_listen: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    #[clap(flatten)]
+    pub render: RenderArgs,
+
+    /// Address to listen on
+    #[clap(long, value_name="ADDR", default_value = "127.0.0.1:8080")]
+    pub bind: String,
+
+    #[clap(flatten)]
+    pub rendering: ServeRenderArgs,
+
+    /// Ask for HTTP basic authentication, "USER:PASSWORD": for the whole site, or with
+    /// --private-layer for those layers and the markers. Sent in the clear without a TLS proxy
+    #[clap(long, value_name="USER:PASSWORD")]
+    pub auth: Option<String>,
+
+    /// Also accept "Authorization: Bearer TOKEN" where --auth is asked for, or ask for it
+    /// alone; for scripts and tile clients that don't sign in
+    #[clap(long, value_name="TOKEN")]
+    pub auth_token: Option<String>,
+
+    /// Layers only served with the --auth or --auth-token credentials (overworld, nether,
+    /// end; "map" with -d), listed in the viewer after signing in at /login; the rest of the
+    /// site is public
+    #[clap(long, value_name="LAYER,...", use_value_delimiter = true)]
+    pub private_layer: Vec<String>,
+}
+
+/// What serve renders while it serves.
+#[derive(Args, Debug, Clone)]
+pub struct ServeRenderArgs {
+    /// Also render what changed every this many seconds, like watch
+    #[clap(long, value_name="SECS")]
+    pub rerender_interval: Option<u64>,
+
+    /// Let the viewer ask for the regions it shows to be rendered first, ahead of the
+    /// --rerender-interval passes, which pause between chunks meanwhile
+    #[clap(long, requires = "tiles")]
+    pub render_viewport: bool,
+
+    /// Render tiles that are missing or older than their region files when they are asked
+    /// for, on the --render-viewport lane, showing a placeholder meanwhile
+    #[clap(long, requires = "tiles")]
+    pub render_missing: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchMatrixArgs {
+    /// Region directory of the fixture world
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub dimension_path: PathBuf,
+
+    /// Palette path
+    #[clap(short, long, value_name="FILE", parse(from_os_str))]
+    pub palette_path: PathBuf,
+
+    /// Thread counts to try
+    #[clap(long, value_name="N,...", use_value_delimiter = true, default_values = &["1", "2", "4"])]
+    pub threads: Vec<usize>,
+
+    /// Render modes to try
+    #[clap(long, arg_enum, value_name="MODE,...", use_value_delimiter = true, default_values = &["map"])]
+    pub modes: Vec<RenderMode>,
+
+    /// Image encodings to try
+    #[clap(long, arg_enum, value_name="PRESET,...", use_value_delimiter = true, default_values = &["plain"])]
+    pub encodings: Vec<EncodePreset>,
+
+    /// Runs of each combination
+    #[clap(long, default_value_t = 1)]
+    pub repeat: usize,
+
+    /// Where the runs write their caches and images (emptied first)
+    #[clap(long, value_name="DIR", parse(from_os_str), default_value = "bench-work")]
+    pub work_dir: PathBuf,
+
+    /// CSV file of the timings
+    #[clap(short, long, value_name="FILE", parse(from_os_str), default_value = "bench.csv")]
+    pub out: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct SynthWorldArgs {
+    /// Region directory to write (usable as --dimension-path)
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub out: PathBuf,
+
+    /// Generated area, in --select syntax; chunks overlapping it are written
+    #[clap(long, value_name="SHAPE", multiple_occurrences(true), required = true)]
+    pub area: Vec<Shape>,
+
+    /// Header timestamp of every chunk
+    #[clap(long, default_value_t = 1_600_000_000)]
+    pub timestamp: u32,
+
+    /// Give a chunk a later timestamp, "chunk_x,chunk_z:timestamp", to test change detection
+    #[clap(long, value_name="X,Z:TIMESTAMP", multiple_occurrences(true), parse(try_from_str = parse_touch))]
+    pub touch: Vec<(ChunkPos, u32)>,
+
+    /// Y of the grass surface
+    #[clap(long, default_value_t = 64)]
+    pub surface_y: i32,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportTilesArgs {
+    /// Region directory the tiles were rendered from
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub dimension_path: PathBuf,
+
+    /// Cache path of the renders to come
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub cache_path: PathBuf,
+
+    /// Cache backend of the renders to come
+    #[clap(long, arg_enum, default_value_t = CacheBackend::Files)]
+    pub cache_backend: CacheBackend,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    /// Image path of the renders to come
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub image_path: PathBuf,
+
+    /// Most detailed zoom directory of the tile tree (one block per pixel)
+    #[clap(long, value_name="DIR", parse(from_os_str))]
+    pub from: PathBuf,
+
+    /// File layout of the tile tree
+    #[clap(long, arg_enum, default_value_t = TileLayout::Xyz)]
+    pub layout: TileLayout,
+
+    /// Tile edge in pixels; 256 for xyz, 128 for dynmap-flat by default
+    #[clap(long, value_name="PIXELS")]
+    pub tile_size: Option<i32>,
+
+    /// Block X at the north-west pixel of tile 0,0
+    #[clap(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub origin_x: i32,
+
+    /// Block Z at the north-west pixel of tile 0,0
+    #[clap(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub origin_z: i32,
+
+    /// Replace region images that are already there
+    #[clap(long)]
+    pub overwrite: bool,
+
+    /// Also write this tool's tile pyramid from the imported regions
+    #[clap(long)]
+    pub tiles: bool,
+
+    /// Zoom levels of the tile pyramid
+    #[clap(long, default_value_t = 5)]
+    pub tile_levels: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct DataVersionsArgs {
+    /// World path
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub dimension_path: PathBuf,
+
+    /// Release ("1.21") or DataVersion to list the regions with older chunks for
+    #[clap(long, value_name="VERSION", parse(try_from_str = crate::data_versions::parse_target))]
+    pub upgrade_to: Option<i32>,
+
+    /// Also write the counts of every region to this JSON file
+    #[clap(long, value_name="FILE", parse(from_os_str))]
+    pub json: Option<PathBuf>,
+
+    /// Number of regions read in parallel
+    #[clap(short, long, default_value_t = num_cpus::get())]
+    pub threads: usize,
+}
+
+/// The lock of the cache path, taken by the commands that write to it.
+#[derive(Args, Debug, Clone)]
+pub struct LockArgs {
+    /// When another run holds the cache path's lock, wait for it instead of exiting
+    #[clap(long, conflicts_with = "force")]
+    pub wait: bool,
+
+    /// Take the cache path's lock even if another run holds it
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// World path
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub dimension_path: PathBuf,
+
+    /// Cache path of the renders
+    #[clap(short, long, value_name="DIR", parse(from_os_str))]
+    pub cache_path: PathBuf,
+
+    /// Cache backend of the renders
+    #[clap(long, arg_enum, default_value_t = CacheBackend::Files)]
+    pub cache_backend: CacheBackend,
+
+    /// Compare chunk hashes as --verify-chunk-hash does
+    #[clap(long)]
+    pub verify_chunk_hash: bool,
+
+    /// Output: text, or one JSON document on stdout
+    #[clap(long, arg_enum, default_value_t = DiffFormat::Text)]
+    pub format: DiffFormat,
+
+    /// List the chunks of each region in the text output
+    #[clap(long)]
+    pub chunks: bool,
+
+    /// Number of regions read in parallel
+    #[clap(short, long, default_value_t = num_cpus::get())]
+    pub threads: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Number of worlds and dimensions rendered at once, each with its own --threads
+    #[clap(long, value_name="N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Write one JSON report of every render of the batch to this file
+    #[clap(long, value_name="FILE", parse(from_os_str))]
+    pub report: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+}
+
+/// PNG encodings compared by bench-matrix.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum EncodePreset {
+    /// Plain RGBA PNGs
+    Plain,
+    /// --indexed-png
+    Indexed,
+    /// --indexed-png --dither
+    IndexedDither,
+}
+
+#[derive(Args, Debug)]
+pub struct PaletteArgs {
+    #[clap(subcommand)]
+    pub command: PaletteCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PaletteCommand {
+    /// Generate (or verify the cached copy of) the palette of a world's Minecraft version, as -p auto does
+    Fetch {
+        /// World path
+        #[clap(short, long, value_name="DIR", parse(from_os_str))]
+        dimension_path: PathBuf,
+
+        /// Cache path
+        #[clap(short, long, value_name="DIR", parse(from_os_str))]
+        cache_path: PathBuf,
+    },
+    /// Sample random chunks and report how many of their surface blocks the palette covers
+    Coverage {
+        /// World path
+        #[clap(short, long, value_name="DIR", parse(from_os_str))]
+        dimension_path: PathBuf,
+
+        /// Palette path
+        #[clap(short, long, value_name="FILE", parse(from_os_str))]
+        palette_path: PathBuf,
+
+        /// Number of chunks to sample
+        #[clap(long, default_value_t = 200)]
+        samples: usize,
+
+        /// Seed of the sampling, for repeatable reports
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Build a palette from the block textures of a Minecraft client jar
+    Generate {
+        /// Client jar, e.g. .minecraft/versions/1.20.4/1.20.4.jar
+        #[clap(long, value_name="FILE", parse(from_os_str))]
+        jar: PathBuf,
+
+        /// Palette to write (palette.tar.gz)
+        #[clap(short, long, value_name="FILE", parse(from_os_str))]
+        out: PathBuf,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum ProgressFormat {
+    Bars,
+    Text,
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum RenderMode {
+    Map,
+    Night,
+    Heightmap,
+    BlockEntities,
+    Isometric,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
+pub enum CacheMode {
+    Default, // cache SAVE and LOAD
+    Refresh, // cache SAVE only
+    ReadOnly, // cache LOAD only
+    NoCache, // ignore cache
+}
+
+/// Parse a rectangle "x1,z1:x2,z2"
+pub fn parse_range_val(s: &str) -> Result<((i32, i32), (i32, i32)), Box<dyn Error + Send + Sync + 'static>>
+{
+    let (first, second) = s.split_once(':').ok_or("invalid x1,z1:x2,z2")?;
+    Ok((parse_location_val(first)?, parse_location_val(second)?))
+}
+
+/// Parse a --far-scale, 1/2 or 1/4
+fn parse_far_scale(s: &str) -> Result<Scale, Box<dyn Error + Send + Sync + 'static>>
+{
+    match s.parse::<Scale>()? {
+        scale @ Scale::Down(_) => Ok(scale),
+        Scale::Up(_) => Err("--far-scale is 1/2 or 1/4".into()),
+    }
+}
+
+/// Parse a value from 0 to 1
+fn parse_strength(s: &str) -> Result<f32, Box<dyn Error + Send + Sync + 'static>>
+{
+    let strength: f32 = s.parse()?;
+    if !(0.0..=1.0).contains(&strength) {
+        return Err("expected a value from 0 to 1".into());
+    }
+    Ok(strength)
+}
+
+/// Parse a SHA-256 checksum, 64 hex digits, into lowercase
+fn parse_sha256(s: &str) -> Result<String, Box<dyn Error + Send + Sync + 'static>>
+{
+    let s = s.trim();
+    if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("expected 64 hex digits".into());
+    }
+    Ok(s.to_ascii_lowercase())
+}
+
+/// Parse a "chunk_x,chunk_z:timestamp" value of synth-world --touch
+fn parse_touch(s: &str) -> Result<(ChunkPos, u32), Box<dyn Error + Send + Sync + 'static>>
+{
+    let (chunk, timestamp) = s.split_once(':').ok_or("invalid chunk_x,chunk_z:timestamp")?;
+    Ok((ChunkPos::from(parse_location_val(chunk)?), timestamp.trim().parse()?))
+}
+
+/// Parse location value
+pub fn parse_location_val(s: &str) -> Result<(i32, i32), Box<dyn Error + Send + Sync + 'static>>
+{
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(-?\d+),(-?\d+)").unwrap();
+    }
+    if let Some(cap) = RE.captures(s) {
+       let x: i32 = cap.get(1).unwrap().as_str().parse().unwrap();
+       let z: i32 = cap.get(2).unwrap().as_str().parse().unwrap();
+       return Ok((x, z));
+    } else {
+        return Err(format!("invalid xloc,zloc").into());
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+use clap::Parser;
+use fastanvil::RenderedPalette;
+
+use crate::cli::{BatchArgs, Cli, Command, ProgressFormat, RenderArgs};
+use crate::commands::render::{load_palette, render_pass, PassResult, FAILED_EXIT_CODE};
+use crate::dim_renderer::RegionProgress;
+use crate::durability::Durability;
+use crate::events::{EventBus, DEFAULT_BUFFER};
+use crate::render_report::REPORT_NAME;
+use crate::run_lock::{self, RunLock};
+use crate::shutdown::{self, INTERRUPTED_EXIT_CODE};
+use crate::{config, i18n, palette_fetch, path_layout, progress, verbosity};
+
+/// One render of the batch: a dimension of a profile.
+struct Job {
+    /// "survival/nether"; the dimension alone without profiles.
+    name: String,
+    profile: Option<String>,
+    args: RenderArgs,
+    /// The directory shared by its image and cache paths, sorted into them once locked.
+    shared_root: Option<PathBuf>,
+}
+
+/// What a job did, as it goes into the batch report.
+struct JobResult {
+    index: usize,
+    rendered: usize,
+    failed: usize,
+    report: serde_json::Value,
+}
+
+/// The render options of a profile (None: the top level of the file), as
+/// `render --config FILE --profile NAME` reads them, and the directory their
+/// image and cache paths share, if they do.
+fn profile_args(config_path: &Path, profile: Option<&str>) -> Result<(RenderArgs, Option<PathBuf>), String> {
+    let mut config = OsString::from("--config=");
+    config.push(config_path);
+    let mut argv: Vec<OsString> = vec!["mcanvilrenderer".into(), "render".into(), config];
+    if let Some(profile) = profile {
+        argv.push(format!("--profile={}", profile).into());
+    }
+    let expanded = config::expand(argv).map_err(|e| e.to_string())?;
+    match Cli::try_parse_from(expanded.args).map_err(|e| e.to_string())?.command {
+        Command::Render(mut args) => {
+            let shared_root = path_layout::separate(&mut args.image_path, &mut args.cache_path);
+            args.dimension_config = expanded.dimensions;
+            Ok((args, shared_root))
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Every dimension of the profiles `selected` (default: all of them, or the
+/// top level of a file without profiles).
+fn jobs(config_path: &Path, selected: Option<&str>) -> Result<Vec<Job>, String> {
+    let mut profiles: Vec<Option<String>> = config::profile_names(config_path, selected.unwrap_or("all"))
+        .map_err(|e| e.to_string())?
+        .into_iter().map(Some).collect();
+    if profiles.is_empty() && selected.is_none() {
+        profiles.push(None);
+    }
+    let mut jobs = vec![];
+    for profile in profiles {
+        let (args, shared_root) = profile_args(config_path, profile.as_deref()).map_err(|e| match &profile {
+            Some(name) => format!("[profile.{}]: {}", name, e),
+            None => e,
+        })?;
+        for dim_args in args.dimensions() {
+            let dimension = dim_args.dimension.filter(|_| args.world_path.is_some()).map(|kind| kind.id().to_string());
+            let name = match (&profile, dimension) {
+                (Some(profile), Some(dimension)) => format!("{}/{}", profile, dimension),
+                (Some(name), None) | (None, Some(name)) => name.clone(),
+                (None, None) => dim_args.dim_path().display().to_string(),
+            };
+            jobs.push(Job { name, profile: profile.clone(), args: dim_args, shared_root: shared_root.clone() });
+        }
+    }
+    Ok(jobs)
+}
+
+/// The render-report.json the job wrote.
+fn pass_report(args: &RenderArgs) -> serde_json::Value {
+    let path = args.image_path.join(REPORT_NAME);
+    std::fs::read(&path).ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or(serde_json::Value::Null)
+}
+
+/// Render every profile and dimension of the config file in this process:
+/// palettes are loaded once for the jobs that share them, the jobs run one
+/// after the other or `--jobs` at a time, and their progress goes on one bar.
+pub fn run(args: &BatchArgs, config_path: Option<&Path>, selected: Option<&str>) {
+    let config_path = match config_path {
+        Some(path) => path,
+        None => {
+            eprintln!("batch needs --config FILE");
+            std::process::exit(2);
+        }
+    };
+    let mut jobs = match jobs(config_path, selected) {
+        Ok(jobs) if jobs.is_empty() => {
+            eprintln!("--config {}: nothing to render", config_path.display());
+            std::process::exit(2);
+        },
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("--config: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    // Jobs sharing a cache path share its lock.
+    let mut locks: HashMap<PathBuf, RunLock> = HashMap::new();
+    for job in &jobs {
+        if locks.contains_key(&job.args.cache_path) {
+            continue;
+        }
+        match RunLock::acquire(&job.args.cache_path, args.lock.wait, args.lock.force) {
+            Ok(lock) => {
+                locks.insert(job.args.cache_path.clone(), lock);
+            },
+            Err(e) => {
+                eprintln!("cannot lock {}: {}", job.args.cache_path.display(), e);
+                run_lock::exit(2);
+            }
+        }
+        std::fs::create_dir_all(&job.args.image_path).unwrap();
+    }
+    let mut shared_roots: Vec<&PathBuf> = jobs.iter().filter_map(|job| job.shared_root.as_ref()).collect();
+    shared_roots.dedup();
+    for root in shared_roots {
+        path_layout::migrate(root);
+    }
+    shutdown::install();
+
+    // An "auto" palette depends on the world it was resolved for.
+    let mut palettes: HashMap<(PathBuf, Option<PathBuf>), Arc<RenderedPalette>> = HashMap::new();
+    let job_palettes: Vec<Arc<RenderedPalette>> = jobs.iter().map(|job| {
+        let world = Some(job.args.dim_path().clone()).filter(|_| palette_fetch::is_auto(&job.args.palette_path));
+        Arc::clone(palettes.entry((job.args.palette_path.clone(), world)).or_insert_with(|| load_palette(&job.args)))
+    }).collect();
+
+    // One bar for the batch, unless a job asks for another display.
+    let bars = !verbosity::quiet() && jobs.iter().all(|job| job.args.progress_format() == ProgressFormat::Bars);
+    let bus = EventBus::default();
+    let progress_handle = if bars {
+        let receiver = bus.subscribe(DEFAULT_BUFFER);
+        let count = jobs.len();
+        for job in &mut jobs {
+            job.args.progress_relay = Some(bus.clone());
+        }
+        Some(std::thread::spawn(move || progress::batch_mode(receiver, count)))
+    } else {
+        None
+    };
+
+    let started_at = chrono::Local::now();
+    let started = Instant::now();
+    let count = jobs.len();
+    let pool = threadpool::ThreadPool::new(args.jobs.max(1));
+    let (sender, receiver) = mpsc::channel();
+    for (index, (job, palette)) in jobs.into_iter().zip(job_palettes).enumerate() {
+        let (sender, bus) = (sender.clone(), bus.clone());
+        pool.execute(move || {
+            if shutdown::requested() {
+                return;
+            }
+            if job.args.progress_relay.is_none() {
+                note!("{}", tr!("Job: {}", job.name));
+            }
+            let job_started = Instant::now();
+            let result = render_pass(&job.args, job.args.bounds().as_ref(), &palette, job.args.cache_mode, job.args.retry_broken, None, None);
+            // Every pass that gets past the scan writes a report, if only one of nothing.
+            let pass_report = if result.is_ok() { pass_report(&job.args) } else { serde_json::Value::Null };
+            // A job that cannot scan fails alone, like one whose regions cannot be written.
+            let result = result
+                .unwrap_or_else(|e| {
+                    eprintln!("{}: {}", job.name, e);
+                    PassResult { failed_outputs: vec![e.to_string()], ..Default::default() }
+                });
+            if job.args.progress_relay.is_some() {
+                bus.publish(RegionProgress::EndAll);
+            }
+            let report = serde_json::json!({
+                "name": job.name,
+                "profile": job.profile,
+                "dimension": job.args.dimension.map(|kind| kind.id()),
+                "image_path": job.args.image_path,
+                "cache_path": job.args.cache_path,
+                "seconds": job_started.elapsed().as_secs_f64(),
+                "regions_rendered": result.rendered,
+                "regions_failed": result.failed.iter().map(|(rloc, message)| serde_json::json!({
+                    "x": rloc.x,
+                    "z": rloc.z,
+                    "error": message,
+                })).collect::<Vec<_>>(),
+                "outputs_failed": result.failed_outputs,
+                "report": pass_report,
+            });
+            sender.send(JobResult { index, rendered: result.rendered, failed: result.failures(), report }).unwrap();
+        });
+    }
+    drop(sender);
+    let mut results: Vec<JobResult> = receiver.iter().collect();
+    pool.join();
+    // Dropping the bus ends the progress subscription.
+    drop(bus);
+    if let Some(handle) = progress_handle {
+        handle.join().unwrap();
+    }
+    results.sort_by_key(|result| result.index);
+
+    let rendered: usize = results.iter().map(|result| result.rendered).sum();
+    let failed: usize = results.iter().map(|result| result.failed).sum();
+    println!("{}", tr!("Batch: {} of {} jobs rendered {} regions, {} failed.", results.len(), count, i18n::number(rendered), i18n::number(failed)));
+    if let Some(path) = &args.report {
+        let report = serde_json::json!({
+            "started": started_at.to_rfc3339(),
+            "wall_seconds": started.elapsed().as_secs_f64(),
+            "jobs": count,
+            "jobs_done": results.len(),
+            "regions_rendered": rendered,
+            "regions_failed": failed,
+            "results": results.iter().map(|result| &result.report).collect::<Vec<_>>(),
+        });
+        if let Err(e) = Durability::Fast.write(path, &serde_json::to_vec_pretty(&report).unwrap()) {
+            eprintln!("cannot write {}: {}", path.display(), e);
+        }
+    }
+    if shutdown::requested() {
+        run_lock::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if failed > 0 {
+        run_lock::exit(FAILED_EXIT_CODE);
+    }
+}
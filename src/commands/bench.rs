@@ -0,0 +1,72 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::time::Instant;
+use clap::{ArgEnum, Parser};
+
+use crate::cli::{BenchMatrixArgs, CacheMode, Cli, Command, EncodePreset, RenderArgs};
+use crate::commands::render::{load_palette, render_pass, FAILED_EXIT_CODE};
+
+/// Render the fixture dimension once per combination of mode, encoding and
+/// thread count, from scratch, and write the timings as CSV.
+pub fn run(args: &BenchMatrixArgs) {
+    let mut out = File::create(&args.out).unwrap();
+    writeln!(out, "mode,encoding,threads,run,regions,seconds,regions_per_second").unwrap();
+    let mut palette = None;
+    for mode in &args.modes {
+        for encoding in &args.encodings {
+            for threads in &args.threads {
+                for run in 0..args.repeat {
+                    let (mode_name, encoding_name) = (arg_name(*mode), arg_name(*encoding));
+                    let work = args.work_dir.join(format!("{}-{}-{}", mode_name, encoding_name, threads));
+                    if work.exists() {
+                        std::fs::remove_dir_all(&work).unwrap();
+                    }
+                    std::fs::create_dir_all(work.join("cache")).unwrap();
+                    std::fs::create_dir_all(work.join("images")).unwrap();
+                    let render = render_args(args, &mode_name, *encoding, *threads, &work);
+                    let palette = palette.get_or_insert_with(|| load_palette(&render));
+
+                    let started = Instant::now();
+                    let result = match render_pass(&render, None, palette, CacheMode::NoCache, false, None, None) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            std::process::exit(FAILED_EXIT_CODE);
+                        }
+                    };
+                    let seconds = started.elapsed().as_secs_f64();
+                    writeln!(out, "{},{},{},{},{},{:.3},{:.3}", mode_name, encoding_name, threads, run,
+                        result.rendered, seconds, result.rendered as f64 / seconds).unwrap();
+                    out.flush().unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// The command-line spelling of an `ArgEnum` value.
+pub fn arg_name<T: ArgEnum>(value: T) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// The render options of one run, parsed like a `render` command line so
+/// every other option keeps its default.
+fn render_args(args: &BenchMatrixArgs, mode: &str, encoding: EncodePreset, threads: usize, work: &std::path::Path) -> RenderArgs {
+    let mut argv: Vec<OsString> = vec!["mcanvilrenderer".into(), "render".into(), "--progress-format".into(), "text".into()];
+    argv.extend(["-d".into(), args.dimension_path.clone().into_os_string()]);
+    argv.extend(["-p".into(), args.palette_path.clone().into_os_string()]);
+    argv.extend(["-c".into(), work.join("cache").into_os_string()]);
+    argv.extend(["-i".into(), work.join("images").into_os_string()]);
+    argv.extend(["--mode".into(), mode.into(), "--threads".into(), threads.to_string().into()]);
+    match encoding {
+        EncodePreset::Plain => (),
+        EncodePreset::Indexed => argv.push("--indexed-png".into()),
+        EncodePreset::IndexedDither => argv.extend(["--indexed-png".into(), "--dither".into()]),
+    }
+    match Cli::parse_from(argv).command {
+        Command::Render(render) => render,
+        _ => unreachable!(),
+    }
+}
+
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use crate::cli::DataVersionsArgs;
+use crate::data_versions::{self, release_of, RegionVersions};
+use crate::durability::Durability;
+
+/// Regions listed by the text report; --json has them all.
+const LISTED_REGIONS: usize = 20;
+
+/// Print how the chunks of a dimension spread over DataVersions, and with
+/// `--upgrade-to` which regions the game will convert on that upgrade, most
+/// chunks first: those are the ones to back up, pre-generate or trim.
+pub fn run(args: &DataVersionsArgs) {
+    let regions = match data_versions::scan(&args.dimension_path, args.threads) {
+        Ok(regions) => regions,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut by_release: BTreeMap<i32, (&str, usize)> = Default::default();
+    let (mut total, mut unreadable) = (0, 0);
+    for region in &regions {
+        for (version, count) in &region.versions {
+            let release = release_of(*version);
+            let first = data_versions::RELEASES.iter().find(|(name, _)| *name == release).map_or(0, |(_, first)| *first);
+            by_release.entry(first).or_insert((release, 0)).1 += count;
+            total += count;
+        }
+        unreadable += region.unreadable;
+    }
+    println!("{} chunks in {} regions ({} unreadable)", total, regions.len(), unreadable);
+    for (release, count) in by_release.values() {
+        println!("  {:>10}  {:>10}  {:>5.1}%", release, count, 100.0 * *count as f64 / total.max(1) as f64);
+    }
+
+    if let Some(target) = args.upgrade_to {
+        let mut converted: Vec<(&RegionVersions, usize)> = regions.iter()
+            .map(|region| (region, region.older_than(target)))
+            .filter(|(_, older)| *older > 0)
+            .collect();
+        converted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| (a.0.x, a.0.z).cmp(&(b.0.x, b.0.z))));
+        let chunks: usize = converted.iter().map(|(_, older)| older).sum();
+        println!("Upgrading to DataVersion {} ({}) converts {} chunks in {} regions:", target, release_of(Some(target)), chunks, converted.len());
+        for (region, older) in converted.iter().take(LISTED_REGIONS) {
+            let oldest = region.versions.keys().next().copied().flatten();
+            println!("  r.{}.{}.mca  {} chunks, oldest {}", region.x, region.z, older, release_of(oldest));
+        }
+        if converted.len() > LISTED_REGIONS {
+            println!("  ... and {} more regions", converted.len() - LISTED_REGIONS);
+        }
+    }
+
+    if let Some(path) = &args.json {
+        let report = serde_json::json!({
+            "chunks": total,
+            "unreadable": unreadable,
+            "releases": by_release.values().map(|(release, count)| serde_json::json!({ "release": release, "chunks": count })).collect::<Vec<_>>(),
+            "upgrade_to": args.upgrade_to,
+            "regions": regions.iter().map(|region| serde_json::json!({
+                "x": region.x,
+                "z": region.z,
+                "versions": region.versions.iter().map(|(version, count)| serde_json::json!({ "data_version": version, "chunks": count })).collect::<Vec<_>>(),
+                "unreadable": region.unreadable,
+                "converted": args.upgrade_to.map(|target| region.older_than(target)),
+            })).collect::<Vec<_>>(),
+        });
+        if let Err(e) = Durability::Fast.write(path, &serde_json::to_vec_pretty(&report).unwrap()) {
+            eprintln!("cannot write {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use crate::cache_store::CacheStore;
+use crate::durability::Durability;
+use crate::cli::{DiffArgs, DiffFormat};
+use crate::coords::{ChunkPos, RegionPos};
+use crate::dimension::{Dimension, ScanOptions};
+
+/// Why a region is in the render set.
+fn reason(dim: &Dimension, rloc: &RegionPos) -> &'static str {
+    if !dim.timestamps.contains_key(rloc) {
+        // Only south neighbours of changed chunks of the region north of it.
+        "shading"
+    } else if matches!(dim.cache.read(rloc), Ok(None)) {
+        "no cache"
+    } else {
+        "changed"
+    }
+}
+
+/// Run the scan of a render against the caches and print what it selects,
+/// without rendering or writing anything. Caches are read as with any
+/// palette: a palette change, which redraws everything, is not detected.
+pub fn run(args: &DiffArgs) {
+    let cache = match CacheStore::open(args.cache_backend, &args.cache_path, Durability::Fast) {
+        Ok(cache) => Arc::new(cache),
+        Err(e) => {
+            eprintln!("cannot open the caches of {}: {}", args.cache_path.display(), e);
+            std::process::exit(2);
+        }
+    };
+    let options = ScanOptions { cache_ro: true, verify: args.verify_chunk_hash, threads: args.threads, ..Default::default() };
+    let dim = match Dimension::from_dimdir(&args.dimension_path, &args.cache_path, &cache, &options, None, None) {
+        Ok(dim) => dim,
+        Err(e) => {
+            eprintln!("scan of {} failed: {}", args.dimension_path.display(), e);
+            std::process::exit(2);
+        }
+    };
+    let mut regions: Vec<(&RegionPos, Vec<ChunkPos>)> = dim.render_regions.iter()
+        .map(|(rloc, clocs)| {
+            let mut clocs: Vec<ChunkPos> = clocs.iter().copied().collect();
+            clocs.sort_by_key(|cloc| (cloc.z, cloc.x));
+            (rloc, clocs)
+        })
+        .collect();
+    regions.sort_by_key(|(rloc, _)| (rloc.z, rloc.x));
+    let chunks: usize = regions.iter().map(|(_, clocs)| clocs.len()).sum();
+
+    match args.format {
+        DiffFormat::Text => {
+            println!("{} chunks in {} regions would be rendered", chunks, regions.len());
+            for (rloc, clocs) in &regions {
+                println!("  r.{}.{}.mca  {} chunks ({})", rloc.x, rloc.z, clocs.len(), reason(&dim, rloc));
+                if args.chunks {
+                    let list: Vec<String> = clocs.iter().map(|cloc| format!("{},{}", cloc.x, cloc.z)).collect();
+                    println!("    {}", list.join(" "));
+                }
+            }
+        },
+        DiffFormat::Json => {
+            let report = serde_json::json!({
+                "chunks": chunks,
+                "regions": regions.iter().map(|(rloc, clocs)| serde_json::json!({
+                    "x": rloc.x,
+                    "z": rloc.z,
+                    "reason": reason(&dim, rloc),
+                    "chunks": clocs.iter().map(|cloc| [cloc.x, cloc.z]).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+            });
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        },
+    }
+}
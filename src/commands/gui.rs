@@ -0,0 +1,213 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use clap::ArgEnum;
+use eframe::egui;
+
+use crate::cli::RenderMode;
+use crate::commands::bench::arg_name;
+use crate::commands::render::{FAILED_EXIT_CODE, UNCHANGED_EXIT_CODE};
+
+/// Open the launcher window: pick the world, palette and output folders and
+/// a mode, then render with a progress bar. Each render is this binary run as
+/// `render --world-path ... --progress-format json`, so the window stays
+/// responsive and a crash in a region only ends that run.
+pub fn run() {
+    let options = eframe::NativeOptions {
+        initial_window_size: Some(egui::vec2(560.0, 480.0)),
+        ..Default::default()
+    };
+    if let Err(e) = eframe::run_native("mcanvilrenderer", options, Box::new(|_| Box::new(Launcher::default()))) {
+        eprintln!("cannot open the launcher window: {}", e);
+        std::process::exit(2);
+    }
+}
+
+struct Launcher {
+    world: String,
+    palette: String,
+    output: String,
+    mode: RenderMode,
+    tiles: bool,
+    threads: usize,
+    running: Option<Running>,
+    /// Outcome of the last run.
+    status: String,
+    log: Vec<String>,
+}
+
+/// A render in progress: the child and what its events said so far.
+struct Running {
+    child: Child,
+    lines: Receiver<String>,
+    scan: (usize, usize),
+    chunks: (usize, usize),
+    regions: usize,
+}
+
+impl Default for Launcher {
+    fn default() -> Self {
+        Launcher {
+            world: String::new(),
+            palette: "auto".to_string(),
+            output: String::new(),
+            mode: RenderMode::Map,
+            tiles: true,
+            threads: num_cpus::get(),
+            running: None,
+            status: String::new(),
+            log: vec![],
+        }
+    }
+}
+
+impl Launcher {
+    /// Images go to the output folder; the caches to its `cache` subdirectory.
+    fn start(&mut self) {
+        let output = PathBuf::from(&self.output);
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command.args(["render", "--progress-format", "json"])
+            .arg("--world-path").arg(&self.world)
+            .arg("-p").arg(&self.palette)
+            .arg("-i").arg(&output)
+            .arg("-c").arg(output.join("cache"))
+            .args(["--mode", &arg_name(self.mode), "--threads", &self.threads.to_string()]);
+        if self.tiles {
+            command.arg("--tiles");
+        }
+        self.log.clear();
+        let spawned = std::fs::create_dir_all(output.join("cache"))
+            .and_then(|_| command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn());
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                self.status = format!("Cannot start the renderer: {}", e);
+                return;
+            }
+        };
+        let (sender, lines) = channel();
+        let streams: Vec<Box<dyn std::io::Read + Send>> = vec![
+            Box::new(child.stdout.take().unwrap()),
+            Box::new(child.stderr.take().unwrap()),
+        ];
+        for stream in streams {
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        self.status = "Rendering...".to_string();
+        self.running = Some(Running { child, lines, scan: (0, 0), chunks: (0, 0), regions: 0 });
+    }
+
+    /// Take in what the child printed, and notice when it is done.
+    fn poll(&mut self) {
+        let running = match &mut self.running {
+            Some(running) => running,
+            None => return,
+        };
+        while let Ok(line) = running.lines.try_recv() {
+            let event: serde_json::Value = match serde_json::from_str(&line) {
+                Ok(event) => event,
+                Err(_) => {
+                    self.log.push(line);
+                    continue;
+                }
+            };
+            let count = |key: &str| event[key].as_u64().unwrap_or(0) as usize;
+            match event["event"].as_str().unwrap_or_default() {
+                "scan_begin" => running.scan = (0, count("regions")),
+                "scan_step" => running.scan.0 += count("regions"),
+                "begin_all" => running.chunks = (0, count("chunks")),
+                "step" => running.chunks.0 += count("chunks"),
+                "end" => running.regions += 1,
+                "failed" | "warning" => self.log.push(format!("region {},{}: {}",
+                    event["region"]["x"], event["region"]["z"], event["message"].as_str().unwrap_or_default())),
+                _ => (),
+            }
+        }
+        if let Ok(Some(exit)) = running.child.try_wait() {
+            self.status = match exit.code() {
+                Some(0) => format!("Done: {} regions rendered.", running.regions),
+                Some(UNCHANGED_EXIT_CODE) => "Nothing changed since the last render.".to_string(),
+                Some(FAILED_EXIT_CODE) => "Some regions could not be rendered, see the log.".to_string(),
+                _ => format!("The renderer stopped ({}).", exit),
+            };
+            self.running = None;
+        }
+    }
+
+    fn open_output(&self) {
+        let opener = if cfg!(target_os = "windows") { "explorer" } else if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+        if let Err(e) = Command::new(opener).arg(&self.output).spawn() {
+            eprintln!("cannot open {}: {}", self.output, e);
+        }
+    }
+}
+
+impl eframe::App for Launcher {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll();
+        let idle = self.running.is_none();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::Grid::new("paths").num_columns(2).show(ui, |ui| {
+                ui.label("World folder (with level.dat)");
+                ui.add_enabled(idle, egui::TextEdit::singleline(&mut self.world));
+                ui.end_row();
+                ui.label("Palette (\"auto\" for the world's version)");
+                ui.add_enabled(idle, egui::TextEdit::singleline(&mut self.palette));
+                ui.end_row();
+                ui.label("Output folder");
+                ui.add_enabled(idle, egui::TextEdit::singleline(&mut self.output));
+                ui.end_row();
+            });
+            ui.add_enabled_ui(idle, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Mode").selected_text(arg_name(self.mode)).show_ui(ui, |ui| {
+                        for mode in RenderMode::value_variants() {
+                            ui.selectable_value(&mut self.mode, *mode, arg_name(*mode));
+                        }
+                    });
+                    ui.checkbox(&mut self.tiles, "Tiles for the web viewer");
+                });
+                ui.add(egui::Slider::new(&mut self.threads, 1..=num_cpus::get().max(1) * 2).text("Threads"));
+            });
+            ui.horizontal(|ui| {
+                let ready = !self.world.is_empty() && !self.output.is_empty() && !self.palette.is_empty();
+                if idle && ui.add_enabled(ready, egui::Button::new("Render")).clicked() {
+                    self.start();
+                }
+                if let (Some(running), false) = (&mut self.running, idle) {
+                    if ui.button("Stop").clicked() {
+                        let _ = running.child.kill();
+                    }
+                }
+                if ui.add_enabled(!self.output.is_empty(), egui::Button::new("Open output folder")).clicked() {
+                    self.open_output();
+                }
+            });
+            if let Some(running) = &self.running {
+                let (done, total) = if running.chunks.1 > 0 { running.chunks } else { running.scan };
+                let label = if running.chunks.1 > 0 { "Rendering chunks" } else { "Scanning regions" };
+                ui.add(egui::ProgressBar::new(if total > 0 { done as f32 / total as f32 } else { 0.0 })
+                    .text(format!("{}: {} / {}", label, done, total)));
+            }
+            ui.label(&self.status);
+            ui.separator();
+            egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+                for line in &self.log {
+                    ui.monospace(line);
+                }
+            });
+        });
+        if self.running.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(200));
+        }
+    }
+}
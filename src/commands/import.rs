@@ -0,0 +1,97 @@
+use std::fs::File;
+use log::{info, warn};
+use image::DynamicImage;
+
+use crate::cli::ImportTilesArgs;
+use crate::coords::{BlockPos, RegionPos};
+use crate::dim_renderer::to_image_name;
+use crate::cache_store::CacheStore;
+use crate::dimension::scan_region_files;
+use crate::durability::Durability;
+use crate::indexed_png::PngEncoding;
+use crate::tile_import::TileTree;
+use crate::tiles;
+use crate::shutdown;
+use crate::update_detector::RegionTimestamps;
+
+/// Seed the region images and caches of a dimension from the tiles of
+/// another renderer, so the first `render` only draws what changed since.
+/// A region is imported only when every tile it needs is there; its chunks
+/// saved after the tiles under them were written are left out of the cache
+/// and rendered by the next run.
+pub fn run(args: &ImportTilesArgs) {
+    let mut regions: Vec<_> = match scan_region_files(&args.dimension_path, None) {
+        Ok(files) => files.into_iter().collect(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    // Row by row, so tiles can be dropped once every region over them is done.
+    regions.sort_by_key(|(rloc, _)| (rloc.z, rloc.x));
+    std::fs::create_dir_all(&args.cache_path).unwrap();
+    std::fs::create_dir_all(&args.image_path).unwrap();
+    let cache_store = match CacheStore::open(args.cache_backend, &args.cache_path, Durability::Fast) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("cannot open the caches of {}: {}", args.cache_path.display(), e);
+            std::process::exit(2);
+        }
+    };
+    let size = args.tile_size.unwrap_or_else(|| args.layout.default_tile_size());
+    let mut tree = TileTree::new(&args.from, args.layout, size, BlockPos::new(args.origin_x, args.origin_z));
+    let (mut imported, mut stale, mut skipped) = (0, 0, 0);
+    let mut written: Vec<RegionPos> = vec![];
+    for (rloc, path) in regions {
+        if shutdown::requested() {
+            println!("Interrupted, the regions left are not imported.");
+            break;
+        }
+        tree.forget_north_of(rloc.block_origin().z);
+        let image_path = args.image_path.join(to_image_name(&rloc));
+        if image_path.exists() && !args.overwrite {
+            info!("region {} already has an image, not imported", rloc);
+            skipped += 1;
+            continue;
+        }
+        let timestamps = match File::open(&path).and_then(|mut file| RegionTimestamps::from_regiondata(&mut file)) {
+            Ok(timestamps) => timestamps,
+            Err(e) => {
+                warn!("cannot read the header of {}: {}", path.display(), e);
+                skipped += 1;
+                continue;
+            }
+        };
+        let (image, tile_mtimes) = match tree.region(&rloc) {
+            Some(region) => region,
+            None => {
+                info!("region {} is not fully covered by tiles, left to render", rloc);
+                skipped += 1;
+                continue;
+            }
+        };
+        let newer: Vec<(usize, usize)> = (0..32).flat_map(|z| (0..32).map(move |x| (x, z)))
+            .filter(|(x, z)| timestamps.timestamp(*x, *z) > tile_mtimes[z * 32 + x])
+            .collect();
+        stale += newer.len();
+        let mut cache = vec![];
+        // No palette hash: the cache stands for whatever palette renders next.
+        let saved = PngEncoding::Plain.encode(&DynamicImage::ImageRgba8(image))
+            .and_then(|data| Ok(Durability::Fast.write(&image_path, &data)?))
+            .and_then(|_| Ok(timestamps.with_cleared(newer).save_cache(&mut cache, None, None)?))
+            .and_then(|_| Ok(cache_store.write(&rloc, &cache, Durability::Fast)?));
+        match saved {
+            Ok(()) => {
+                imported += 1;
+                written.push(rloc);
+            },
+            Err(e) => warn!("cannot import region {}: {}", rloc, e),
+        }
+    }
+    if args.tiles && !written.is_empty() {
+        if let Err(e) = tiles::TilePyramid::new(&args.image_path, args.tile_levels).update(&args.image_path, &written) {
+            warn!("cannot write tiles: {}", e);
+        }
+    }
+    println!("Imported {} regions ({} chunks newer than their tiles left to render), {} skipped.", imported, stale, skipped);
+}
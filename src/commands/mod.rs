@@ -0,0 +1,12 @@
+pub mod render;
+pub mod watch;
+pub mod serve;
+pub mod palette;
+pub mod bench;
+pub mod synth;
+pub mod import;
+pub mod data_versions;
+pub mod diff;
+pub mod batch;
+#[cfg(feature = "gui")]
+pub mod gui;
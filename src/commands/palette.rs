@@ -0,0 +1,42 @@
+use crate::cli::{PaletteArgs, PaletteCommand};
+use crate::{block_alias, palette_coverage, palette_fetch, palette_gen};
+
+pub fn run(args: &PaletteArgs) {
+    match &args.command {
+        PaletteCommand::Fetch { dimension_path, cache_path } => {
+            match palette_fetch::resolve_auto(dimension_path, cache_path) {
+                Ok(palette_path) => println!("{}", palette_path.display()),
+                Err(e) => {
+                    eprintln!("cannot fetch the palette of {}: {}", dimension_path.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        },
+        PaletteCommand::Coverage { dimension_path, palette_path, samples, seed } => {
+            let mut palette = match crate::renderer::get_palette(palette_path) {
+                Ok(palette) => palette,
+                Err(e) => {
+                    eprintln!("cannot load palette {}: {}", palette_path.display(), e);
+                    std::process::exit(2);
+                }
+            };
+            block_alias::apply(&mut palette.blockstates);
+            let seed = seed.unwrap_or_else(|| std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH).map_or(1, |d| d.as_nanos() as u64));
+            let coverage = palette_coverage::sample(dimension_path, &palette, *samples, seed).unwrap();
+            println!("Sampled {} chunks, {} surface columns, {} distinct block states.",
+                coverage.chunks, coverage.columns, coverage.distinct);
+            println!("Palette coverage: {:.1}%", coverage.percent());
+            if !coverage.missing.is_empty() {
+                println!("Missing from the palette (drawn magenta):");
+                for (state, columns) in &coverage.missing {
+                    println!("  {:>8} {}", columns, state);
+                }
+            }
+        },
+        PaletteCommand::Generate { jar, out } => {
+            let states = palette_gen::generate(jar, out).unwrap();
+            println!("{} block states written to {}", states, out.to_str().unwrap());
+        },
+    }
+}
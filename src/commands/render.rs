@@ -0,0 +1,745 @@
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use std::time::{Duration, Instant};
+use fastanvil::RenderedPalette;
+use image::Rgba;
+
+use crate::cache_store::CacheStore;
+use crate::change_spool::ChangeSpool;
+use crate::cli::{CacheMode, ProgressFormat, RenderArgs, RenderMode};
+use crate::coords::{BlockPos, ChunkPos, RegionPos, RegionBounds};
+use crate::dim_renderer::{DimensionRenderer, RenderOptions};
+use crate::dimension::{Dimension, DimensionKind, ScanOptions};
+use crate::error::Error;
+use crate::events::EventBus;
+use crate::heightmap::HeightmapOptions;
+use crate::journal::{RunJournal, JOURNAL_NAME};
+use crate::mbtiles::{MbTiles, TileOutput};
+use crate::region_index::RegionIndex;
+use crate::render_report::{self, RenderReport, MANIFEST_NAME, REPORT_NAME};
+use crate::scheduler::{self, Preemption};
+use crate::shutdown::INTERRUPTED_EXIT_CODE;
+use crate::throttle::Throttle;
+use crate::progress_socket::ProgressSocket;
+use crate::renderer::{ShadeHeight, Shading, WaterStyle};
+use crate::highlight::Highlight;
+use crate::grief_alert::{self, AlertRule};
+use crate::scale::{FarScale, Scale};
+use crate::render_order::RenderOrder;
+use crate::commands::bench::arg_name;
+use crate::watermark::{Watermark, WatermarkTarget};
+use crate::contours::ContourOverlay;
+use crate::grid::GridOverlay;
+use crate::overlay::{Overlay, OverlayKind};
+use crate::slime::SlimeOverlay;
+use crate::spawn::SpawnOverlay;
+use crate::background::Background;
+use crate::level::LevelDat;
+use crate::{block_alias, i18n, level, markers, palette_fetch, progress, prune, run_lock, shutdown, skip_list, stitch, tiles, upload, verbosity};
+
+/// Exit code of a --skip-if-unchanged run that found nothing to render.
+pub const UNCHANGED_EXIT_CODE: i32 = 3;
+/// Exit code of a run where some regions could not be written, or the scan failed.
+pub const FAILED_EXIT_CODE: i32 = 1;
+
+lazy_static! {
+    /// By --throttle and the bits of --io-limit.
+    static ref THROTTLES: Mutex<HashMap<(u64, Option<u64>), Arc<Throttle>>> = Default::default();
+    /// By --progress-socket path, bound by the first pass that names it; None after a failure.
+    static ref PROGRESS_SOCKETS: Mutex<HashMap<PathBuf, Option<Arc<ProgressSocket>>>> = Default::default();
+}
+
+/// What a render pass did.
+#[derive(Default)]
+pub struct PassResult {
+    pub rendered: usize,
+    /// Regions that could not be written, with the reason.
+    pub failed: Vec<(RegionPos, String)>,
+    /// Other outputs of the pass (tiles, markers, reports...) that could not
+    /// be written, with the reason.
+    pub failed_outputs: Vec<String>,
+}
+
+impl PassResult {
+    pub fn failures(&self) -> usize {
+        self.failed.len() + self.failed_outputs.len()
+    }
+}
+
+/// Print the outputs of a pass that could not be written.
+fn print_failed_outputs(failed_outputs: &[String]) {
+    if !failed_outputs.is_empty() {
+        println!("{}", tr!("{} outputs could not be written:", failed_outputs.len()));
+        for message in failed_outputs {
+            println!("  {}", message);
+        }
+    }
+}
+
+pub fn run(args: &RenderArgs) {
+    let dimensions = args.dimensions();
+    let first = match dimensions.first() {
+        Some(first) => first,
+        None => {
+            eprintln!("no region directory found in {}", args.given_path().display());
+            run_lock::exit(2);
+        }
+    };
+    let palette = load_palette(first);
+    let mut rendered = 0;
+    let mut failed = 0;
+    for dim_args in &dimensions {
+        if shutdown::requested() {
+            break;
+        }
+        let created = if args.world_path.is_some() {
+            note!("{}", tr!("Dimension: {}", dim_args.dimension.unwrap().id()));
+            create_dirs(dim_args)
+        } else {
+            Ok(())
+        };
+        // A dimension that cannot be rendered fails alone, the others still are.
+        let result = match created.and_then(|_| render_pass(dim_args, dim_args.bounds().as_ref(), &palette, dim_args.cache_mode, dim_args.retry_broken, None, None)) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        rendered += result.rendered;
+        failed += result.failures();
+    }
+    if shutdown::requested() {
+        run_lock::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if failed > 0 {
+        run_lock::exit(FAILED_EXIT_CODE);
+    }
+    if args.skip_if_unchanged && rendered == 0 {
+        run_lock::exit(UNCHANGED_EXIT_CODE);
+    }
+}
+
+/// Create the cache and image directories of a dimension of --world-path.
+pub fn create_dirs(dim_args: &RenderArgs) -> crate::error::Result<()> {
+    for path in [&dim_args.cache_path, &dim_args.image_path] {
+        std::fs::create_dir_all(path).map_err(|source| Error::CreateDir { path: path.clone(), source })?;
+    }
+    Ok(())
+}
+
+/// Load the palette given with `-p`, downloading and checking it first, and
+/// fill in renamed blocks.
+pub fn load_palette(args: &RenderArgs) -> Arc<RenderedPalette> {
+    let palette = palette_fetch::resolve(&args.palette_path, args.palette_sha256.as_deref(), args.dim_path(), &args.cache_path)
+        .and_then(|palette_path| crate::renderer::get_palette(&palette_path));
+    let mut palette = match palette {
+        Ok(palette) => palette,
+        Err(e) => {
+            eprintln!("cannot load palette {}: {}", args.palette_path.display(), e);
+            run_lock::exit(2);
+        }
+    };
+    let aliases = block_alias::apply(&mut palette.blockstates);
+    if !aliases.is_empty() {
+        let names: Vec<String> = aliases.iter().map(|alias| format!("{} -> {} ({} states)", alias.from, alias.to, alias.states)).collect();
+        note!("{}", tr!("Palette aliases used for renamed blocks: {}", names.join(", ")));
+    }
+    Arc::new(palette)
+}
+
+/// Whether `image_path` already has `r.X.Z.png` images in it.
+fn holds_region_images(image_path: &Path) -> bool {
+    std::fs::read_dir(image_path).map_or(false, |entries| entries.filter_map(|entry| entry.ok()).any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name.starts_with("r.") && name.ends_with(".png")
+    }))
+}
+
+/// Positions for --around-players: the --player-pos list, or the players of
+/// the rendered dimension.
+fn player_positions(args: &RenderArgs) -> Vec<BlockPos> {
+    if !args.player_pos.is_empty() {
+        return args.player_pos.iter().map(|pos| BlockPos::from(*pos)).collect();
+    }
+    let world_path = match level::find_world_root(args.dim_path()) {
+        Some(world_path) => world_path,
+        None => {
+            warn!("--around-players: no level.dat found above {}, nothing rendered", args.dim_path().to_str().unwrap());
+            return vec![];
+        }
+    };
+    let kind = args.dimension.unwrap_or_else(|| DimensionKind::from_path(args.dim_path()));
+    markers::collect(&world_path, kind).iter()
+        .filter(|marker| marker.kind == markers::MarkerKind::Player)
+        .map(markers::Marker::block)
+        .collect()
+}
+
+/// Centre of --order spiral and --far-scale: the world spawn in the
+/// overworld, 0,0 elsewhere or without a level.dat.
+fn spawn(args: &RenderArgs, kind: DimensionKind) -> BlockPos {
+    let level = level::find_world_root(args.dim_path()).and_then(|world_path| LevelDat::from_world(&world_path).ok());
+    match (kind, level.as_ref().and_then(|level| level.data.spawn_x.zip(level.data.spawn_z))) {
+        (DimensionKind::Overworld, Some((x, z))) => BlockPos::new(x, z),
+        (DimensionKind::Overworld, None) => {
+            warn!("no spawn in a level.dat above {}, using 0,0", args.dim_path().to_str().unwrap());
+            BlockPos::new(0, 0)
+        },
+        _ => BlockPos::new(0, 0),
+    }
+}
+
+/// --progress-socket, bound once per path and kept for every pass of the
+/// process so clients stay connected across watch and serve runs. Batch jobs
+/// naming different sockets each get their own.
+fn progress_socket(args: &RenderArgs) -> Option<Arc<ProgressSocket>> {
+    let path = args.progress_socket.as_ref()?;
+    let mut sockets = PROGRESS_SOCKETS.lock().unwrap();
+    sockets.entry(path.clone()).or_insert_with(|| match ProgressSocket::bind(path) {
+        Ok(socket) => Some(Arc::new(socket)),
+        Err(e) => {
+            warn!("--progress-socket: cannot listen on {}: {}", path.display(), e);
+            None
+        }
+    }).clone()
+}
+
+/// --throttle and --io-limit, shared by every pass of the process with the
+/// same settings so the limit holds across watch and serve runs too, while
+/// batch jobs with other settings get their own.
+fn throttle(args: &RenderArgs) -> Option<Arc<Throttle>> {
+    if args.throttle == 0 && args.io_limit.is_none() {
+        return None;
+    }
+    let key = (args.throttle, args.io_limit.map(f64::to_bits));
+    let mut throttles = THROTTLES.lock().unwrap();
+    Some(Arc::clone(throttles.entry(key).or_insert_with(|| Arc::new(Throttle::new(args.throttle, args.io_limit)))))
+}
+
+/// Look for --grief-alert clusters of changed chunks, and report them.
+fn grief_alerts(args: &RenderArgs, kind: DimensionKind, changed: &HashMap<ChunkPos, u64>, chunks: usize) {
+    let areas = match &args.grief.build_areas {
+        Some(path) => grief_alert::load_areas(path).unwrap_or_else(|e| {
+            warn!("cannot read --build-areas {}, alerting everywhere: {}", path.display(), e);
+            vec![]
+        }),
+        None => vec![],
+    };
+    let rule = AlertRule { chunks, radius: args.grief.grief_radius, min_columns: args.grief.grief_min_columns };
+    let alerts = grief_alert::find(changed, kind, &areas, &rule);
+    for alert in &alerts {
+        let centre = alert.centre.block_origin();
+        warn!("grief alert: {} chunks changed around {},{} ({} surface blocks)", alert.chunks.len(), centre.x, centre.z, alert.columns);
+    }
+    if let Some(path) = &args.grief.grief_json {
+        if let Err(e) = grief_alert::write_json(path, kind, &alerts) {
+            warn!("cannot write {}: {}", path.display(), e);
+        }
+    }
+    if let (Some(url), false) = (&args.grief.grief_webhook, alerts.is_empty()) {
+        if let Err(e) = grief_alert::post(url, kind, &alerts) {
+            warn!("cannot post grief alerts to {}: {}", url, e);
+        }
+    }
+}
+
+/// Where --far-distance is measured from: the spawn (0,0 outside the
+/// overworld) and the players.
+fn far_centres(args: &RenderArgs, kind: DimensionKind) -> Vec<BlockPos> {
+    let mut centres = vec![spawn(args, kind)];
+    centres.extend(player_positions(args));
+    centres
+}
+
+/// Layers of --overlay, --grid and --background, in drawing order.
+fn overlays(args: &RenderArgs, kind: DimensionKind) -> Vec<Overlay> {
+    let mut overlays = vec![];
+    if args.overlays.overlay.contains(&OverlayKind::Slime) {
+        let seed = args.overlays.seed.or_else(|| level::find_world_root(args.dim_path())
+            .and_then(|world_path| LevelDat::from_world(&world_path).ok())
+            .and_then(|level| level.data.seed()));
+        match (seed, kind) {
+            (_, DimensionKind::Nether | DimensionKind::End) => warn!("--overlay slime: slime chunks are an overworld thing, not drawn"),
+            (None, _) => warn!("--overlay slime: no --seed and no seed in level.dat above {}, not drawn", args.dim_path().to_str().unwrap()),
+            (Some(seed), _) => overlays.push(Overlay::Slime(SlimeOverlay { seed })),
+        }
+    }
+    if args.overlays.overlay.contains(&OverlayKind::Spawn) {
+        let level = level::find_world_root(args.dim_path()).and_then(|world_path| LevelDat::from_world(&world_path).ok());
+        match (kind, level.as_ref().and_then(|level| level.data.spawn_x.zip(level.data.spawn_z))) {
+            (DimensionKind::Nether | DimensionKind::End, _) => warn!("--overlay spawn: the world spawn is in the overworld, not drawn"),
+            (_, None) => warn!("--overlay spawn: no spawn in a level.dat above {}, not drawn", args.dim_path().to_str().unwrap()),
+            (_, Some((x, z))) => overlays.push(Overlay::Spawn(SpawnOverlay { spawn: BlockPos::new(x, z) })),
+        }
+    }
+    if args.overlays.overlay.contains(&OverlayKind::Contours) {
+        overlays.push(Overlay::Contours(ContourOverlay::new(args.cache_path.clone(), args.overlays.contour_interval)));
+    }
+    if let Some(kind) = args.overlays.grid {
+        overlays.push(Overlay::Grid(GridOverlay { kind, labels: args.overlays.grid_labels }));
+    }
+    if args.overlays.background != Background::Transparent {
+        overlays.push(Overlay::Background(args.overlays.background));
+    }
+    overlays
+}
+
+/// Scan the dimension and render the regions that need it. Caches that
+/// cannot be opened and a failed scan are returned as errors, for the command
+/// to decide whether to go on; regions that fail to render are listed and
+/// returned in the result.
+pub fn render_pass(args: &RenderArgs, bounds: Option<&RegionBounds>, palette: &Arc<RenderedPalette>, cache_mode: CacheMode, retry_broken: bool, index: Option<&mut RegionIndex>, preemption: Option<Arc<dyn Preemption>>) -> crate::error::Result<PassResult> {
+    let nocache = cache_mode == CacheMode::NoCache || cache_mode == CacheMode::Refresh || args.force_all;
+    let cache_ro = cache_mode == CacheMode::ReadOnly;
+    let cache_store = match CacheStore::open(args.cache_backend, &args.cache_path, args.durability) {
+        Ok(store) => Arc::new(store),
+        Err(source) => return Err(Error::OpenCaches { path: args.cache_path.clone(), source }),
+    };
+    let around_players = args.around_players.map(|radius| (player_positions(args), radius as i64));
+
+    // The progress display runs from the scan to the end of the render.
+    let events = EventBus::default();
+    let progress_receiver = events.subscribe(args.progress_buffer);
+    let threads = args.threads.max(1);
+    let progress_format = args.progress_format();
+    let relay = args.progress_relay.clone();
+    let progress_handle = std::thread::spawn(move || {
+        match progress_format {
+            _ if relay.is_some() => progress::relay(progress_receiver, &relay.unwrap()),
+            // Even JSON: --quiet is for runs nobody watches.
+            _ if verbosity::quiet() => progress::quiet_mode(progress_receiver),
+            ProgressFormat::Bars => progress::normal_mode(progress_receiver, threads),
+            ProgressFormat::Text => progress::bg_mode(progress_receiver),
+            ProgressFormat::Json => progress::json_mode(progress_receiver),
+        }
+    });
+    let socket_handle = progress_socket(args).map(|socket| {
+        let receiver = events.subscribe(args.progress_buffer);
+        std::thread::spawn(move || socket.stream(receiver))
+    });
+
+    let scan_options = ScanOptions {
+        bounds: bounds.copied(),
+        nocache,
+        cache_ro,
+        force: args.force_region.iter().map(|(x, z)| RegionPos::new(*x, *z)).collect(),
+        // Heightmaps don't depend on the palette.
+        palette_hash: if args.mode == RenderMode::Heightmap { None } else { Some(crate::renderer::palette_hash(palette)) },
+        verify: args.verify_chunk_hash,
+        threads,
+    };
+    let mut spool = None;
+    let scanned = if let Some(compare_path) = &args.compare_path {
+        // The dimmed map would replace the regular images.
+        if holds_region_images(&args.image_path) {
+            Err(Error::CompareOverwrite { path: args.image_path.clone() })
+        } else {
+            Dimension::compare_dimdirs(args.dim_path(), compare_path, bounds).and_then(|dim| {
+                dim.write_changed_chunks(&args.image_path.join("changed-chunks.csv"))?;
+                Ok(dim)
+            })
+        }
+    } else if let Some(spool_dir) = &args.changes_from {
+        match ChangeSpool::read(spool_dir) {
+            Ok(read) => {
+                let scanned = Dimension::from_changes(args.dim_path(), &args.cache_path, &cache_store, &read.chunks, &scan_options);
+                spool = Some(read);
+                scanned
+            },
+            Err(e) => Err(e.into()),
+        }
+    } else {
+        Dimension::from_dimdir(args.dim_path(), &args.cache_path, &cache_store, &scan_options, index, Some(&events))
+    };
+    let mut dim = match scanned {
+        Ok(dim) => dim,
+        Err(e) => {
+            drop(events);
+            progress_handle.join().unwrap();
+            if let Some(handle) = socket_handle {
+                handle.join().unwrap();
+            }
+            return Err(Error::Scan { path: args.dim_path().clone(), source: Box::new(e) });
+        }
+    };
+    if let Some(kind) = args.dimension {
+        dim.kind = kind;
+    }
+    dim.durability = args.durability;
+    info!("dimension: {:?}", dim.kind);
+    let kind = dim.kind;
+    if let Some((positions, radius)) = around_players {
+        dim.retain_regions(|rloc| positions.iter().any(|pos| {
+            let nearest = rloc.nearest_block(pos);
+            let (dx, dz) = ((nearest.x - pos.x) as i64, (nearest.z - pos.z) as i64);
+            dx * dx + dz * dz <= radius * radius
+        }));
+    }
+    if args.limit_to_border {
+        let level = level::find_world_root(args.dim_path()).and_then(|world_path| LevelDat::from_world(&world_path).ok());
+        match level.and_then(|level| level.data.border(kind)) {
+            Some(border) => {
+                let before = dim.render_regions.len();
+                dim.retain_regions(|rloc| border.overlaps_region(rloc));
+                let outside = before - dim.render_regions.len();
+                if outside > 0 {
+                    note!("{}", tr!("World border: skipped {} regions outside it.", i18n::number(outside)));
+                }
+            },
+            None => warn!("--limit-to-border: no world border in a level.dat above {}, every region rendered", args.dim_path().to_str().unwrap()),
+        }
+    }
+    let mask = args.selection();
+    if let Some(mask) = &mask {
+        dim.retain_chunks(|cloc| mask.overlaps_chunk(cloc));
+    }
+    // Forced and cache-less runs pick the same regions again, so an
+    // interrupted one is continued from the journal.
+    let journal = if cache_ro || args.compare_path.is_some() { None } else {
+        match RunJournal::open(&args.cache_path, args.journal_name.unwrap_or(JOURNAL_NAME), args.resume) {
+            Ok(journal) => Some(Arc::new(journal)),
+            Err(e) => {
+                warn!("cannot write run journal, --resume will not be possible: {}", e);
+                None
+            }
+        }
+    };
+    // Their tiles may not have been updated before the interruption.
+    let resumed: Vec<RegionPos> = journal.as_ref().map(|journal| journal.done()).unwrap_or_default();
+    if let (Some(journal), false) = (&journal, resumed.is_empty()) {
+        note!("{}", tr!("Resuming: {} regions were done by the interrupted run.", i18n::number(resumed.len())));
+        dim.retain_regions(|rloc| !journal.is_done(rloc));
+    }
+    if args.skip_if_unchanged && dim.render_regions.is_empty() {
+        drop(events);
+        progress_handle.join().unwrap();
+        if let Some(handle) = socket_handle {
+            handle.join().unwrap();
+        }
+        let mut failed_outputs = vec![];
+        if let Some(path) = &args.block_stats {
+            if let Err(e) = crate::block_stats::BlockStats::write_noop_json(path) {
+                failed_outputs.push(format!("{}: {}", path.display(), e));
+            }
+        }
+        if let Some(path) = &args.grief.grief_json {
+            if let Err(e) = grief_alert::write_json(path, kind, &[]) {
+                failed_outputs.push(format!("{}: {}", path.display(), e));
+            }
+        }
+        // A report and manifest of nothing, so that the previous pass's
+        // aren't taken for this one's.
+        {
+            let _publishing = scheduler::PUBLISH.lock().unwrap();
+            let report_path = args.image_path.join(REPORT_NAME);
+            if let Err(e) = RenderReport::new(chrono::Local::now(), Duration::ZERO, vec![], 0, 0).write(&report_path) {
+                failed_outputs.push(format!("{}: {}", report_path.display(), e));
+            }
+            if let Err(e) = render_report::write_manifest(&args.image_path, vec![]) {
+                failed_outputs.push(format!("{}: {}", args.image_path.join(MANIFEST_NAME).display(), e));
+            }
+        }
+        if let Some(journal) = journal.and_then(|journal| Arc::try_unwrap(journal).ok()) {
+            journal.finish();
+        }
+        if let Some(spool) = spool {
+            spool.consume();
+        }
+        note!("{}", tr!("Nothing changed, skipped."));
+        print_failed_outputs(&failed_outputs);
+        return Ok(PassResult { failed_outputs, ..Default::default() });
+    }
+    if args.water == WaterStyle::Depth && args.shade_height == ShadeHeight::Seabed {
+        warn!("--water depth is ignored with --shade-height seabed, whose tint already deepens with the water");
+    }
+    if (args.scale != Scale::default() || args.far_scale.is_some()) && matches!(args.mode, RenderMode::Heightmap | RenderMode::Isometric) {
+        warn!("--scale and --far-scale are ignored with --mode {}", arg_name(args.mode));
+    }
+    if args.chunk_mask.is_some() && args.mode == RenderMode::Isometric {
+        warn!("--chunk-mask is ignored with --mode isometric");
+    }
+    let options = RenderOptions {
+        ceiling_y: if dim.kind == DimensionKind::Nether { Some(args.ceiling_y) } else { None },
+        shade_height: args.shade_height,
+        water: args.water,
+        shading: Shading { mode: args.shade, strength: args.shade_strength },
+        height_source: args.height_mode,
+        heightmap: match args.mode {
+            RenderMode::Heightmap => Some(HeightmapOptions {
+                format: args.heightmap_format,
+                gradient: args.heightmap_gradient.clone().unwrap_or_default(),
+            }),
+            RenderMode::Map | RenderMode::Night | RenderMode::BlockEntities | RenderMode::Isometric => None,
+        },
+        sidecar: args.sidecar,
+        night: args.mode == RenderMode::Night,
+        block_entities: args.mode == RenderMode::BlockEntities,
+        mask,
+        png_encoding: args.png_encoding(),
+        isometric: if args.mode == RenderMode::Isometric { Some(args.iso_direction) } else { None },
+        heights: args.overlays.overlay.contains(&OverlayKind::Contours) && !matches!(args.mode, RenderMode::Heightmap | RenderMode::Isometric),
+        chunk_mask: if args.mode == RenderMode::Isometric { None } else { args.chunk_mask },
+        error_style: args.error_style,
+        scale: args.scale,
+        far_scale: match (args.far_scale, args.mode) {
+            (_, RenderMode::Heightmap | RenderMode::Isometric) => None,
+            (Some(scale), _) => Some(FarScale { scale, distance: args.far_distance as i64, centres: far_centres(args, dim.kind) }),
+            (None, _) => None,
+        },
+    };
+    // A comparison renders every chunk, so never start from the previous images.
+    let nocache = nocache || args.compare_path.is_some();
+
+    let mut rendered_regions: Vec<RegionPos> = dim.render_regions.keys().copied().collect();
+    let mut rendered_chunks: usize = dim.render_regions.values().map(|clocs| clocs.len()).sum();
+    let region_chunks: HashMap<RegionPos, usize> = dim.render_regions.iter().map(|(rloc, clocs)| (*rloc, clocs.len())).collect();
+    let centre = if args.order == RenderOrder::Spiral { spawn(args, dim.kind) } else { BlockPos::new(0, 0) };
+    let mut dim_renderer = DimensionRenderer::new(dim, &args.image_path, options)
+        .with_chunk_cache(args.max_chunk_cache_mb, args.chunk_eviction)
+        .with_order(args.order, centre)
+        .with_work_seed(args.work_seed)
+        .with_preemption(preemption)
+        .with_throttle(throttle(args))
+        .with_readahead(args.io_threads, args.readahead);
+    if args.block_stats.is_some() || args.grief.grief_alert.is_some() {
+        dim_renderer = dim_renderer.with_block_stats(!nocache);
+    }
+    let mut failed_outputs = vec![];
+    if let Some(path) = &args.highlight {
+        if matches!(args.mode, RenderMode::Heightmap | RenderMode::Isometric) {
+            warn!("--highlight is ignored with --mode {}", arg_name(args.mode));
+        } else {
+            // Read for every pass, so a watch picks up an edited file; a broken
+            // one only leaves the highlight images as they are.
+            match Highlight::load(path) {
+                Ok(highlight) => dim_renderer = dim_renderer.with_highlight(highlight),
+                Err(e) => failed_outputs.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+    if args.compare_path.is_none() {
+        dim_renderer = dim_renderer.with_skip_list(skip_list::SkipList::load(&args.cache_path, retry_broken));
+    }
+    if let Some(journal) = &journal {
+        dim_renderer = dim_renderer.with_journal(Arc::clone(journal));
+    }
+
+    let started = Instant::now();
+    dim_renderer.render_all(Arc::clone(palette), &events, nocache, threads);
+    if let Some(path) = &args.block_stats {
+        if let Err(e) = dim_renderer.write_block_stats(path) {
+            failed_outputs.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    if let Some(chunks) = args.grief.grief_alert {
+        grief_alerts(args, kind, &dim_renderer.changed_surface_chunks(), chunks);
+    }
+    let (cache_degraded, skip_summary) = (dim_renderer.cache_degraded(), dim_renderer.finish_skip_list());
+    let mut failed = dim_renderer.failed_regions();
+    let interrupted = dim_renderer.interrupted_regions();
+    drop(dim_renderer);
+    rendered_regions.retain(|rloc| !interrupted.contains(rloc));
+    rendered_chunks -= interrupted.iter().map(|rloc| region_chunks[rloc]).sum::<usize>();
+    // Failed and interrupted regions are left in the journal's care for --resume.
+    if let (Some(journal), true) = (journal, failed.is_empty() && interrupted.is_empty()) {
+        if let Ok(journal) = Arc::try_unwrap(journal) {
+            journal.finish();
+        }
+    }
+    // Reports of failed regions stay in the spool for the next pass.
+    if let (Some(spool), true) = (spool, failed.is_empty() && interrupted.is_empty()) {
+        spool.consume();
+    }
+    // Dropping the bus ends the progress subscription.
+    drop(events);
+    progress_handle.join().unwrap();
+    if let Some(handle) = socket_handle {
+        handle.join().unwrap();
+    }
+
+    let mut tile_regions = rendered_regions.clone();
+    tile_regions.extend(resumed);
+    let mut tile_markers = vec![];
+    if args.markers {
+        match level::find_world_root(args.dim_path()) {
+            Some(world_path) => {
+                let markers_path = args.image_path.join(markers::MARKERS_NAME);
+                let previous = markers::load(&markers_path);
+                let mut current = markers::collect(&world_path, kind);
+                if let Some(path) = &args.marker_labels {
+                    match markers::load_labels(path) {
+                        Ok(labels) => markers::apply_labels(&mut current, &labels),
+                        Err(e) => warn!("cannot read --marker-labels {}: {}", path.display(), e),
+                    }
+                }
+                if let Err(e) = markers::write(&markers_path, &current) {
+                    failed_outputs.push(format!("{}: {}", markers_path.display(), e));
+                }
+                for lang in &args.marker_lang {
+                    let path = args.image_path.join(markers::to_markers_name(lang));
+                    if let Err(e) = markers::write(&path, &markers::localized(&current, lang)) {
+                        failed_outputs.push(format!("{}: {}", path.display(), e));
+                    }
+                }
+                if args.markers_draw {
+                    // Redraw the tiles where a marker was and where it is now.
+                    let mut regions: HashSet<RegionPos> = tile_regions.into_iter().collect();
+                    regions.extend(previous.iter().chain(current.iter()).flat_map(markers::Marker::icon_regions));
+                    tile_regions = regions.into_iter().collect();
+                    tile_markers = current.iter().map(|marker| (marker.block(), match marker.kind {
+                        markers::MarkerKind::Player => Rgba([220, 40, 40, 255]),
+                        markers::MarkerKind::Spawn => Rgba([240, 200, 40, 255]),
+                    })).collect();
+                }
+            },
+            None => warn!("--markers: no level.dat found above {}", args.dim_path().to_str().unwrap()),
+        }
+    }
+    // A region can fail without being rendered (interrupted after failing), so count the written ones.
+    let written: HashSet<RegionPos> = rendered_regions.iter().copied()
+        .filter(|rloc| !failed.iter().any(|(failed, _)| failed == rloc))
+        .collect();
+    println!("{}", tr!("Rendered {} regions ({} chunks) in {}.", i18n::number(written.len()),
+        i18n::number(rendered_chunks), i18n::duration(started.elapsed())));
+    if let Some(summary) = skip_summary {
+        if summary.known > 0 || summary.skipped > 0 {
+            note!("{}", tr!("Broken chunks: {} failed this run, {} skipped, {} on the skip list (--retry-broken to retry).",
+                summary.failed, summary.skipped, summary.known));
+        }
+    }
+    if cache_degraded {
+        note!("{}", tr!("Note: cache directory was not writable, caches were not updated in this run."));
+    }
+    if !failed.is_empty() {
+        failed.sort();
+        println!("{}", tr!("{} regions could not be written, their caches were kept for the next run:", failed.len()));
+        for (rloc, message) in &failed {
+            println!("  {}: {}", rloc, message);
+        }
+    }
+    if !interrupted.is_empty() {
+        println!("{}", tr!("Interrupted: {} regions ({} chunks) were left for the next run.", i18n::number(interrupted.len()),
+            i18n::number(interrupted.iter().map(|rloc| region_chunks[rloc]).sum::<usize>())));
+    }
+    let mut result = PassResult { rendered: written.len(), failed, failed_outputs };
+
+    let orphans = if args.prune && args.compare_path.is_none() {
+        match prune::find_orphans(args.dim_path(), &[&args.image_path, &args.cache_path]) {
+            Ok(orphans) => Some(orphans),
+            Err(e) => {
+                result.failed_outputs.push(format!("--prune: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if let Some(orphans) = orphans {
+        if args.prune_dry_run {
+            for path in &orphans.files {
+                note!("orphan: {}", path.to_str().unwrap());
+            }
+            note!("{}", tr!("Prune: {} files of {} deleted regions would be removed.", orphans.files.len(), orphans.regions.len()));
+        } else if !orphans.files.is_empty() {
+            let removed = prune::remove(&orphans);
+            if let Err(e) = cache_store.remove(&orphans.regions) {
+                warn!("cannot remove the caches of deleted regions: {}", e);
+            }
+            note!("{}", tr!("Prune: removed {} files of {} deleted regions.", removed, orphans.regions.len()));
+            // Redraw the tiles those regions covered.
+            tile_regions.extend(orphans.regions);
+        }
+    }
+
+    // Only what this pass wrote is uploaded.
+    let mut uploads: Vec<PathBuf> = vec![];
+    if args.upload.upload.is_some() {
+        uploads.extend(upload::region_files(&args.image_path, &written));
+        if args.markers {
+            uploads.push(args.image_path.join(markers::MARKERS_NAME));
+            uploads.extend(args.marker_lang.iter().map(|lang| args.image_path.join(markers::to_markers_name(lang))));
+        }
+    }
+
+    // Tiles and the stitched image are built from the map images, by one
+    // pass at a time (serve renders in two lanes).
+    let _publishing = scheduler::PUBLISH.lock().unwrap();
+    if matches!(args.mode, RenderMode::Heightmap | RenderMode::Isometric) && (args.tile.tiles || args.stitch.is_some()) {
+        warn!("--tiles and --stitch are ignored with --mode heightmap and isometric");
+    } else {
+        let watermark = args.overlays.watermark.as_ref().and_then(|spec| match Watermark::load(spec) {
+            Ok(watermark) => Some(Arc::new(watermark)),
+            Err(e) => {
+                result.failed_outputs.push(format!("--watermark: {}", e));
+                None
+            }
+        });
+        let overlays = overlays(args, kind);
+        let watermark_on = |target: WatermarkTarget| watermark.clone()
+            .filter(|_| args.overlays.watermark_on == WatermarkTarget::All || args.overlays.watermark_on == target);
+        if args.tile.tiles {
+            let pyramid = tiles::TilePyramid::new(&args.image_path, args.tile.tile_levels)
+                .with_zoom_range(args.tile.tile_min_zoom, args.tile.tile_max_zoom)
+                .with_pruning(args.tile.tile_prune)
+                .with_markers(tile_markers)
+                .with_png_encoding(args.png_encoding())
+                .with_grid(args.tile.tile_grid)
+                .with_watermark(watermark_on(WatermarkTarget::Tiles))
+                .with_overlays(overlays.clone());
+            let pyramid = match &args.tile.output {
+                TileOutput::Dir => Some(pyramid),
+                TileOutput::MbTiles(path) => {
+                    if args.tile.tile_grid {
+                        warn!("--tile-grid is not written into --output mbtiles archives");
+                    }
+                    match MbTiles::open(path) {
+                        Ok(archive) => Some(pyramid.with_archive(Some(archive))),
+                        Err(e) => {
+                            result.failed_outputs.push(format!("{}: {}", path.display(), e));
+                            None
+                        }
+                    }
+                },
+            };
+            match pyramid.map(|pyramid| (pyramid.update(&args.image_path, &tile_regions), pyramid)) {
+                Some((Ok(written), pyramid)) => match &args.tile.output {
+                    TileOutput::Dir => for tile in &written {
+                        let path = pyramid.tile_path(tile);
+                        if args.tile.tile_grid {
+                            uploads.push(path.with_extension("grid.json"));
+                        }
+                        uploads.push(path);
+                    },
+                    TileOutput::MbTiles(path) => uploads.push(path.clone()),
+                },
+                Some((Err(e), _)) => result.failed_outputs.push(format!("--tiles: {}", e)),
+                None => {},
+            }
+        }
+        if let Some(stitch_path) = &args.stitch {
+            // The whole of -R, also when this pass renders a few regions of it.
+            match stitch::stitch(&args.image_path, stitch_path, args.stitch_scale, args.bounds().as_ref(), &overlays, watermark_on(WatermarkTarget::Stitch).as_deref()) {
+                Ok(_) => uploads.push(stitch_path.clone()),
+                Err(e) => result.failed_outputs.push(format!("{}: {}", stitch_path.display(), e)),
+            }
+        }
+    }
+
+    if let Some(target) = &args.upload.upload {
+        match upload::Uploader::new(target, args.upload.upload_endpoint.as_deref(), &args.upload.upload_region) {
+            Ok(uploader) => {
+                let uploaded = uploader.upload(&args.image_path, &uploads);
+                note!("{}", tr!("Uploaded {} of {} changed files.", i18n::number(uploaded), i18n::number(uploads.len())));
+            },
+            Err(e) => warn!("--upload: cannot connect to the bucket: {}", e),
+        }
+    }
+    print_failed_outputs(&result.failed_outputs);
+    Ok(result)
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use clap::ArgEnum;
+use log::{info, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cli::{CacheMode, RenderArgs, ServeArgs};
+use crate::commands::render::{load_palette, render_pass};
+use crate::commands::watch::watch;
+use crate::coords::{region_bounds, BlockPos, RegionBounds, RegionPos, TilePos};
+use crate::i18n::{self, Lang};
+use crate::journal::INTERACTIVE_JOURNAL_NAME;
+use crate::markers::MARKERS_NAME;
+use crate::mbtiles::{MbTiles, TileOutput};
+use crate::region_index::to_region_name;
+use crate::scheduler::{Job, Scheduler, MAX_JOB_REGIONS};
+use crate::shutdown::{self, INTERRUPTED_EXIT_CODE};
+use crate::run_lock;
+
+const VIEWER: &str = include_str!("viewer.html");
+
+/// Tiles of one dimension, from its tiles directory or its MBTiles archive.
+struct Layer {
+    name: String,
+    dim_path: PathBuf,
+    image_path: PathBuf,
+    archive: Option<Mutex<MbTiles>>,
+    /// Only served with the --auth credentials (--private-layer).
+    private: bool,
+}
+
+/// A region --render-missing queued, for a modification time of its region file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Queued {
+    /// Waiting for the interactive lane, or being rendered.
+    Pending(SystemTime),
+    /// Rendered, or failed: not queued again until the region file changes.
+    Done(SystemTime),
+}
+
+type QueuedRegions = Mutex<HashMap<(usize, RegionPos), Queued>>;
+
+struct Site {
+    layers: Vec<Layer>,
+    levels: u32,
+    min_zoom: u32,
+    max_zoom: u32,
+    /// The interactive lane, with --render-viewport or --render-missing.
+    scheduler: Option<Arc<Scheduler>>,
+    render_missing: bool,
+    /// Regions --render-missing queued, shared with the interactive lane.
+    queued: Arc<QueuedRegions>,
+    placeholder: Vec<u8>,
+    /// The `Authorization` headers --auth and --auth-token accept.
+    auth: Vec<String>,
+    /// Whether to ask browsers for --auth credentials, rather than for a token.
+    basic: bool,
+    /// With --private-layer: the viewer and the other layers need no credentials.
+    partly_public: bool,
+}
+
+impl Site {
+    fn authorized(&self, request: &Request) -> bool {
+        if self.auth.is_empty() {
+            return true;
+        }
+        request.headers().iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .map_or(false, |header| self.auth.iter().fold(false, |ok, expected| ok | same_secret(header.value.as_str().trim(), expected)))
+    }
+
+    /// Whether the path needs the --auth credentials: everything without
+    /// --private-layer, else the private layers, every layer's markers
+    /// (player positions) and /login.
+    fn protected(&self, segments: &[&str]) -> bool {
+        match segments {
+            _ if self.auth.is_empty() => false,
+            _ if !self.partly_public => true,
+            ["login"] => true,
+            ["layers", _, file] if *file == MARKERS_NAME => true,
+            ["layers", i, ..] => layer_at(self, i).map_or(false, |layer| layer.private),
+            _ => false,
+        }
+    }
+}
+
+/// Compare without stopping at the first difference.
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Standard base64, as in an `Authorization: Basic` header.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    out
+}
+
+/// Serve the tile pyramid with a Leaflet viewer, and with `--rerender-interval`
+/// keep rendering what changed in the background like `watch`. With
+/// `--render-viewport` the viewer also posts the area it shows, rendered
+/// ahead of those passes.
+pub fn run(args: &ServeArgs) {
+    let render = &args.render;
+    let dimensions = render.dimensions();
+    if dimensions.is_empty() {
+        eprintln!("no region directory found in {}", render.given_path().display());
+        std::process::exit(2);
+    }
+    if !render.tile.tiles {
+        warn!("serve shows the tile pyramid; without --tiles only existing tiles are served");
+    }
+    if args.auth.as_ref().is_some_and(|auth| !auth.contains(':')) {
+        eprintln!("--auth: expected USER:PASSWORD");
+        std::process::exit(2);
+    }
+    if args.auth_token.as_ref().is_some_and(|token| token.is_empty() || token.contains(char::is_whitespace)) {
+        eprintln!("--auth-token: expected a token without spaces");
+        std::process::exit(2);
+    }
+    if !args.private_layer.is_empty() && args.auth.is_none() && args.auth_token.is_none() {
+        eprintln!("--private-layer needs --auth or --auth-token");
+        std::process::exit(2);
+    }
+    let mut layers: Vec<Layer> = dimensions.iter().map(layer).collect();
+    for name in &args.private_layer {
+        match layers.iter_mut().find(|layer| layer.name == *name) {
+            Some(layer) => layer.private = true,
+            None => warn!("--private-layer {}: no such layer", name),
+        }
+    }
+    let levels = render.tile.tile_levels.max(1);
+    let max_zoom = render.tile.tile_max_zoom.unwrap_or(levels - 1).min(levels - 1);
+    let site = Site {
+        layers,
+        levels,
+        min_zoom: render.tile.tile_min_zoom.min(max_zoom),
+        max_zoom,
+        scheduler: if args.rendering.render_viewport || args.rendering.render_missing { Some(Default::default()) } else { None },
+        render_missing: args.rendering.render_missing,
+        queued: Default::default(),
+        placeholder: placeholder(),
+        auth: args.auth.iter().map(|auth| format!("Basic {}", base64(auth.as_bytes())))
+            .chain(args.auth_token.iter().map(|token| format!("Bearer {}", token)))
+            .collect(),
+        basic: args.auth.is_some(),
+        partly_public: !args.private_layer.is_empty(),
+    };
+
+    let lane = site.scheduler.as_ref().map(|scheduler| {
+        let (scheduler, queued) = (Arc::clone(scheduler), Arc::clone(&site.queued));
+        std::thread::spawn(move || interactive_lane(&dimensions, &scheduler, &queued))
+    });
+    let watcher = args.rendering.rerender_interval.map(|interval| {
+        let render = render.clone();
+        let scheduler = site.scheduler.clone();
+        std::thread::spawn(move || watch(&render, interval, scheduler.as_ref(), false))
+    });
+
+    let server = match Server::http(&args.bind) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("cannot listen on {}: {}", args.bind, e);
+            std::process::exit(2);
+        }
+    };
+    info!("serving on http://{}/", args.bind);
+    let site = Arc::new(site);
+    let pool = threadpool::ThreadPool::new(4);
+    while !shutdown::requested() {
+        match server.recv_timeout(Duration::from_millis(250)) {
+            Ok(Some(request)) => {
+                let site = Arc::clone(&site);
+                pool.execute(move || respond(&site, request));
+            },
+            Ok(None) => {},
+            Err(e) => {
+                warn!("cannot accept requests on {}: {}", args.bind, e);
+                return;
+            }
+        }
+    }
+    // The interactive lane returns after the regions in progress; a
+    // --rerender-interval pass exits the process itself when it is done.
+    for handle in lane.into_iter().chain(watcher) {
+        let _ = handle.join();
+    }
+    run_lock::exit(INTERRUPTED_EXIT_CODE);
+}
+
+/// Render the viewports posted to `scheduler`, one job at a time. Regions a
+/// batch pass is in the middle of are left to it. The regions of a forced
+/// job are done in `queued` afterwards, whatever came of it. Returns once a
+/// stop is requested.
+fn interactive_lane(dimensions: &[RenderArgs], scheduler: &Scheduler, queued: &QueuedRegions) {
+    let palette = load_palette(&dimensions[0]);
+    while !shutdown::requested() {
+        let job = match scheduler.next(Duration::from_millis(250)) {
+            Some(job) => job,
+            None => continue,
+        };
+        let (min, max) = job.bounds;
+        let regions: Vec<RegionPos> = (min.z..=max.z).flat_map(|z| (min.x..=max.x).map(move |x| RegionPos::new(x, z))).collect();
+        let mut dim_args = dimensions[job.layer].clone();
+        // A viewer's request is always rendered, never skipped as done by an earlier one.
+        dim_args.journal_name = Some(INTERACTIVE_JOURNAL_NAME);
+        dim_args.resume = false;
+        if job.force {
+            // A current cache would draw nothing, leaving the tile as it is.
+            dim_args.force_region = regions.iter().map(|rloc| (rloc.x, rloc.z)).collect();
+        }
+        // One request failing to scan is no reason to stop serving.
+        let pass = |bounds: &RegionBounds| if let Err(e) = render_pass(&dim_args, Some(bounds), &palette, CacheMode::Default, false, None, None) {
+            warn!("{}", e);
+        };
+        if regions.iter().any(|rloc| scheduler.batch_busy(job.layer, rloc)) {
+            for rloc in regions.iter().filter(|rloc| !scheduler.batch_busy(job.layer, rloc)) {
+                pass(&(*rloc, *rloc));
+            }
+        } else {
+            pass(&job.bounds);
+        }
+        if job.force {
+            let mut queued = queued.lock().unwrap();
+            for rloc in &regions {
+                if let Some(entry) = queued.get_mut(&(job.layer, *rloc)) {
+                    if let Queued::Pending(version) = *entry {
+                        *entry = Queued::Done(version);
+                    }
+                }
+            }
+        }
+        scheduler.finish();
+    }
+}
+
+fn layer(dim_args: &RenderArgs) -> Layer {
+    let archive = match &dim_args.tile.output {
+        TileOutput::Dir => None,
+        TileOutput::MbTiles(path) => match MbTiles::open(path) {
+            Ok(archive) => Some(Mutex::new(archive)),
+            Err(e) => {
+                warn!("cannot open {}: {}", path.display(), e);
+                None
+            }
+        },
+    };
+    Layer {
+        name: dim_args.dimension.map_or("map", |kind| kind.id()).to_string(),
+        dim_path: dim_args.dim_path().clone(),
+        image_path: dim_args.image_path.clone(),
+        archive,
+        private: false,
+    }
+}
+
+fn respond(site: &Site, request: Request) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let authorized = site.authorized(&request);
+    if site.protected(&segments) && !authorized {
+        let scheme: &[u8] = if site.basic { b"Basic realm=\"mcanvilrenderer\"" } else { b"Bearer realm=\"mcanvilrenderer\"" };
+        let challenge = Header::from_bytes(&b"WWW-Authenticate"[..], scheme).unwrap();
+        if let Err(e) = request.respond(Response::from_string("sign in to see this").with_status_code(401).with_header(challenge)) {
+            warn!("cannot answer {}: {}", path, e);
+        }
+        return;
+    }
+    if segments == ["login"] {
+        // Signed in: back to the viewer, which now lists the private layers.
+        let location = Header::from_bytes(&b"Location"[..], &b"/"[..]).unwrap();
+        if let Err(e) = request.respond(Response::empty(303).with_header(location)) {
+            warn!("cannot answer {}: {}", path, e);
+        }
+        return;
+    }
+    if let (["layers", i, "render"], Some(scheduler)) = (segments.as_slice(), &site.scheduler) {
+        let response = match (layer_index(site, i), viewport(query)) {
+            _ if *request.method() != Method::Post => Response::from_string("POST the view to render").with_status_code(405),
+            (Some(layer), Some(bounds)) => {
+                let (min, max) = bounds;
+                let regions = ((max.x - min.x + 1) * (max.z - min.z + 1)) as usize;
+                if regions <= MAX_JOB_REGIONS {
+                    scheduler.submit(Job { layer, bounds, force: false });
+                    Response::from_string("queued").with_status_code(202)
+                } else {
+                    Response::from_string("view too large, left to the scheduled passes").with_status_code(413)
+                }
+            },
+            _ => Response::from_string("expected x1, z1, x2, z2 block coordinates").with_status_code(400),
+        };
+        if let Err(e) = request.respond(response) {
+            warn!("cannot answer {}: {}", path, e);
+        }
+        return;
+    }
+    let found = match segments.as_slice() {
+        [] | ["index.html"] => Some((VIEWER.as_bytes().to_vec(), "text/html; charset=utf-8")),
+        ["config.json"] => Some((config(site, authorized).into_bytes(), "application/json")),
+        ["layers", i, file] if *file == MARKERS_NAME => layer_at(site, i)
+            .and_then(|layer| std::fs::read(layer.image_path.join(MARKERS_NAME)).ok())
+            .map(|data| (data, "application/json")),
+        ["layers", i, z, x, y] => match (layer_index(site, i), tile_pos(site, z, x, y)) {
+            (Some(layer), Some((zoom, pos))) => tile(site, layer, zoom, &pos).map(|data| (data, "image/png")),
+            _ => None,
+        },
+        _ => None,
+    };
+    let result = match found {
+        Some((data, content_type)) => request.respond(Response::from_data(data)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap())),
+        None => request.respond(Response::from_string("not found").with_status_code(404)),
+    };
+    if let Err(e) = result {
+        warn!("cannot answer {}: {}", path, e);
+    }
+}
+
+/// Names of a layer and of its markers in the layer control, by language code.
+fn legend(layer: &Layer) -> serde_json::Value {
+    Lang::value_variants().iter().map(|lang| {
+        let name = i18n::translate_in(*lang, &layer.name);
+        let markers = i18n::fill(i18n::translate_in(*lang, "{} markers"), &[name.to_string()]);
+        (lang.code().to_string(), serde_json::json!({ "name": name, "markers": markers }))
+    }).collect::<serde_json::Map<_, _>>().into()
+}
+
+/// The layers the request may see, and how the viewer shows them.
+fn config(site: &Site, authorized: bool) -> String {
+    let layers: Vec<_> = site.layers.iter().enumerate().filter(|(_, layer)| authorized || !layer.private).map(|(i, layer)| serde_json::json!({
+        "name": layer.name,
+        "legend": legend(layer),
+        "tiles": format!("layers/{}/{{z}}/{{x}}/{{y}}.png", i),
+        "markers": format!("layers/{}/{}", i, MARKERS_NAME),
+        "render": format!("layers/{}/render", i),
+    })).collect();
+    serde_json::json!({
+        "layers": layers,
+        "minZoom": site.min_zoom,
+        "maxZoom": site.max_zoom,
+        "renderViewport": site.scheduler.is_some(),
+    }).to_string()
+}
+
+fn layer_at<'a>(site: &'a Site, index: &str) -> Option<&'a Layer> {
+    site.layers.get(layer_index(site, index)?)
+}
+
+fn layer_index(site: &Site, index: &str) -> Option<usize> {
+    Some(index.parse::<usize>().ok()?).filter(|i| *i < site.layers.len())
+}
+
+/// Regions of the block rectangle `x1=..&z1=..&x2=..&z2=..`.
+fn viewport(query: &str) -> Option<RegionBounds> {
+    let value = |key: &str| query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| v.parse::<f64>().ok())
+        .filter(|v| v.is_finite())
+        .map(|v| v.floor().clamp(i32::MIN as f64, i32::MAX as f64) as i32);
+    let (x1, z1, x2, z2) = (value("x1")?, value("z1")?, value("x2")?, value("z2")?);
+    Some(region_bounds(&(BlockPos::new(x1.min(x2), z1.min(z2)), BlockPos::new(x1.max(x2), z1.max(z2)))))
+}
+
+/// The zoom and position of tile z/x/y, as Leaflet asks for it.
+fn tile_pos(site: &Site, z: &str, x: &str, y: &str) -> Option<(u32, TilePos)> {
+    let zoom: u32 = z.parse().ok()?;
+    if zoom < site.min_zoom || zoom > site.max_zoom {
+        return None;
+    }
+    Some((zoom, TilePos { level: site.levels - 1 - zoom, x: x.parse().ok()?, z: y.strip_suffix(".png")?.parse().ok()? }))
+}
+
+/// The PNG of a tile. With --render-missing a missing tile, or one older
+/// than its region files, is queued; a missing one is answered with the
+/// placeholder until it is rendered, a stale one as it is.
+fn tile(site: &Site, index: usize, zoom: u32, pos: &TilePos) -> Option<Vec<u8>> {
+    let layer = &site.layers[index];
+    let (data, written) = match &layer.archive {
+        Some(archive) => (archive.lock().unwrap().get(zoom, pos).ok()?, None),
+        None => {
+            let path = layer.image_path.join("tiles").join(zoom.to_string()).join(pos.x.to_string()).join(format!("{}.png", pos.z));
+            (std::fs::read(&path).ok(), std::fs::metadata(&path).and_then(|meta| meta.modified()).ok())
+        },
+    };
+    // An archive keeps no modification times: only missing tiles are queued.
+    let pending = site.render_missing && (data.is_none() || written.is_some()) && queue_outdated(site, index, pos, written);
+    match data {
+        None if pending => Some(site.placeholder.clone()),
+        data => data,
+    }
+}
+
+/// Queue the regions of `pos` whose region files changed after the tile was
+/// `written` (all of them if it never was), forced so their tiles are written
+/// again. Whether one of them is still to be rendered: once they all are, a
+/// tile still missing or older has nothing more to draw.
+fn queue_outdated(site: &Site, index: usize, pos: &TilePos, written: Option<SystemTime>) -> bool {
+    let (scheduler, layer) = match &site.scheduler {
+        Some(scheduler) => (scheduler, &site.layers[index]),
+        None => return false,
+    };
+    let regions = pos.regions();
+    if regions.len() > MAX_JOB_REGIONS {
+        // Left to the scheduled passes, like a view that large.
+        return false;
+    }
+    let modified = |path: PathBuf| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut queued = site.queued.lock().unwrap();
+    let mut pending = false;
+    for rloc in regions {
+        let changed = match modified(layer.dim_path.join(to_region_name(&rloc))) {
+            Some(changed) => changed,
+            None => continue,
+        };
+        if written.map_or(false, |written| written >= changed) {
+            continue;
+        }
+        match queued.get(&(index, rloc)) {
+            Some(Queued::Pending(version)) if *version == changed => pending = true,
+            Some(Queued::Done(version)) if *version == changed => (),
+            _ => {
+                scheduler.submit(Job { layer: index, bounds: (rloc, rloc), force: true });
+                queued.insert((index, rloc), Queued::Pending(changed));
+                pending = true;
+            },
+        }
+    }
+    pending
+}
+
+/// Served for a missing tile being rendered: one grey pixel, which the
+/// viewer stretches over the tile and asks for again.
+fn placeholder() -> Vec<u8> {
+    let mut data = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut data, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[128, 128, 128, 96]).unwrap();
+    }
+    data
+}
@@ -0,0 +1,28 @@
+use log::error;
+
+use crate::cli::SynthWorldArgs;
+use crate::selection::Selection;
+use crate::synth_world::SynthWorld;
+
+/// Write a synthetic region directory, e.g. as the fixture of bench-matrix
+/// or of a scripted render, re-run with --touch to mark chunks as changed.
+pub fn run(args: &SynthWorldArgs) {
+    let area = match Selection::new(args.area.clone()) {
+        Some(area) => area,
+        None => {
+            eprintln!("synth-world needs at least one --area");
+            std::process::exit(2);
+        }
+    };
+    let mut world = SynthWorld::new(args.timestamp).with_surface_y(args.surface_y);
+    for (cloc, timestamp) in &args.touch {
+        world.touch(*cloc, *timestamp);
+    }
+    match world.write(&args.out, &area) {
+        Ok(regions) => println!("Wrote {} regions to {}", regions, args.out.display()),
+        Err(e) => {
+            error!("cannot write {}: {}", args.out.display(), e);
+            std::process::exit(1);
+        },
+    }
+}
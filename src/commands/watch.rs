@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use std::time::Duration;
+use log::{info, warn};
+
+use crate::cli::{CacheMode, RenderArgs, RenderMode, WatchArgs};
+use crate::commands::render::{create_dirs, load_palette, render_pass, FAILED_EXIT_CODE};
+use crate::console::Console;
+use crate::dim_renderer::to_image_name;
+use crate::heightmap::to_heightmap_name;
+use crate::isometric::to_isometric_name;
+use crate::region_index::RegionIndex;
+use crate::change_spool;
+use crate::scheduler::Scheduler;
+use crate::shutdown::{self, INTERRUPTED_EXIT_CODE};
+use crate::run_lock;
+
+/// Re-scan the dimension every `--interval` seconds and render what changed.
+pub fn run(args: &WatchArgs) {
+    if let (Some(addr), Some(spool)) = (&args.changes_listen, &args.render.changes_from) {
+        if let Err(e) = change_spool::listen(addr, spool.clone()) {
+            eprintln!("cannot listen on {}: {}", addr, e);
+            std::process::exit(2);
+        }
+    }
+    watch(&args.render, args.interval, None, args.console);
+}
+
+/// Render, then re-scan every `interval` seconds and render what changed. Never returns:
+/// an interrupt exits once the pass in progress has stopped.
+/// With a `scheduler` these passes are its batch lane. With `console`, regions
+/// picked on stdin are rendered in between (see `Console`).
+pub fn watch(render: &RenderArgs, interval: u64, scheduler: Option<&Arc<Scheduler>>, console: bool) {
+    if render.cache_mode == CacheMode::ReadOnly || render.cache_mode == CacheMode::NoCache {
+        eprintln!("watch needs a cache it can save (--cache-mode default or refresh).");
+        std::process::exit(2);
+    }
+    let mut dimensions = render.dimensions();
+    if dimensions.is_empty() {
+        eprintln!("no region directory found in {}", render.given_path().display());
+        std::process::exit(2);
+    }
+    let palette = load_palette(&dimensions[0]);
+    let mut cache_mode = render.cache_mode;
+    let mut retry_broken = render.retry_broken;
+    let console = if console {
+        let image_name = match render.mode {
+            RenderMode::Heightmap => to_heightmap_name,
+            RenderMode::Isometric => to_isometric_name,
+            _ => to_image_name,
+        };
+        Some(Console::start(dimensions[0].dim_path().clone(), dimensions[0].image_path.clone(), image_name))
+    } else {
+        None
+    };
+    let mut indexes: Vec<RegionIndex> = dimensions.iter().map(|dim_args| {
+        if let (true, Err(e)) = (render.world_path.is_some(), create_dirs(dim_args)) {
+            eprintln!("{}", e);
+            run_lock::exit(FAILED_EXIT_CODE);
+        }
+        RegionIndex::load(&dim_args.cache_path)
+    }).collect();
+    loop {
+        let mut rendered = 0;
+        for (layer, (dim_args, index)) in dimensions.iter().zip(indexes.iter_mut()).enumerate() {
+            if shutdown::requested() {
+                break;
+            }
+            let preemption = scheduler.map(|scheduler| scheduler.batch_lane(layer));
+            match render_pass(dim_args, dim_args.bounds().as_ref(), &palette, cache_mode, retry_broken, Some(index), preemption) {
+                Ok(result) => rendered += result.rendered,
+                // Serving goes on, and the next pass tries again.
+                Err(e) if scheduler.is_some() => warn!("{}", e),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    run_lock::exit(FAILED_EXIT_CODE);
+                }
+            }
+        }
+        // Later passes only pick up what changed since the previous one.
+        cache_mode = CacheMode::Default;
+        retry_broken = false;
+        for dim_args in dimensions.iter_mut() {
+            dim_args.force_all = false;
+            dim_args.force_region.clear();
+        }
+        if shutdown::requested() {
+            run_lock::exit(INTERRUPTED_EXIT_CODE);
+        }
+        info!("watch: {} regions rendered, next scan in {}s", rendered, interval);
+        match &console {
+            // A picked region is redrawn from its chunks, whatever its cache says.
+            Some(console) => console.wait(Duration::from_secs(interval), |rloc| {
+                for dim_args in &dimensions {
+                    if let Err(e) = render_pass(dim_args, Some(&(rloc, rloc)), &palette, CacheMode::Refresh, false, None, None) {
+                        warn!("{}", e);
+                    }
+                }
+            }),
+            None => shutdown::sleep(Duration::from_secs(interval)),
+        }
+        if shutdown::requested() {
+            run_lock::exit(INTERRUPTED_EXIT_CODE);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use clap::{ArgEnum, CommandFactory};
+use serde::Deserialize;
+
+use crate::cli::{parse_location_val, CacheMode, Cli, RenderArgs};
+use crate::dimension::DimensionKind;
+use crate::error::Result;
+
+/// A `[overworld]`, `[nether]` or `[end]` section as written.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct DimensionSection {
+    image_path: Option<PathBuf>,
+    cache_path: Option<PathBuf>,
+    range: Option<Vec<String>>,
+    cache_mode: Option<String>,
+    threads: Option<usize>,
+}
+
+/// Settings of one dimension of a --world-path render, from its section of
+/// the config file. Its paths are used as they are, not suffixed with the
+/// dimension like the top-level ones.
+#[derive(Debug, Clone, Default)]
+pub struct DimensionConfig {
+    pub image_path: Option<PathBuf>,
+    pub cache_path: Option<PathBuf>,
+    pub range: Option<Vec<(i32, i32)>>,
+    pub cache_mode: Option<CacheMode>,
+    pub threads: Option<usize>,
+}
+
+impl DimensionConfig {
+    pub fn apply(&self, args: &mut RenderArgs) {
+        if let Some(path) = &self.image_path {
+            args.image_path = path.clone();
+        }
+        if let Some(path) = &self.cache_path {
+            args.cache_path = path.clone();
+        }
+        if let Some(range) = &self.range {
+            args.range = Some(range.clone());
+        }
+        if let Some(cache_mode) = self.cache_mode {
+            args.cache_mode = cache_mode;
+        }
+        if let Some(threads) = self.threads {
+            args.threads = threads;
+        }
+    }
+}
+
+/// The command line with the options of `--config` put in.
+pub struct Expanded {
+    pub args: Vec<OsString>,
+    pub dimensions: Vec<(DimensionKind, DimensionConfig)>,
+}
+
+/// The value of `--NAME VALUE` (or `--NAME=VALUE`), if given.
+fn option_value(args: &[OsString], name: &str) -> Option<String> {
+    let (flag, prefix) = (format!("--{}", name), format!("--{}=", name));
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == flag {
+            return args.next().map(|value| value.to_string_lossy().into_owned());
+        }
+        if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// `args` without `--NAME VALUE` (or `--NAME=VALUE`).
+fn without_option(args: &[OsString], name: &str) -> Vec<OsString> {
+    let (flag, prefix) = (format!("--{}", name), format!("--{}=", name));
+    let mut kept = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == flag {
+            args.next();
+        } else if !text.starts_with(&prefix) {
+            kept.push(arg.clone());
+        }
+    }
+    kept
+}
+
+/// Where the subcommand is in `args`, and its definition.
+fn subcommand(args: &[OsString]) -> Option<(usize, clap::Command<'static>)> {
+    let mut cli = Cli::command();
+    cli.build();
+    let position = args.iter().position(|arg| cli.get_subcommands().any(|sub| arg.to_str() == Some(sub.get_name())))?;
+    let command = cli.find_subcommand(args[position].to_str().unwrap()).unwrap().clone();
+    Some((position, command))
+}
+
+/// `batch` reads the config file itself, for each of its profiles.
+fn is_batch(args: &[OsString]) -> bool {
+    matches!(subcommand(args), Some((_, command)) if command.get_name() == "batch")
+}
+
+/// Long names of the options given on the command line.
+fn given(args: &[OsString], command: &clap::Command) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        if let Some(long) = arg.strip_prefix("--") {
+            names.insert(long.split('=').next().unwrap().to_string());
+        } else if let Some(shorts) = arg.strip_prefix('-').filter(|shorts| !shorts.starts_with(|c: char| c.is_ascii_digit())) {
+            for short in shorts.chars() {
+                if let Some(long) = command.get_arguments().find(|a| a.get_short() == Some(short)).and_then(|a| a.get_long()) {
+                    names.insert(long.to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+fn scalar(key: &str, value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        _ => Err(format!("{}: expected a string or a number", key).into()),
+    }
+}
+
+/// The options of a top-level key, as they would be written on the command line.
+fn to_flags(key: &str, value: &toml::Value, command: &clap::Command) -> Result<Vec<OsString>> {
+    let long = key.replace('_', "-");
+    let arg = command.get_arguments().find(|arg| arg.get_long() == Some(long.as_str()))
+        .ok_or_else(|| format!("unknown option \"{}\" for {}", key, command.get_name()))?;
+    let flag = format!("--{}", long);
+    if !arg.is_takes_value_set() {
+        return match value {
+            toml::Value::Boolean(true) => Ok(vec![flag.into()]),
+            toml::Value::Boolean(false) => Ok(vec![]),
+            // -v counted: verbose = 2
+            toml::Value::Integer(n) if *n >= 0 => Ok(vec![flag.into(); *n as usize]),
+            _ => Err(format!("{}: expected true or false", key).into()),
+        };
+    }
+    let values = match value {
+        toml::Value::Array(values) => values.iter().map(|value| scalar(key, value)).collect::<Result<Vec<_>>>()?,
+        value => vec![scalar(key, value)?],
+    };
+    if arg.is_multiple_occurrences_set() {
+        Ok(values.iter().map(|value| format!("{}={}", flag, value).into()).collect())
+    } else {
+        Ok(vec![format!("{}={}", flag, values.join(",")).into()])
+    }
+}
+
+fn dimension_config(kind: DimensionKind, section: toml::Value) -> Result<DimensionConfig> {
+    let section: DimensionSection = section.try_into().map_err(|e| format!("[{}]: {}", kind.id(), e))?;
+    let range = match section.range {
+        Some(range) if range.is_empty() || range.len() > 2 => return Err(format!("[{}] range: one or two \"x,z\"", kind.id()).into()),
+        Some(range) => Some(range.iter().map(|loc| parse_location_val(loc)).collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| format!("[{}] range: {}", kind.id(), e))?),
+        None => None,
+    };
+    let cache_mode = match section.cache_mode {
+        Some(mode) => Some(CacheMode::from_str(&mode, true).map_err(|e| format!("[{}] cache-mode: {}", kind.id(), e))?),
+        None => None,
+    };
+    Ok(DimensionConfig { image_path: section.image_path, cache_path: section.cache_path, range, cache_mode, threads: section.threads })
+}
+
+fn load(path: &Path) -> Result<toml::value::Table> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    text.parse::<toml::Value>().map_err(|e| format!("{}: {}", path.display(), e))?
+        .as_table().cloned().ok_or_else(|| format!("{}: not a table", path.display()).into())
+}
+
+/// The `[profile.NAME]` sections of a config file.
+fn profile_tables(config: &toml::value::Table) -> Result<toml::value::Table> {
+    match config.get("profile") {
+        Some(toml::Value::Table(profiles)) => Ok(profiles.clone()),
+        Some(_) => Err("profile: expected [profile.NAME] sections".into()),
+        None => Ok(Default::default()),
+    }
+}
+
+/// The options of profile `name`: the top level of the file, with the
+/// profile's keys (and the keys of its dimension sections) over it.
+fn with_profile(mut config: toml::value::Table, name: Option<&str>) -> Result<toml::value::Table> {
+    let profiles = profile_tables(&config)?;
+    config.remove("profile");
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(config),
+    };
+    let profile = match profiles.get(name) {
+        Some(toml::Value::Table(profile)) => profile,
+        _ => return Err(format!("no [profile.{}] in the config file", name).into()),
+    };
+    for (key, value) in profile {
+        match (config.get_mut(key), value) {
+            (Some(toml::Value::Table(section)), toml::Value::Table(overrides)) => {
+                section.extend(overrides.iter().map(|(key, value)| (key.clone(), value.clone())));
+            },
+            _ => {
+                config.insert(key.clone(), value.clone());
+            },
+        }
+    }
+    Ok(config)
+}
+
+/// The profiles `--profile` selects: the names given, or every profile of
+/// the file for `all`.
+pub fn profile_names(path: &Path, selected: &str) -> Result<Vec<String>> {
+    if selected == "all" {
+        Ok(profile_tables(&load(path)?)?.keys().cloned().collect())
+    } else {
+        Ok(selected.split(',').map(str::to_string).collect())
+    }
+}
+
+/// With `--profile` naming several profiles, or `all`: each profile's
+/// command line, to be run one after the other. None for a single run.
+pub fn profile_runs(args: &[OsString]) -> Result<Option<Vec<(String, Vec<OsString>)>>> {
+    let (path, selected) = match (option_value(args, "config"), option_value(args, "profile")) {
+        (Some(path), Some(selected)) if !is_batch(args) => (PathBuf::from(path), selected),
+        _ => return Ok(None),
+    };
+    let names = profile_names(&path, &selected)?;
+    if names.len() < 2 && selected != "all" {
+        return Ok(None);
+    }
+    let rest = without_option(args, "profile");
+    Ok(Some(names.into_iter().map(|name| {
+        let mut run = rest.clone();
+        run.push(format!("--profile={}", name).into());
+        (name, run)
+    }).collect()))
+}
+
+/// Run each profile with a process of its own, so one that fails or exits
+/// early doesn't end the others, and return the highest exit code.
+pub fn run_profiles(runs: Vec<(String, Vec<OsString>)>) -> i32 {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("cannot run the profiles: {}", e);
+            return 2;
+        }
+    };
+    let mut worst = 0;
+    for (name, args) in runs {
+        if crate::shutdown::requested() {
+            break;
+        }
+        note!("Profile: {}", name);
+        let code = match std::process::Command::new(&exe).args(&args[1..]).status() {
+            Ok(status) => status.code().unwrap_or(crate::shutdown::INTERRUPTED_EXIT_CODE),
+            Err(e) => {
+                eprintln!("cannot run profile {}: {}", name, e);
+                2
+            }
+        };
+        worst = worst.max(code);
+    }
+    worst
+}
+
+/// Put the options of the `--config` file in `args`, right after the
+/// subcommand. Top-level keys are long option names of the subcommand
+/// (`cache-path = "..."`, `tiles = true`); `[overworld]`, `[nether]` and
+/// `[end]` sections hold what differs per dimension, and `[profile.NAME]`
+/// sections what differs for `--profile NAME`. Options given on the command
+/// line are taken from there only, lists included.
+pub fn expand(args: Vec<OsString>) -> Result<Expanded> {
+    let path = match option_value(&args, "config") {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(Expanded { args, dimensions: vec![] }),
+    };
+    let (position, command) = subcommand(&args).ok_or("--config needs a subcommand")?;
+    if command.get_name() == "batch" {
+        return Ok(Expanded { args, dimensions: vec![] });
+    }
+    let given = given(&args[1..], &command);
+
+    let mut flags: Vec<OsString> = vec![];
+    let mut dimensions = vec![];
+    let config = with_profile(load(&path)?, option_value(&args, "profile").as_deref())?;
+    for (key, value) in config {
+        let kind = [DimensionKind::Overworld, DimensionKind::Nether, DimensionKind::End].iter().copied().find(|kind| kind.id() == key);
+        match (kind, value) {
+            (Some(kind), section @ toml::Value::Table(_)) => {
+                let mut config = dimension_config(kind, section)?;
+                // What the command line gives applies to every dimension.
+                if given.contains("image-path") { config.image_path = None; }
+                if given.contains("cache-path") { config.cache_path = None; }
+                if given.contains("range") { config.range = None; }
+                if given.contains("cache-mode") { config.cache_mode = None; }
+                if given.contains("threads") { config.threads = None; }
+                dimensions.push((kind, config));
+            },
+            (_, value) if !given.contains(&key.replace('_', "-")) => {
+                flags.extend(to_flags(&key, &value, &command)?);
+            },
+            _ => (),
+        }
+    }
+    let mut expanded = args[..=position].to_vec();
+    expanded.extend(flags);
+    expanded.extend_from_slice(&args[position + 1..]);
+    Ok(Expanded { args: expanded, dimensions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use crate::cli::Command;
+
+    fn config_file(dir: &tempfile::TempDir, name: &str, text: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn argv(config: &Path, args: &[&str]) -> Vec<OsString> {
+        let mut argv: Vec<OsString> = vec!["mcanvilrenderer".into(), "render".into(), "--config".into(), config.into()];
+        argv.extend(args.iter().map(OsString::from));
+        argv
+    }
+
+    fn render_args(args: Vec<OsString>) -> RenderArgs {
+        match Cli::try_parse_from(args).unwrap().command {
+            Command::Render(render) => render,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn command_line_beats_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_file(&dir, "config.toml", "cache-path = \"file-cache\"\nthreads = 3\ntiles = true\n");
+        let expanded = expand(argv(&config, &["-d", "world", "-i", "images", "-p", "palette.tar.gz", "--cache-path=cli-cache"])).unwrap();
+        let render = render_args(expanded.args);
+        assert_eq!(render.cache_path, PathBuf::from("cli-cache"));
+        assert_eq!(render.threads, 3);
+        assert!(render.tile.tiles);
+
+        let expanded = expand(argv(&config, &["-d", "world", "-i", "images", "-p", "palette.tar.gz", "-c", "short-cache", "-t", "1"])).unwrap();
+        let render = render_args(expanded.args);
+        assert_eq!(render.cache_path, PathBuf::from("short-cache"));
+        assert_eq!(render.threads, 1);
+    }
+
+    #[test]
+    fn profile_keys_go_over_the_top_level() {
+        let config: toml::value::Table = toml::from_str(r#"
+            mode = "map"
+            threads = 4
+            [nether]
+            threads = 2
+            image-path = "nether-images"
+            [profile.night]
+            mode = "night"
+            [profile.night.nether]
+            threads = 1
+        "#).unwrap();
+        let night = with_profile(config.clone(), Some("night")).unwrap();
+        assert_eq!(night["mode"].as_str(), Some("night"));
+        assert_eq!(night["threads"].as_integer(), Some(4));
+        assert_eq!(night["nether"]["threads"].as_integer(), Some(1));
+        assert_eq!(night["nether"]["image-path"].as_str(), Some("nether-images"));
+        assert!(!night.contains_key("profile"));
+
+        let top = with_profile(config.clone(), None).unwrap();
+        assert_eq!(top["mode"].as_str(), Some("map"));
+        assert!(with_profile(config, Some("day")).is_err());
+    }
+
+    #[test]
+    fn dimension_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_file(&dir, "config.toml", "[nether]\nimage-path = \"nether-images\"\nrange = [\"-1,-1\", \"1,1\"]\ncache-mode = \"refresh\"\n[end]\nthreads = 2\n");
+        let expanded = expand(argv(&config, &["--world-path", "world", "-c", "cache", "-i", "images", "-p", "palette.tar.gz"])).unwrap();
+        let nether = &expanded.dimensions.iter().find(|(kind, _)| *kind == DimensionKind::Nether).unwrap().1;
+        assert_eq!(nether.image_path, Some(PathBuf::from("nether-images")));
+        assert_eq!(nether.range, Some(vec![(-1, -1), (1, 1)]));
+        assert_eq!(nether.cache_mode, Some(CacheMode::Refresh));
+        let end = &expanded.dimensions.iter().find(|(kind, _)| *kind == DimensionKind::End).unwrap().1;
+        assert_eq!(end.threads, Some(2));
+
+        // What the command line gives applies to every dimension.
+        let expanded = expand(argv(&config, &["--world-path", "world", "-c", "cache", "-i", "images", "-p", "palette.tar.gz", "-R", "0,0"])).unwrap();
+        let nether = &expanded.dimensions.iter().find(|(kind, _)| *kind == DimensionKind::Nether).unwrap().1;
+        assert_eq!(nether.range, None);
+        assert_eq!(nether.image_path, Some(PathBuf::from("nether-images")));
+
+        let bad = config_file(&dir, "bad.toml", "[nether]\nrange = [\"0,0\", \"1,1\", \"2,2\"]\n");
+        assert!(expand(argv(&bad, &["--world-path", "world"])).is_err());
+    }
+
+    #[test]
+    fn negative_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_file(&dir, "config.toml", "range = [\"-3,-4\", \"-1,2\"]\nseed = -42\n");
+        let expanded = expand(argv(&config, &["-d", "world", "-c", "cache", "-i", "images", "-p", "palette.tar.gz"])).unwrap();
+        let render = render_args(expanded.args);
+        assert_eq!(render.range, Some(vec![(-3, -4), (-1, 2)]));
+        assert_eq!(render.overlays.seed, Some(-42));
+
+        // A negative value on the command line is not taken for short options.
+        let (_, command) = subcommand(&argv(&config, &[])).unwrap();
+        let given = given(&argv(&config, &["--seed", "-7", "-R", "L-1,2"])[1..], &command);
+        assert!(given.contains("range") && given.contains("seed"));
+        assert!(!given.contains("threads"));
+        let expanded = expand(argv(&config, &["-d", "world", "-c", "cache", "-i", "images", "-p", "palette.tar.gz", "--seed", "-7", "-R", "L-1,2"])).unwrap();
+        let render = render_args(expanded.args);
+        assert_eq!(render.range, Some(vec![(-1, 2)]));
+        assert_eq!(render.overlays.seed, Some(-7));
+    }
+}
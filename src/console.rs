@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::cli::parse_location_val;
+use crate::coords::RegionPos;
+use crate::shutdown;
+
+/// Regions drawn on each side of the centre of the mini-map.
+const MAP_RADIUS: i32 = 16;
+
+const HELP: &str = "commands:
+  map [X,Z]  draw the regions around X,Z (# rendered, + not rendered yet, . no region file, @ centre)
+  X,Z        re-render region X,Z now, ahead of the next scan
+  help       this list";
+
+/// Commands typed while `watch --console` runs. Regions picked with `X,Z` are
+/// queued for `wait`, which renders them before the next scheduled scan.
+pub struct Console {
+    picked: Receiver<RegionPos>,
+}
+
+impl Console {
+    /// Read commands from stdin until it closes. `image_name` names the image
+    /// of a region in `image_path`, to tell rendered regions on the mini-map.
+    pub fn start(dim_path: PathBuf, image_path: PathBuf, image_name: fn(&RegionPos) -> String) -> Self {
+        let (sender, picked) = channel();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let line = line.trim();
+                match line.split_once(' ').unwrap_or((line, "")) {
+                    ("", _) => {},
+                    ("help", _) => println!("{}", HELP),
+                    ("map", centre) => match centre.trim() {
+                        "" => print_map(&dim_path, &image_path, image_name, None),
+                        centre => match parse_location_val(centre) {
+                            Ok(centre) => print_map(&dim_path, &image_path, image_name, Some(RegionPos::from(centre))),
+                            Err(e) => println!("map {}: {}", centre, e),
+                        },
+                    },
+                    _ => match parse_location_val(line) {
+                        Ok(pos) => {
+                            let rloc = RegionPos::from(pos);
+                            println!("r.{}.{} queued", rloc.x, rloc.z);
+                            if sender.send(rloc).is_err() {
+                                break;
+                            }
+                        },
+                        Err(_) => println!("unknown command {:?} (help lists them)", line),
+                    },
+                }
+            }
+        });
+        Console { picked }
+    }
+
+    /// Sleep for `interval`, waking up to `render` the regions picked
+    /// meanwhile, each once, in the order they were picked. Returns early
+    /// when a stop is requested.
+    pub fn wait(&self, interval: Duration, mut render: impl FnMut(RegionPos)) {
+        let deadline = Instant::now() + interval;
+        while !shutdown::requested() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let first = match self.picked.recv_timeout(left.min(Duration::from_millis(250))) {
+                Ok(rloc) => rloc,
+                Err(RecvTimeoutError::Timeout) if left.is_zero() => return,
+                Err(RecvTimeoutError::Timeout) => continue,
+                // stdin closed: nothing more to pick, sleep out the interval.
+                Err(RecvTimeoutError::Disconnected) => return shutdown::sleep(left),
+            };
+            let mut seen = HashSet::new();
+            for rloc in std::iter::once(first).chain(self.picked.try_iter()) {
+                if seen.insert(rloc) && !shutdown::requested() {
+                    render(rloc);
+                }
+            }
+        }
+    }
+}
+
+/// Regions with an `r.X.Z.mca` file in `dim_path`.
+fn region_files(dim_path: &Path) -> HashSet<RegionPos> {
+    let entries = match dim_path.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return HashSet::new(),
+    };
+    entries.filter_map(|entry| {
+        let name = entry.ok()?.file_name().into_string().ok()?;
+        let (x, z) = name.strip_prefix("r.")?.strip_suffix(".mca")?.split_once('.')?;
+        Some(RegionPos::new(x.parse().ok()?, z.parse().ok()?))
+    }).collect()
+}
+
+/// Draw the region files around `centre` (by default the middle of the
+/// world), one character per region, north up.
+fn print_map(dim_path: &Path, image_path: &Path, image_name: fn(&RegionPos) -> String, centre: Option<RegionPos>) {
+    let regions = region_files(dim_path);
+    let centre = match centre {
+        Some(centre) => centre,
+        None if regions.is_empty() => {
+            println!("no region files in {}", dim_path.display());
+            return;
+        },
+        None => {
+            let (min_x, max_x) = (regions.iter().map(|rloc| rloc.x).min().unwrap(), regions.iter().map(|rloc| rloc.x).max().unwrap());
+            let (min_z, max_z) = (regions.iter().map(|rloc| rloc.z).min().unwrap(), regions.iter().map(|rloc| rloc.z).max().unwrap());
+            RegionPos::new(min_x + (max_x - min_x) / 2, min_z + (max_z - min_z) / 2)
+        },
+    };
+    println!("x {}..{}", centre.x - MAP_RADIUS, centre.x + MAP_RADIUS);
+    for z in centre.z - MAP_RADIUS..=centre.z + MAP_RADIUS {
+        let row: String = (centre.x - MAP_RADIUS..=centre.x + MAP_RADIUS).map(|x| {
+            let rloc = RegionPos::new(x, z);
+            match (regions.contains(&rloc), rloc == centre) {
+                (_, true) => '@',
+                (false, _) => '.',
+                (true, _) if image_path.join(image_name(&rloc)).exists() => '#',
+                (true, _) => '+',
+            }
+        }).collect();
+        println!("z {:>5} {}", z, row);
+    }
+}
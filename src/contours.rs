@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use image::RgbaImage;
+use log::debug;
+
+use crate::coords::{BlockPos, RegionPos};
+use crate::grid::blend;
+use crate::heightmap::{to_heights_name, Heights};
+
+/// Regions' heights kept loaded between tiles.
+const MAX_LOADED: usize = 64;
+/// Every this many contours one is drawn darker.
+const INDEX_EVERY: i32 = 5;
+
+/// Elevation isolines drawn onto published images from the heights kept in
+/// the cache path (`r.X.Z.heights`) by map renders with `--overlay contours`.
+/// Regions rendered without it have no heights yet and get no lines.
+#[derive(Clone)]
+pub struct ContourOverlay {
+    pub cache_path: PathBuf,
+    pub interval: i32,
+    loaded: Arc<Mutex<HashMap<RegionPos, Option<Arc<Heights>>>>>,
+}
+
+impl std::fmt::Debug for ContourOverlay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContourOverlay").field("cache_path", &self.cache_path).field("interval", &self.interval).finish()
+    }
+}
+
+impl ContourOverlay {
+    pub fn new(cache_path: PathBuf, interval: i32) -> Self {
+        ContourOverlay { cache_path, interval: interval.max(1), loaded: Default::default() }
+    }
+
+    fn heights(&self, rloc: &RegionPos) -> Option<Arc<Heights>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if !loaded.contains_key(rloc) && loaded.len() >= MAX_LOADED {
+            loaded.clear();
+        }
+        loaded.entry(*rloc).or_insert_with(|| {
+            Heights::load(&self.cache_path.join(to_heights_name(rloc))).map_err(|e| {
+                debug!("no heights for contours of {}: {}", rloc, e);
+            }).ok().map(Arc::new)
+        }).clone()
+    }
+
+    /// Draw onto `image`, whose top-left pixel is at block `origin` and whose
+    /// pixels each cover `scale` blocks: a line where the contour band changes
+    /// towards the pixel to the east or south. Ungenerated (transparent) pixels stay as they are.
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        // Bands of the pixel row, plus one pixel past the edge.
+        let bands = |y: i32| -> Vec<Option<i32>> {
+            (0..=width).map(|x| self.band(&origin.offset(x * scale, y * scale))).collect()
+        };
+        let mut row = bands(0);
+        for y in 0..height {
+            let below = bands(y + 1);
+            for x in 0..width as usize {
+                let band = match row[x] {
+                    Some(band) => band,
+                    None => continue,
+                };
+                // The line goes on the lower side, labelled by the higher band.
+                let crossed = [row[x + 1], below[x]].iter().flatten()
+                    .filter(|other| **other != band)
+                    .map(|other| band.max(*other))
+                    .max();
+                if let Some(line) = crossed {
+                    let alpha = if line % INDEX_EVERY == 0 { 0.7 } else { 0.4 };
+                    blend(image, x as u32, y as u32, [70, 45, 20], alpha);
+                }
+            }
+            row = below;
+        }
+    }
+
+    fn band(&self, block: &BlockPos) -> Option<i32> {
+        let heights = self.heights(&block.region())?;
+        let (x, z) = block.in_region();
+        heights.get(x, z).map(|y| (y as i32).div_euclid(self.interval))
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+
+/// Blocks along one edge of a chunk.
+pub const CHUNK_SIZE: i32 = 16;
+/// Chunks along one edge of a region.
+pub const REGION_CHUNKS: i32 = 32;
+/// Blocks (= pixels of a region image) along one edge of a region.
+pub const REGION_SIZE: i32 = CHUNK_SIZE * REGION_CHUNKS;
+/// Pixels along one edge of a pyramid tile.
+pub const TILE_SIZE: i32 = 256;
+
+/// A block column in world coordinates.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default)]
+pub struct BlockPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// A chunk in world coordinates (block coordinate / 16).
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// A region in world coordinates, as in `r.<x>.<z>.mca`.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default)]
+pub struct RegionPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// A 256×256 tile of the output pyramid.
+/// At `level` 0 one pixel is one block; every level above halves the
+/// resolution, so a tile covers `TILE_SIZE << level` blocks per edge.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default)]
+pub struct TilePos {
+    pub level: u32,
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Inclusive region rectangle (min, max).
+pub type RegionBounds = (RegionPos, RegionPos);
+
+/// Inclusive block rectangle (min, max).
+pub type BlockBounds = (BlockPos, BlockPos);
+
+/// Regions overlapping a block rectangle.
+pub fn region_bounds(blocks: &BlockBounds) -> RegionBounds {
+    (blocks.0.region(), blocks.1.region())
+}
+
+macro_rules! impl_xz {
+    ($t:ident) => {
+        impl $t {
+            pub const fn new(x: i32, z: i32) -> Self {
+                Self { x, z }
+            }
+            pub fn offset(&self, dx: i32, dz: i32) -> Self {
+                Self::new(self.x + dx, self.z + dz)
+            }
+        }
+        impl From<(i32, i32)> for $t {
+            fn from(tuple: (i32, i32)) -> Self {
+                Self::new(tuple.0, tuple.1)
+            }
+        }
+        impl Add for $t {
+            type Output = Self;
+            fn add(self, other: Self) -> Self {
+                Self::new(self.x + other.x, self.z + other.z)
+            }
+        }
+        impl Sub for $t {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                Self::new(self.x - other.x, self.z - other.z)
+            }
+        }
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "({}, {})", self.x, self.z)
+            }
+        }
+    };
+}
+
+impl_xz!(BlockPos);
+impl_xz!(ChunkPos);
+impl_xz!(RegionPos);
+
+impl BlockPos {
+    pub fn chunk(&self) -> ChunkPos {
+        ChunkPos::new(self.x.div_euclid(CHUNK_SIZE), self.z.div_euclid(CHUNK_SIZE))
+    }
+    pub fn region(&self) -> RegionPos {
+        self.chunk().region()
+    }
+    /// Pixel inside the region image, 0..512 on both axes.
+    pub fn in_region(&self) -> (usize, usize) {
+        (self.x.rem_euclid(REGION_SIZE) as usize, self.z.rem_euclid(REGION_SIZE) as usize)
+    }
+    pub fn in_bounds(&self, bounds: &BlockBounds) -> bool {
+        bounds.0.x <= self.x && self.x <= bounds.1.x && bounds.0.z <= self.z && self.z <= bounds.1.z
+    }
+}
+
+impl ChunkPos {
+    pub fn region(&self) -> RegionPos {
+        RegionPos::new(self.x.div_euclid(REGION_CHUNKS), self.z.div_euclid(REGION_CHUNKS))
+    }
+    /// Position inside the region file, 0..32 on both axes.
+    pub fn local(&self) -> (usize, usize) {
+        (self.x.rem_euclid(REGION_CHUNKS) as usize, self.z.rem_euclid(REGION_CHUNKS) as usize)
+    }
+    /// North-west block of the chunk.
+    pub fn block_origin(&self) -> BlockPos {
+        BlockPos::new(self.x * CHUNK_SIZE, self.z * CHUNK_SIZE)
+    }
+}
+
+impl RegionPos {
+    /// Chunk at a region-local position (0..32).
+    pub fn chunk(&self, local_x: usize, local_z: usize) -> ChunkPos {
+        debug_assert!(local_x < REGION_CHUNKS as usize && local_z < REGION_CHUNKS as usize);
+        ChunkPos::new(self.x * REGION_CHUNKS + local_x as i32, self.z * REGION_CHUNKS + local_z as i32)
+    }
+    /// North-west block of the region.
+    pub fn block_origin(&self) -> BlockPos {
+        BlockPos::new(self.x * REGION_SIZE, self.z * REGION_SIZE)
+    }
+    pub fn contains_chunk(&self, chunk: &ChunkPos) -> bool {
+        chunk.region() == *self
+    }
+    /// All 1024 chunks of the region, row by row.
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> {
+        let region = *self;
+        (0..REGION_CHUNKS as usize).flat_map(move |z| (0..REGION_CHUNKS as usize).map(move |x| region.chunk(x, z)))
+    }
+    /// Region coordinates as fastanvil expects them.
+    pub fn to_anvil(&self) -> (fastanvil::RCoord, fastanvil::RCoord) {
+        (fastanvil::RCoord(self.x as isize), fastanvil::RCoord(self.z as isize))
+    }
+    pub fn in_bounds(&self, bounds: &RegionBounds) -> bool {
+        bounds.0.x <= self.x && self.x <= bounds.1.x && bounds.0.z <= self.z && self.z <= bounds.1.z
+    }
+    /// Block of the region closest to `block` (`block` itself if inside).
+    pub fn nearest_block(&self, block: &BlockPos) -> BlockPos {
+        let origin = self.block_origin();
+        BlockPos::new(
+            block.x.clamp(origin.x, origin.x + REGION_SIZE - 1),
+            block.z.clamp(origin.z, origin.z + REGION_SIZE - 1))
+    }
+    /// Tiles at `level` overlapping this region.
+    pub fn tiles(&self, level: u32) -> Vec<TilePos> {
+        let origin = self.block_origin();
+        let first = TilePos::containing(&origin, level);
+        let last = TilePos::containing(&origin.offset(REGION_SIZE - 1, REGION_SIZE - 1), level);
+        (first.z..=last.z)
+            .flat_map(|z| (first.x..=last.x).map(move |x| TilePos { level, x, z }))
+            .collect()
+    }
+}
+
+impl TilePos {
+    /// Blocks covered by one edge of a tile at `level`.
+    pub fn span(level: u32) -> i32 {
+        TILE_SIZE << level
+    }
+    pub fn containing(block: &BlockPos, level: u32) -> Self {
+        let span = Self::span(level);
+        TilePos { level, x: block.x.div_euclid(span), z: block.z.div_euclid(span) }
+    }
+    /// North-west block of the tile.
+    pub fn block_origin(&self) -> BlockPos {
+        let span = Self::span(self.level);
+        BlockPos::new(self.x * span, self.z * span)
+    }
+    /// The four tiles one level down, ordered NW, NE, SW, SE.
+    pub fn children(&self) -> [TilePos; 4] {
+        assert!(self.level > 0, "level 0 tiles have no children");
+        let level = self.level - 1;
+        let (x, z) = (self.x * 2, self.z * 2);
+        [
+            TilePos { level, x, z },
+            TilePos { level, x: x + 1, z },
+            TilePos { level, x, z: z + 1 },
+            TilePos { level, x: x + 1, z: z + 1 },
+        ]
+    }
+    /// Regions overlapping this tile.
+    pub fn regions(&self) -> Vec<RegionPos> {
+        let origin = self.block_origin();
+        let span = Self::span(self.level);
+        let first = origin.region();
+        let last = origin.offset(span - 1, span - 1).region();
+        (first.z..=last.z)
+            .flat_map(|z| (first.x..=last.x).map(move |x| RegionPos::new(x, z)))
+            .collect()
+    }
+}
+
+impl fmt::Display for TilePos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.level, self.x, self.z)
+    }
+}
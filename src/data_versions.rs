@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::mpsc::channel;
+use fastanvil::Region;
+use log::debug;
+use serde::Deserialize;
+use threadpool::ThreadPool;
+
+use crate::coords::{RegionPos, REGION_CHUNKS};
+use crate::dimension::scan_region_files;
+use crate::error::Result;
+
+/// First DataVersion of each release, oldest first.
+pub const RELEASES: &[(&str, i32)] = &[
+    ("1.9", 169),
+    ("1.10", 510),
+    ("1.11", 819),
+    ("1.12", 1139),
+    ("1.13", 1519),
+    ("1.14", 1952),
+    ("1.15", 2225),
+    ("1.16", 2566),
+    ("1.17", 2724),
+    ("1.18", 2860),
+    ("1.19", 3105),
+    ("1.20", 3463),
+    ("1.20.5", 3837),
+    ("1.21", 3953),
+];
+
+/// Release a DataVersion belongs to, "before 1.9" for chunks without one.
+pub fn release_of(version: Option<i32>) -> &'static str {
+    let version = match version {
+        Some(version) => version,
+        None => return "before 1.9",
+    };
+    RELEASES.iter().rev().find(|(_, first)| version >= *first).map_or("before 1.9", |(name, _)| name)
+}
+
+/// `--upgrade-to`: a release name from `RELEASES` or a DataVersion.
+pub fn parse_target(s: &str) -> std::result::Result<i32, String> {
+    RELEASES.iter().find(|(name, _)| *name == s).map(|(_, version)| *version)
+        .or_else(|| s.parse().ok())
+        .ok_or_else(|| format!("\"{}\" is neither a known release ({}) nor a DataVersion",
+            s, RELEASES.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")))
+}
+
+/// Only the DataVersion of a chunk: the rest of the NBT is skipped, not decoded.
+#[derive(Deserialize)]
+struct VersionNbt {
+    #[serde(rename = "DataVersion")]
+    data_version: Option<i32>,
+}
+
+/// Chunks of a region by DataVersion (None: stored without one, before 1.9).
+#[derive(Debug, Clone)]
+pub struct RegionVersions {
+    pub x: i32,
+    pub z: i32,
+    pub versions: BTreeMap<Option<i32>, usize>,
+    /// Chunks that could not be read.
+    pub unreadable: usize,
+}
+
+impl RegionVersions {
+    /// Chunks older than `target`, which the game converts when it loads them.
+    pub fn older_than(&self, target: i32) -> usize {
+        self.versions.iter().filter(|(version, _)| version.map_or(true, |v| v < target)).map(|(_, count)| count).sum()
+    }
+}
+
+fn read_region(rloc: &RegionPos, path: &Path) -> RegionVersions {
+    let mut versions = RegionVersions { x: rloc.x, z: rloc.z, versions: Default::default(), unreadable: 0 };
+    let mut region = match File::open(path).map_err(|e| e.to_string())
+        .and_then(|file| Region::from_stream(file).map_err(|e| e.to_string())) {
+        Ok(region) => region,
+        Err(e) => {
+            debug!("cannot open region {}: {}", rloc, e);
+            versions.unreadable = (REGION_CHUNKS * REGION_CHUNKS) as usize;
+            return versions;
+        }
+    };
+    for z in 0..REGION_CHUNKS as usize {
+        for x in 0..REGION_CHUNKS as usize {
+            match region.read_chunk(x, z) {
+                Ok(Some(data)) => match fastnbt::from_bytes::<VersionNbt>(&data) {
+                    Ok(chunk) => *versions.versions.entry(chunk.data_version).or_default() += 1,
+                    Err(e) => {
+                        debug!("cannot read the DataVersion of chunk {},{} of region {}: {}", x, z, rloc, e);
+                        versions.unreadable += 1;
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    debug!("cannot read chunk {},{} of region {}: {}", x, z, rloc, e);
+                    versions.unreadable += 1;
+                }
+            }
+        }
+    }
+    versions
+}
+
+/// DataVersions of every chunk of the dimension, per region, sorted by position.
+pub fn scan(dim_path: &Path, threads: usize) -> Result<Vec<RegionVersions>> {
+    let regions = scan_region_files(dim_path, None)?;
+    let pool = ThreadPool::new(threads.max(1));
+    let (sender, receiver) = channel();
+    for (rloc, path) in regions {
+        let sender = sender.clone();
+        pool.execute(move || {
+            let _ = sender.send(read_region(&rloc, &path));
+        });
+    }
+    drop(sender);
+    let mut found: Vec<RegionVersions> = receiver.iter().collect();
+    found.sort_by_key(|region| (region.x, region.z));
+    Ok(found)
+}
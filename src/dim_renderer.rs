@@ -1,256 +1,998 @@
-use fastanvil::{Region, RegionLoader, RegionFileLoader, JavaChunk, TopShadeRenderer, Chunk};
-use std::collections::{HashMap, HashSet};
+use fastanvil::{JavaChunk, TopShadeRenderer, Chunk};
+use std::collections::HashSet;
 use std::mem::drop;
-use std::sync::{Arc, Mutex, RwLock, mpsc::SyncSender};
-use log::{info, debug};
-use std::fs::File;
+use std::sync::{Arc, Mutex};
+use log::{info, debug, warn, error};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use threadpool::ThreadPool;
 use image::{ImageBuffer, Rgba};
 use slice_of_array::prelude::*;
-use crate::dimension::Dimension;
-use crate::update_detector::{RLoc, CLoc, r2r};
+use crate::dimension::{Dimension, RegionCheck};
+use crate::snapshot::{ShareRegion, WorldSnapshot};
+use crate::renderer::{CeilingChunk, SeabedChunk, ErrorStyle, ShadeHeight, ShadeMode, Shading, HeightSource, WaterStyle, chunk_height_mode, error_pattern, clear_water, darken_water, shade_chunk, tint_water, water_colour, water_depth};
+use crate::block_stats::{BlockStats, to_surface_name};
+use crate::highlight::{Highlight, to_highlight_name};
+use crate::skip_list::SkipList;
+use crate::journal::RunJournal;
+use crate::light::{ChunkLight, shade_night};
+use crate::block_entities::{BlockEntityCounts, tint_block_entities};
+use crate::heightmap::{Heights, HeightmapOptions, to_heights_name, to_heightmap_name};
+use crate::isometric::{RegionColumns, ViewDirection, to_isometric_name};
+use crate::coords::{BlockPos, ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+use crate::selection::Selection;
+use crate::chunk_cache::{ChunkCache, Eviction};
+use crate::indexed_png::PngEncoding;
+use crate::events::EventBus;
+use crate::error::{Error, Result};
+use crate::chunk_mask::{self, MaskDepth};
+use crate::scale::{FarScale, Scale};
+use crate::image_pool::{self, ImagePool, Previous};
+use crate::render_order::RenderOrder;
+use crate::scheduler::{self, Preemption};
+use crate::throttle::Throttle;
+use crate::readahead::Readahead;
+use crate::shutdown;
+use crate::render_report::{self, ChangedRegion, RegionReport, RegionStatus, RenderReport, REPORT_NAME};
 
-type ShareRegion = Arc<Mutex<Box<Region<File>>>>;
 type ChunkImageBuffer = [fastanvil::Rgba; 16*16];
 
-pub fn to_image_name(rloc: &RLoc) -> String {
-    format!("r.{:0}.{:0}.png", rloc.0, rloc.1)
+pub fn to_image_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.png", rloc.x, rloc.z)
 }
 
+pub fn to_sidecar_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.json", rloc.x, rloc.z)
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Grey out and darken pixels, used for unchanged chunks in comparison renders.
+fn dim_pixels(buf: &mut ChunkImageBuffer) {
+    for px in buf.iter_mut() {
+        let luma = (px[0] as u32 * 3 + px[1] as u32 * 6 + px[2] as u32) / 10;
+        let dimmed = (luma * 2 / 5) as u8;
+        px[0] = dimmed;
+        px[1] = dimmed;
+        px[2] = dimmed;
+    }
+}
+
+/// Make the pixels of a region image outside `mask` transparent.
+fn mask_pixels(buf: &mut [fastanvil::Rgba], rloc: &RegionPos, mask: &Selection, scale: Scale) {
+    let origin = rloc.block_origin();
+    let width = scale.region_pixels();
+    for (i, px) in buf.iter_mut().enumerate() {
+        let (x, z) = scale.block_at(i % width, i / width);
+        let block = origin.offset(x, z);
+        if !mask.contains(&block) {
+            *px = [0, 0, 0, 0];
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum RegionProgress {
+    /// Region headers and caches to read before rendering.
+    ScanBegin(usize),
+    /// Regions scanned since the last `ScanStep`.
+    ScanStep(usize),
+    ScanEnd,
     BeginAll(usize),
     EndAll,
-    Begin(RLoc, usize),
-    Step(RLoc),
-    // Error(RLoc),
-    End(RLoc),
+    Begin(RegionPos, usize),
+    /// Chunks of the region done since its last `Step`.
+    Step(RegionPos, usize),
+    /// A chunk could not be read or decoded; the rest of the region is still rendered.
+    Error(RegionPos, ChunkPos),
+    /// The region could not be written; its cache is left as it was. Followed by its `End`.
+    Failed(RegionPos, String),
+    /// Something about the region worth knowing that did not stop the run.
+    /// A region skipped this way gets no `Begin` or `End`.
+    Warning(RegionPos, String),
+    End(RegionPos),
+}
+
+/// Per-run rendering options.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Render the terrain under this Y instead of the top surface (Nether roof).
+    pub ceiling_y: Option<isize>,
+    /// Shade water columns by the water surface or by the seabed.
+    pub shade_height: ShadeHeight,
+    /// Draw water flat, darkened by depth, or with the floor showing through.
+    pub water: WaterStyle,
+    /// Relief shading of the map modes.
+    pub shading: Shading,
+    /// Whether the chunks' heightmaps are used (when they look right).
+    pub height_source: HeightSource,
+    /// Write surface Y images (`r.X.Z.height.png`) instead of the map.
+    pub heightmap: Option<HeightmapOptions>,
+    /// Write `r.X.Z.json` next to each region image listing the chunks rendered this run.
+    pub sidecar: bool,
+    /// Darken the map to night, lit by the block and sky light of each column.
+    pub night: bool,
+    /// Grey out the map and tint chunks by their block entity count.
+    pub block_entities: bool,
+    /// Leave the blocks outside this area transparent (--block-range, --chunk-range).
+    pub mask: Option<Selection>,
+    /// Encoding of the region images.
+    pub png_encoding: PngEncoding,
+    /// Write isometric views (`r.X.Z.iso.png`) seen from this side instead of the map.
+    pub isometric: Option<ViewDirection>,
+    /// Keep the surface Y of the map's columns in the cache path (`r.X.Z.heights`), for --overlay contours.
+    pub heights: bool,
+    /// Write `r.X.Z.mask.png` next to each region image marking the pixels of generated chunks.
+    pub chunk_mask: Option<MaskDepth>,
+    /// Pixels per block of the map modes' region images.
+    pub scale: Scale,
+    /// Lower scale of the regions far from the spawn and the players.
+    pub far_scale: Option<FarScale>,
+    /// What the map modes draw for chunks that cannot be read.
+    pub error_style: ErrorStyle,
 }
 
 struct DimensionRendererInner {
     image_path: PathBuf,
-    loader: RegionFileLoader,
-    dimension: Box<Dimension>,
-    regions: Arc<Mutex<HashMap<RLoc, ShareRegion>>>,
-    chunks: Arc<RwLock<HashMap<(RLoc, CLoc), Arc<JavaChunk>>>>,
+    options: RenderOptions,
+    block_stats: Option<BlockStats>,
+    highlight: Option<Highlight>,
+    /// Which regions `render_all` starts with, and the centre of a spiral.
+    order: RenderOrder,
+    spawn: BlockPos,
+    /// Shuffles regions the order ranks the same.
+    work_seed: u64,
+    skip_list: Option<SkipList>,
+    journal: Option<Arc<RunJournal>>,
+    preemption: Option<Arc<dyn Preemption>>,
+    throttle: Option<Arc<Throttle>>,
+    images: ImagePool,
+    /// Threads reading chunks ahead of the workers (0: the workers read them),
+    /// and how many regions they may get ahead.
+    io_threads: usize,
+    readahead: usize,
+    failed_chunks: Mutex<HashSet<ChunkPos>>,
+    /// Regions that could not be written, with the reason.
+    failed_regions: Mutex<Vec<(RegionPos, String)>>,
+    /// Regions left undrawn because the run was interrupted.
+    interrupted: Mutex<Vec<RegionPos>>,
+    snapshot: Arc<WorldSnapshot>,
+    chunks: Mutex<ChunkCache<CachedChunk>>,
+    /// Chunks drawn into their region, only kept for the sidecars.
+    drawn_chunks: Mutex<HashSet<ChunkPos>>,
+}
+
+/// A decoded chunk, with its light in night mode and its block entity
+/// counts in block entity mode.
+#[derive(Clone)]
+struct CachedChunk {
+    chunk: Arc<JavaChunk>,
+    light: Option<Arc<ChunkLight>>,
+    block_entities: Option<Arc<BlockEntityCounts>>,
 }
 
+/// Default budget of the decoded chunk cache.
+pub const DEFAULT_CHUNK_CACHE_MB: usize = 2048;
+
 pub struct DimensionRenderer {
     inner: Arc<DimensionRendererInner>,
 }
 
 impl DimensionRenderer {
-    fn get_region(inner: &DimensionRendererInner, rloc: &RLoc) -> Option<ShareRegion> {
-        let mut regions_l = inner.regions.lock().unwrap();
-
-        // Regionがtraitからstructに変わった。
-
-        // 0.24
-        // pub trait Region<C: Chunk>: Send + Sync {
-        //     fn chunk(&self, x: CCoord, z: CCoord) -> Option<C>;
-        // }
-        // 0.30
-        // pub struct Region<S> {
-        //     stream: S,
-        //     // last offset is always the next valid place to write a chunk.
-        //     offsets: Vec<u64>,
-        // }
-        //   pub fn read_chunk(&mut self, x: usize, z: usize) -> Result<Option<Vec<u8>>>
-        // 
-
-        regions_l.get(&rloc).map(|r| Arc::clone(&r)).or_else(|| {
-            debug!("region: {:?}", rloc);
-            let region_opt = inner.loader.region(r2r(rloc.0), r2r(rloc.1)).unwrap();
-            if let Some(region_unwrapped) = region_opt {
-                let region = Arc::new(Mutex::new(Box::new(region_unwrapped)));
-                regions_l.insert(rloc.clone(), Arc::clone(&region));
-                Some(region)
-            } else {
+    fn get_region(inner: &DimensionRendererInner, rloc: &RegionPos) -> Result<Option<ShareRegion>> {
+        inner.snapshot.region(rloc)
+    }
+
+    /// Between two chunks: yield to more urgent work, then to --throttle.
+    fn chunk_boundary(inner: &DimensionRendererInner) {
+        if let Some(preemption) = &inner.preemption {
+            preemption.chunk_boundary();
+        }
+        if let Some(throttle) = &inner.throttle {
+            throttle.chunk_done();
+        }
+    }
+
+    fn get_chunk(inner: &DimensionRendererInner, cloc: &ChunkPos) -> Option<Arc<JavaChunk>> {
+        Self::get_cached(inner, cloc).map(|cached| cached.chunk)
+    }
+
+    fn get_cached(inner: &DimensionRendererInner, cloc: &ChunkPos) -> Option<CachedChunk> {
+        if let Some(cached) = inner.chunks.lock().unwrap().get(cloc) {
+            return Some(cached);
+        }
+        let timestamp = inner.snapshot.dimension.chunk_timestamp(cloc);
+        if let Some(skip_list) = &inner.skip_list {
+            if skip_list.should_skip(cloc, timestamp) {
+                trace_region!(cloc.region(), "chunk {} skipped, it is on the broken chunk list", cloc);
                 return None;
             }
-        })
-    }
-
-    fn get_chunk(inner: &DimensionRendererInner, rloc: &RLoc, cloc: &CLoc) -> Option<Arc<JavaChunk>> {
-        let key = (rloc.clone(), cloc.clone());
-        let chunks_r = Arc::clone(&inner.chunks);
-        let chunks_rl = chunks_r.read().unwrap();
-        let chunk = chunks_rl.get(&key);
-        if let None = chunk {
-            drop(chunks_rl);
-            let mut chunks_wl = chunks_r.write().unwrap();
-            if let Some(chunk) = chunks_wl.get(&key) {
-                return Some(Arc::clone(&chunk));
-            }
-            let region = Self::get_region(inner, rloc);
-            let new_chunk_data = match region {
-                None => {
-                    debug!("None chunk!_1 {}, {}", cloc.0, cloc.1);
-                    return None
-                },
-                Some(region) => {
-                    region.lock().unwrap().read_chunk(cloc.0, cloc.1).unwrap()
-                }
-            };
-            let new_chunk: JavaChunk = match new_chunk_data {
-                None => {
-                    debug!("None chunk!_2 {}, {}", cloc.0, cloc.1);
-                    return None
-                }
-                Some(chunk) => { 
-                    JavaChunk::from_bytes(&chunk).unwrap()
+        }
+        // Read and decode without holding the cache lock so other workers
+        // are not stalled; if two workers race on one chunk, the first
+        // insert wins.
+        let region = Self::get_region(inner, &cloc.region());
+        let new_chunk_data = match region {
+            Err(e) => {
+                Self::chunk_failed(inner, cloc, timestamp, &e);
+                return None;
+            },
+            Ok(None) => {
+                debug!("None chunk!_1 {}", cloc);
+                return None
+            },
+            Ok(Some(region)) => {
+                let (x, z) = cloc.local();
+                let data = region.read_chunk(x, z);
+                match data {
+                    Ok(data) => data,
+                    Err(e) => {
+                        Self::chunk_failed(inner, cloc, timestamp, &e);
+                        return None;
+                    }
                 }
-            };
-            let new_insert_chunk = Arc::new(new_chunk);
-            chunks_wl.insert(key, Arc::clone(&new_insert_chunk));
+            }
+        };
+        let data = match new_chunk_data {
+            None => {
+                debug!("None chunk!_2 {}", cloc);
+                return None
+            }
+            Some(data) => data,
+        };
+        Self::decode_chunk(inner, cloc, timestamp, data, true)
+    }
 
-            return Some(new_insert_chunk);
+    /// Decode the NBT of a chunk and cache it. Failures are recorded when
+    /// `report` is set, otherwise left for a worker to find again.
+    fn decode_chunk(inner: &DimensionRendererInner, cloc: &ChunkPos, timestamp: u32, data: Vec<u8>, report: bool) -> Option<CachedChunk> {
+        if let Some(throttle) = &inner.throttle {
+            throttle.read(data.len());
         }
-        chunk.map(|c| Arc::clone(&c))
+        let light = if inner.options.night {
+            match ChunkLight::from_bytes(&data) {
+                Ok(light) => Some(Arc::new(light)),
+                Err(e) => {
+                    debug!("no light for chunk {}: {}", cloc, e);
+                    None
+                },
+            }
+        } else { None };
+        let block_entities = if inner.options.block_entities {
+            match BlockEntityCounts::from_bytes(&data) {
+                Ok(counts) => Some(Arc::new(counts)),
+                Err(e) => {
+                    debug!("no block entities for chunk {}: {}", cloc, e);
+                    None
+                },
+            }
+        } else { None };
+        let new_chunk = match JavaChunk::from_bytes(&data) {
+            Ok(chunk) => chunk,
+            Err(e) if report => {
+                Self::chunk_failed(inner, cloc, timestamp, &e);
+                return None;
+            },
+            Err(e) => {
+                debug!("chunk {} read ahead cannot be decoded: {}", cloc, e);
+                return None;
+            }
+        };
+        if let Some(skip_list) = &inner.skip_list {
+            skip_list.record_success(cloc);
+        }
+        let cached = CachedChunk { chunk: Arc::new(new_chunk), light, block_entities };
+        // Decoded chunks take roughly twice their NBT size.
+        Some(inner.chunks.lock().unwrap().insert(*cloc, cached, data.len() * 2))
     }
 
-    pub fn new(dimension: Dimension, image_path: &Path) -> Self {
+    /// Read and decode the chunks `rloc`'s render will ask for.
+    fn read_ahead(inner: &DimensionRendererInner, rloc: &RegionPos) {
+        let clocs: Vec<ChunkPos> = match (inner.options.isometric, inner.snapshot.dimension.render_regions.get(rloc)) {
+            (Some(_), _) => rloc.chunks().collect(),
+            (None, Some(clocs)) => clocs.iter().copied().collect(),
+            (None, None) => return,
+        };
+        let region = match inner.snapshot.region(rloc) {
+            Ok(Some(region)) => region,
+            Ok(None) => return,
+            Err(e) => {
+                debug!("cannot read ahead region {}: {}", rloc, e);
+                return;
+            }
+        };
+        for cloc in clocs {
+            if inner.chunks.lock().unwrap().contains(&cloc) {
+                continue;
+            }
+            let timestamp = inner.snapshot.dimension.chunk_timestamp(&cloc);
+            if inner.skip_list.as_ref().is_some_and(|skip_list| skip_list.should_skip(&cloc, timestamp)) {
+                continue;
+            }
+            let (x, z) = cloc.local();
+            if let Ok(Some(data)) = region.read_chunk(x, z) {
+                Self::decode_chunk(inner, &cloc, timestamp, data, false);
+            }
+        }
+    }
+
+    fn chunk_failed(inner: &DimensionRendererInner, cloc: &ChunkPos, timestamp: u32, e: &dyn std::fmt::Display) {
+        error!("chunk {} cannot be read: {}", cloc, e);
+        trace_region!(cloc.region(), "chunk {} failed: {}", cloc, e);
+        inner.failed_chunks.lock().unwrap().insert(*cloc);
+        if let Some(skip_list) = &inner.skip_list {
+            skip_list.record_failure(cloc, timestamp);
+        }
+    }
+
+    pub fn new(dimension: Dimension, image_path: &Path, options: RenderOptions) -> Self {
+        Self::from_snapshot(WorldSnapshot::new(dimension), image_path, options)
+    }
+
+    /// A renderer sharing `snapshot` with others, e.g. several modes of one
+    /// scan rendered concurrently. Every renderer of a snapshot must be made
+    /// before any of them starts rendering, and each must render.
+    pub fn from_snapshot(snapshot: Arc<WorldSnapshot>, image_path: &Path, options: RenderOptions) -> Self {
+        snapshot.claim();
         DimensionRenderer {
             inner: Arc::new(DimensionRendererInner {
                 image_path: PathBuf::from(image_path),
-                loader: RegionFileLoader::new(dimension.dim_path.clone()),
-                dimension: Box::new(dimension),
-                regions: Default::default(),
-                chunks: Default::default(),
+                options: options,
+                block_stats: None,
+                highlight: None,
+                order: RenderOrder::default(),
+                spawn: BlockPos::new(0, 0),
+                work_seed: 0,
+                skip_list: None,
+                journal: None,
+                preemption: None,
+                throttle: None,
+                images: Default::default(),
+                io_threads: 0,
+                readahead: 0,
+                failed_chunks: Default::default(),
+                failed_regions: Default::default(),
+                interrupted: Default::default(),
+                snapshot,
+                chunks: Mutex::new(ChunkCache::new(DEFAULT_CHUNK_CACHE_MB << 20, Eviction::Lru)),
+                drawn_chunks: Default::default(),
             }),
         }
     }
 
-    fn render_region(inner: &DimensionRendererInner, rloc: &RLoc, buf: Vec<fastanvil::Rgba>, palette: Arc<fastanvil::RenderedPalette>, sender: SyncSender<RegionProgress>) -> Vec<fastanvil::Rgba> {
-        let clocs = if let Some(clocs) = inner.dimension.render_regions.get(rloc) {
+    fn render_region(inner: &DimensionRendererInner, rloc: &RegionPos, buf_l: &mut [fastanvil::Rgba], palette: Arc<fastanvil::RenderedPalette>, nocache: bool, events: &EventBus) {
+        let clocs = if let Some(clocs) = inner.snapshot.dimension.render_regions.get(rloc) {
             clocs
         } else {
-            return buf;
+            return;
         };
-        sender.send(RegionProgress::Begin(rloc.clone(), clocs.len())).unwrap();
+        events.publish(RegionProgress::Begin(*rloc, clocs.len()));
         
         info!("render_region clocs:{:?}", clocs.len());
-        let mut buf = buf;
-        let buf_l = buf.as_mut_slice();
+        let scale = Self::region_scale(inner, rloc);
+        let mut surface = inner.block_stats.as_ref().map(|stats| stats.load_surface(&inner.snapshot.dimension.cache_path, rloc));
+        let heights_path = inner.snapshot.dimension.cache_path.join(to_heights_name(rloc));
+        let mut heights = if !inner.options.heights { None }
+            else if nocache { Some(Heights::empty()) }
+            else { Some(Heights::load(&heights_path).unwrap_or_else(|_| Heights::empty())) };
+        let mut highlight_image = inner.highlight.as_ref().map(|highlight| if nocache {
+            image::RgbaImage::new(REGION_SIZE as u32, REGION_SIZE as u32)
+        } else {
+            highlight.load_image(&inner.image_path, rloc)
+        });
+        // Transparent water is drawn from the floor up, like the seabed shading.
+        let water = match (inner.options.shade_height, inner.options.water) {
+            (ShadeHeight::Seabed, _) | (_, WaterStyle::Transparent) => Some(water_colour(&palette)),
+            (ShadeHeight::Water, _) => None,
+        };
         for cloc in clocs {
             // if cloc.0 != 15 || cloc.1 != 16 { continue; }
-            let renderer = TopShadeRenderer::new(&*palette, fastanvil::HeightMode::Trust);
-            if let Some(chunk_buf) = Self::render_chunk(&inner, &renderer, &rloc, &cloc) {
-                for y in 0..16 {
-                    let px = (cloc.0 * 16) as usize;
-                    let py = (cloc.1 * 16 + y) as usize;
-
-                    unsafe {
-                        std::ptr::copy_nonoverlapping(
-                            &chunk_buf[(y * 16) as usize],
-                            &mut buf_l[py * 512 + px],
-                            16);
+            if inner.snapshot.dimension.is_cleared(cloc) {
+                let (cx, cz) = cloc.local();
+                scale.composite(buf_l, &[[0u8; 4]; 16*16], cx, cz);
+                if let Some(heights) = heights.as_mut() {
+                    heights.clear_chunk(cloc);
+                }
+            }
+            if let Some(mut chunk_buf) = Self::render_chunk(inner, &palette, cloc, water, heights.as_mut()) {
+                if inner.snapshot.dimension.is_unchanged(cloc) {
+                    dim_pixels(&mut chunk_buf);
+                }
+                let (cx, cz) = cloc.local();
+                scale.composite(buf_l, &chunk_buf, cx, cz);
+                if let (Some(stats), Some(surface)) = (&inner.block_stats, surface.as_mut()) {
+                    if let Some(chunk) = Self::get_chunk(inner, cloc) {
+                        stats.record_chunk(surface, cloc, &chunk, inner.options.ceiling_y);
+                    }
+                }
+                if let (Some(highlight), Some(image)) = (&inner.highlight, highlight_image.as_mut()) {
+                    if let Some(chunk) = Self::get_chunk(inner, cloc) {
+                        highlight.record_chunk(image, cloc, &*chunk);
                     }
                 }
+                if inner.options.sidecar {
+                    inner.drawn_chunks.lock().unwrap().insert(*cloc);
+                }
+            }
+            if inner.failed_chunks.lock().unwrap().contains(cloc) {
+                events.publish(RegionProgress::Error(*rloc, *cloc));
+                let (cx, cz) = cloc.local();
+                match inner.options.error_style {
+                    ErrorStyle::Keep => (),
+                    ErrorStyle::Blank => scale.composite(buf_l, &[[0u8; 4]; 16*16], cx, cz),
+                    ErrorStyle::Pattern => scale.composite(buf_l, &error_pattern(), cx, cz),
+                }
             }
-            sender.send(RegionProgress::Step(rloc.clone())).unwrap();
+            events.publish(RegionProgress::Step(*rloc, 1));
+            Self::chunk_boundary(inner);
         }
-        return buf;
-    }
-
-    fn render_chunk<'b>(inner: &DimensionRendererInner, renderer: &TopShadeRenderer<'b, fastanvil::RenderedPalette>, rloc: &RLoc, cloc: &CLoc) -> Option<ChunkImageBuffer> {
-        let chunk = Self::get_chunk(inner, rloc, &cloc);
-        if let None = chunk {
-            debug!("render_chunk chunk=None, {}, {}", cloc.0, cloc.1);
-            return None;
+        if let Some(mask) = &inner.options.mask {
+            mask_pixels(buf_l, rloc, mask, scale);
+        }
+        if let Some(surface) = surface {
+            if inner.snapshot.dimension.cache_writable() {
+                if let Err(e) = surface.save(&inner.snapshot.dimension.cache_path.join(to_surface_name(rloc))) {
+                    warn!("cannot write surface cache for region {}: {}", rloc, e);
+                }
+            }
         }
+        if let Some(image) = highlight_image {
+            let path = inner.image_path.join(to_highlight_name(rloc));
+            let written = PngEncoding::Plain.encode(&image::DynamicImage::ImageRgba8(image))
+                .and_then(|data| Ok(inner.snapshot.dimension.durability.write(&path, &data)?));
+            if let Err(e) = written {
+                warn!("cannot write {}: {}", path.display(), e);
+            }
+        }
+        if let Some(mut heights) = heights {
+            if let Some(mask) = &inner.options.mask {
+                heights.clear_outside(rloc, mask);
+            }
+            if inner.snapshot.dimension.cache_writable() {
+                if let Err(e) = heights.save(&heights_path) {
+                    warn!("cannot write heights cache for region {}: {}", rloc, e);
+                }
+            }
+        }
+    }
 
-        // get north chunk
-        let chunk_north = if cloc.1 == 0 {
-            Self::get_chunk(inner, &rloc.offset(0, -1), &cloc.offset(0, 31).unwrap())
+    /// Heightmap counterpart of `render_region`: update the region's cached
+    /// heights with its changed chunks and write them as an image.
+    fn render_region_heights(inner: &DimensionRendererInner, rloc: &RegionPos, options: &HeightmapOptions, nocache: bool, events: &EventBus) -> Result<()> {
+        let clocs = if let Some(clocs) = inner.snapshot.dimension.render_regions.get(rloc) {
+            clocs
         } else {
-            Self::get_chunk(inner, rloc, &cloc.offset(0, -1).unwrap())
+            return Ok(());
         };
+        events.publish(RegionProgress::Begin(*rloc, clocs.len()));
 
-        let chunk = &*chunk.unwrap();
-        if let Some(chunk_north) = chunk_north {
-            return Some(renderer.render(chunk, Some(&*chunk_north)));
+        let heights_path = inner.snapshot.dimension.cache_path.join(to_heights_name(rloc));
+        let mut heights = if nocache { Heights::empty() } else {
+            Heights::load(&heights_path).unwrap_or_else(|e| {
+                debug!("no heights cache for {}: {}", rloc, e);
+                Heights::empty()
+            })
+        };
+        for cloc in clocs {
+            if inner.snapshot.dimension.is_cleared(cloc) {
+                heights.clear_chunk(cloc);
+            }
+            if let Some(chunk) = Self::get_chunk(inner, cloc) {
+                let mode = Self::height_mode(inner, cloc, &*chunk);
+                match inner.options.ceiling_y {
+                    Some(cut_y) => heights.record_chunk(cloc, &CeilingChunk::new(&*chunk, cut_y), mode),
+                    None => heights.record_chunk(cloc, &*chunk, mode),
+                }
+                if inner.options.sidecar {
+                    inner.drawn_chunks.lock().unwrap().insert(*cloc);
+                }
+            }
+            if inner.failed_chunks.lock().unwrap().contains(cloc) {
+                events.publish(RegionProgress::Error(*rloc, *cloc));
+            }
+            events.publish(RegionProgress::Step(*rloc, 1));
+            Self::chunk_boundary(inner);
+        }
+        if let Some(mask) = &inner.options.mask {
+            heights.clear_outside(rloc, mask);
+        }
+        let write_path = inner.image_path.join(to_heightmap_name(rloc));
+        Self::save_image(inner, rloc, &write_path, &heights.to_image(options), PngEncoding::Plain)?;
+
+        // After the image, like the timestamp cache.
+        if inner.snapshot.dimension.cache_writable() {
+            if let Err(e) = heights.save(&heights_path) {
+                warn!("cannot write heights cache for region {}: {}", rloc, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Isometric counterpart of `render_region`. The view of a region depends
+    /// on all its columns, so every chunk is read and the image redrawn.
+    fn render_region_isometric(inner: &DimensionRendererInner, rloc: &RegionPos, palette: &fastanvil::RenderedPalette, direction: ViewDirection, events: &EventBus) -> Result<()> {
+        let clocs = if let Some(clocs) = inner.snapshot.dimension.render_regions.get(rloc) {
+            clocs
         } else {
-            return Some(renderer.render(chunk, None));
+            return Ok(());
         };
+        events.publish(RegionProgress::Begin(*rloc, clocs.len()));
+
+        let mut columns = RegionColumns::empty();
+        for cloc in rloc.chunks() {
+            if inner.options.mask.as_ref().is_some_and(|mask| !mask.overlaps_chunk(&cloc)) {
+                continue;
+            }
+            let changed = clocs.contains(&cloc);
+            if let Some(chunk) = Self::get_chunk(inner, &cloc) {
+                let mode = Self::height_mode(inner, &cloc, &*chunk);
+                match inner.options.ceiling_y {
+                    Some(cut_y) => columns.record_chunk(&cloc, &CeilingChunk::new(&*chunk, cut_y), palette, mode),
+                    None => columns.record_chunk(&cloc, &*chunk, palette, mode),
+                }
+                if changed && inner.options.sidecar {
+                    inner.drawn_chunks.lock().unwrap().insert(cloc);
+                }
+            }
+            if changed {
+                if inner.failed_chunks.lock().unwrap().contains(&cloc) {
+                    events.publish(RegionProgress::Error(*rloc, cloc));
+                }
+                events.publish(RegionProgress::Step(*rloc, 1));
+            }
+            Self::chunk_boundary(inner);
+        }
+        let write_path = inner.image_path.join(to_isometric_name(rloc));
+        let image = image::DynamicImage::ImageRgba8(columns.render(direction));
+        Self::save_image(inner, rloc, &write_path, &image, inner.options.png_encoding)
+    }
+
+    fn save_image(inner: &DimensionRendererInner, rloc: &RegionPos, write_path: &Path, image: &image::DynamicImage, encoding: PngEncoding) -> Result<()> {
+        info!("{:?}", write_path.to_str());
+        trace_region!(rloc, "image saved to {}", write_path.display());
+        inner.snapshot.dimension.durability.save_png(write_path, image, encoding)
+            .map_err(|e| Error::WriteImage { path: write_path.to_path_buf(), message: e.to_string() })
+    }
+
+    fn height_mode(inner: &DimensionRendererInner, cloc: &ChunkPos, chunk: &JavaChunk) -> fastanvil::HeightMode {
+        let mode = chunk_height_mode(chunk, inner.options.height_source);
+        if matches!(mode, fastanvil::HeightMode::Calculate) && inner.options.height_source == HeightSource::Trust {
+            trace_region!(cloc.region(), "chunk {}: heightmap doesn't match the blocks, heights calculated", cloc);
+        }
+        mode
     }
 
-    fn load_cached_image(inner: &DimensionRendererInner, rloc: &RLoc) -> Vec<fastanvil::Rgba> {
-        let image = if let Ok(image) = image::open(inner.image_path.join(to_image_name(rloc))) {
-            image
+    /// Draw a chunk, and record the surface Y of the drawn columns into `heights`.
+    fn render_chunk(inner: &DimensionRendererInner, palette: &fastanvil::RenderedPalette, cloc: &ChunkPos, water: Option<fastanvil::Rgba>, heights: Option<&mut Heights>) -> Option<ChunkImageBuffer> {
+        let cached = match Self::get_cached(inner, cloc) {
+            Some(cached) => cached,
+            None => {
+                debug!("render_chunk chunk=None, {}", cloc);
+                trace_region!(cloc.region(), "chunk {} has no data, not drawn", cloc);
+                return None;
+            }
+        };
+
+        // get north chunk (may belong to the north region)
+        let north = cloc.offset(0, -1);
+        let chunk_north = Self::get_chunk(inner, &north);
+        trace_region!(cloc.region(), "chunk {} drawn, north neighbour {} {}", cloc, north,
+            if chunk_north.is_some() { "found" } else { "missing (no north shading)" });
+        // The west one only shades with --shade full.
+        let shading = &inner.options.shading;
+        let chunk_west = if shading.mode == ShadeMode::Full { Self::get_chunk(inner, &cloc.offset(-1, 0)) } else { None };
+
+        let CachedChunk { chunk, light, block_entities } = cached;
+        let chunk = &*chunk;
+        let mode = Self::height_mode(inner, cloc, chunk);
+        let renderer = TopShadeRenderer::new(palette, mode);
+        let mut buf = if let Some(cut_y) = inner.options.ceiling_y {
+            let chunk = CeilingChunk::new(chunk, cut_y);
+            let chunk_north = chunk_north.as_deref().map(|c| CeilingChunk::new(c, cut_y));
+            let chunk_west = chunk_west.as_deref().map(|c| CeilingChunk::new(c, cut_y));
+            let mut buf = shade_chunk(&renderer, palette, mode, &chunk, chunk_north.as_ref(), chunk_west.as_ref(), shading);
+            if let Some(light) = &light {
+                shade_night(&mut buf, &chunk, light);
+            }
+            if let Some(heights) = heights {
+                heights.record_chunk(cloc, &chunk, mode);
+            }
+            buf
+        } else if let Some(water) = water {
+            let chunk = SeabedChunk::new(chunk, mode);
+            let chunk_north = chunk_north.as_deref().map(|c| SeabedChunk::new(c, mode));
+            let chunk_west = chunk_west.as_deref().map(|c| SeabedChunk::new(c, mode));
+            let mut buf = shade_chunk(&renderer, palette, mode, &chunk, chunk_north.as_ref(), chunk_west.as_ref(), shading);
+            let chunk_size = CHUNK_SIZE as usize;
+            let tint = if inner.options.water == WaterStyle::Transparent { clear_water } else { tint_water };
+            for (i, px) in buf.iter_mut().enumerate() {
+                tint(px, water, chunk.water_depth(i % chunk_size, i / chunk_size));
+            }
+            if let Some(light) = &light {
+                shade_night(&mut buf, &chunk, light);
+            }
+            if let Some(heights) = heights {
+                heights.record_chunk(cloc, &chunk, mode);
+            }
+            buf
         } else {
-            return vec![[0u8;4]; 512*512];
+            let mut buf = shade_chunk(&renderer, palette, mode, chunk, chunk_north.as_deref(), chunk_west.as_deref(), shading);
+            if inner.options.water == WaterStyle::Depth {
+                let chunk_size = CHUNK_SIZE as usize;
+                for (i, px) in buf.iter_mut().enumerate() {
+                    darken_water(px, water_depth(chunk, i % chunk_size, i / chunk_size, mode));
+                }
+            }
+            if let Some(light) = &light {
+                shade_night(&mut buf, chunk, light);
+            }
+            if let Some(heights) = heights {
+                heights.record_chunk(cloc, chunk, mode);
+            }
+            buf
         };
+        if let Some(counts) = &block_entities {
+            tint_block_entities(&mut buf, counts);
+        }
+        Some(buf)
+    }
+
+    /// Scale of the map image of `rloc`: --far-scale when it is far out.
+    fn region_scale(inner: &DimensionRendererInner, rloc: &RegionPos) -> Scale {
+        match &inner.options.far_scale {
+            Some(far) if far.is_far(rloc) => far.scale,
+            _ => inner.options.scale,
+        }
+    }
 
-        use slice_of_array::prelude::*;
-        match image {
-            image::DynamicImage::ImageRgba8(image) => {
-                return Vec::from(image.into_vec().as_slice().nest::<[_; 4]>());
+    /// Fill `buf` with the previous image of the region, or blank.
+    fn load_cached_image(inner: &DimensionRendererInner, rloc: &RegionPos, buf: &mut [u8]) {
+        let size = Self::region_scale(inner, rloc).region_pixels();
+        let path = inner.image_path.join(to_image_name(rloc));
+        match image_pool::read_into(&path, size, buf) {
+            Previous::Read => {
+                trace_region!(rloc, "drawing over the previous image");
+                return;
             },
-            _ => {
-                return vec![[0u8;4]; 512*512];
-            }
-        }
-    }
-
-    pub fn render_all(&self, palette: Arc<fastanvil::RenderedPalette>, sender: SyncSender<RegionProgress>, nocache: bool) {
-        use std::iter::FromIterator;
-        sender.send(RegionProgress::BeginAll(self.inner.dimension.render_regions.iter().fold(0, |c, (_, v)| c + v.len()))).unwrap();
-        let regions = self.inner.dimension.render_regions.keys();
-        let regions_remind = HashSet::<RLoc>::from_iter(regions.clone().map(Clone::clone).collect::<Vec<_>>());
-        let regions_remind = Arc::new(Mutex::new(regions_remind));
-        let pool = ThreadPool::new(1);
-        for rloc in regions {
-            let inner = Arc::clone(&self.inner);
-            let rloc = rloc.clone();
-            let regions_remind = Arc::clone(&regions_remind);
-            let palette = Arc::clone(&palette);
-            let sender = sender.clone();
-            pool.execute(move || {
-                // Load cached image.
-                let cached_image = if nocache { vec![[0u8;4]; 512*512] }
-                    else { Self::load_cached_image(&inner, &rloc) };
-                // Render the region
-                let new_image = Self::render_region(&inner, &rloc, cached_image, palette, sender.clone());
-
-                // Unload chunks.
-                let north_region = rloc.offset(0, -1);
-                let south_region = rloc.offset(0, 1);
-                let exist_north: bool;
-                let exist_south: bool;
-                {
-                    let mut regions_remind_l = regions_remind.lock().unwrap();
-                    regions_remind_l.remove(&rloc);
-                    exist_north = regions_remind_l.contains(&north_region);
-                    exist_south = regions_remind_l.contains(&south_region);
+            Previous::Missing => trace_region!(rloc, "no previous image, starting blank"),
+            // Moved across --far-distance: the old image stands in until its chunks change.
+            Previous::OtherSize if inner.options.far_scale.is_some() => {
+                if let Ok(image) = image::open(&path) {
+                    trace_region!(rloc, "drawing over the previous image, rescaled");
+                    let image = image::imageops::resize(&image.into_rgba8(), size as u32, size as u32, image::imageops::FilterType::Triangle);
+                    buf.copy_from_slice(image.as_raw());
+                    return;
                 }
+            },
+            Previous::OtherSize => warn!("region {}: the previous image has another --scale, starting blank (use --cache-mode refresh to redraw every chunk)", rloc),
+            Previous::NotRgba => trace_region!(rloc, "previous image is not RGBA8, starting blank"),
+        }
+        buf.fill(0);
+    }
 
-                {
-                    let mut chunks_l = inner.chunks.write().unwrap();
-                    chunks_l.retain(|(c_rloc, c_cloc), _| {
-                            !(
-                                (c_rloc == &rloc && c_cloc.1 < 15) || // current region chunk exept bottom
-                                (c_rloc == &rloc && c_cloc.1 == 15 && !exist_south) || // bottom
-                                (c_rloc == &north_region && !exist_north)
-                            )
-                        });
-                }
-                // save region image
-                let flat_buf: &[u8] = new_image.as_slice().flat();
-                let bufvec: Vec<u8> = Vec::from(flat_buf);
-                let write_path = inner.image_path.join(to_image_name(&rloc));
-                let imgbuf: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(512, 512, bufvec).unwrap();
+    /// Write the chunk mask of `rloc` next to its image.
+    fn save_chunk_mask(inner: &DimensionRendererInner, rloc: &RegionPos, depth: MaskDepth) {
+        let mask_path = inner.image_path.join(chunk_mask::to_mask_name(rloc));
+        let generated = match inner.snapshot.dimension.generated_chunks(rloc) {
+            Some(generated) => generated,
+            // Its header wasn't read; the mask it has is kept rather than blanked.
+            None => {
+                trace_region!(rloc, "not scanned, chunk mask left as it is");
+                return;
+            },
+        };
+        let scale = Self::region_scale(inner, rloc);
+        let written = chunk_mask::encode(rloc, &generated, inner.options.mask.as_ref(), depth, scale)
+            .and_then(|data| Ok(inner.snapshot.dimension.durability.write(&mask_path, &data)?));
+        if let Err(e) = written {
+            warn!("cannot write {}: {}", mask_path.display(), e);
+        }
+    }
 
-                info!("{:?}", write_path.to_str());
-                imgbuf.save(write_path).unwrap();
-                
-                // save cache
-                inner.dimension.save_cache(&rloc).unwrap();
+    /// Name of the image `render_all` writes for `rloc` in this mode.
+    fn output_name(inner: &DimensionRendererInner, rloc: &RegionPos) -> String {
+        if inner.options.heightmap.is_some() {
+            to_heightmap_name(rloc)
+        } else if inner.options.isometric.is_some() {
+            to_isometric_name(rloc)
+        } else {
+            to_image_name(rloc)
+        }
+    }
 
-                sender.send(RegionProgress::End(rloc.clone())).unwrap();
-            });
+    /// Provenance of the chunks rendered into a region this run, for the
+    /// `r.X.Z.json` sidecar.
+    fn sidecar(inner: &DimensionRendererInner, rloc: &RegionPos) -> Option<serde_json::Value> {
+        if !inner.options.sidecar {
+            return None;
+        }
+        let mut clocs: Vec<&ChunkPos> = inner.snapshot.dimension.render_regions.get(rloc)?.iter().collect();
+        clocs.sort();
+        let mut drawn_l = inner.drawn_chunks.lock().unwrap();
+        let failed_l = inner.failed_chunks.lock().unwrap();
+        let chunks: Vec<serde_json::Value> = clocs.into_iter().map(|cloc| {
+            let status = if failed_l.contains(cloc) {
+                "error"
+            } else if drawn_l.remove(cloc) {
+                "rendered"
+            } else {
+                "empty"
+            };
+            serde_json::json!({
+                "x": cloc.x,
+                "z": cloc.z,
+                "timestamp": inner.snapshot.dimension.chunk_timestamp(cloc),
+                "status": status,
+            })
+        }).collect();
+        Some(serde_json::json!({
+            "region": { "x": rloc.x, "z": rloc.z },
+            "rendered_at": chrono::Local::now().to_rfc3339(),
+            "chunks": chunks,
+        }))
+    }
+
+    /// Collect surface block change counts against the previous run.
+    pub fn with_block_stats(mut self, load_previous: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().block_stats = Some(BlockStats::new(load_previous));
+        self
+    }
+
+    /// Render regions in `order`, spiralling out from `spawn` with `RenderOrder::Spiral`.
+    pub fn with_order(mut self, order: RenderOrder, spawn: BlockPos) -> Self {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.order = order;
+        inner.spawn = spawn;
+        self
+    }
+
+    /// Order regions the order ranks the same by `seed` (0 by default); the
+    /// same seed gives the same order on every run.
+    pub fn with_work_seed(mut self, seed: u64) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().work_seed = seed;
+        self
+    }
+
+    /// Draw `r.X.Z.highlight.png` of the blocks `highlight` looks for.
+    pub fn with_highlight(mut self, highlight: Highlight) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().highlight = Some(highlight);
+        self
+    }
+
+    /// Bound the decoded chunks kept in memory to about `mb` megabytes,
+    /// dropping them as `eviction` says.
+    pub fn with_chunk_cache(mut self, mb: usize, eviction: Eviction) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().chunks = Mutex::new(ChunkCache::new(mb << 20, eviction));
+        self
+    }
+
+    /// Skip chunks that keep failing to decode, tracked in the cache directory.
+    pub fn with_skip_list(mut self, skip_list: SkipList) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().skip_list = Some(skip_list);
+        self
+    }
+
+    /// Note every region finished in `journal`, for --resume.
+    pub fn with_journal(mut self, journal: Arc<RunJournal>) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().journal = Some(journal);
+        self
+    }
+
+    /// Pause between chunks while `preemption` has more urgent work (serve's batch lane).
+    pub fn with_preemption(mut self, preemption: Option<Arc<dyn Preemption>>) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().preemption = preemption;
+        self
+    }
+
+    /// Read and decode chunks on `io_threads` threads of their own, at most
+    /// `depth` regions ahead of the workers.
+    pub fn with_readahead(mut self, io_threads: usize, depth: usize) -> Self {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.io_threads = io_threads;
+        inner.readahead = depth;
+        self
+    }
+
+    /// Slow the workers down to `throttle`.
+    pub fn with_throttle(mut self, throttle: Option<Arc<Throttle>>) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().throttle = throttle;
+        self
+    }
+
+    /// Persist the skip list and summarize this run.
+    pub fn finish_skip_list(&self) -> Option<crate::skip_list::SkipListSummary> {
+        let skip_list = self.inner.skip_list.as_ref()?;
+        if self.inner.snapshot.dimension.cache_writable() {
+            let _publishing = scheduler::PUBLISH.lock().unwrap();
+            if let Err(e) = skip_list.save() {
+                warn!("cannot write skip list: {}", e);
+            }
+        }
+        Some(skip_list.summary())
+    }
+
+    /// Surface columns changed in each chunk since the previous run (with block stats).
+    pub fn changed_surface_chunks(&self) -> std::collections::HashMap<ChunkPos, u64> {
+        self.inner.block_stats.as_ref().map(BlockStats::changed_chunks).unwrap_or_default()
+    }
+
+    pub fn write_block_stats(&self, path: &Path) -> std::io::Result<()> {
+        match &self.inner.block_stats {
+            Some(stats) => stats.write_json(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Regions whose image could not be written this run, with the reason.
+    pub fn failed_regions(&self) -> Vec<(RegionPos, String)> {
+        self.inner.failed_regions.lock().unwrap().clone()
+    }
+
+    pub fn interrupted_regions(&self) -> Vec<RegionPos> {
+        self.inner.interrupted.lock().unwrap().clone()
+    }
+
+    pub fn cache_degraded(&self) -> bool {
+        self.inner.snapshot.dimension.cache_degraded()
+    }
+
+    /// Render one region of `render_all` on a worker, from the recheck of its
+    /// file to its report.
+    #[allow(clippy::too_many_arguments)]
+    fn render_one(inner: &DimensionRendererInner, rloc: RegionPos, chunks: usize, palette: &Arc<fastanvil::RenderedPalette>, events: &EventBus, reports: &Mutex<Vec<RegionReport>>, changed: &Mutex<Vec<ChangedRegion>>, nocache: bool) {
+        let region_started = Instant::now();
+        if shutdown::requested() {
+            // Its cache is not saved, so the next run picks it up.
+            inner.snapshot.finish_region(&rloc, &[], false);
+            inner.interrupted.lock().unwrap().push(rloc);
+            return;
+        }
+        // The region file may have gone or changed since the scan.
+        let check = inner.snapshot.dimension.recheck_region(&rloc);
+        match check {
+            RegionCheck::Missing => {
+                warn!("region {} disappeared since the scan, skipped", rloc);
+                events.publish(RegionProgress::Warning(rloc, "region file disappeared since the scan, skipped".to_string()));
+                inner.snapshot.finish_region(&rloc, &[], false);
+                reports.lock().unwrap().push(RegionReport::new(&rloc, RegionStatus::Missing, 0, region_started.elapsed(), None));
+                return;
+            },
+            RegionCheck::Changed => {
+                info!("region {} changed since the scan, its cache is left for the next run", rloc);
+                events.publish(RegionProgress::Warning(rloc, "region file changed since the scan, rendered again next run".to_string()));
+            },
+            RegionCheck::Unchanged => (),
+        }
+        if let Some(preemption) = &inner.preemption {
+            preemption.region_started(&rloc);
+        }
+        let image_name = Self::output_name(inner, &rloc);
+        let old_mtime = modified(&inner.image_path.join(&image_name));
+        let sidecar;
+        let result;
+        if let Some(heightmap) = &inner.options.heightmap {
+            result = Self::render_region_heights(inner, &rloc, heightmap, nocache, events);
+            sidecar = Self::sidecar(inner, &rloc);
+        } else if let Some(direction) = inner.options.isometric {
+            result = Self::render_region_isometric(inner, &rloc, palette, direction, events);
+            sidecar = Self::sidecar(inner, &rloc);
+        } else {
+            // Load cached image, into a buffer of an earlier region.
+            let size = Self::region_scale(inner, &rloc).region_pixels();
+            let mut buf = inner.images.take(size);
+            if nocache { buf.fill(0); }
+            else { Self::load_cached_image(inner, &rloc, &mut buf); }
+            // Render the region
+            Self::render_region(inner, &rloc, buf.nest_mut(), Arc::clone(palette), nocache, events);
+
+            sidecar = Self::sidecar(inner, &rloc);
+
+            // save region image
+            let write_path = inner.image_path.join(to_image_name(&rloc));
+            let image = image::DynamicImage::ImageRgba8(ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(size as u32, size as u32, buf).unwrap());
+            result = Self::save_image(inner, &rloc, &write_path, &image, inner.options.png_encoding);
+            if let image::DynamicImage::ImageRgba8(image) = image {
+                inner.images.give(image.into_raw());
+            }
+        }
+        if let Err(e) = &result {
+            error!("region {} failed: {}", rloc, e);
+            inner.failed_regions.lock().unwrap().push((rloc, e.to_string()));
+            events.publish(RegionProgress::Failed(rloc, e.to_string()));
+        }
+        if let (Some(sidecar), true) = (sidecar, result.is_ok()) {
+            let sidecar_path = inner.image_path.join(to_sidecar_name(&rloc));
+            let written = serde_json::to_vec_pretty(&sidecar).map_err(std::io::Error::from)
+                .and_then(|data| std::fs::write(&sidecar_path, data));
+            if let Err(e) = written {
+                warn!("cannot write {}: {}", sidecar_path.display(), e);
+            }
+        }
+        if let (Some(depth), true) = (inner.options.chunk_mask, result.is_ok()) {
+            Self::save_chunk_mask(inner, &rloc, depth);
+        }
+        if result.is_ok() {
+            let new_mtime = modified(&inner.image_path.join(&image_name));
+            changed.lock().unwrap().push(ChangedRegion::new(&rloc, image_name, old_mtime, new_mtime, chunks));
+        }
+
+        // Unload chunks and save cache.
+        // Chunks of this region that failed are left stale in the cache,
+        // and a region whose image was not written, or whose file
+        // changed under it, keeps its old cache.
+        let failed: Vec<ChunkPos> = inner.failed_chunks.lock().unwrap()
+            .iter().filter(|cloc| rloc.contains_chunk(cloc)).copied().collect();
+        let done = result.is_ok() && check == RegionCheck::Unchanged;
+        inner.snapshot.finish_region(&rloc, &failed, done);
+        inner.chunks.lock().unwrap().region_done(&rloc, inner.options.shading.mode == ShadeMode::Full);
+        if let (Some(journal), true) = (&inner.journal, done) {
+            journal.record(&rloc);
+        }
+        let status = match (&result, check) {
+            (Err(_), _) => RegionStatus::Failed,
+            (Ok(()), RegionCheck::Changed) => RegionStatus::Changed,
+            (Ok(()), _) => RegionStatus::Rendered,
+        };
+        reports.lock().unwrap().push(RegionReport::new(&rloc, status, chunks, region_started.elapsed(), result.err().map(|e| e.to_string()))
+            .with_failed_chunks(&failed));
+        if let Some(preemption) = &inner.preemption {
+            preemption.region_done(&rloc);
+        }
+
+        events.publish(RegionProgress::End(rloc));
+    }
+
+    pub fn render_all(&self, palette: Arc<fastanvil::RenderedPalette>, events: &EventBus, nocache: bool, threads: usize) {
+        events.publish(RegionProgress::BeginAll(self.inner.snapshot.dimension.render_regions.iter().fold(0, |c, (_, v)| c + v.len())));
+        let (started, started_at) = (Instant::now(), chrono::Local::now());
+        let reports: Arc<Mutex<Vec<RegionReport>>> = Default::default();
+        let changed: Arc<Mutex<Vec<ChangedRegion>>> = Default::default();
+        // In --order, and a seeded order among equals instead of the HashMap's.
+        let dimension = &self.inner.snapshot.dimension;
+        let mut regions: Vec<(&RegionPos, &HashSet<ChunkPos>)> = dimension.render_regions.iter().collect();
+        self.inner.order.sort(&mut regions, dimension, self.inner.spawn, self.inner.work_seed);
+        self.inner.chunks.lock().unwrap().plan(regions.iter().map(|(rloc, _)| **rloc));
+        let pool = ThreadPool::new(threads.max(1));
+        if self.inner.io_threads > 0 {
+            let order: Vec<RegionPos> = regions.iter().map(|(rloc, _)| **rloc).collect();
+            let reader = Arc::clone(&self.inner);
+            let readahead = Readahead::start(order, self.inner.io_threads, self.inner.readahead, move |rloc| if !shutdown::requested() { Self::read_ahead(&reader, rloc) });
+            for _ in 0..threads.max(1) {
+                let (inner, readahead) = (Arc::clone(&self.inner), Arc::clone(&readahead));
+                let (palette, events, reports, changed) = (Arc::clone(&palette), events.clone(), Arc::clone(&reports), Arc::clone(&changed));
+                pool.execute(move || while let Some(rloc) = readahead.next() {
+                    let chunks = inner.snapshot.dimension.render_regions.get(&rloc).map_or(0, HashSet::len);
+                    Self::render_one(&inner, rloc, chunks, &palette, &events, &reports, &changed, nocache);
+                });
+            }
+        } else {
+            for (rloc, clocs) in regions {
+                let inner = Arc::clone(&self.inner);
+                let (rloc, chunks) = (*rloc, clocs.len());
+                let (palette, events, reports, changed) = (Arc::clone(&palette), events.clone(), Arc::clone(&reports), Arc::clone(&changed));
+                pool.execute(move || Self::render_one(&inner, rloc, chunks, &palette, &events, &reports, &changed, nocache));
+            }
         }
         pool.join();
-        sender.send(RegionProgress::EndAll).unwrap();
+
+        let dimension = &self.inner.snapshot.dimension;
+        let chunks_from_cache = if nocache { 0 } else {
+            dimension.render_regions.iter().map(|(rloc, clocs)| dimension.stored_chunks(rloc).saturating_sub(clocs.len())).sum()
+        };
+        let chunk_errors = self.inner.failed_chunks.lock().unwrap().len();
+        let reports = std::mem::take(&mut *reports.lock().unwrap());
+        let report = RenderReport::new(started_at, started.elapsed(), reports, chunks_from_cache, chunk_errors);
+        let _publishing = scheduler::PUBLISH.lock().unwrap();
+        let report_path = self.inner.image_path.join(REPORT_NAME);
+        if let Err(e) = report.write(&report_path) {
+            warn!("cannot write {}: {}", report_path.display(), e);
+        }
+        let changed = std::mem::take(&mut *changed.lock().unwrap());
+        if let Err(e) = render_report::write_manifest(&self.inner.image_path, changed) {
+            warn!("cannot write the changed regions manifest: {}", e);
+        }
+        events.publish(RegionProgress::EndAll);
     }
 }
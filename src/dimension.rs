@@ -1,177 +1,767 @@
-use log::{info, debug};
-use std::error::Error;
-use std::path::PathBuf;
-use std::cell::RefCell;
-use std::rc::Rc;
+use log::{info, debug, warn};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::collections::{HashMap, HashSet};
-use std::fs::{OpenOptions, File};
-use std::cmp::Eq;
-use std::hash::Hash;
+use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use threadpool::ThreadPool;
 use regex::Regex;
+use clap::ArgEnum;
 
-use crate::update_detector::RegionTimestamps;
-use crate::update_detector::{CLoc, RLoc, RegionBounds};
+use crate::update_detector::{payload_hash, CacheData, ChunkChange, RegionTimestamps, CACHE_FORMAT};
+use crate::region_index::{FileStamp, RegionIndex, to_region_name};
+use crate::dim_renderer::RegionProgress;
+use crate::events::EventBus;
+use crate::durability::Durability;
+use crate::cache_store::CacheStore;
+use crate::coords::{ChunkPos, RegionPos, RegionBounds};
+use crate::error::{Error, Result};
 
-type Result<T> = std::result::Result<T, Box<dyn Error>>;
-type ShareHashMap<K, V> = Rc<RefCell<HashMap<K, V>>>;
-type ShareHashSet<T> = Rc<RefCell<HashSet<T>>>;
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum DimensionKind {
+    Overworld,
+    Nether,
+    End,
+}
+
+impl DimensionKind {
+    /// Name of the dimension's image and cache subdirectories with --world-path.
+    pub fn id(&self) -> &'static str {
+        match self {
+            DimensionKind::Overworld => "overworld",
+            DimensionKind::Nether => "nether",
+            DimensionKind::End => "end",
+        }
+    }
+    /// Region directory of the dimension under the world root.
+    pub fn region_dir(&self) -> &'static str {
+        match self {
+            DimensionKind::Overworld => "region",
+            DimensionKind::Nether => "DIM-1/region",
+            DimensionKind::End => "DIM1/region",
+        }
+    }
+    /// Guess from the vanilla layout: `DIM-1/region` is the Nether, `DIM1/region` the End.
+    pub fn from_path(dim_path: &Path) -> Self {
+        let names: Vec<_> = dim_path.components().map(|c| c.as_os_str().to_os_string()).collect();
+        if names.iter().any(|n| n == "DIM-1") {
+            DimensionKind::Nether
+        } else if names.iter().any(|n| n == "DIM1") {
+            DimensionKind::End
+        } else {
+            DimensionKind::Overworld
+        }
+    }
+}
 
 pub struct Dimension {
     pub dim_path: PathBuf,
+    pub kind: DimensionKind,
     pub cache_path: PathBuf,
-    pub timestamps: HashMap<RLoc, RegionTimestamps>,
-    pub render_regions: HashMap<RLoc, HashSet<CLoc>>,
-    cache_ro: bool,
+    /// Where the caches of `cache_path` are read from and saved to.
+    pub cache: Arc<CacheStore>,
+    pub timestamps: HashMap<RegionPos, RegionTimestamps>,
+    pub render_regions: HashMap<RegionPos, HashSet<ChunkPos>>,
+    /// Set when comparing against an older snapshot: chunks that differ from it.
+    pub changed_chunks: Option<HashMap<RegionPos, HashSet<ChunkPos>>>,
+    /// Changed chunks left out by `retain_chunks`, kept stale in the cache.
+    masked_chunks: HashMap<RegionPos, Vec<ChunkPos>>,
+    /// Caches of regions built from reported changes: only the rendered
+    /// chunks get their new timestamps when saved.
+    merge_caches: HashMap<RegionPos, RegionTimestamps>,
+    /// `--verify-chunk-hash`: payload hashes saved with the caches.
+    chunk_hashes: HashMap<RegionPos, Vec<u64>>,
+    /// Chunks reset or removed since the cache, whose old pixels are blanked.
+    cleared_chunks: HashSet<ChunkPos>,
+    /// Hash of the palette, stored with each cache so a palette change redraws everything.
+    palette_hash: Option<u64>,
+    pub durability: Durability,
+    cache_ro: AtomicBool,
+    cache_degraded: AtomicBool,
+}
+
+/// A region file as found again when its render starts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionCheck {
+    Unchanged,
+    /// New chunk timestamps since the scan (the game saved, a backup was restored).
+    Changed,
+    /// Deleted or unreadable since the scan.
+    Missing,
+}
+
+pub fn to_cache_name(loc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.cache", loc.x, loc.z)
+}
+
+/// Outcome of reading one region's header and cache.
+enum ScanResult {
+    /// The header cannot be read; the region is skipped.
+    Unreadable,
+    /// The header hash matches the region index's last clean scan.
+    HeaderUnchanged(u64),
+    /// Region-local chunks whose timestamp differs from the cache, and the
+    /// chunk hashes to save with its cache when verified.
+    Diff(RegionTimestamps, std::io::Result<Vec<(usize, usize, ChunkChange)>>, Option<Vec<u64>>),
+}
+
+/// What a scan reads and selects, shared by `from_dimdir` and `from_changes`.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Regions outside are left out of the render set.
+    pub bounds: Option<RegionBounds>,
+    /// Ignore the caches, as if every chunk had changed.
+    pub nocache: bool,
+    /// Never write the caches (migrations, verified timestamps).
+    pub cache_ro: bool,
+    /// Regions treated as if they had no cache (--force-region).
+    pub force: HashSet<RegionPos>,
+    /// Caches saved with another palette are ignored.
+    pub palette_hash: Option<u64>,
+    /// --verify-chunk-hash
+    pub verify: bool,
+    /// Workers reading region headers and caches.
+    pub threads: usize,
+}
+
+fn scan_region(rloc: &RegionPos, path: &Path, cache: &CacheStore, nocache: bool, clean_hash: Option<u64>, options: &ScanOptions) -> ScanResult {
+    let (cache_ro, palette_hash) = (options.cache_ro, options.palette_hash);
+    let region = match File::open(path).and_then(|mut region_file| RegionTimestamps::from_regiondata(&mut region_file)) {
+        Ok(region) => region,
+        Err(_) => {
+            debug!("region {:?} cannot be read.", rloc);
+            trace_region!(rloc, "region header of {} cannot be read, skipped", path.display());
+            return ScanResult::Unreadable;
+        }
+    };
+    let header_hash = region.header_hash();
+    if clean_hash == Some(header_hash) {
+        trace_region!(rloc, "header unchanged since the last clean scan (region index), not rendered");
+        return ScanResult::HeaderUnchanged(header_hash);
+    }
+
+    let cached = if nocache {
+        trace_region!(rloc, "cache ignored (cache mode)");
+        None
+    } else {
+        read_cache(rloc, cache, palette_hash, !cache_ro)
+    };
+
+    // If cache not exists, pass None.
+    let diff = region.changes(cached.as_ref().map(|cached| &cached.timestamps));
+    let diff = match diff {
+        Ok(diff) if options.verify => diff,
+        diff => return ScanResult::Diff(region, diff, None),
+    };
+    let positions: Vec<(usize, usize)> = diff.iter().map(|(x, z, _)| (*x, *z)).collect();
+    let (dirty, hashes) = verify_chunks(rloc, path, &positions, cached.and_then(|cached| cached.hashes));
+    if dirty.is_empty() && !diff.is_empty() && !cache_ro {
+        // The new timestamps are saved now, as the region isn't rendered.
+        let mut data = vec![];
+        let saved = region.save_cache(&mut data, palette_hash, Some(&hashes))
+            .and_then(|_| cache.write(rloc, &data, Durability::Fast));
+        if let Err(e) = saved {
+            debug!("cannot save verified cache {}: {}", cache.describe(rloc), e);
+        }
+    }
+    let dirty = diff.into_iter().filter(|(x, z, _)| dirty.contains(&(*x, *z))).collect();
+    ScanResult::Diff(region, Ok(dirty), Some(hashes))
 }
 
-fn to_cache_name(loc: &RLoc) -> String {
-    format!("r.{:0}.{:0}.cache", loc.0, loc.1)
+/// `--verify-chunk-hash`: the chunks of `diff` whose payload differs from
+/// the hash in the cache, and the hashes of the region afterwards. Chunks
+/// that cannot be read are dirty, and left without a hash.
+fn verify_chunks(rloc: &RegionPos, path: &Path, diff: &[(usize, usize)], stored: Option<Vec<u64>>) -> (Vec<(usize, usize)>, Vec<u64>) {
+    let mut hashes = stored.filter(|hashes| hashes.len() == 1024).unwrap_or_else(|| vec![0; 1024]);
+    let mut region = match File::open(path).map_err(|e| e.to_string())
+        .and_then(|file| fastanvil::Region::from_stream(file).map_err(|e| e.to_string())) {
+        Ok(region) => region,
+        Err(e) => {
+            debug!("cannot verify chunks of {}: {}", path.display(), e);
+            for (x, z) in diff {
+                hashes[z * 32 + x] = 0;
+            }
+            return (diff.to_vec(), hashes);
+        }
+    };
+    let mut dirty = vec![];
+    for &(x, z) in diff {
+        let hash = match region.read_chunk(x, z) {
+            Ok(Some(data)) => payload_hash(&data),
+            _ => 0,
+        };
+        if hash == 0 || hash != hashes[z * 32 + x] {
+            dirty.push((x, z));
+        }
+        hashes[z * 32 + x] = hash;
+    }
+    trace_region!(rloc, "{} of {} chunks with new timestamps changed (--verify-chunk-hash)", dirty.len(), diff.len());
+    (dirty, hashes)
+}
+
+/// The cache of a region, unless missing, unreadable or rendered with
+/// another palette than `palette_hash`. Headerless caches are rewritten in
+/// the current format when `migrate`.
+fn read_cache(rloc: &RegionPos, store: &CacheStore, palette_hash: Option<u64>, migrate: bool) -> Option<CacheData> {
+    match store.read(rloc) {
+        Ok(Some(data)) => {
+            match RegionTimestamps::from_cachedata(&mut data.as_slice()) {
+                Ok(CacheData { palette_hash: Some(cached_palette), .. }) if palette_hash.is_some_and(|hash| hash != cached_palette) => {
+                    trace_region!(rloc, "cache ignored, it was rendered with another palette");
+                    None
+                },
+                Ok(cache) => {
+                    info!("cache OK {}", store.describe(rloc));
+                    trace_region!(rloc, "cache loaded from {} (written by {})", store.describe(rloc), cache.renderer.as_deref().unwrap_or("a renderer before versioned caches"));
+                    if cache.legacy && migrate {
+                        migrate_cache(rloc, store, &cache);
+                    }
+                    Some(cache)
+                },
+                Err(e) => {
+                    warn!("ignore unreadable cache {}: {}", store.describe(rloc), e);
+                    None
+                },
+            }
+        },
+        Ok(None) => {
+            trace_region!(rloc, "no cache at {}", store.describe(rloc));
+            None
+        },
+        Err(e) => {
+            warn!("ignore unreadable cache {}: {}", store.describe(rloc), e);
+            None
+        },
+    }
 }
 
-fn share_borrow_mut_with<K: Eq + Hash, V, F: FnOnce() -> Rc<V>>(hash_map: &ShareHashMap<K, Rc<V>>, key: K, default: F) -> Rc<V> {
-    let map = Rc::clone(hash_map);
-    let mut map_m = map.borrow_mut();
-    let e = map_m.entry(key).or_insert_with(default);
-    Rc::clone(&e)
+fn migrate_cache(rloc: &RegionPos, store: &CacheStore, cache: &CacheData) {
+    let mut data = vec![];
+    let migrated = cache.timestamps.save_cache(&mut data, cache.palette_hash, cache.hashes.as_deref())
+        .and_then(|_| store.write(rloc, &data, Durability::Fast));
+    match migrated {
+        Ok(()) => debug!("cache {} migrated to format {}", store.describe(rloc), CACHE_FORMAT),
+        Err(e) => debug!("cannot migrate cache {}: {}", store.describe(rloc), e),
+    }
+}
+
+/// Region files of a dimension directory, filtered by `bounds`. The
+/// directory may be a symlink; entries are not descended into, and symlinked
+/// entries are only taken when they resolve to a regular file.
+/// Names are matched regardless of case, but a region is always read as
+/// `r.X.Z.mca` (the name the chunk loader opens): other spellings of it
+/// (`R.0.0.MCA`, `r.00.0.mca`) are stale copies or what a case-insensitive
+/// filesystem shows, and are skipped with a warning instead of rendering the
+/// region twice.
+pub fn scan_region_files(dim_path: &Path, bounds: Option<&RegionBounds>) -> Result<HashMap<RegionPos, PathBuf>> {
+    let mut region_locs: HashMap<RegionPos, PathBuf> = Default::default();
+    let mut spellings: HashMap<RegionPos, Vec<String>> = Default::default();
+    let dir = dim_path.read_dir()
+        .map_err(|source| Error::ListRegions { path: dim_path.to_path_buf(), source })?;
+    let region_re = Regex::new(r"(?i)^r\.(-?\d+)\.(-?\d+)\.mca$").unwrap();
+    for entry in dir {
+        let file = entry?;
+        let file_type = file.file_type()?;
+        if file_type.is_dir() { continue; }
+        if file_type.is_symlink() {
+            match std::fs::metadata(file.path()) {
+                Ok(metadata) if metadata.is_file() => (),
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("skip broken link {}: {}", file.path().display(), e);
+                    continue;
+                }
+            }
+        }
+
+        let filestr = match file.file_name().into_string() {
+            Ok(filestr) => filestr,
+            Err(_) => continue,
+        };
+        let caps = match region_re.captures(&filestr) {
+            Some(caps) => caps,
+            None => continue,
+        };
+        let rloc = match (caps[1].parse(), caps[2].parse()) {
+            (Ok(x), Ok(z)) => RegionPos::new(x, z),
+            _ => {
+                warn!("skip {}, its region position is out of range", file.path().display());
+                continue;
+            }
+        };
+
+        // if bounds is None => true
+        // if inner of bounds => true
+        // if out of bounds => false
+        let is_target = if let Some(bounds) = bounds {
+            rloc.in_bounds(bounds)
+        } else { true };
+
+        if !is_target {
+            trace_region!(rloc, "outside of the render range, skipped");
+            continue;
+        }
+        spellings.entry(rloc).or_default().push(filestr);
+    }
+    for (rloc, mut names) in spellings {
+        names.sort();
+        let canonical = to_region_name(&rloc);
+        let path = dim_path.join(&canonical);
+        if !names.contains(&canonical) && !path.is_file() {
+            warn!("skip region {}: {} cannot be read as {}", rloc, names.join(", "), canonical);
+            continue;
+        }
+        if names.len() > 1 {
+            warn!("region {} has several files ({}), reading {} only", rloc, names.join(", "), canonical);
+        } else if names[0] != canonical {
+            debug!("region {} listed as {}, read as {}", rloc, names[0], canonical);
+        }
+        region_locs.insert(rloc, path);
+    }
+    Ok(region_locs)
 }
 
 impl Dimension {
-    pub fn from_dimdir(dim_path: &PathBuf, cache_path: &PathBuf, bounds: Option<&RegionBounds>, nocache: bool, cache_ro: bool) -> Result<Dimension> {
-        // Read regions
-        let mut region_locs: HashMap<RLoc, PathBuf> = Default::default();
-        let dir = dim_path.read_dir()?;
-        let region_re = Regex::new(r"r\.(-?\d+)\.(-?\d+)\.mca").unwrap();
-        for entry in dir {
-            let file = entry?;
-            if file.path().is_dir() { continue; }
-
-            let filestr = file.file_name().into_string().unwrap();
-            let caps = region_re.captures(&filestr);
-            if let None = caps { continue; }
-            let caps = caps.unwrap();
-
-            let x: i32 = caps.get(1).unwrap().as_str().parse().unwrap();
-            let z: i32 = caps.get(2).unwrap().as_str().parse().unwrap();
-
-            // if bounds is None => true
-            // if inner of bounds => true
-            // if out of bounds => false
-            let is_target = if let Some(bounds) = bounds {
-                bounds.0.0 <= x && x <= bounds.1.0 && bounds.0.1 <= z && z <= bounds.1.1
-            } else { true };
-            // if let Some(bounds) = bounds {
-            //     info!("{}, {} <= {} <= {}, {} <= {} <= {}", is_target, bounds.0.0, x, bounds.1.0, bounds.0.1, z, bounds.1.1);
-            // }
-            
-            if !is_target { continue; }
-            region_locs.insert(RLoc(x, z), file.path());
+    /// With an `index` (watch mode), regions it knows to be unchanged are not opened.
+    /// Regions in `options.force`, and regions whose cache was saved with a
+    /// palette other than `options.palette_hash`, are treated as if they had
+    /// no cache. With `options.verify`, chunks with a new timestamp are only
+    /// selected when their payload hash changed too.
+    /// Region headers and caches are read by `options.threads` workers,
+    /// reporting `ScanBegin`/`ScanStep`/`ScanEnd` to `progress`.
+    pub fn from_dimdir(dim_path: &PathBuf, cache_path: &PathBuf, cache: &Arc<CacheStore>, options: &ScanOptions, index: Option<&mut RegionIndex>, progress: Option<&EventBus>) -> Result<Dimension> {
+        let (bounds, cache_ro, palette_hash) = (options.bounds.as_ref(), options.cache_ro, options.palette_hash);
+        let mut index = index;
+        if let (Some(index), Some(palette_hash)) = (index.as_deref_mut(), palette_hash) {
+            index.set_palette(palette_hash);
         }
+        // Read regions
+        let region_locs: Vec<(RegionPos, PathBuf)> = match index.as_deref_mut() {
+            Some(index) => index.region_files(dim_path, bounds)?,
+            None => scan_region_files(dim_path, bounds)?.into_iter().collect(),
+        };
 
-        // Get chunk timestamps for regions and caches
-        let mut timestamps: HashMap<RLoc, RegionTimestamps> = Default::default();
-        let render_regions: ShareHashMap<RLoc, ShareHashSet<CLoc>> = Default::default();
+        // Regions the index knows to be unchanged are not opened at all.
+        let mut jobs: Vec<(RegionPos, PathBuf, Option<FileStamp>, Option<u64>, bool)> = vec![];
         for (rloc, path) in region_locs {
-            let mut region_file = File::open(&path).unwrap();
-            let region = match RegionTimestamps::from_regiondata(&mut region_file) {
-                Ok(region) => region,
-                Err(_) => {
-                    debug!("region {:?} cannot be read.", rloc);
+            let stamp = if index.is_some() { FileStamp::of_region(&path) } else { None };
+            let mut clean_hash = None;
+            let forced = options.force.contains(&rloc);
+            if forced {
+                trace_region!(rloc, "forced (--force-region), rendered in full");
+            }
+            let nocache = options.nocache || forced;
+            if let (Some(index), Some(stamp), false) = (index.as_deref(), stamp, nocache) {
+                if index.is_clean(&rloc, &stamp) {
+                    trace_region!(rloc, "file unchanged since the last clean scan (region index), not opened");
                     continue;
                 }
-            };
+                clean_hash = index.clean_hash(&rloc);
+            }
+            jobs.push((rloc, path, stamp, clean_hash, nocache));
+        }
 
-            let mut cache_path = PathBuf::from(&cache_path);
-            cache_path.push(to_cache_name(&rloc));
-            let cache = if nocache { None } else {
-                match File::open(&cache_path) {
-                    Ok(mut cache_file) => {
-                        // info!("cache path {}", std::fs::canonicalize(&cache_path).unwrap().to_str().unwrap());
-                        info!("cache OK {}", cache_path.to_str().unwrap());
-                        Some(RegionTimestamps::from_cachedata(&mut cache_file).unwrap())
-                    },
-                    Err(_) => None,
+        // Get chunk timestamps for regions and caches
+        if let Some(progress) = progress {
+            progress.publish(RegionProgress::ScanBegin(jobs.len()));
+        }
+        let (result_sender, result_receiver) = channel();
+        let pool = ThreadPool::new(options.threads.max(1));
+        let shared = Arc::new(options.clone());
+        let mut stamps: HashMap<RegionPos, FileStamp> = Default::default();
+        for (rloc, path, stamp, clean_hash, nocache) in jobs {
+            if let Some(stamp) = stamp {
+                stamps.insert(rloc, stamp);
+            }
+            let (cache, options) = (Arc::clone(cache), Arc::clone(&shared));
+            let result_sender = result_sender.clone();
+            let progress = progress.cloned();
+            pool.execute(move || {
+                let result = scan_region(&rloc, &path, &cache, nocache, clean_hash, &options);
+                // The receiver outlives the pool.
+                let _ = result_sender.send((rloc, result));
+                if let Some(progress) = progress {
+                    progress.publish(RegionProgress::ScanStep(1));
                 }
-            };
+            });
+        }
+        drop(result_sender);
 
-            // If cache not exists, pass None.
-            let diff = region.diffs(cache.as_ref())?;
+        // Merge the scan results.
+        let mut timestamps: HashMap<RegionPos, RegionTimestamps> = Default::default();
+        let mut render_regions: HashMap<RegionPos, HashSet<ChunkPos>> = Default::default();
+        let mut chunk_hashes: HashMap<RegionPos, Vec<u64>> = Default::default();
+        let mut cleared_chunks: HashSet<ChunkPos> = Default::default();
+        for (rloc, result) in result_receiver {
+            let stamp = stamps.get(&rloc).copied();
+            let (region, diff, hashes) = match result {
+                ScanResult::Unreadable => continue,
+                ScanResult::HeaderUnchanged(header_hash) => {
+                    if let (Some(index), Some(stamp)) = (index.as_deref_mut(), stamp) {
+                        index.mark_clean(&rloc, stamp, header_hash);
+                    }
+                    continue;
+                },
+                ScanResult::Diff(region, diff, hashes) => (region, diff?, hashes),
+            };
 
             if diff.len() == 0 {
+                trace_region!(rloc, "no chunk changed since the cache, not rendered");
+                if let (Some(index), Some(stamp)) = (index.as_deref_mut(), stamp) {
+                    index.mark_clean(&rloc, stamp, region.header_hash());
+                }
                 continue;
             }
+            // Rendered regions are diffed against their cache again next time,
+            // so chunks that fail to render are not hidden by the index.
+            if let Some(index) = index.as_deref_mut() {
+                index.mark_dirty(&rloc);
+            }
             debug!("diff.len = {}", diff.len());
-            timestamps.insert(rloc.clone(), region);
-
-            // Get render chunks hashset for the region.
-            let render_required_chunks_r = share_borrow_mut_with(&render_regions, rloc.clone(), || Default::default());
-            let mut render_required_chunks = render_required_chunks_r.borrow_mut();
-            for cloc_tuple in diff {
-                let cloc = CLoc::from(cloc_tuple);
-                render_required_chunks.insert(cloc.clone());
-                
-                // Set South chunk
-                if cloc.1 < 31 {
-                    // in the region
-                    let south = CLoc::from(cloc).offset(0, 1);
-                    render_required_chunks.insert(south.unwrap());
-                } else {
-                    // In South region
-                    // Convert chunk location for south region.
-                    let south = cloc.offset(0, -31);
-                    // Get render chunks hashset for south region.
-                    let render_required_chunks_south_r = share_borrow_mut_with(
-                        &render_regions, rloc.offset(0, 1), || Default::default());
-                    let mut render_required_chunks_south = render_required_chunks_south_r.borrow_mut();
-                    render_required_chunks_south.insert(south.unwrap());
+            trace_region!(rloc, "{} chunks changed since the cache: {:?}", diff.len(), diff);
+            timestamps.insert(rloc, region);
+            if let Some(hashes) = hashes {
+                chunk_hashes.insert(rloc, hashes);
+            }
+
+            for (x, z, change) in diff {
+                let cloc = rloc.chunk(x, z);
+                if change != ChunkChange::Updated {
+                    trace_region!(rloc, "chunk {} {} since the cache, its old pixels are cleared", cloc,
+                        if change == ChunkChange::Reset { "reset" } else { "removed" });
+                    cleared_chunks.insert(cloc);
+                }
+                // The chunk itself, and its south neighbour whose shading
+                // depends on it (which may lie in the next region).
+                for target in [cloc, cloc.offset(0, 1)] {
+                    if target.region() != rloc {
+                        trace_region!(target.region(), "chunk {} selected for shading, its north neighbour {} changed", target, cloc);
+                    }
+                    render_regions.entry(target.region()).or_default().insert(target);
                 }
             }
         }
+        pool.join();
+        if let Some(progress) = progress {
+            progress.publish(RegionProgress::ScanEnd);
+        }
+        for (rloc, clocs) in &render_regions {
+            trace_region!(rloc, "{} chunks selected for rendering", clocs.len());
+        }
+        info!("render_regions count: {}", render_regions.keys().len());
+        if let (Some(index), false) = (index, cache_ro) {
+            let _publishing = crate::scheduler::PUBLISH.lock().unwrap();
+            if let Err(e) = index.save() {
+                warn!("cannot write region index: {}", e);
+            }
+        }
 
+        Ok(Dimension {
+            dim_path: dim_path.to_path_buf(),
+            kind: DimensionKind::from_path(dim_path),
+            cache_path: cache_path.to_path_buf(),
+            cache: Arc::clone(cache),
+            timestamps: timestamps,
+            render_regions: render_regions,
+            changed_chunks: None,
+            masked_chunks: Default::default(),
+            merge_caches: Default::default(),
+            chunk_hashes,
+            cleared_chunks,
+            palette_hash,
+            durability: Default::default(),
+            cache_ro: AtomicBool::new(cache_ro),
+            cache_degraded: AtomicBool::new(false),
+        })
+    }
+    /// Build a "what changed" dimension: every chunk of `dim_path` is rendered,
+    /// and `changed_chunks` holds the chunks whose timestamp differs from the
+    /// same region file in `base_path` (e.g. a backup). Caches are not touched.
+    pub fn compare_dimdirs(dim_path: &PathBuf, base_path: &Path, bounds: Option<&RegionBounds>) -> Result<Dimension> {
+        let region_locs = scan_region_files(dim_path, bounds)?;
 
-        // render_regions: ShareHashMap<RLoc, ShareHashSet<CLoc>>,
-        // Rc<RefCell<HashMap<RLoc, Rc<RefCell<HashSet>>>>>
-        // to HashMap<RLoc, HashSet>
-        let render_regions = {
-            let mut regions = render_regions.borrow_mut();
-            let mut new_render_regions: HashMap<RLoc, HashSet<CLoc>> = Default::default();
-            let rlocs: Vec<RLoc> = regions.keys().cloned().collect();
+        let mut render_regions: HashMap<RegionPos, HashSet<ChunkPos>> = Default::default();
+        let mut changed_chunks: HashMap<RegionPos, HashSet<ChunkPos>> = Default::default();
+        for (rloc, path) in region_locs {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!("skip region {}: cannot open {}: {}", rloc, path.display(), e);
+                    continue;
+                }
+            };
+            let region = match RegionTimestamps::from_regiondata(&mut file) {
+                Ok(region) => region,
+                Err(_) => {
+                    debug!("region {:?} cannot be read.", rloc);
+                    continue;
+                }
+            };
+            // A region missing from the base snapshot is entirely new.
+            let base = path.file_name()
+                .map(|name| base_path.join(name))
+                .and_then(|base_file| File::open(base_file).ok())
+                .and_then(|mut base_file| RegionTimestamps::from_regiondata(&mut base_file).ok());
 
-            rlocs.iter().for_each(|rloc| {
-                let region = regions.remove(&rloc).unwrap();
-                let region = Rc::try_unwrap(region).ok().unwrap().into_inner();
-                new_render_regions.insert(rloc.clone(), region);
-            });
+            let all: HashSet<ChunkPos> = region.diffs(None)?.into_iter().map(|(x, z)| rloc.chunk(x, z)).collect();
+            let changed: HashSet<ChunkPos> = region.diffs(base.as_ref())?.into_iter().map(|(x, z)| rloc.chunk(x, z)).collect();
+            debug!("compare {}: {} chunks, {} changed", rloc, all.len(), changed.len());
+            if all.is_empty() { continue; }
+            render_regions.insert(rloc, all);
+            changed_chunks.insert(rloc, changed);
+        }
+        info!("render_regions count: {}", render_regions.keys().len());
 
-            new_render_regions
-        };
+        Ok(Dimension {
+            dim_path: dim_path.to_path_buf(),
+            kind: DimensionKind::from_path(dim_path),
+            cache_path: PathBuf::new(),
+            cache: Arc::new(CacheStore::Files(PathBuf::new())),
+            timestamps: Default::default(),
+            render_regions: render_regions,
+            changed_chunks: Some(changed_chunks),
+            masked_chunks: Default::default(),
+            merge_caches: Default::default(),
+            chunk_hashes: Default::default(),
+            cleared_chunks: Default::default(),
+            palette_hash: None,
+            durability: Default::default(),
+            cache_ro: AtomicBool::new(true),
+            cache_degraded: AtomicBool::new(false),
+        })
+    }
+    /// Build the render set from chunks reported changed (`--changes-from`)
+    /// instead of diffing every region header against its cache: only the
+    /// reported regions are opened. Their caches only take the new timestamps
+    /// of the chunks rendered, so changes the report missed are still found by
+    /// a later scan. Regions without a usable cache, and regions in
+    /// `options.force`, are rendered in full. Reported chunks the header shows
+    /// reset or removed since the cache are cleared, as in `from_dimdir`.
+    pub fn from_changes(dim_path: &PathBuf, cache_path: &PathBuf, cache: &Arc<CacheStore>, changes: &HashMap<RegionPos, HashSet<ChunkPos>>, options: &ScanOptions) -> Result<Dimension> {
+        let (bounds, nocache, cache_ro, palette_hash) = (options.bounds.as_ref(), options.nocache, options.cache_ro, options.palette_hash);
+        let mut timestamps: HashMap<RegionPos, RegionTimestamps> = Default::default();
+        let mut merge_caches: HashMap<RegionPos, RegionTimestamps> = Default::default();
+        let mut render_regions: HashMap<RegionPos, HashSet<ChunkPos>> = Default::default();
+        let mut cleared_chunks: HashSet<ChunkPos> = Default::default();
+        for (rloc, reported) in changes {
+            if bounds.is_some_and(|bounds| !rloc.in_bounds(bounds)) {
+                trace_region!(rloc, "reported changed, outside of the render range");
+                continue;
+            }
+            let path = dim_path.join(to_region_name(rloc));
+            let region = match File::open(&path).and_then(|mut region_file| RegionTimestamps::from_regiondata(&mut region_file)) {
+                Ok(region) => region,
+                Err(e) => {
+                    debug!("reported region {} cannot be read: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let forced = options.force.contains(rloc);
+            if forced {
+                trace_region!(rloc, "forced (--force-region), rendered in full");
+            }
+            let cached = if nocache || forced { None } else { read_cache(rloc, cache, palette_hash, !cache_ro).map(|cached| cached.timestamps) };
+            let changed: Vec<(ChunkPos, ChunkChange)> = match cached {
+                Some(cached) => {
+                    // A reported chunk whose header entry did not change is drawn as updated.
+                    let changes: HashMap<ChunkPos, ChunkChange> = region.changes(Some(&cached))?.into_iter()
+                        .map(|(x, z, change)| (rloc.chunk(x, z), change))
+                        .collect();
+                    merge_caches.insert(*rloc, cached);
+                    reported.iter().map(|cloc| (*cloc, changes.get(cloc).copied().unwrap_or(ChunkChange::Updated))).collect()
+                },
+                None => region.changes(None)?.into_iter().map(|(x, z, change)| (rloc.chunk(x, z), change)).collect(),
+            };
+            trace_region!(rloc, "{} chunks reported changed, {} selected", reported.len(), changed.len());
+            timestamps.insert(*rloc, region);
+            for (cloc, change) in changed {
+                if change != ChunkChange::Updated {
+                    trace_region!(rloc, "chunk {} {} since the cache, its old pixels are cleared", cloc,
+                        if change == ChunkChange::Reset { "reset" } else { "removed" });
+                    cleared_chunks.insert(cloc);
+                }
+                for target in [cloc, cloc.offset(0, 1)] {
+                    render_regions.entry(target.region()).or_default().insert(target);
+                }
+            }
+        }
         info!("render_regions count: {}", render_regions.keys().len());
 
         Ok(Dimension {
             dim_path: dim_path.to_path_buf(),
+            kind: DimensionKind::from_path(dim_path),
             cache_path: cache_path.to_path_buf(),
-            timestamps: timestamps,
-            render_regions: render_regions,
-            cache_ro: cache_ro,
+            cache: Arc::clone(cache),
+            timestamps,
+            render_regions,
+            changed_chunks: None,
+            masked_chunks: Default::default(),
+            merge_caches,
+            chunk_hashes: Default::default(),
+            cleared_chunks,
+            palette_hash,
+            durability: Default::default(),
+            cache_ro: AtomicBool::new(cache_ro),
+            cache_degraded: AtomicBool::new(false),
         })
     }
+    /// Drop the regions `keep` rejects from the render set. Chunks are still
+    /// selected as usual, so neighbours outside the set are simply not redrawn.
+    pub fn retain_regions<F: Fn(&RegionPos) -> bool>(&mut self, keep: F) {
+        self.render_regions.retain(|rloc, _| {
+            let kept = keep(rloc);
+            if !kept {
+                trace_region!(rloc, "outside of the selected regions, not rendered");
+            }
+            kept
+        });
+        self.timestamps.retain(|rloc, _| keep(rloc));
+        if let Some(changed) = &mut self.changed_chunks {
+            changed.retain(|rloc, _| keep(rloc));
+        }
+        info!("render_regions count after selection: {}", self.render_regions.len());
+    }
+    /// Drop the chunks `keep` rejects from the render set. They are saved
+    /// without a timestamp, so a later run without the selection draws them.
+    pub fn retain_chunks<F: Fn(&ChunkPos) -> bool>(&mut self, keep: F) {
+        let masked_chunks = &mut self.masked_chunks;
+        self.render_regions.retain(|rloc, clocs| {
+            clocs.retain(|cloc| {
+                let kept = keep(cloc);
+                if !kept {
+                    masked_chunks.entry(*rloc).or_default().push(*cloc);
+                }
+                kept
+            });
+            if clocs.is_empty() {
+                trace_region!(rloc, "no changed chunk inside the selected area, not rendered");
+            }
+            !clocs.is_empty()
+        });
+        info!("render_regions count after selection: {}", self.render_regions.len());
+    }
+    /// Whether the chunk was reset or removed since the cache: nothing of the
+    /// old one may stay in the image, even if the new one cannot be drawn.
+    pub fn is_cleared(&self, cloc: &ChunkPos) -> bool {
+        self.cleared_chunks.contains(cloc)
+    }
+    /// Whether a chunk should be drawn dimmed in a comparison render.
+    pub fn is_unchanged(&self, cloc: &ChunkPos) -> bool {
+        match &self.changed_chunks {
+            Some(changed) => !changed.get(&cloc.region()).is_some_and(|c| c.contains(cloc)),
+            None => false,
+        }
+    }
+    /// Write the changed chunk list of a comparison as `chunk_x,chunk_z,region_x,region_z` lines.
+    pub fn write_changed_chunks(&self, path: &Path) -> std::io::Result<()> {
+        let mut chunks: Vec<&ChunkPos> = self.changed_chunks.iter().flat_map(|c| c.values()).flatten().collect();
+        chunks.sort();
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        writeln!(file, "chunk_x,chunk_z,region_x,region_z")?;
+        for cloc in chunks {
+            let rloc = cloc.region();
+            writeln!(file, "{},{},{},{}", cloc.x, cloc.z, rloc.x, rloc.z)?;
+        }
+        Ok(())
+    }
     #[allow(dead_code)]
     pub fn save_cache_all(&self) -> std::io::Result<()> {
         for rloc in self.timestamps.keys() {
-            self.save_cache(&rloc)?;
+            self.save_cache(&rloc, &[])?;
         }
         Ok(())
     }
-    pub fn save_cache(&self, rloc: &RLoc) -> std::io::Result<()> {
-        if self.cache_ro { return Ok(()); }
+    /// Save the timestamps of `rloc` as its cache. Chunks in `stale` (e.g. ones
+    /// that failed to render) are saved without a timestamp so that the next
+    /// run picks them up again.
+    pub fn save_cache(&self, rloc: &RegionPos, stale: &[ChunkPos]) -> std::io::Result<()> {
+        if self.cache_ro.load(Ordering::Relaxed) { return Ok(()); }
         if let Some(timestamps) = self.timestamps.get(rloc) {
-            info!("save {} {}", rloc.0, rloc.1);
-            let filepath = self.cache_path.join(to_cache_name(&rloc));
-            let mut file = OpenOptions::new()
-                            .write(true)
-                            .create(true)
-                            .open(filepath)?;
-            timestamps.save_cache(&mut file)?;
+            let merged;
+            let timestamps = match self.merge_caches.get(rloc) {
+                Some(cache) => {
+                    let rendered = self.render_regions.get(rloc).into_iter().flatten().map(ChunkPos::local);
+                    merged = cache.with_chunks_from(timestamps, rendered);
+                    &merged
+                },
+                None => timestamps,
+            };
+            let masked = self.masked_chunks.get(rloc).map_or(&[][..], Vec::as_slice);
+            let cleared;
+            let timestamps = if stale.is_empty() && masked.is_empty() { timestamps } else {
+                cleared = timestamps.with_cleared(stale.iter().chain(masked).map(ChunkPos::local));
+                &cleared
+            };
+            info!("save {} {}", rloc.x, rloc.z);
+            trace_region!(rloc, "cache saved, stale chunks kept for the next run: {:?}", stale);
+            let hashes = self.chunk_hashes.get(rloc).map(|hashes| {
+                let mut hashes = hashes.clone();
+                for cloc in stale.iter().chain(masked) {
+                    let (x, z) = cloc.local();
+                    hashes[z * 32 + x] = 0;
+                }
+                hashes
+            });
+            let mut data = vec![];
+            timestamps.save_cache(&mut data, self.palette_hash, hashes.as_deref())?;
+            self.cache.write(rloc, &data, self.durability)?;
         }
         Ok(())
     }
+    /// Like `save_cache`, but if the cache cannot be written (read-only mount,
+    /// permissions) switch to ReadOnly cache semantics for the rest of the run
+    /// instead of failing the region.
+    pub fn save_cache_or_degrade(&self, rloc: &RegionPos, stale: &[ChunkPos]) {
+        if let Err(e) = self.save_cache(rloc, stale) {
+            if !self.cache_ro.swap(true, Ordering::SeqCst) {
+                warn!("cannot write cache for region {}: {}; continuing with read-only cache", rloc, e);
+                self.cache_degraded.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+    /// Read the header of a region file again and compare it with the scan.
+    /// Regions without scanned timestamps (comparisons) are not checked.
+    pub fn recheck_region(&self, rloc: &RegionPos) -> RegionCheck {
+        let scanned = match self.timestamps.get(rloc) {
+            Some(scanned) => scanned,
+            None => return RegionCheck::Unchanged,
+        };
+        let path = self.dim_path.join(to_region_name(rloc));
+        match File::open(&path).and_then(|mut file| RegionTimestamps::from_regiondata(&mut file)) {
+            Ok(current) if current == *scanned => RegionCheck::Unchanged,
+            Ok(_) => RegionCheck::Changed,
+            Err(e) => {
+                debug!("region {} cannot be read again: {}", path.display(), e);
+                RegionCheck::Missing
+            },
+        }
+    }
+    /// Number of chunks stored in a region file as scanned.
+    pub fn stored_chunks(&self, rloc: &RegionPos) -> usize {
+        self.timestamps.get(rloc).map_or(0, |t| {
+            (0..32).flat_map(|z| (0..32).map(move |x| (x, z))).filter(|(x, z)| t.timestamp(*x, *z) > 0).count()
+        })
+    }
+    /// Whether each chunk of the region is stored in its file (timestamp
+    /// set in the scanned header), region-local, z-major. None for a region
+    /// that wasn't scanned, such as one only loaded as a shading neighbour.
+    pub fn generated_chunks(&self, rloc: &RegionPos) -> Option<Vec<bool>> {
+        let t = self.timestamps.get(rloc)?;
+        Some((0..32).flat_map(|z| (0..32).map(move |x| (x, z)))
+            .map(|(x, z)| t.timestamp(x, z) > 0).collect())
+    }
+    /// Header timestamp of a chunk as scanned, 0 if unknown.
+    pub fn chunk_timestamp(&self, cloc: &ChunkPos) -> u32 {
+        let (x, z) = cloc.local();
+        self.timestamps.get(&cloc.region()).map_or(0, |t| t.timestamp(x, z))
+    }
+    pub fn cache_writable(&self) -> bool {
+        !self.cache_ro.load(Ordering::Relaxed)
+    }
+    /// Whether `save_cache_or_degrade` had to fall back to a read-only cache.
+    pub fn cache_degraded(&self) -> bool {
+        self.cache_degraded.load(Ordering::SeqCst)
+    }
 }
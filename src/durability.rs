@@ -0,0 +1,50 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use clap::ArgEnum;
+use image::DynamicImage;
+
+use crate::error::Result;
+use crate::indexed_png::PngEncoding;
+
+/// How region images and caches are written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum Durability {
+    /// Write to a temporary file and rename it into place, leaving flushing
+    /// to the OS: a killed process never leaves a half-written file, though
+    /// a power loss still can.
+    #[default]
+    Fast,
+    /// Like `Fast`, but fsync the file and the rename, so a file is either
+    /// the old or the new version even after a crash. Since each region's
+    /// image is written before its cache, a cache is never newer than its image.
+    Safe,
+}
+
+impl Durability {
+    pub fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let mut tmp_name = path.file_name().unwrap().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        file.write_all(data)?;
+        if *self == Durability::Safe {
+            file.sync_all()?;
+        }
+        drop(file);
+        std::fs::rename(&tmp_path, path)?;
+        if *self == Durability::Safe {
+            // Persist the rename itself.
+            if let Some(dir) = path.parent() {
+                let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+                File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: &Path, image: &DynamicImage, encoding: PngEncoding) -> Result<()> {
+        self.write(path, &encoding.encode(image)?)?;
+        Ok(())
+    }
+}
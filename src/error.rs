@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::coords::RegionPos;
+
+/// Errors of scanning and rendering a dimension, and of the files written
+/// around it (tiles, archives, palettes, reports). Chunk-level problems are
+/// not errors: they are reported as `RegionProgress::Error` and the rest of
+/// the region is still drawn.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cannot list region directory {}: {source}", path.display())]
+    ListRegions { path: PathBuf, source: std::io::Error },
+    #[error("cannot open the region file of {rloc}: {message}")]
+    OpenRegion { rloc: RegionPos, message: String },
+    #[error("cannot write {}: {message}", path.display())]
+    WriteImage { path: PathBuf, message: String },
+    #[error("{} already holds rendered regions; give --compare-path its own --image-path", path.display())]
+    CompareOverwrite { path: PathBuf },
+    #[error("cannot create {}: {source}", path.display())]
+    CreateDir { path: PathBuf, source: std::io::Error },
+    #[error("cannot open the caches of {}: {source}", path.display())]
+    OpenCaches { path: PathBuf, source: std::io::Error },
+    #[error("scan of {} failed: {source}", path.display())]
+    Scan { path: PathBuf, source: Box<Error> },
+    #[error("{what}: checksum mismatch, expected {expected}, got {actual}")]
+    Checksum { what: String, expected: String, actual: String },
+    #[error("cannot listen on {addr}: {message}")]
+    Listen { addr: String, message: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Nbt(#[from] fastnbt::error::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    S3(#[from] s3::error::S3Error),
+    #[error(transparent)]
+    Http(Box<ureq::Error>),
+    #[error(transparent)]
+    ParseInt(#[from] std::num::ParseIntError),
+    /// Invalid input, e.g. a config file or a palette missing a part.
+    #[error("{0}")]
+    Message(String),
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        Error::Http(Box::new(e))
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Message(message.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
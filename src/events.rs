@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+
+use crate::dim_renderer::RegionProgress;
+
+/// Channel depth of a subscriber unless given otherwise.
+pub const DEFAULT_BUFFER: usize = 64;
+
+struct Subscriber {
+    sender: SyncSender<RegionProgress>,
+    /// Events the channel had no room for yet, oldest first.
+    backlog: VecDeque<RegionProgress>,
+}
+
+impl Subscriber {
+    /// Queue an event; consecutive steps of one region are merged into one.
+    fn push(&mut self, event: RegionProgress) {
+        use RegionProgress::*;
+        match event {
+            Step(rloc, n) => {
+                for queued in self.backlog.iter_mut().rev() {
+                    match queued {
+                        Step(queued_rloc, queued_n) if *queued_rloc == rloc => {
+                            *queued_n += n;
+                            return;
+                        },
+                        Begin(queued_rloc, _) | End(queued_rloc) if *queued_rloc == rloc => break,
+                        _ => (),
+                    }
+                }
+                self.backlog.push_back(event);
+            },
+            ScanStep(n) => match self.backlog.back_mut() {
+                Some(ScanStep(queued_n)) => *queued_n += n,
+                _ => self.backlog.push_back(event),
+            },
+            _ => self.backlog.push_back(event),
+        }
+    }
+
+    /// Hand over as much of the backlog as the channel takes without
+    /// blocking. Returns false once the subscriber hung up.
+    fn flush(&mut self) -> bool {
+        while let Some(event) = self.backlog.pop_front() {
+            match self.sender.try_send(event) {
+                Ok(()) => (),
+                Err(TrySendError::Full(event)) => {
+                    self.backlog.push_front(event);
+                    return true;
+                },
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Subscriber {
+    /// The render is over; whatever is left is delivered, now waiting for room.
+    fn drop(&mut self) {
+        for event in self.backlog.drain(..) {
+            if self.sender.send(event).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Publishes render progress to any number of subscribers (progress display,
+/// status writers, notifiers, ...). Publishing never blocks: each subscriber
+/// has a bounded channel, and what doesn't fit waits in a backlog where steps
+/// of the same region are merged, so a slow consumer sees fewer, larger steps
+/// instead of stalling the render workers. Subscribers that hang up are
+/// dropped. A subscription ends once every clone of the bus has been dropped.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "EventBus({} subscribers)", self.subscribers.lock().unwrap().len())
+    }
+}
+
+impl EventBus {
+    /// Receive every event published from now on, through a channel of `buffer` events.
+    pub fn subscribe(&self, buffer: usize) -> Receiver<RegionProgress> {
+        let (sender, receiver) = sync_channel(buffer.max(1));
+        self.subscribers.lock().unwrap().push(Subscriber { sender, backlog: Default::default() });
+        receiver
+    }
+
+    pub fn publish(&self, event: RegionProgress) {
+        self.subscribers.lock().unwrap().retain_mut(|subscriber| {
+            subscriber.push(event.clone());
+            subscriber.flush()
+        });
+    }
+}
@@ -0,0 +1,124 @@
+use clap::ArgEnum;
+use image::RgbaImage;
+
+use crate::coords::{BlockPos, CHUNK_SIZE, REGION_SIZE};
+
+/// Which boundaries `--grid` draws.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum GridKind {
+    Chunk,
+    Region,
+    Both,
+}
+
+/// Chunk lines closer than this many pixels are left out.
+const MIN_CHUNK_SPACING: i32 = 4;
+/// Regions narrower than this many pixels get no label.
+const MIN_LABEL_SPACING: i32 = 48;
+
+/// 3x5 glyphs of the region labels, one row per byte (bit 2 = left column).
+const GLYPHS: [(char, [u8; 5]); 12] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+];
+
+/// Faint chunk and region lines, and optionally region coordinates, drawn
+/// onto published images (tiles, the stitched image). Region images are
+/// never drawn on: they are the pixel cache of the next run.
+#[derive(Debug, Clone)]
+pub struct GridOverlay {
+    pub kind: GridKind,
+    pub labels: bool,
+}
+
+impl GridOverlay {
+    /// Draw onto `image`, whose top-left pixel is at block `origin` and whose
+    /// pixels each cover `scale` blocks. Ungenerated (transparent) pixels stay as they are.
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        let chunk = self.kind != GridKind::Region && CHUNK_SIZE / scale >= MIN_CHUNK_SPACING;
+        let region = self.kind != GridKind::Chunk;
+        // Whether the pixel starting at block `b` covers a boundary every `size` blocks.
+        let on_line = |b: i32, size: i32| b.rem_euclid(size) < scale;
+        let (width, height) = (image.width(), image.height());
+        for y in 0..height {
+            let bz = origin.z + y as i32 * scale;
+            for x in 0..width {
+                let bx = origin.x + x as i32 * scale;
+                let alpha = if region && (on_line(bx, REGION_SIZE) || on_line(bz, REGION_SIZE)) { 0.5 }
+                    else if chunk && (on_line(bx, CHUNK_SIZE) || on_line(bz, CHUNK_SIZE)) { 0.2 }
+                    else { continue };
+                blend(image, x, y, [255, 255, 255], alpha);
+            }
+        }
+        if self.labels && REGION_SIZE / scale >= MIN_LABEL_SPACING {
+            self.draw_labels(image, origin, scale);
+        }
+    }
+
+    /// Write "x,z" near the top-left corner of every region in the image.
+    fn draw_labels(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        let end = origin.offset(image.width() as i32 * scale, image.height() as i32 * scale);
+        let first = origin.region();
+        let last = end.offset(-1, -1).region();
+        for rz in first.z..=last.z {
+            for rx in first.x..=last.x {
+                let corner = crate::coords::RegionPos::new(rx, rz).block_origin() - origin;
+                let (px, py) = (corner.x / scale + 3, corner.z / scale + 3);
+                draw_text(image, px, py, &format!("{},{}", rx, rz));
+            }
+        }
+    }
+}
+
+pub fn blend(image: &mut RgbaImage, x: u32, y: u32, colour: [u8; 3], alpha: f32) {
+    let px = image.get_pixel_mut(x, y);
+    if px[3] == 0 {
+        return;
+    }
+    for (c, l) in px.0.iter_mut().zip(colour.iter()) {
+        *c = (*c as f32 * (1.0 - alpha) + *l as f32 * alpha).round() as u8;
+    }
+}
+
+/// White glyphs with a dark outline, clipped to the image.
+fn draw_text(image: &mut RgbaImage, x: i32, y: i32, text: &str) {
+    let mut put = |px: i32, py: i32, colour: [u8; 4]| {
+        if px >= 0 && py >= 0 && (px as u32) < image.width() && (py as u32) < image.height() {
+            image.put_pixel(px as u32, py as u32, image::Rgba(colour));
+        }
+    };
+    for pass in 0..2 {
+        for (i, c) in text.chars().enumerate() {
+            let glyph = match GLYPHS.iter().find(|(g, _)| *g == c) {
+                Some((_, glyph)) => glyph,
+                None => continue,
+            };
+            let gx = x + i as i32 * 4;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) == 0 {
+                        continue;
+                    }
+                    let (px, py) = (gx + col, y + row as i32);
+                    if pass == 0 {
+                        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                            put(px + dx, py + dy, [0, 0, 0, 255]);
+                        }
+                    } else {
+                        put(px, py, [255, 255, 255, 255]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use serde::Deserialize;
+
+use crate::coords::{BlockPos, ChunkPos, CHUNK_SIZE};
+use crate::dimension::DimensionKind;
+use crate::durability::Durability;
+use crate::error::Result;
+
+/// `--build-areas`: where large changes are expected and never alerted on.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BuildArea {
+    #[serde(default)]
+    pub name: String,
+    /// "overworld", "nether" or "end"; every dimension when missing.
+    pub dimension: Option<String>,
+    pub x1: i32,
+    pub z1: i32,
+    pub x2: i32,
+    pub z2: i32,
+}
+
+impl BuildArea {
+    fn covers(&self, kind: DimensionKind, cloc: &ChunkPos) -> bool {
+        if self.dimension.as_deref().map_or(false, |id| id != kind.id()) {
+            return false;
+        }
+        // Any overlap: a build's edge chunks change with it.
+        let origin = cloc.block_origin();
+        origin.x <= self.x1.max(self.x2) && self.x1.min(self.x2) < origin.x + CHUNK_SIZE
+            && origin.z <= self.z1.max(self.z2) && self.z1.min(self.z2) < origin.z + CHUNK_SIZE
+    }
+}
+
+pub fn load_areas(path: &Path) -> Result<Vec<BuildArea>> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+/// `--grief-alert` and the options that go with it.
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    /// Alert when more than this many chunks changed together.
+    pub chunks: usize,
+    /// Chunks at most this far apart (in chunks) count as together.
+    pub radius: i32,
+    /// Surface columns that must change for a chunk to count.
+    pub min_columns: u64,
+}
+
+/// Chunks that changed together this run, outside every build area.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// The chunk the changes cluster around.
+    pub centre: ChunkPos,
+    pub chunks: Vec<ChunkPos>,
+    pub columns: u64,
+}
+
+impl Alert {
+    fn to_json(&self, kind: DimensionKind) -> serde_json::Value {
+        let centre = self.centre.block_origin() + BlockPos::new(CHUNK_SIZE / 2, CHUNK_SIZE / 2);
+        serde_json::json!({
+            "dimension": kind.id(),
+            "x": centre.x,
+            "z": centre.z,
+            "chunks": self.chunks.len(),
+            "changed_columns": self.columns,
+            "chunk_list": self.chunks.iter().map(|cloc| [cloc.x, cloc.z]).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Clusters of more than `rule.chunks` changed chunks within `rule.radius`
+/// chunks of one of them, densest first. `changed` holds the surface
+/// columns each chunk changed by; chunks of a build area are left out, and
+/// every chunk is in one alert at most.
+pub fn find(changed: &HashMap<ChunkPos, u64>, kind: DimensionKind, areas: &[BuildArea], rule: &AlertRule) -> Vec<Alert> {
+    let mut left: Vec<(ChunkPos, u64)> = changed.iter()
+        .filter(|(cloc, columns)| **columns >= rule.min_columns && !areas.iter().any(|area| area.covers(kind, cloc)))
+        .map(|(cloc, columns)| (*cloc, *columns))
+        .collect();
+    left.sort_by_key(|(cloc, _)| (cloc.z, cloc.x));
+    let near = |a: &ChunkPos, b: &ChunkPos| (a.x - b.x).abs() <= rule.radius && (a.z - b.z).abs() <= rule.radius;
+    let mut alerts = vec![];
+    loop {
+        let densest = left.iter()
+            .map(|(centre, _)| (*centre, left.iter().filter(|(cloc, _)| near(centre, cloc)).count()))
+            .max_by_key(|(_, count)| *count);
+        let centre = match densest {
+            Some((centre, count)) if count > rule.chunks => centre,
+            _ => break,
+        };
+        let (cluster, rest): (Vec<_>, Vec<_>) = left.into_iter().partition(|(cloc, _)| near(&centre, cloc));
+        left = rest;
+        alerts.push(Alert {
+            centre,
+            columns: cluster.iter().map(|(_, columns)| columns).sum(),
+            chunks: cluster.into_iter().map(|(cloc, _)| cloc).collect(),
+        });
+    }
+    alerts
+}
+
+pub fn write_json(path: &Path, kind: DimensionKind, alerts: &[Alert]) -> std::io::Result<()> {
+    let json = serde_json::json!({
+        "generated": chrono::Local::now().to_rfc3339(),
+        "alerts": alerts.iter().map(|alert| alert.to_json(kind)).collect::<Vec<_>>(),
+    });
+    Durability::Fast.write(path, &serde_json::to_vec_pretty(&json).unwrap())
+}
+
+/// POST the alerts to `url` as JSON; `text` and `content` carry a summary
+/// for chat webhooks (Slack, Discord) that show nothing else.
+pub fn post(url: &str, kind: DimensionKind, alerts: &[Alert]) -> Result<()> {
+    let lines: Vec<String> = alerts.iter().map(|alert| {
+        let centre = alert.centre.block_origin();
+        format!("{} chunks changed around {},{} in the {} ({} surface blocks)", alert.chunks.len(), centre.x, centre.z, kind.id(), alert.columns)
+    }).collect();
+    let text = format!("Possible griefing:\n{}", lines.join("\n"));
+    let body = serde_json::json!({
+        "text": text,
+        "content": text,
+        "alerts": alerts.iter().map(|alert| alert.to_json(kind)).collect::<Vec<_>>(),
+    });
+    ureq::post(url).set("Content-Type", "application/json").send_string(&body.to_string())?;
+    Ok(())
+}
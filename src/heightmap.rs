@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use clap::ArgEnum;
+use fastanvil::{Chunk, HeightMode};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use image::{DynamicImage, ImageBuffer, LumaA, Rgba};
+
+use crate::coords::{ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+use crate::selection::Selection;
+
+const HEIGHTS_MAGIC: &[u8; 4] = b"MCHT";
+/// Column without a rendered chunk.
+const NO_HEIGHT: i16 = i16::MIN;
+/// Y range mapped onto the full range of the grayscale formats.
+const MIN_Y: i32 = -64;
+const MAX_Y: i32 = 320;
+
+pub fn to_heights_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.heights", rloc.x, rloc.z)
+}
+
+pub fn to_heightmap_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.height.png", rloc.x, rloc.z)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum HeightmapFormat {
+    /// 8-bit grayscale, Y -64..320 over 0..255
+    Gray8,
+    /// 16-bit grayscale, Y -64..320 over 0..65535
+    Gray16,
+    /// RGBA through a colour gradient (hypsometric tint)
+    Tint,
+}
+
+/// Colour stops of the tint format, sorted by Y.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<(i32, [u8; 3])>,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        "-64:1a1a40,0:2b4c8c,62:4f86c6,63:3c8c3c,90:8cb45a,130:c8b478,180:8c6e50,230:ffffff".parse().unwrap()
+    }
+}
+
+impl FromStr for Gradient {
+    type Err = String;
+
+    /// "Y:RRGGBB,Y:RRGGBB,..." (a leading '#' on the colour is allowed).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stops = vec![];
+        for stop in s.split(',') {
+            let (y, colour) = stop.trim().split_once(':').ok_or_else(|| format!("invalid gradient stop \"{}\"", stop))?;
+            let y: i32 = y.trim().parse().map_err(|_| format!("invalid gradient Y \"{}\"", y))?;
+            let colour = colour.trim().trim_start_matches('#');
+            let rgb = u32::from_str_radix(colour, 16).ok().filter(|_| colour.len() == 6)
+                .ok_or_else(|| format!("invalid gradient colour \"{}\"", colour))?;
+            stops.push((y, [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]));
+        }
+        stops.sort_by_key(|stop| stop.0);
+        Ok(Gradient { stops })
+    }
+}
+
+impl Gradient {
+    fn colour(&self, y: i32) -> [u8; 3] {
+        let first = self.stops[0];
+        if y <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((y0, c0), (y1, c1)) = (pair[0], pair[1]);
+            if y <= y1 {
+                let t = (y - y0) as f32 / (y1 - y0).max(1) as f32;
+                return [0, 1, 2].map(|i| (c0[i] as f32 + (c1[i] as f32 - c0[i] as f32) * t).round() as u8);
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeightmapOptions {
+    pub format: HeightmapFormat,
+    pub gradient: Gradient,
+}
+
+/// Surface Y of every column of a region, kept in the cache path so
+/// regions can be updated chunk by chunk like the map images.
+pub struct Heights {
+    columns: Vec<i16>,
+}
+
+impl Heights {
+    pub fn empty() -> Self {
+        Heights { columns: vec![NO_HEIGHT; (REGION_SIZE * REGION_SIZE) as usize] }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut reader = GzDecoder::new(BufReader::new(File::open(path)?));
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != HEIGHTS_MAGIC {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a heights cache"));
+        }
+        let mut heights = Self::empty();
+        let mut buf = [0u8; 2];
+        for column in heights.columns.iter_mut() {
+            reader.read_exact(&mut buf)?;
+            *column = i16::from_le_bytes(buf);
+        }
+        Ok(heights)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::fast());
+        writer.write_all(HEIGHTS_MAGIC)?;
+        for column in &self.columns {
+            writer.write_all(&column.to_le_bytes())?;
+        }
+        writer.finish()?.flush()
+    }
+
+    /// Store the surface Y (of the top block) of every column of `chunk`.
+    pub fn record_chunk<C: Chunk>(&mut self, cloc: &ChunkPos, chunk: &C, mode: HeightMode) {
+        let (cx, cz) = cloc.local();
+        let chunk_size = CHUNK_SIZE as usize;
+        for z in 0..chunk_size {
+            for x in 0..chunk_size {
+                let y = chunk.surface_height(x, z, mode) - 1;
+                let index = (cz * chunk_size + z) * REGION_SIZE as usize + cx * chunk_size + x;
+                self.columns[index] = y as i16;
+            }
+        }
+    }
+
+    /// Forget the columns of `cloc`, a chunk that is gone.
+    pub fn clear_chunk(&mut self, cloc: &ChunkPos) {
+        let (cx, cz) = cloc.local();
+        let chunk_size = CHUNK_SIZE as usize;
+        for z in 0..chunk_size {
+            let start = (cz * chunk_size + z) * REGION_SIZE as usize + cx * chunk_size;
+            self.columns[start..start + chunk_size].fill(NO_HEIGHT);
+        }
+    }
+
+    /// Surface Y of a region-local column, if its chunk was recorded.
+    pub fn get(&self, x: usize, z: usize) -> Option<i16> {
+        Some(self.columns[z * REGION_SIZE as usize + x]).filter(|y| *y != NO_HEIGHT)
+    }
+
+    /// Forget the columns outside `bounds`.
+    pub fn clear_outside(&mut self, rloc: &RegionPos, selection: &Selection) {
+        let origin = rloc.block_origin();
+        for (i, column) in self.columns.iter_mut().enumerate() {
+            let block = origin.offset(i as i32 % REGION_SIZE, i as i32 / REGION_SIZE);
+            if !selection.contains(&block) {
+                *column = NO_HEIGHT;
+            }
+        }
+    }
+
+    /// Encode the heights; columns without data are transparent.
+    pub fn to_image(&self, options: &HeightmapOptions) -> DynamicImage {
+        let size = REGION_SIZE as u32;
+        let scale = |y: i16, max: u32| -> u32 {
+            let y = (y as i32).clamp(MIN_Y, MAX_Y - 1);
+            ((y - MIN_Y) as u64 * max as u64 / (MAX_Y - MIN_Y - 1) as u64) as u32
+        };
+        match options.format {
+            HeightmapFormat::Gray8 => DynamicImage::ImageLumaA8(ImageBuffer::from_fn(size, size, |x, z| {
+                match self.columns[(z * size + x) as usize] {
+                    NO_HEIGHT => LumaA([0, 0]),
+                    y => LumaA([scale(y, u8::MAX as u32) as u8, u8::MAX]),
+                }
+            })),
+            HeightmapFormat::Gray16 => DynamicImage::ImageLumaA16(ImageBuffer::from_fn(size, size, |x, z| {
+                match self.columns[(z * size + x) as usize] {
+                    NO_HEIGHT => LumaA([0, 0]),
+                    y => LumaA([scale(y, u16::MAX as u32) as u16, u16::MAX]),
+                }
+            })),
+            HeightmapFormat::Tint => DynamicImage::ImageRgba8(ImageBuffer::from_fn(size, size, |x, z| {
+                match self.columns[(z * size + x) as usize] {
+                    NO_HEIGHT => Rgba([0, 0, 0, 0]),
+                    y => {
+                        let [r, g, b] = options.gradient.colour(y as i32);
+                        Rgba([r, g, b, u8::MAX])
+                    }
+                }
+            })),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::path::Path;
+use fastanvil::{Chunk, Rgba};
+use image::RgbaImage;
+use serde::Deserialize;
+
+use crate::coords::{ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+use crate::error::Result;
+
+/// Matching blocks in a column at which its pixel is fully opaque.
+const SATURATION: u32 = 4;
+
+pub fn to_highlight_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.highlight.png", rloc.x, rloc.z)
+}
+
+#[derive(Deserialize)]
+struct HighlightFile {
+    /// Lowest Y searched, the bottom of the world by default.
+    min_y: Option<isize>,
+    /// Highest Y searched, the top of the world by default.
+    max_y: Option<isize>,
+    /// Block id to "RRGGBB" colour.
+    blocks: HashMap<String, String>,
+}
+
+/// `--highlight`: blocks of interest (ores, spawners, ...) looked for in
+/// every column, drawn as `r.X.Z.highlight.png` next to the region images:
+/// transparent where there are none, more opaque the more a column holds.
+#[derive(Debug)]
+pub struct Highlight {
+    min_y: Option<isize>,
+    max_y: Option<isize>,
+    colours: HashMap<String, [u8; 3]>,
+}
+
+impl Highlight {
+    /// `{"min_y": -64, "max_y": 16, "blocks": {"minecraft:diamond_ore": "00ffff"}}`
+    pub fn load(path: &Path) -> Result<Self> {
+        let file: HighlightFile = serde_json::from_reader(std::fs::File::open(path)?)?;
+        let mut colours = HashMap::new();
+        for (block, colour) in file.blocks {
+            let hex = colour.trim_start_matches('#');
+            let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)
+                .ok_or_else(|| format!("invalid colour \"{}\" for {}", colour, block))?;
+            colours.insert(block, [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]);
+        }
+        if colours.is_empty() {
+            return Err("no blocks to highlight".into());
+        }
+        Ok(Highlight { min_y: file.min_y, max_y: file.max_y, colours })
+    }
+
+    /// The previous image of `rloc`, so chunks not rendered this run keep
+    /// their pixels; blank when there is none.
+    pub fn load_image(&self, image_path: &Path, rloc: &RegionPos) -> RgbaImage {
+        image::open(image_path.join(to_highlight_name(rloc))).ok()
+            .map(|image| image.into_rgba8())
+            .filter(|image| image.dimensions() == (REGION_SIZE as u32, REGION_SIZE as u32))
+            .unwrap_or_else(|| RgbaImage::new(REGION_SIZE as u32, REGION_SIZE as u32))
+    }
+
+    /// Redraw the pixels of `cloc` in its region's `image`.
+    pub fn record_chunk<C: Chunk>(&self, image: &mut RgbaImage, cloc: &ChunkPos, chunk: &C) {
+        let y_range = chunk.y_range();
+        let bottom = self.min_y.map_or(y_range.start, |y| y.max(y_range.start));
+        let top = self.max_y.map_or(y_range.end - 1, |y| y.min(y_range.end - 1));
+        let (cx, cz) = cloc.local();
+        let chunk_size = CHUNK_SIZE as usize;
+        for z in 0..chunk_size {
+            for x in 0..chunk_size {
+                let mut found = 0u32;
+                let mut sum = [0u32; 3];
+                for y in bottom..=top {
+                    let colour = chunk.block(x, y, z).and_then(|block| self.colours.get(block.name()));
+                    if let Some(colour) = colour {
+                        found += 1;
+                        for (s, c) in sum.iter_mut().zip(colour.iter()) {
+                            *s += *c as u32;
+                        }
+                    }
+                }
+                let px: Rgba = if found == 0 { [0; 4] } else {
+                    let alpha = 255 * found.min(SATURATION) / SATURATION;
+                    [(sum[0] / found) as u8, (sum[1] / found) as u8, (sum[2] / found) as u8, alpha as u8]
+                };
+                image.put_pixel((cx * chunk_size + x) as u32, (cz * chunk_size + z) as u32, image::Rgba(px));
+            }
+        }
+    }
+}
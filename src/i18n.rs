@@ -0,0 +1,166 @@
+use std::sync::RwLock;
+use std::time::Duration;
+use clap::ArgEnum;
+use lazy_static::lazy_static;
+
+/// Language of progress and summary messages. Logs and JSON events stay in English.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    Ja,
+}
+
+lazy_static! {
+    static ref LANG: RwLock<Lang> = RwLock::new(Lang::En);
+}
+
+/// Use `lang`, or else the one of the environment: MCANVIL_LANG, then
+/// LC_ALL, LC_MESSAGES and LANG ("ja_JP.UTF-8" is Japanese).
+pub fn set_lang(lang: Option<Lang>) {
+    let lang = lang.unwrap_or_else(|| {
+        ["MCANVIL_LANG", "LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+            .map_or(Lang::En, |value| if value.starts_with("ja") { Lang::Ja } else { Lang::En })
+    });
+    *LANG.write().unwrap() = lang;
+}
+
+pub fn lang() -> Lang {
+    *LANG.read().unwrap()
+}
+
+impl Lang {
+    /// Language code, as in `?lang=` of the viewer.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Ja => "ja",
+        }
+    }
+}
+
+/// Japanese messages, keyed by the English one as written at the call site.
+/// `{}` takes the next argument and `{N}` the N-th, for a different word order.
+const JA: &[(&str, &str)] = &[
+    ("Total", "全体"),
+    ("Scan", "スキャン"),
+    ("Total OK", "全体 完了"),
+    ("Region: ", "リージョン: "),
+    ("Error region:{} chunk:{} could not be read", "エラー リージョン:{} チャンク:{} を読み込めませんでした"),
+    ("Failed region:{} {}", "失敗 リージョン:{} {}"),
+    ("Warning region:{} {}", "警告 リージョン:{} {}"),
+    ("Begin region:{} / chunks: {}", "開始 リージョン:{} / チャンク数: {}"),
+    ("End region:{}", "終了 リージョン:{}"),
+    ("Scan regions: {}", "スキャン リージョン数: {}"),
+    ("Scan done.", "スキャン完了。"),
+    ("Begin total chunks: {}", "開始 総チャンク数: {}"),
+    ("End all.", "すべて完了。"),
+    ("Dimension: {}", "ディメンション: {}"),
+    ("Palette aliases used for renamed blocks: {}", "名前が変わったブロックにパレットの別名を使用: {}"),
+    ("Resuming: {} regions were done by the interrupted run.", "再開: 中断された実行で {} リージョンが完了済みです。"),
+    ("Nothing changed, skipped.", "変更がないためスキップしました。"),
+    ("Broken chunks: {} failed this run, {} skipped, {} on the skip list (--retry-broken to retry).",
+        "壊れたチャンク: 今回の失敗 {}、スキップ {}、スキップリスト登録 {} (--retry-broken で再試行)。"),
+    ("Note: cache directory was not writable, caches were not updated in this run.",
+        "注意: キャッシュディレクトリに書き込めないため、今回はキャッシュを更新していません。"),
+    ("{} regions could not be written, their caches were kept for the next run:",
+        "{} リージョンを書き込めませんでした。次回のためにキャッシュは残しています:"),
+    ("{} outputs could not be written:", "{} 個の出力を書き込めませんでした:"),
+    ("Prune: {} files of {} deleted regions would be removed.",
+        "削除: 削除された {1} リージョンのファイル {0} 個が削除対象です。"),
+    ("Prune: removed {} files of {} deleted regions.",
+        "削除: 削除された {1} リージョンのファイル {0} 個を削除しました。"),
+    ("Rendered {} regions ({} chunks) in {}.", "{} リージョン ({} チャンク) を {} で描画しました。"),
+    ("Interrupted: {} regions ({} chunks) were left for the next run.",
+        "中断: {} リージョン ({} チャンク) を次回に残しました。"),
+    ("World border: skipped {} regions outside it.", "ワールドボーダー: 外側の {} リージョンをスキップしました。"),
+    ("Batch {}/{}", "バッチ {}/{}"),
+    ("Job: {}", "ジョブ: {}"),
+    ("Batch: {} of {} jobs rendered {} regions, {} failed.", "バッチ: {1} ジョブのうち {0} ジョブで {2} リージョンを描画、{3} リージョンが失敗しました。"),
+    ("Uploaded {} of {} changed files.", "変更された {1} ファイルのうち {0} ファイルをアップロードしました。"),
+    // Legend of the viewer.
+    ("map", "マップ"),
+    ("overworld", "オーバーワールド"),
+    ("nether", "ネザー"),
+    ("end", "エンド"),
+    ("{} markers", "{}のマーカー"),
+];
+
+/// The message for the current language, `{}` and `{N}` still to fill.
+pub fn translate(message: &'static str) -> &'static str {
+    translate_in(lang(), message)
+}
+
+/// The message in `lang`, e.g. for output read in every language.
+pub fn translate_in(lang: Lang, message: &str) -> &str {
+    match lang {
+        Lang::En => message,
+        Lang::Ja => JA.iter().find(|(en, _)| *en == message).map_or(message, |(_, ja)| ja),
+    }
+}
+
+/// Fill the placeholders of a translated message.
+pub fn fill(template: &str, args: &[String]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        let index = match placeholder {
+            Some("") => { next += 1; Some(next - 1) },
+            Some(digits) => digits.parse::<usize>().ok(),
+            None => None,
+        };
+        match (placeholder, index.and_then(|i| args.get(i))) {
+            (Some(placeholder), Some(arg)) => {
+                out.push_str(arg);
+                rest = &rest[start + placeholder.len() + 2..];
+            },
+            _ => {
+                out.push('{');
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Translate a message and fill its placeholders, like `format!`.
+macro_rules! tr {
+    ($message:literal) => {
+        crate::i18n::translate($message).to_string()
+    };
+    ($message:literal, $($arg:expr),+ $(,)?) => {
+        crate::i18n::fill(crate::i18n::translate($message), &[$($arg.to_string()),+])
+    };
+}
+
+/// A count with thousands separators ("12,345"; the same in both languages).
+pub fn number(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// A duration to the second: "1h 02m 03s", or "1時間2分3秒" in Japanese.
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (lang(), hours, minutes) {
+        (Lang::En, 0, 0) => format!("{}s", seconds),
+        (Lang::En, 0, _) => format!("{}m {:02}s", minutes, seconds),
+        (Lang::En, _, _) => format!("{}h {:02}m {:02}s", hours, minutes, seconds),
+        (Lang::Ja, 0, 0) => format!("{}秒", seconds),
+        (Lang::Ja, 0, _) => format!("{}分{}秒", minutes, seconds),
+        (Lang::Ja, _, _) => format!("{}時間{}分{}秒", hours, minutes, seconds),
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Region image buffers (RGBA8, row by row), handed back once a region is
+/// saved and reused for the next one instead of allocating, filling and
+/// copying a fresh one per region. There are never more than the workers.
+#[derive(Default)]
+pub struct ImagePool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl ImagePool {
+    /// A buffer of `size`×`size` pixels, with whatever the last region left in it.
+    pub fn take(&self, size: usize) -> Vec<u8> {
+        let mut buf = self.free.lock().unwrap().pop().unwrap_or_default();
+        buf.resize(size * size * 4, 0);
+        buf
+    }
+
+    pub fn give(&self, buf: Vec<u8>) {
+        self.free.lock().unwrap().push(buf);
+    }
+}
+
+/// What `read_into` found of a previous region image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Previous {
+    Read,
+    Missing,
+    OtherSize,
+    NotRgba,
+}
+
+/// Decode the PNG at `path` straight into `buf` if it is `size`×`size`
+/// RGBA8 (indexed images with transparency are expanded to it). `buf`
+/// holds nothing of use unless it returns `Previous::Read`.
+pub fn read_into(path: &Path, size: usize, buf: &mut [u8]) -> Previous {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Previous::Missing,
+    };
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(_) => return Previous::NotRgba,
+    };
+    let info = reader.info();
+    if (info.width as usize, info.height as usize) != (size, size) {
+        return Previous::OtherSize;
+    }
+    if reader.output_color_type() != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        return Previous::NotRgba;
+    }
+    match reader.next_frame(buf) {
+        Ok(_) => Previous::Read,
+        Err(_) => Previous::NotRgba,
+    }
+}
@@ -0,0 +1,90 @@
+use image::{DynamicImage, RgbaImage};
+
+use crate::error::Result;
+
+/// Sampling factor of the quantizer: 1 is the slowest and best, 30 the fastest.
+const SAMPLE_FACTOR: i32 = 10;
+
+/// How map images are encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PngEncoding {
+    /// Keep the image's own pixel format.
+    #[default]
+    Plain,
+    /// Reduce each image to its own 256 colour palette, optionally with
+    /// Floyd-Steinberg dithering.
+    Indexed { dither: bool },
+}
+
+impl PngEncoding {
+    pub fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>> {
+        match self {
+            PngEncoding::Plain => {
+                let mut data = vec![];
+                image.write_to(&mut data, image::ImageOutputFormat::Png)?;
+                Ok(data)
+            },
+            PngEncoding::Indexed { dither } => encode_indexed(&image.to_rgba8(), *dither),
+        }
+    }
+}
+
+fn encode_indexed(image: &RgbaImage, dither: bool) -> Result<Vec<u8>> {
+    let quant = color_quant::NeuQuant::new(SAMPLE_FACTOR, 256, image.as_raw());
+    let indices = if dither { dithered_indices(image, &quant) } else {
+        image.pixels().map(|p| quant.index_of(&p.0) as u8).collect()
+    };
+    let colour_map = quant.color_map_rgba();
+    let palette: Vec<u8> = colour_map.chunks(4).flat_map(|c| c[..3].to_vec()).collect();
+    let alpha: Vec<u8> = colour_map.chunks(4).map(|c| c[3]).collect();
+
+    let mut data = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut data, image.width(), image.height());
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette);
+        encoder.set_trns(alpha);
+        encoder.write_header()?.write_image_data(&indices)?;
+    }
+    Ok(data)
+}
+
+/// Palette indices with the quantization error of each pixel spread over
+/// its unvisited neighbours. Transparent pixels neither take nor spread error.
+fn dithered_indices(image: &RgbaImage, quant: &color_quant::NeuQuant) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let colour_map = quant.color_map_rgba();
+    let mut error = vec![[0f32; 3]; width * height];
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let p = image.get_pixel(x as u32, y as u32).0;
+            if p[3] == 0 {
+                indices.push(quant.index_of(&p) as u8);
+                continue;
+            }
+            let wanted = [0, 1, 2].map(|c| (p[c] as f32 + error[i][c]).clamp(0.0, 255.0));
+            let index = quant.index_of(&[wanted[0] as u8, wanted[1] as u8, wanted[2] as u8, p[3]]);
+            indices.push(index as u8);
+            let got = &colour_map[index * 4..index * 4 + 3];
+            let diff = [0, 1, 2].map(|c| wanted[c] - got[c] as f32);
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx < 0 || nx as usize >= width || y + dy >= height {
+                    return;
+                }
+                let n = (y + dy) * width + nx as usize;
+                for (e, d) in error[n].iter_mut().zip(diff.iter()) {
+                    *e += d * weight;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    indices
+}
@@ -0,0 +1,141 @@
+use clap::ArgEnum;
+use fastanvil::{Chunk, HeightMode, Palette, Rgba};
+use image::RgbaImage;
+
+use crate::coords::{ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+
+/// Half the width of a block's top face, in pixels; the face is a 2:1 diamond.
+const HALF_WIDTH: u32 = 2;
+/// Pixels per block of height.
+const BLOCK_HEIGHT: u32 = 2;
+/// Y range drawn; taller columns are clipped.
+const MIN_Y: isize = -64;
+const MAX_Y: isize = 320;
+/// Brightness of the side faces facing left and right.
+const LEFT_SHADE: f32 = 0.75;
+const RIGHT_SHADE: f32 = 0.55;
+
+pub fn to_isometric_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.iso.png", rloc.x, rloc.z)
+}
+
+/// Which way the camera looks; that side of the region is at the top of the image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum ViewDirection {
+    #[default]
+    North,
+    East,
+    South,
+    West,
+}
+
+impl ViewDirection {
+    /// Region-local (x, z) to view (u, v); u runs to the right-down and v to
+    /// the left-down of the image, so (0, 0) is its farthest point.
+    fn rotate(&self, x: i32, z: i32) -> (i32, i32) {
+        let last = REGION_SIZE - 1;
+        match self {
+            ViewDirection::North => (x, z),
+            ViewDirection::East => (z, last - x),
+            ViewDirection::South => (last - x, last - z),
+            ViewDirection::West => (last - z, x),
+        }
+    }
+}
+
+/// Top block of a column: the Y above it and its colour.
+#[derive(Clone, Copy)]
+pub struct Column {
+    pub top: isize,
+    pub colour: Rgba,
+}
+
+/// Top blocks of every column of a region, row by row.
+pub struct RegionColumns {
+    columns: Vec<Option<Column>>,
+}
+
+impl RegionColumns {
+    pub fn empty() -> Self {
+        RegionColumns { columns: vec![None; (REGION_SIZE * REGION_SIZE) as usize] }
+    }
+
+    pub fn record_chunk<C: Chunk, P: Palette>(&mut self, cloc: &ChunkPos, chunk: &C, palette: &P, mode: HeightMode) {
+        let (cx, cz) = cloc.local();
+        let chunk_size = CHUNK_SIZE as usize;
+        for z in 0..chunk_size {
+            for x in 0..chunk_size {
+                let top = chunk.surface_height(x, z, mode);
+                let index = (cz * chunk_size + z) * REGION_SIZE as usize + cx * chunk_size + x;
+                self.columns[index] = chunk.block(x, top - 1, z).map(|block| Column {
+                    top,
+                    colour: palette.pick(block, chunk.biome(x, top - 1, z)),
+                });
+            }
+        }
+    }
+
+    fn at(&self, x: i32, z: i32) -> Option<Column> {
+        if x < 0 || z < 0 || x >= REGION_SIZE || z >= REGION_SIZE {
+            return None;
+        }
+        self.columns[(z * REGION_SIZE + x) as usize]
+    }
+
+    /// Draw the region as a 2:1 isometric image seen from `direction`: the
+    /// top face of every column plus the side faces its front neighbours
+    /// leave uncovered, painted back to front.
+    pub fn render(&self, direction: ViewDirection) -> RgbaImage {
+        let size = REGION_SIZE as u32;
+        let width = size * 2 * HALF_WIDTH;
+        let height = size * 2 + (MAX_Y - MIN_Y) as u32 * BLOCK_HEIGHT;
+        let mut image = RgbaImage::new(width, height);
+        // View position back to region position.
+        let mut by_view = vec![None; (size * size) as usize];
+        for z in 0..REGION_SIZE {
+            for x in 0..REGION_SIZE {
+                let (u, v) = direction.rotate(x, z);
+                by_view[(v * REGION_SIZE + u) as usize] = self.at(x, z);
+            }
+        }
+        let view = |u: i32, v: i32| -> Option<Column> {
+            if u < 0 || v < 0 || u >= REGION_SIZE || v >= REGION_SIZE { None } else { by_view[(v * REGION_SIZE + u) as usize] }
+        };
+        for depth in 0..(REGION_SIZE * 2 - 1) {
+            for u in 0.max(depth - REGION_SIZE + 1)..=depth.min(REGION_SIZE - 1) {
+                let v = depth - u;
+                let column = match view(u, v) {
+                    Some(column) => column,
+                    None => continue,
+                };
+                let top = column.top.clamp(MIN_Y, MAX_Y);
+                let sx = ((u - v + REGION_SIZE - 1) as u32) * HALF_WIDTH;
+                let sy = (u + v) as u32 + (MAX_Y - top) as u32 * BLOCK_HEIGHT;
+                // Sides run down to the neighbour in front on that side.
+                let exposed = |neighbour: Option<Column>| -> u32 {
+                    let below = neighbour.map_or(MIN_Y, |n| n.top.clamp(MIN_Y, MAX_Y));
+                    (top - below).max(0) as u32 * BLOCK_HEIGHT
+                };
+                let left = exposed(view(u, v + 1));
+                let right = exposed(view(u + 1, v));
+                fill(&mut image, sx, sy + 1, HALF_WIDTH, left + 1, shade(column.colour, LEFT_SHADE));
+                fill(&mut image, sx + HALF_WIDTH, sy + 1, HALF_WIDTH, right + 1, shade(column.colour, RIGHT_SHADE));
+                fill(&mut image, sx + 1, sy, HALF_WIDTH * 2 - 2, 1, column.colour);
+                fill(&mut image, sx, sy + 1, HALF_WIDTH * 2, 1, column.colour);
+            }
+        }
+        image
+    }
+}
+
+fn shade(colour: Rgba, factor: f32) -> Rgba {
+    [(colour[0] as f32 * factor) as u8, (colour[1] as f32 * factor) as u8, (colour[2] as f32 * factor) as u8, colour[3]]
+}
+
+fn fill(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, colour: Rgba) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, image::Rgba(colour));
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::warn;
+
+use crate::coords::RegionPos;
+
+pub const JOURNAL_NAME: &str = "run-journal.txt";
+/// The journal of serve's interactive lane, which renders on the same cache
+/// path as its batch lane.
+pub const INTERACTIVE_JOURNAL_NAME: &str = "run-journal.interactive.txt";
+
+/// Regions finished by the current run, one "x,z" line each, in the cache
+/// directory. The journal is removed when the run completes, so one left
+/// behind means the run was interrupted and `--resume` can skip what it did.
+pub struct RunJournal {
+    path: PathBuf,
+    done: HashSet<RegionPos>,
+    file: Mutex<File>,
+}
+
+impl RunJournal {
+    /// Start the journal `name`, continuing the one left by an interrupted run if `resume`.
+    pub fn open(cache_path: &Path, name: &str, resume: bool) -> std::io::Result<Self> {
+        let path = cache_path.join(name);
+        let mut done: HashSet<RegionPos> = Default::default();
+        if resume {
+            if let Ok(file) = File::open(&path) {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let parsed = line.split_once(',')
+                        .and_then(|(x, z)| Some(RegionPos::new(x.parse().ok()?, z.parse().ok()?)));
+                    // A line cut short by the interruption is simply not done.
+                    if let Some(rloc) = parsed {
+                        done.insert(rloc);
+                    }
+                }
+            }
+        } else if path.exists() {
+            warn!("the previous run was interrupted; starting over (--resume continues it)");
+        }
+        let file = OpenOptions::new().create(true).write(true).append(resume).truncate(!resume).open(&path)?;
+        Ok(RunJournal { path, done, file: Mutex::new(file) })
+    }
+
+    /// Whether the interrupted run already finished the region.
+    pub fn is_done(&self, rloc: &RegionPos) -> bool {
+        self.done.contains(rloc)
+    }
+
+    /// Regions the interrupted run finished.
+    pub fn done(&self) -> Vec<RegionPos> {
+        self.done.iter().copied().collect()
+    }
+
+    /// Note a region as finished, once its image and cache are written.
+    pub fn record(&self, rloc: &RegionPos) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{},{}", rloc.x, rloc.z) {
+            warn!("cannot write run journal: {}", e);
+        }
+    }
+
+    /// The run completed: nothing to resume.
+    pub fn finish(self) {
+        drop(self.file);
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("cannot remove {}: {}", self.path.display(), e);
+        }
+    }
+}
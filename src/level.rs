@@ -0,0 +1,137 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use crate::coords::{RegionPos, REGION_SIZE};
+use crate::dimension::DimensionKind;
+use crate::error::Result;
+
+/// The subset of `level.dat` the renderer cares about.
+#[derive(Deserialize, Debug)]
+pub struct LevelDat {
+    #[serde(rename = "Data")]
+    pub data: LevelData,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LevelData {
+    /// Missing on worlds saved before 1.9.
+    #[serde(rename = "Version")]
+    pub version: Option<LevelVersion>,
+    /// Missing on worlds saved before 1.9.
+    #[serde(rename = "DataVersion")]
+    pub data_version: Option<i32>,
+    #[serde(rename = "SpawnX")]
+    pub spawn_x: Option<i32>,
+    #[serde(rename = "SpawnY")]
+    pub spawn_y: Option<i32>,
+    #[serde(rename = "SpawnZ")]
+    pub spawn_z: Option<i32>,
+    /// The player of a single-player world, who has no playerdata file.
+    #[serde(rename = "Player")]
+    pub player: Option<PlayerData>,
+    /// The world seed before 1.16.
+    #[serde(rename = "RandomSeed")]
+    pub random_seed: Option<i64>,
+    #[serde(rename = "WorldGenSettings")]
+    pub world_gen_settings: Option<WorldGenSettings>,
+    /// The world border, in overworld blocks.
+    #[serde(rename = "BorderCenterX")]
+    pub border_center_x: Option<f64>,
+    #[serde(rename = "BorderCenterZ")]
+    pub border_center_z: Option<f64>,
+    #[serde(rename = "BorderSize")]
+    pub border_size: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WorldGenSettings {
+    pub seed: i64,
+}
+
+impl LevelData {
+    pub fn seed(&self) -> Option<i64> {
+        self.world_gen_settings.as_ref().map(|settings| settings.seed).or(self.random_seed)
+    }
+
+    /// The world border in `kind`: the nether's is an eighth of the
+    /// overworld's, like its coordinates.
+    pub fn border(&self, kind: DimensionKind) -> Option<WorldBorder> {
+        let size = self.border_size?;
+        let (x, z) = (self.border_center_x.unwrap_or(0.0), self.border_center_z.unwrap_or(0.0));
+        let scale = if kind == DimensionKind::Nether { 8.0 } else { 1.0 };
+        let half = size / 2.0;
+        Some(WorldBorder {
+            min_x: (x - half) / scale,
+            min_z: (z - half) / scale,
+            max_x: (x + half) / scale,
+            max_z: (z + half) / scale,
+        })
+    }
+}
+
+/// The square inside the world border, in blocks of one dimension. Regions
+/// beyond it only hold chunks generated by players teleporting out there.
+#[derive(Debug, Copy, Clone)]
+pub struct WorldBorder {
+    pub min_x: f64,
+    pub min_z: f64,
+    pub max_x: f64,
+    pub max_z: f64,
+}
+
+impl WorldBorder {
+    /// Whether any block of `rloc` is inside the border.
+    pub fn overlaps_region(&self, rloc: &RegionPos) -> bool {
+        let origin = rloc.block_origin();
+        let (x0, z0) = (origin.x as f64, origin.z as f64);
+        let (x1, z1) = (x0 + REGION_SIZE as f64, z0 + REGION_SIZE as f64);
+        x1 > self.min_x && x0 < self.max_x && z1 > self.min_z && z0 < self.max_z
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LevelVersion {
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+/// The subset of `playerdata/<uuid>.dat` (or `level.dat`'s Player) the markers need.
+#[derive(Deserialize, Debug)]
+pub struct PlayerData {
+    #[serde(rename = "Pos")]
+    pub pos: Vec<f64>,
+    /// "minecraft:the_nether" etc. since 1.16, -1/0/1 before.
+    #[serde(rename = "Dimension")]
+    pub dimension: Option<fastnbt::Value>,
+}
+
+fn read_gzip_nbt<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let f = std::fs::File::open(path)?;
+    let mut buf = vec![];
+    GzDecoder::new(f).read_to_end(&mut buf)?;
+    Ok(fastnbt::from_bytes(&buf)?)
+}
+
+impl LevelDat {
+    pub fn from_world(world_path: &Path) -> Result<LevelDat> {
+        read_gzip_nbt(&world_path.join("level.dat"))
+    }
+}
+
+impl PlayerData {
+    pub fn from_file(path: &Path) -> Result<PlayerData> {
+        read_gzip_nbt(path)
+    }
+}
+
+/// Find the world directory (the one holding level.dat) for a region directory.
+/// `world/region`, `world/DIM-1/region` and `world/DIM1/region` are all covered.
+pub fn find_world_root(dim_path: &Path) -> Option<PathBuf> {
+    let dim_path = dim_path.canonicalize().ok()?;
+    dim_path.ancestors()
+        .take(4)
+        .find(|p| p.join("level.dat").is_file())
+        .map(PathBuf::from)
+}
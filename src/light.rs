@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use fastanvil::{Chunk, HeightMode, Rgba};
+use serde::Deserialize;
+
+use crate::coords::CHUNK_SIZE;
+
+/// Sky light during the night, on the 0..15 light scale.
+const MOON_LIGHT: f32 = 4.0;
+/// Brightness of a column without any light.
+const DARKNESS: f32 = 0.08;
+
+#[derive(Deserialize)]
+struct SectionNbt {
+    #[serde(rename = "Y")]
+    y: i8,
+    #[serde(rename = "BlockLight")]
+    block_light: Option<fastnbt::ByteArray>,
+    #[serde(rename = "SkyLight")]
+    sky_light: Option<fastnbt::ByteArray>,
+}
+
+#[derive(Deserialize)]
+struct LevelNbt {
+    #[serde(rename = "Sections", default)]
+    sections: Vec<SectionNbt>,
+}
+
+/// Only the light arrays of a chunk; `sections` since 1.18, `Level.Sections` before.
+#[derive(Deserialize)]
+struct ChunkNbt {
+    #[serde(default)]
+    sections: Vec<SectionNbt>,
+    #[serde(rename = "Level")]
+    level: Option<LevelNbt>,
+}
+
+struct SectionLight {
+    block: Option<Vec<u8>>,
+    sky: Option<Vec<u8>>,
+}
+
+/// Block and sky light of a chunk, 2048-byte nibble arrays per 16-block section.
+pub struct ChunkLight {
+    sections: HashMap<i8, SectionLight>,
+}
+
+fn nibbles(array: Option<fastnbt::ByteArray>) -> Option<Vec<u8>> {
+    let bytes: Vec<u8> = array?.iter().map(|b| *b as u8).collect();
+    if bytes.len() == 2048 { Some(bytes) } else { None }
+}
+
+fn nibble(array: &[u8], x: usize, y: isize, z: usize) -> u8 {
+    let index = (y.rem_euclid(16) as usize * 16 + z) * 16 + x;
+    let byte = array[index / 2];
+    if index % 2 == 0 { byte & 0x0f } else { byte >> 4 }
+}
+
+impl ChunkLight {
+    /// Read the light from uncompressed chunk NBT, as returned by `Region::read_chunk`.
+    pub fn from_bytes(data: &[u8]) -> fastnbt::error::Result<Self> {
+        let chunk: ChunkNbt = fastnbt::from_bytes(data)?;
+        let sections = match chunk.level {
+            Some(level) if chunk.sections.is_empty() => level.sections,
+            _ => chunk.sections,
+        };
+        Ok(ChunkLight {
+            sections: sections.into_iter()
+                .map(|section| (section.y, SectionLight { block: nibbles(section.block_light), sky: nibbles(section.sky_light) }))
+                .collect(),
+        })
+    }
+
+    /// (block light, sky light) at a position. Sections without a sky light
+    /// array are open sky, sections without block light are dark.
+    pub fn at(&self, x: usize, y: isize, z: usize) -> (u8, u8) {
+        match self.sections.get(&(y.div_euclid(16) as i8)) {
+            Some(section) => (
+                section.block.as_ref().map_or(0, |array| nibble(array, x, y, z)),
+                section.sky.as_ref().map_or(15, |array| nibble(array, x, y, z)),
+            ),
+            None => (0, 15),
+        }
+    }
+}
+
+/// Darken a rendered chunk to night: each column is lit by the light of the
+/// air block above its surface, block light warming the colour a little.
+pub fn shade_night<C: Chunk>(buf: &mut [Rgba], chunk: &C, light: &ChunkLight) {
+    let chunk_size = CHUNK_SIZE as usize;
+    for (i, px) in buf.iter_mut().enumerate() {
+        let (x, z) = (i % chunk_size, i / chunk_size);
+        let y = chunk.surface_height(x, z, HeightMode::Trust);
+        let (block, sky) = light.at(x, y, z);
+        let sky = sky as f32 * MOON_LIGHT / 15.0;
+        let level = (block as f32).max(sky) / 15.0;
+        let brightness = DARKNESS + (1.0 - DARKNESS) * level.powf(1.5);
+        // Torch light is yellowish; moonlight stays neutral.
+        let warmth = if block as f32 > sky { block as f32 / 15.0 * 0.15 } else { 0.0 };
+        let factors = [brightness * (1.0 + warmth), brightness, brightness * (1.0 - warmth)];
+        for (c, factor) in px.iter_mut().zip(factors.iter()) {
+            *c = (*c as f32 * factor).min(255.0) as u8;
+        }
+    }
+}
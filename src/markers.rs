@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use fastnbt::Value;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::coords::{BlockPos, RegionPos};
+use crate::dim_renderer::to_image_name;
+use crate::dimension::DimensionKind;
+use crate::error::Result;
+use crate::level::{LevelDat, PlayerData};
+
+pub const MARKERS_NAME: &str = "markers.json";
+
+/// `markers.<lang>.json`, the markers with their labels in `lang`.
+pub fn to_markers_name(lang: &str) -> String {
+    format!("markers.{}.json", lang)
+}
+
+/// `--marker-labels`: labels by language for the markers labelled a given
+/// way, e.g. {"spawn": {"ja": "スポーン", "de": "Spawnpunkt"}}.
+pub type MarkerLabels = HashMap<String, BTreeMap<String, String>>;
+
+pub fn load_labels(path: &Path) -> Result<MarkerLabels> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerKind {
+    Player,
+    Spawn,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorldPos {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// Where a marker lands in the output: the region image and the pixel in it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PixelPos {
+    pub image: String,
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    pub label: String,
+    /// `label` in other languages, by language code.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub world: WorldPos,
+    pub pixel: PixelPos,
+}
+
+impl Marker {
+    fn new(kind: MarkerKind, label: String, x: f64, y: f64, z: f64) -> Self {
+        let block = BlockPos::new(x.floor() as i32, z.floor() as i32);
+        let (px, py) = block.in_region();
+        Marker {
+            kind,
+            label,
+            labels: Default::default(),
+            world: WorldPos { x, y, z },
+            pixel: PixelPos { image: to_image_name(&block.region()), x: px, y: py },
+        }
+    }
+
+    /// The label in `lang`, `label` when there is none.
+    pub fn label_in(&self, lang: &str) -> &str {
+        self.labels.get(lang).unwrap_or(&self.label)
+    }
+
+    pub fn block(&self) -> BlockPos {
+        BlockPos::new(self.world.x.floor() as i32, self.world.z.floor() as i32)
+    }
+
+    /// Regions the icon of this marker can overlap when drawn on tiles.
+    pub fn icon_regions(&self) -> Vec<RegionPos> {
+        let block = self.block();
+        let reach = crate::tiles::ICON_RADIUS as i32 + 1;
+        let mut regions: Vec<RegionPos> = [(-reach, -reach), (reach, -reach), (-reach, reach), (reach, reach)].iter()
+            .map(|(dx, dz)| block.offset(*dx, *dz).region())
+            .collect();
+        regions.sort();
+        regions.dedup();
+        regions
+    }
+}
+
+fn in_dimension(dimension: Option<&Value>, kind: DimensionKind) -> bool {
+    let name = match dimension {
+        // Worlds from before dimensions were saved only have the overworld.
+        None => "minecraft:overworld",
+        Some(Value::String(name)) => name.as_str(),
+        Some(Value::Int(-1)) => "minecraft:the_nether",
+        Some(Value::Int(1)) => "minecraft:the_end",
+        Some(Value::Int(_)) => "minecraft:overworld",
+        Some(_) => return false,
+    };
+    name == match kind {
+        DimensionKind::Overworld => "minecraft:overworld",
+        DimensionKind::Nether => "minecraft:the_nether",
+        DimensionKind::End => "minecraft:the_end",
+    }
+}
+
+fn player_marker(player: &PlayerData, label: String, kind: DimensionKind) -> Option<Marker> {
+    if !in_dimension(player.dimension.as_ref(), kind) || player.pos.len() != 3 {
+        return None;
+    }
+    Some(Marker::new(MarkerKind::Player, label, player.pos[0], player.pos[1], player.pos[2]))
+}
+
+#[derive(Deserialize)]
+struct UserCacheEntry {
+    name: String,
+    uuid: String,
+}
+
+/// Player names from the server's `usercache.json` (next to the world directory), if any.
+fn player_names(world_path: &Path) -> HashMap<String, String> {
+    let path = match world_path.parent() {
+        Some(server_path) => server_path.join("usercache.json"),
+        None => return Default::default(),
+    };
+    let entries: Vec<UserCacheEntry> = File::open(path).ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default();
+    entries.into_iter().map(|entry| (entry.uuid, entry.name)).collect()
+}
+
+/// Players in dimension `kind` (from `playerdata/*.dat` and level.dat),
+/// and the world spawn when rendering the overworld.
+pub fn collect(world_path: &Path, kind: DimensionKind) -> Vec<Marker> {
+    let mut markers = vec![];
+    match LevelDat::from_world(world_path) {
+        Ok(level) => {
+            if let (DimensionKind::Overworld, Some(x), Some(y), Some(z)) = (kind, level.data.spawn_x, level.data.spawn_y, level.data.spawn_z) {
+                let mut spawn = Marker::new(MarkerKind::Spawn, "spawn".to_string(), x as f64 + 0.5, y as f64, z as f64 + 0.5);
+                spawn.labels.insert("ja".to_string(), "スポーン".to_string());
+                markers.push(spawn);
+            }
+            if let Some(player) = &level.data.player {
+                markers.extend(player_marker(player, "player".to_string(), kind));
+            }
+        },
+        Err(e) => warn!("cannot read level.dat in {}: {}", world_path.to_str().unwrap(), e),
+    }
+
+    let names = player_names(world_path);
+    if let Ok(dir) = world_path.join("playerdata").read_dir() {
+        let mut paths: Vec<_> = dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let uuid = path.file_stem().unwrap().to_string_lossy().into_owned();
+            match PlayerData::from_file(&path) {
+                Ok(player) => {
+                    let label = names.get(&uuid).cloned().unwrap_or(uuid);
+                    markers.extend(player_marker(&player, label, kind));
+                },
+                Err(e) => warn!("cannot read {}: {}", path.to_str().unwrap(), e),
+            }
+        }
+    }
+    info!("markers: {}", markers.len());
+    markers
+}
+
+/// Add the `labels` given for each marker's label to the ones it has.
+pub fn apply_labels(markers: &mut [Marker], labels: &MarkerLabels) {
+    for marker in markers.iter_mut() {
+        if let Some(translated) = labels.get(&marker.label) {
+            marker.labels.extend(translated.iter().map(|(lang, label)| (lang.clone(), label.clone())));
+        }
+    }
+}
+
+/// The markers labelled in `lang`, for a variant of the map in that language.
+pub fn localized(markers: &[Marker], lang: &str) -> Vec<Marker> {
+    markers.iter().map(|marker| Marker {
+        label: marker.label_in(lang).to_string(),
+        labels: Default::default(),
+        ..marker.clone()
+    }).collect()
+}
+
+/// Markers of the previous run, empty if there are none.
+pub fn load(path: &Path) -> Vec<Marker> {
+    File::open(path).ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default()
+}
+
+pub fn write(path: &Path, markers: &[Marker]) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(file, markers)?;
+    Ok(())
+}
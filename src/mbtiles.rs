@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::coords::TilePos;
+use crate::error::Result;
+
+/// Where the tile pyramid goes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TileOutput {
+    /// z/x/y.png files under `<image_path>/tiles`.
+    #[default]
+    Dir,
+    /// One MBTiles (SQLite) archive.
+    MbTiles(PathBuf),
+}
+
+impl FromStr for TileOutput {
+    type Err = String;
+
+    /// "dir" or "mbtiles:<file>".
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "dir" => Ok(TileOutput::Dir),
+            Some(("mbtiles", path)) if !path.is_empty() => Ok(TileOutput::MbTiles(PathBuf::from(path))),
+            _ => Err(format!("invalid tile output \"{}\", expected dir or mbtiles:<file>", s)),
+        }
+    }
+}
+
+/// An MBTiles 1.3 archive of PNG tiles. Tiles of a run are upserted in one
+/// transaction, so the archive a server reads is never half updated.
+///
+/// Rows are TMS as the spec requires: `tile_row` counts up from the bottom of
+/// the zoom, so a tile at z/x/y of the directory pyramid is at
+/// row `2^z - 1 - y`. Minecraft tiles can be negative; they are stored as is.
+pub struct MbTiles {
+    conn: Connection,
+}
+
+impl MbTiles {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        // WAL lets `serve` read tiles while a render writes them.
+        conn.execute_batch("
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS metadata (name TEXT PRIMARY KEY, value TEXT);
+            CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+            CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);
+        ")?;
+        Ok(MbTiles { conn })
+    }
+
+    /// Record the name, format and zoom range of the pyramid.
+    pub fn set_metadata(&self, name: &str, min_zoom: u32, max_zoom: u32) -> Result<()> {
+        for (key, value) in [
+            ("name", name.to_string()),
+            ("format", "png".to_string()),
+            ("type", "baselayer".to_string()),
+            ("minzoom", min_zoom.to_string()),
+            ("maxzoom", max_zoom.to_string()),
+        ] {
+            self.conn.execute("INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)", params![key, value])?;
+        }
+        Ok(())
+    }
+
+    pub fn begin(&self) -> Result<()> {
+        self.conn.execute_batch("BEGIN")?;
+        Ok(())
+    }
+
+    pub fn commit(&self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn row(zoom: u32, tile: &TilePos) -> i64 {
+        (1i64 << zoom) - 1 - tile.z as i64
+    }
+
+    pub fn put(&self, zoom: u32, tile: &TilePos, data: &[u8]) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+            params![zoom, tile.x, Self::row(zoom, tile), data])?;
+        Ok(())
+    }
+
+    pub fn get(&self, zoom: u32, tile: &TilePos) -> Result<Option<Vec<u8>>> {
+        Ok(self.conn.query_row(
+            "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            params![zoom, tile.x, Self::row(zoom, tile)], |row| row.get(0)).optional()?)
+    }
+
+    pub fn remove(&self, zoom: u32, tile: &TilePos) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            params![zoom, tile.x, Self::row(zoom, tile)])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use clap::ArgEnum;
+use image::RgbaImage;
+
+use crate::background::Background;
+use crate::coords::BlockPos;
+use crate::contours::ContourOverlay;
+use crate::grid::GridOverlay;
+use crate::slime::SlimeOverlay;
+use crate::spawn::SpawnOverlay;
+
+/// Layers of `--overlay`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum OverlayKind {
+    /// Tint slime chunks (overworld only)
+    Slime,
+    /// Elevation isolines every --contour-interval blocks
+    Contours,
+    /// Mark the world spawn (overworld only)
+    Spawn,
+}
+
+/// A layer drawn onto the published images only, so that the region images
+/// stay usable as the pixel cache whichever layers a run asks for.
+#[derive(Debug, Clone)]
+pub enum Overlay {
+    Slime(SlimeOverlay),
+    Contours(ContourOverlay),
+    Grid(GridOverlay),
+    Spawn(SpawnOverlay),
+    /// Drawn last: it fills what the map and the other layers left empty.
+    Background(Background),
+}
+
+impl Overlay {
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        match self {
+            Overlay::Slime(slime) => slime.apply(image, origin, scale),
+            Overlay::Contours(contours) => contours.apply(image, origin, scale),
+            Overlay::Grid(grid) => grid.apply(image, origin, scale),
+            Overlay::Spawn(spawn) => spawn.apply(image, origin, scale),
+            Overlay::Background(background) => background.apply(image, origin, scale),
+        }
+    }
+}
+
+/// Draw `overlays` in order (tints before lines).
+pub fn apply_all(overlays: &[Overlay], image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+    for overlay in overlays {
+        overlay.apply(image, origin, scale);
+    }
+}
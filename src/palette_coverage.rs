@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use fastanvil::{Chunk, HeightMode, JavaChunk, RenderedPalette, Region};
+use log::debug;
+
+use crate::coords::{RegionPos, CHUNK_SIZE, REGION_CHUNKS};
+use crate::dimension::scan_region_files;
+use crate::error::Result;
+
+/// Tries per requested sample before giving up on sparse worlds.
+const ATTEMPTS_PER_SAMPLE: usize = 8;
+
+/// xorshift64*, enough to spread samples over a world.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+    }
+}
+
+/// Surface blocks of the sampled chunks, by block state.
+pub struct Coverage {
+    pub chunks: usize,
+    pub columns: u64,
+    /// Columns whose top block the palette has no colour for.
+    pub missing_columns: u64,
+    /// Block states without a colour, with their column counts, most common first.
+    pub missing: Vec<(String, u64)>,
+    pub distinct: usize,
+}
+
+impl Coverage {
+    pub fn percent(&self) -> f64 {
+        if self.columns == 0 { 100.0 } else { 100.0 * (self.columns - self.missing_columns) as f64 / self.columns as f64 }
+    }
+}
+
+/// Whether the palette has a colour for a block state, looked up like the renderer does.
+fn has_colour(palette: &RenderedPalette, block: &fastanvil::Block) -> bool {
+    palette.blockstates.contains_key(block.encoded_description()) || palette.blockstates.contains_key(block.name())
+}
+
+/// Read the surface of `samples` random chunks of the dimension and check
+/// their top blocks against `palette`.
+pub fn sample(dim_path: &Path, palette: &RenderedPalette, samples: usize, seed: u64) -> Result<Coverage> {
+    let regions: Vec<(RegionPos, std::path::PathBuf)> = scan_region_files(dim_path, None)?.into_iter().collect();
+    if regions.is_empty() {
+        return Err(format!("no region files in {}", dim_path.to_str().unwrap()).into());
+    }
+    let mut rng = Rng::new(seed);
+    let mut states: HashMap<String, (u64, bool)> = Default::default();
+    let mut coverage = Coverage { chunks: 0, columns: 0, missing_columns: 0, missing: vec![], distinct: 0 };
+    let chunk_size = CHUNK_SIZE as usize;
+    for _ in 0..samples * ATTEMPTS_PER_SAMPLE {
+        if coverage.chunks >= samples {
+            break;
+        }
+        let (rloc, path) = &regions[rng.below(regions.len())];
+        let (x, z) = (rng.below(REGION_CHUNKS as usize), rng.below(REGION_CHUNKS as usize));
+        let data = match File::open(path).map_err(|e| e.to_string())
+            .and_then(|file| Region::from_stream(file).map_err(|e| e.to_string()))
+            .and_then(|mut region| region.read_chunk(x, z).map_err(|e| e.to_string())) {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                debug!("cannot sample chunk {},{} of region {}: {}", x, z, rloc, e);
+                continue;
+            }
+        };
+        let chunk = match JavaChunk::from_bytes(&data) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                debug!("cannot decode chunk {},{} of region {}: {}", x, z, rloc, e);
+                continue;
+            }
+        };
+        coverage.chunks += 1;
+        for cz in 0..chunk_size {
+            for cx in 0..chunk_size {
+                let y = chunk.surface_height(cx, cz, HeightMode::Trust);
+                let block = match chunk.block(cx, y - 1, cz) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                let entry = states.entry(block.encoded_description().to_string())
+                    .or_insert_with(|| (0, has_colour(palette, block)));
+                entry.0 += 1;
+                coverage.columns += 1;
+                if !entry.1 {
+                    coverage.missing_columns += 1;
+                }
+            }
+        }
+    }
+    coverage.distinct = states.len();
+    coverage.missing = states.into_iter().filter(|(_, (_, known))| !known).map(|(state, (count, _))| (state, count)).collect();
+    coverage.missing.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(coverage)
+}
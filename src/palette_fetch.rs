@@ -0,0 +1,193 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::data_versions;
+use crate::durability::Durability;
+use crate::error::{Error, Result};
+use crate::level::{find_world_root, LevelDat};
+use crate::palette_gen;
+
+/// Downloaded and generated palettes, in the cache path.
+pub const PALETTES_DIR: &str = "palettes";
+
+/// Mojang's list of Minecraft versions, each with the URL and SHA-1 of its
+/// metadata, which in turn pins the client jar by URL and SHA-1.
+const VERSION_MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+#[derive(Deserialize)]
+struct VersionManifest {
+    versions: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    id: String,
+    url: String,
+    sha1: String,
+}
+
+#[derive(Deserialize)]
+struct VersionMeta {
+    downloads: VersionDownloads,
+}
+
+#[derive(Deserialize)]
+struct VersionDownloads {
+    client: Download,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    url: String,
+    sha1: String,
+}
+
+/// `--palette-path auto`
+pub fn is_auto(palette_path: &Path) -> bool {
+    palette_path.as_os_str() == "auto"
+}
+
+/// `--palette-path` given as the URL of a palette.tar.gz.
+pub fn is_url(palette_path: &Path) -> bool {
+    palette_path.to_str().map_or(false, |path| path.starts_with("https://") || path.starts_with("http://"))
+}
+
+/// The palette file for `--palette-path` and `--palette-sha256`, the latter
+/// already checked to be 64 lowercase hex digits.
+///
+/// "auto" is the palette of the Minecraft version the world of `dim_path` was
+/// saved with (see `resolve_auto`). A URL needs a checksum: it is downloaded
+/// into `<cache_path>/palettes/` once, checked, and the copy reused offline
+/// afterwards while it still matches. A local file, or the "auto" palette, is
+/// checked against the checksum if one is given.
+pub fn resolve(palette_path: &Path, sha256: Option<&str>, dim_path: &Path, cache_path: &Path) -> Result<PathBuf> {
+    if is_auto(palette_path) {
+        let resolved = resolve_auto(dim_path, cache_path)?;
+        if let Some(expected) = sha256 {
+            check(&std::fs::read(&resolved)?, expected, &resolved.display().to_string())?;
+        }
+        return Ok(resolved);
+    }
+    if !is_url(palette_path) {
+        if let Some(expected) = sha256 {
+            check(&std::fs::read(palette_path)?, expected, &palette_path.display().to_string())?;
+        }
+        return Ok(palette_path.to_path_buf());
+    }
+    let url = palette_path.to_str().unwrap();
+    let expected = sha256.ok_or_else(|| Error::Message(format!("{}: a palette URL needs --palette-sha256", url)))?;
+    let cached = cache_path.join(PALETTES_DIR).join(format!("{}.tar.gz", expected));
+    match std::fs::read(&cached) {
+        Ok(data) if sha256_hex(&data) == expected => {
+            info!("palette cached {}", cached.display());
+            return Ok(cached);
+        },
+        Ok(_) => warn!("cached palette {} has a bad checksum, downloading it again", cached.display()),
+        Err(_) => (),
+    }
+
+    info!("download palette from {}", url);
+    let body = download(url)?;
+    check(&body, expected, url)?;
+    // Renamed into place, so an interrupted download never leaves half a palette.
+    std::fs::create_dir_all(cached.parent().unwrap())?;
+    Durability::Safe.write(&cached, &body)?;
+    Ok(cached)
+}
+
+/// The palette of the world's Minecraft version, `<cache_path>/palettes/<version>.tar.gz`.
+///
+/// The version is level.dat's `Version.Name`, or the release its `DataVersion`
+/// belongs to. On first use the client jar of that version is downloaded from
+/// Mojang, checked against the SHA-1 Mojang's version manifest pins it with,
+/// and the palette generated from it as `palette generate` does. Its SHA-256
+/// is kept next to it (`<version>.tar.gz.sha256`), and the cached palette is
+/// reused offline while it still matches.
+pub fn resolve_auto(dim_path: &Path, cache_path: &Path) -> Result<PathBuf> {
+    let version = world_version(dim_path)?;
+    let palettes = cache_path.join(PALETTES_DIR);
+    let palette_path = palettes.join(format!("{}.tar.gz", version));
+    let pin_path = palettes.join(format!("{}.tar.gz.sha256", version));
+    if let (Ok(data), Ok(pin)) = (std::fs::read(&palette_path), std::fs::read_to_string(&pin_path)) {
+        if sha256_hex(&data) == pin.trim() {
+            info!("palette cached {}", palette_path.display());
+            return Ok(palette_path);
+        }
+        warn!("cached palette {} has a bad checksum, generating it again", palette_path.display());
+    }
+
+    info!("look up Minecraft {} in {}", version, VERSION_MANIFEST_URL);
+    let manifest: VersionManifest = serde_json::from_slice(&download(VERSION_MANIFEST_URL)?)?;
+    let entry = manifest.versions.iter().find(|entry| entry.id == version)
+        .ok_or_else(|| Error::Message(format!("Minecraft {} is not in Mojang's version manifest, pass --palette-path explicitly", version)))?;
+    let meta = download(&entry.url)?;
+    check_sha1(&meta, &entry.sha1, &entry.url)?;
+    let client = serde_json::from_slice::<VersionMeta>(&meta)?.downloads.client;
+    info!("download the Minecraft {} client from {}", version, client.url);
+    let jar = download(&client.url)?;
+    check_sha1(&jar, &client.sha1, &client.url)?;
+
+    std::fs::create_dir_all(&palettes).map_err(|source| Error::CreateDir { path: palettes.clone(), source })?;
+    let jar_path = palettes.join(format!("{}.jar.part", version));
+    let part_path = palettes.join(format!("{}.tar.gz.part", version));
+    std::fs::write(&jar_path, &jar)?;
+    let generated = palette_gen::generate(&jar_path, &part_path);
+    // The jar is only needed to build the palette.
+    let _ = std::fs::remove_file(&jar_path);
+    let states = generated?;
+    let palette = std::fs::read(&part_path)?;
+    let _ = std::fs::remove_file(&part_path);
+    Durability::Safe.write(&palette_path, &palette)?;
+    Durability::Safe.write(&pin_path, sha256_hex(&palette).as_bytes())?;
+    info!("palette for Minecraft {}: {} block states in {}", version, states, palette_path.display());
+    Ok(palette_path)
+}
+
+/// Minecraft version the world of `dim_path` was last saved with.
+fn world_version(dim_path: &Path) -> Result<String> {
+    let world_root = find_world_root(dim_path)
+        .ok_or_else(|| Error::Message(format!("no level.dat above {}, pass --palette-path explicitly", dim_path.display())))?;
+    let level = LevelDat::from_world(&world_root)?;
+    let version = match (level.data.version, level.data.data_version) {
+        (Some(version), _) => version.name,
+        (None, Some(data_version)) => data_versions::release_of(Some(data_version)).to_string(),
+        (None, None) => return Err("level.dat has neither Version.Name nor DataVersion, pass --palette-path explicitly".into()),
+    };
+    // It names files in the cache path.
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+        return Err(Error::Message(format!("unexpected Minecraft version {:?} in level.dat, pass --palette-path explicitly", version)));
+    }
+    Ok(version)
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let mut body = vec![];
+    ureq::get(url).call()?.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+fn check(data: &[u8], expected: &str, what: &str) -> Result<()> {
+    let actual = sha256_hex(data);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Error::Checksum { what: what.to_string(), expected: expected.to_string(), actual })
+    }
+}
+
+fn check_sha1(data: &[u8], expected: &str, what: &str) -> Result<()> {
+    let actual = format!("{:x}", Sha1::digest(data));
+    if actual == expected.to_ascii_lowercase() {
+        Ok(())
+    } else {
+        Err(Error::Checksum { what: what.to_string(), expected: expected.to_string(), actual })
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
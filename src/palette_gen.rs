@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use fastanvil::Rgba;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::tiles::average_block;
+
+const ASSETS: &str = "assets/minecraft/";
+/// Texture variables tried in order to pick the one seen from above.
+const TOP_TEXTURES: &[&str] = &["top", "end", "up", "all", "texture", "cross", "plant", "side", "particle"];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Variant {
+    One(ModelRef),
+    Many(Vec<ModelRef>),
+}
+
+impl Variant {
+    fn model(&self) -> Option<&str> {
+        match self {
+            Variant::One(model) => Some(&model.model),
+            Variant::Many(models) => models.first().map(|model| model.model.as_str()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ModelRef {
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct MultipartCase {
+    apply: Variant,
+}
+
+#[derive(Deserialize)]
+struct BlockstateJson {
+    #[serde(default)]
+    variants: HashMap<String, Variant>,
+    #[serde(default)]
+    multipart: Vec<MultipartCase>,
+}
+
+#[derive(Deserialize)]
+struct ModelJson {
+    parent: Option<String>,
+    #[serde(default)]
+    textures: HashMap<String, String>,
+}
+
+/// Strip the namespace of a resource location ("minecraft:block/stone" -> "block/stone").
+fn resource_path(location: &str) -> &str {
+    location.split_once(':').map_or(location, |(_, path)| path)
+}
+
+struct JarAssets<R: Read + Seek> {
+    jar: zip::ZipArchive<R>,
+    models: HashMap<String, Option<HashMap<String, String>>>,
+    colours: HashMap<String, Option<Rgba>>,
+}
+
+impl<R: Read + Seek> JarAssets<R> {
+    fn read(&mut self, path: &str) -> Option<Vec<u8>> {
+        let mut file = self.jar.by_name(&format!("{}{}", ASSETS, path)).ok()?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    /// Texture variables of a model, merged along its parents (children win).
+    fn model_textures(&mut self, model: &str) -> Option<HashMap<String, String>> {
+        let model = resource_path(model).to_string();
+        if let Some(textures) = self.models.get(&model) {
+            return textures.clone();
+        }
+        // Guards against parent cycles; replaced below.
+        self.models.insert(model.clone(), None);
+        let textures = self.read(&format!("models/{}.json", model))
+            .and_then(|data| serde_json::from_slice::<ModelJson>(&data).ok())
+            .map(|json| {
+                let mut textures = match &json.parent {
+                    Some(parent) => self.model_textures(parent).unwrap_or_default(),
+                    None => Default::default(),
+                };
+                textures.extend(json.textures);
+                textures
+            });
+        self.models.insert(model, textures.clone());
+        textures
+    }
+
+    /// Average colour of the texture a model shows from above.
+    fn model_colour(&mut self, model: &str) -> Option<Rgba> {
+        let textures = self.model_textures(model)?;
+        let mut texture = TOP_TEXTURES.iter().find_map(|name| textures.get(*name))
+            .or_else(|| textures.values().next())?;
+        // "#side" refers to another variable of the model.
+        for _ in 0..8 {
+            match texture.strip_prefix('#') {
+                Some(name) => texture = textures.get(name)?,
+                None => break,
+            }
+        }
+        self.texture_colour(resource_path(texture))
+    }
+
+    fn texture_colour(&mut self, texture: &str) -> Option<Rgba> {
+        if let Some(colour) = self.colours.get(texture) {
+            return *colour;
+        }
+        let colour = self.read(&format!("textures/{}.png", texture))
+            .and_then(|data| image::load_from_memory_with_format(&data, image::ImageFormat::Png).ok())
+            .map(|image| {
+                // Animated textures stack their frames vertically; use the first.
+                let image = image.into_rgba8();
+                average_block(&image, 0, 0, image.width().min(image.height())).0
+            });
+        self.colours.insert(texture.to_string(), colour);
+        colour
+    }
+}
+
+/// Build a palette tarball, as read by `renderer::get_palette`, from the
+/// block models, textures and colour maps of a Minecraft client jar.
+/// Returns the number of block states in it.
+pub fn generate(jar_path: &Path, out_path: &Path) -> Result<usize> {
+    let mut assets = JarAssets {
+        jar: zip::ZipArchive::new(File::open(jar_path)?)?,
+        models: Default::default(),
+        colours: Default::default(),
+    };
+    let prefix = format!("{}blockstates/", ASSETS);
+    let blocks: Vec<String> = assets.jar.file_names()
+        .filter_map(|name| name.strip_prefix(&prefix)?.strip_suffix(".json").map(str::to_string))
+        .collect();
+    if blocks.is_empty() {
+        return Err(format!("no block states in {}, is it a client jar?", jar_path.to_str().unwrap()).into());
+    }
+
+    let mut blockstates: HashMap<String, Rgba> = Default::default();
+    for block in &blocks {
+        let json: BlockstateJson = match assets.read(&format!("blockstates/{}.json", block)).map(|data| serde_json::from_slice(&data)) {
+            Some(Ok(json)) => json,
+            _ => {
+                warn!("unreadable block state file for {}", block);
+                continue;
+            }
+        };
+        let name = format!("minecraft:{}", block);
+        for (state, variant) in &json.variants {
+            let colour = match variant.model().and_then(|model| assets.model_colour(model)) {
+                Some(colour) => colour,
+                None => {
+                    debug!("no texture for {} {}", name, state);
+                    continue;
+                }
+            };
+            let key = if state.is_empty() { name.clone() } else { format!("{}|{}", name, state) };
+            blockstates.insert(key, colour);
+        }
+        // Multipart blocks (fences, walls, ...) get one colour for every state.
+        if let Some(colour) = json.multipart.iter().find_map(|case| assets.model_colour(case.apply.model()?)) {
+            blockstates.insert(name, colour);
+        }
+    }
+
+    let grass = assets.read("textures/colormap/grass.png").ok_or("no grass colour map in the jar")?;
+    let foliage = assets.read("textures/colormap/foliage.png").ok_or("no foliage colour map in the jar")?;
+    let blockstates_json = serde_json::to_vec(&blockstates)?;
+
+    let mut archive = tar::Builder::new(GzEncoder::new(File::create(out_path)?, Compression::default()));
+    for (name, data) in [("blockstates.json", &blockstates_json), ("grass-colourmap.png", &grass), ("foliage-colourmap.png", &foliage)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        archive.append_data(&mut header, name, data.as_slice())?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(blockstates.len())
+}
@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+
+use crate::cache_store::CACHE_DB_NAME;
+use crate::journal::{INTERACTIVE_JOURNAL_NAME, JOURNAL_NAME};
+use crate::markers::MARKERS_NAME;
+use crate::palette_fetch::PALETTES_DIR;
+use crate::region_index::INDEX_NAME;
+use crate::render_report::{MANIFEST_LIST_NAME, MANIFEST_NAME, REPORT_NAME};
+use crate::skip_list::SKIP_LIST_NAME;
+
+/// Subdirectories used when the image and cache paths are the same directory.
+pub const IMAGES_DIR: &str = "images";
+pub const CACHES_DIR: &str = "caches";
+
+/// Per-dimension subdirectories of --world-path, migrated file by file.
+const DIMENSION_DIRS: &[&str] = &["overworld", "nether", "end"];
+
+/// Outputs written next to the region images, besides the `r.X.Z.*` files.
+const OUTPUT_NAMES: &[&str] = &["tiles", REPORT_NAME, MANIFEST_NAME, MANIFEST_LIST_NAME, MARKERS_NAME, "changed-chunks.csv"];
+
+/// Whether `name` is one of the per-region files, `r.X.Z.<anything>`.
+fn is_region_entry(name: &str) -> bool {
+    let mut parts = name.splitn(4, '.');
+    parts.next() == Some("r")
+        && parts.next().map_or(false, |x| x.parse::<i32>().is_ok())
+        && parts.next().map_or(false, |z| z.parse::<i32>().is_ok())
+        && parts.next().map_or(false, |rest| !rest.is_empty())
+}
+
+/// Whether a file (or directory) of a shared directory is a cache this tool
+/// writes: the timestamp caches (files or database), heights, surfaces, the
+/// region index, skip list, journal, and the fetched palettes.
+fn is_cache_entry(name: &str) -> bool {
+    (is_region_entry(name) && [".cache", ".heights", ".surface"].iter().any(|ext| name.ends_with(ext)))
+        || [INDEX_NAME, SKIP_LIST_NAME, JOURNAL_NAME, INTERACTIVE_JOURNAL_NAME, PALETTES_DIR].contains(&name)
+        || name.starts_with(CACHE_DB_NAME)
+}
+
+/// Whether a file (or directory) of a shared directory is an output this tool writes.
+fn is_output_entry(name: &str) -> bool {
+    is_region_entry(name) || OUTPUT_NAMES.contains(&name)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.components().eq(b.components()),
+    }
+}
+
+/// Give `image_path` and `cache_path` their own subdirectories (`images/`,
+/// `caches/`) when they point at the same directory. Every reader and writer
+/// goes through these two paths, so nothing else has to know. Returns the
+/// shared directory, for `migrate` once the cache path is locked.
+pub fn separate(image_path: &mut PathBuf, cache_path: &mut PathBuf) -> Option<PathBuf> {
+    if !same_dir(image_path, cache_path) {
+        return None;
+    }
+    let root = image_path.clone();
+    *image_path = root.join(IMAGES_DIR);
+    *cache_path = root.join(CACHES_DIR);
+    info!("image and cache paths are both {}, using {} and {}", root.display(), image_path.display(), cache_path.display());
+    Some(root)
+}
+
+/// Move what earlier runs left in the shared directory `root` into its
+/// `images/` and `caches/` subdirectories. Only files this tool writes are
+/// moved; anything else (a config file, a world, ...) stays where it is.
+/// Call it holding the lock of the cache path, and only from commands that write.
+pub fn migrate(root: &Path) {
+    let (images, caches) = (root.join(IMAGES_DIR), root.join(CACHES_DIR));
+    match migrate_dir(root, &images, &caches) {
+        Ok(0) => (),
+        Ok(moved) => note!("Moved {} files of {} into its {}/ and {}/ subdirectories.", moved, root.display(), IMAGES_DIR, CACHES_DIR),
+        Err(e) => warn!("cannot sort the files of {} into {}/ and {}/: {}", root.display(), IMAGES_DIR, CACHES_DIR, e),
+    }
+}
+
+fn migrate_dir(dir: &Path, images: &Path, caches: &Path) -> std::io::Result<usize> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut moved = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().into_string().unwrap_or_default();
+        let path = entry.path();
+        if path == images || path == caches || name.ends_with(".tmp") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() && DIMENSION_DIRS.contains(&name.as_str()) {
+            moved += migrate_dir(&path, &images.join(&name), &caches.join(&name))?;
+            // Left in place if anything else was in it.
+            let _ = std::fs::remove_dir(&path);
+            continue;
+        }
+        // Anything else stays, the lock of an earlier layout too: it would replace the one held now.
+        let target = if is_cache_entry(&name) {
+            caches
+        } else if is_output_entry(&name) {
+            images
+        } else {
+            continue;
+        };
+        std::fs::create_dir_all(target)?;
+        std::fs::rename(&path, target.join(&name))?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_entries_need_both_coordinates() {
+        assert!(is_region_entry("r.-1.2.png"));
+        assert!(is_region_entry("r.0.0.highlight.png"));
+        assert!(!is_region_entry("r.0.png"));
+        assert!(!is_region_entry("r.a.0.png"));
+        assert!(!is_region_entry("r.0.0"));
+        assert!(!is_region_entry("readme.txt"));
+    }
+
+    #[test]
+    fn only_own_files_are_sorted() {
+        assert!(is_cache_entry("r.3.-4.cache"));
+        assert!(is_cache_entry(INDEX_NAME));
+        assert!(is_cache_entry("caches.sqlite-wal"));
+        assert!(!is_cache_entry("backup.cache"));
+        assert!(is_output_entry("r.3.-4.png"));
+        assert!(is_output_entry("tiles"));
+        assert!(is_output_entry(REPORT_NAME));
+        for name in ["mcanvilrenderer.toml", "world", "level.dat", ".mcanvilrenderer.lock"] {
+            assert!(!is_cache_entry(name) && !is_output_entry(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use log::info;
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use crate::coords::RegionPos;
+use crate::i18n;
+use crate::dim_renderer::RegionProgress;
+use crate::events::EventBus;
+use crate::dim_renderer::RegionProgress::*;
+
+pub fn normal_mode(receiver: Receiver<RegionProgress>, threads: usize) {
+    use indicatif::{ProgressBar, MultiProgress, ProgressStyle};
+
+    let multi_bar = Arc::new(MultiProgress::new());
+    let mut bars: Vec<ProgressBar> = Default::default();
+    let bar_master = multi_bar.add(ProgressBar::new(0));
+    let sty_master = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/cyan} {pos:>7}/{len:7} {msg} ETA: [{eta_precise}]");
+        //.progress_chars("##-");
+    bar_master.set_style(sty_master.unwrap());
+    bar_master.set_message(tr!("Total"));
+    let sty = ProgressStyle::default_bar()
+        .template(&format!("[{{elapsed_precise}}] {{bar:40.cyan/blue}} {{pos:>7}}/{{len:7}} {}{{msg}}", tr!("Region: ")))
+        .unwrap()
+        .progress_chars("##-");
+    for _ in 0..threads {
+        let bar = multi_bar.add(ProgressBar::new(0));
+        bar.set_style(sty.clone());
+        bar.inc(1);
+        bars.push(bar);
+    }
+
+    let progress_handle = std::thread::spawn(move || {
+        let mut bar_map: HashMap<RegionPos, usize> = Default::default();
+        let mut uses: Vec<bool> = vec![false; threads];
+        for progress in receiver {
+            match progress {
+                Begin(rloc, max) => {
+                    info!("Begin {},{} {}", rloc.x, rloc.z, max);
+
+                    let idx = uses.iter().enumerate().find_map(|(idx, flag)| {
+                        if !flag {
+                            Some(idx)
+                        } else { None }
+                    }).unwrap();
+                    info!("index: {:?}", idx);
+                    uses[idx] = true;
+                    bar_map.insert(rloc, idx);
+                    bars[idx].set_length(max as u64);
+                    bars[idx].set_position(0);
+                    bars[idx].reset_elapsed();
+                    bars[idx].set_message(format!("({:3},{:3})", rloc.x, rloc.z))
+                },
+                Step(rloc, n) => {
+                    let idx = bar_map.get(&rloc).unwrap();
+                    bars[*idx].inc(n as u64);
+                    bar_master.inc(n as u64);
+                },
+                RegionProgress::Error(rloc, cloc) => {
+                    bar_master.println(tr!("Error region:{} chunk:{} could not be read", rloc, cloc));
+                },
+                Failed(rloc, message) => {
+                    bar_master.println(tr!("Failed region:{} {}", rloc, message));
+                },
+                Warning(rloc, message) => {
+                    bar_master.println(tr!("Warning region:{} {}", rloc, message));
+                },
+                End(rloc) => {
+                    info!("  End {},{}", rloc.x, rloc.z);
+                    let idx = bar_map.get(&rloc).unwrap();
+                    bars[*idx].finish_with_message(format!("({:3},{:3}) OK", rloc.x, rloc.z));
+                    uses[*idx] = false;
+                    bar_map.remove(&rloc);
+                },
+                ScanBegin(max) => {
+                    bar_master.set_message(tr!("Scan"));
+                    bar_master.set_length(max as u64);
+                },
+                ScanStep(n) => {
+                    bar_master.inc(n as u64);
+                },
+                ScanEnd => {
+                    bar_master.set_message(tr!("Total"));
+                    bar_master.set_position(0);
+                    bar_master.reset_eta();
+                },
+                BeginAll(max) => {
+                    bar_master.set_length(max as u64);
+                },
+                EndAll => {
+                    bar_master.finish_with_message(tr!("Total OK"));
+                }
+            };
+        }
+    });
+
+    progress_handle.join().unwrap();
+}
+
+/// A pass of a batch: its events go on to the batch's display, but for
+/// `EndAll`, which the batch publishes when the job is over (a skipped or
+/// unchanged pass renders nothing).
+pub fn relay(receiver: Receiver<RegionProgress>, bus: &EventBus) {
+    for progress in receiver {
+        if !matches!(progress, EndAll) {
+            bus.publish(progress);
+        }
+    }
+}
+
+/// The display of a batch: the passes of every job on one bar, which grows
+/// as each job finds what it has to render, and failures printed above it.
+pub fn batch_mode(receiver: Receiver<RegionProgress>, jobs: usize) {
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    let bar = ProgressBar::new(0);
+    let sty = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/cyan} {pos:>7}/{len:7} {msg} ETA: [{eta_precise}]");
+    bar.set_style(sty.unwrap());
+    let mut done = 0;
+    bar.set_message(tr!("Batch {}/{}", done, jobs));
+    for progress in receiver {
+        match progress {
+            BeginAll(max) => bar.inc_length(max as u64),
+            Step(_, n) => bar.inc(n as u64),
+            RegionProgress::Error(rloc, cloc) => bar.println(tr!("Error region:{} chunk:{} could not be read", rloc, cloc)),
+            Failed(rloc, message) => bar.println(tr!("Failed region:{} {}", rloc, message)),
+            Warning(rloc, message) => bar.println(tr!("Warning region:{} {}", rloc, message)),
+            EndAll => {
+                done += 1;
+                bar.set_message(tr!("Batch {}/{}", done, jobs));
+            },
+            ScanBegin(_) | ScanStep(_) | ScanEnd | Begin(..) | End(_) => (),
+        }
+    }
+    bar.finish_with_message(tr!("Total OK"));
+}
+
+/// --quiet: only what went wrong, on stderr.
+pub fn quiet_mode(receiver: Receiver<RegionProgress>) {
+    for progress in receiver {
+        match progress {
+            RegionProgress::Error(rloc, cloc) => eprintln!("{}", tr!("Error region:{} chunk:{} could not be read", rloc, cloc)),
+            Failed(rloc, message) => eprintln!("{}", tr!("Failed region:{} {}", rloc, message)),
+            _ => (),
+        }
+    }
+}
+
+pub fn bg_mode(receiver: Receiver<RegionProgress>) {
+    for progress in receiver {
+        match progress {
+            Begin(rloc, max) => {
+                println!("{}", tr!("Begin region:{} / chunks: {}", rloc, i18n::number(max)));
+            },
+            Step(..) => (),
+            RegionProgress::Error(rloc, cloc) => {
+                println!("  {}", tr!("Error region:{} chunk:{} could not be read", rloc, cloc));
+            },
+            Failed(rloc, message) => {
+                println!("  {}", tr!("Failed region:{} {}", rloc, message));
+            },
+            Warning(rloc, message) => {
+                println!("  {}", tr!("Warning region:{} {}", rloc, message));
+            },
+            End(rloc) => {
+                println!("  {}", tr!("End region:{}", rloc));
+            },
+            ScanBegin(max) => {
+                println!("{}", tr!("Scan regions: {}", i18n::number(max)));
+            },
+            ScanStep(_) => (),
+            ScanEnd => {
+                println!("  {}", tr!("Scan done."));
+            },
+            BeginAll(max) => {
+                println!("{}", tr!("Begin total chunks: {}", i18n::number(max)));
+            },
+            EndAll => {
+                println!("  {}", tr!("End all."));
+            }
+        }
+    }
+}
+/// A progress message as a JSON event, as --progress-format json and
+/// --progress-socket write them.
+pub fn json_event(progress: &RegionProgress) -> serde_json::Value {
+    let region = |rloc: &RegionPos| serde_json::json!({ "x": rloc.x, "z": rloc.z });
+    let mut event = match progress {
+        ScanBegin(max) => serde_json::json!({ "event": "scan_begin", "regions": max }),
+        ScanStep(n) => serde_json::json!({ "event": "scan_step", "regions": n }),
+        ScanEnd => serde_json::json!({ "event": "scan_end" }),
+        BeginAll(max) => serde_json::json!({ "event": "begin_all", "chunks": max }),
+        Begin(rloc, max) => serde_json::json!({ "event": "begin", "region": region(rloc), "chunks": max }),
+        Step(rloc, n) => serde_json::json!({ "event": "step", "region": region(rloc), "chunks": n }),
+        RegionProgress::Error(rloc, cloc) => serde_json::json!({
+            "event": "error",
+            "region": region(rloc),
+            "chunk": { "x": cloc.x, "z": cloc.z },
+            "message": "chunk could not be read",
+        }),
+        Failed(rloc, message) => serde_json::json!({ "event": "failed", "region": region(rloc), "message": message }),
+        Warning(rloc, message) => serde_json::json!({ "event": "warning", "region": region(rloc), "message": message }),
+        End(rloc) => serde_json::json!({ "event": "end", "region": region(rloc) }),
+        EndAll => serde_json::json!({ "event": "end_all" }),
+    };
+    event["time"] = serde_json::Value::from(chrono::Local::now().to_rfc3339());
+    event
+}
+
+/// Newline-delimited JSON events on stdout, one per progress message.
+pub fn json_mode(receiver: Receiver<RegionProgress>) {
+    use std::io::Write;
+    for progress in receiver {
+        let event = json_event(&progress);
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", event).unwrap();
+        stdout.flush().unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::debug;
+
+use crate::dim_renderer::RegionProgress;
+use crate::progress;
+
+/// A client that takes longer than this to read an event is dropped.
+#[cfg_attr(not(unix), allow(dead_code))]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(unix)]
+type Client = std::os::unix::net::UnixStream;
+#[cfg(not(unix))]
+type Client = std::fs::File;
+
+/// `--progress-socket`: the JSON progress events, one per line, to every
+/// client connected to a Unix domain socket. Clients come and go without
+/// the run noticing, and one socket serves every pass of the process. The
+/// socket file stays when the process ends, and the next run replaces it.
+pub struct ProgressSocket {
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl ProgressSocket {
+    /// Listen on `path`. A socket file left by a run that is gone is
+    /// replaced; one that still answers belongs to another run.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, "another run is listening on it"));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let clients: Arc<Mutex<Vec<Client>>> = Default::default();
+        let accepted = Arc::clone(&clients);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|stream| stream.set_write_timeout(Some(WRITE_TIMEOUT)).map(|_| stream)) {
+                    Ok(stream) => accepted.lock().unwrap().push(stream),
+                    Err(e) => debug!("progress socket: {}", e),
+                }
+            }
+        });
+        Ok(ProgressSocket { clients })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> std::io::Result<Self> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "needs Unix domain sockets"))
+    }
+
+    /// Send the events of `receiver` to the clients connected meanwhile,
+    /// until the render's bus is dropped.
+    pub fn stream(&self, receiver: Receiver<RegionProgress>) {
+        for progress in receiver {
+            let mut line = progress::json_event(&progress).to_string();
+            line.push('\n');
+            self.clients.lock().unwrap().retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use log::warn;
+use regex::Regex;
+
+use crate::coords::RegionPos;
+use crate::dimension::scan_region_files;
+use crate::error::Result;
+
+/// Per-region files left behind by a region that no longer exists.
+pub struct Orphans {
+    pub files: Vec<PathBuf>,
+    pub regions: Vec<RegionPos>,
+}
+
+/// Find the per-region files (`r.X.Z.*`: images, sidecars, caches,
+/// heights, surfaces) in `dirs` whose region file is gone from `dim_path`.
+/// Subdirectories (tiles, per-dimension outputs) are not descended into.
+pub fn find_orphans(dim_path: &Path, dirs: &[&Path]) -> Result<Orphans> {
+    let regions = scan_region_files(dim_path, None)?;
+    let file_re = Regex::new(r"^r\.(-?\d+)\.(-?\d+)\.[a-z.]+$").unwrap();
+    let mut orphans = Orphans { files: vec![], regions: vec![] };
+    for dir in dirs {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().into_string().unwrap_or_default();
+            let caps = match file_re.captures(&name) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            let rloc = RegionPos::new(caps[1].parse()?, caps[2].parse()?);
+            if regions.contains_key(&rloc) {
+                continue;
+            }
+            orphans.files.push(entry.path());
+            if !orphans.regions.contains(&rloc) {
+                orphans.regions.push(rloc);
+            }
+        }
+    }
+    orphans.files.sort();
+    Ok(orphans)
+}
+
+/// Remove the orphaned files, returning how many were removed.
+pub fn remove(orphans: &Orphans) -> usize {
+    orphans.files.iter().filter(|path| match std::fs::remove_file(path) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("cannot remove {}: {}", path.to_str().unwrap(), e);
+            false
+        }
+    }).count()
+}
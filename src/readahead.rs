@@ -0,0 +1,43 @@
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+use crate::coords::RegionPos;
+
+/// `--io-threads`: regions whose chunks are read and decoded by dedicated
+/// threads before a render worker takes them, so workers draw instead of
+/// waiting on the disk. At most `depth` regions are prepared ahead of the
+/// workers, which bounds the memory the decoded chunks take.
+pub struct Readahead {
+    ready: Mutex<Receiver<RegionPos>>,
+}
+
+impl Readahead {
+    /// Prepare `regions` in their order with `prepare`, on `io_threads` threads.
+    pub fn start<F>(regions: Vec<RegionPos>, io_threads: usize, depth: usize, prepare: F) -> Arc<Self>
+        where F: Fn(&RegionPos) + Send + Sync + 'static
+    {
+        let (sender, receiver) = sync_channel(depth.max(1));
+        let queue = Arc::new(Mutex::new(regions.into_iter()));
+        let prepare = Arc::new(prepare);
+        for _ in 0..io_threads.max(1) {
+            let (sender, queue, prepare) = (sender.clone(), Arc::clone(&queue), Arc::clone(&prepare));
+            std::thread::spawn(move || loop {
+                let rloc = match queue.lock().unwrap().next() {
+                    Some(rloc) => rloc,
+                    None => break,
+                };
+                prepare(&rloc);
+                // Blocks while `depth` regions wait for a worker.
+                if sender.send(rloc).is_err() {
+                    break;
+                }
+            });
+        }
+        Arc::new(Readahead { ready: Mutex::new(receiver) })
+    }
+
+    /// The next prepared region, None once every one has been handed out.
+    pub fn next(&self) -> Option<RegionPos> {
+        self.ready.lock().unwrap().recv().ok()
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::coords::{RegionPos, RegionBounds};
+use crate::error::Result;
+
+pub const INDEX_NAME: &str = "region-index.json";
+
+/// Modification time (ns since the epoch), length and inode of a file.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileStamp {
+    pub mtime: u64,
+    pub len: u64,
+    /// Catches a file swapped for another one (a restored snapshot) with the same mtime and length.
+    #[serde(default)]
+    pub inode: u64,
+}
+
+#[cfg(unix)]
+fn inode_links(metadata: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.ino(), metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn inode_links(_metadata: &std::fs::Metadata) -> (u64, u64) {
+    (0, 1)
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Ok(FileStamp { mtime, len: metadata.len(), inode: inode_links(&metadata).0 })
+    }
+
+    /// Stamp of a region file, if it can stand in for reading the header.
+    /// Hardlinked files (deduplicated snapshots, backup tools) can change
+    /// through another link or keep a stale mtime, so only their timestamp
+    /// table is trusted.
+    pub fn of_region(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        if inode_links(&metadata).1 > 1 {
+            debug!("{} is hardlinked, its header is always read", path.to_str().unwrap());
+            return None;
+        }
+        Self::of(path).ok()
+    }
+}
+
+/// A region whose cache matched its header when the file had this stamp.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CleanRegion {
+    pub file: FileStamp,
+    pub header_hash: u64,
+}
+
+/// Cache-side index of the region files of a dimension, for watch mode.
+/// The directory is only listed again when its own mtime changes (a region
+/// file was added or removed), and regions whose file stamp matches the last
+/// clean scan are not opened at all.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RegionIndex {
+    #[serde(skip)]
+    path: PathBuf,
+    dir: Option<FileStamp>,
+    known: BTreeSet<String>,
+    clean: BTreeMap<String, CleanRegion>,
+    /// Palette the clean regions were rendered with.
+    #[serde(default)]
+    palette_hash: Option<u64>,
+}
+
+fn to_key(rloc: &RegionPos) -> String {
+    format!("{},{}", rloc.x, rloc.z)
+}
+
+fn from_key(key: &str) -> Option<RegionPos> {
+    let (x, z) = key.split_once(',')?;
+    Some(RegionPos::new(x.parse().ok()?, z.parse().ok()?))
+}
+
+pub fn to_region_name(rloc: &RegionPos) -> String {
+    format!("r.{:0}.{:0}.mca", rloc.x, rloc.z)
+}
+
+impl RegionIndex {
+    pub fn load(cache_path: &Path) -> Self {
+        let path = cache_path.join(INDEX_NAME);
+        let mut index: RegionIndex = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                warn!("ignore unreadable {}: {}", path.to_str().unwrap(), e);
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        index.path = path;
+        index
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let file = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Region files of `dim_path` within `bounds`, listing the directory only if it changed.
+    pub fn region_files(&mut self, dim_path: &Path, bounds: Option<&RegionBounds>) -> Result<Vec<(RegionPos, PathBuf)>> {
+        let dir = FileStamp::of(dim_path)?;
+        if self.dir != Some(dir) {
+            debug!("region directory changed, listing {}", dim_path.to_str().unwrap());
+            let files = crate::dimension::scan_region_files(dim_path, None)?;
+            self.known = files.keys().map(to_key).collect();
+            let known = &self.known;
+            self.clean.retain(|key, _| known.contains(key));
+            self.dir = Some(dir);
+        }
+        Ok(self.known.iter()
+            .filter_map(|key| from_key(key))
+            .filter(|rloc| if let Some(bounds) = bounds { rloc.in_bounds(bounds) } else { true })
+            .map(|rloc| (rloc, dim_path.join(to_region_name(&rloc))))
+            .collect())
+    }
+
+    /// Whether the region file is unchanged since a scan found nothing to render.
+    pub fn is_clean(&self, rloc: &RegionPos, file: &FileStamp) -> bool {
+        self.clean.get(&to_key(rloc)).is_some_and(|clean| clean.file == *file)
+    }
+
+    pub fn clean_hash(&self, rloc: &RegionPos) -> Option<u64> {
+        self.clean.get(&to_key(rloc)).map(|clean| clean.header_hash)
+    }
+
+    pub fn mark_clean(&mut self, rloc: &RegionPos, file: FileStamp, header_hash: u64) {
+        self.clean.insert(to_key(rloc), CleanRegion { file, header_hash });
+    }
+
+    /// Forget every clean region if they were rendered with another palette.
+    pub fn set_palette(&mut self, palette_hash: u64) {
+        if self.palette_hash.is_some_and(|hash| hash != palette_hash) {
+            debug!("palette changed, every region is scanned again");
+            self.clean.clear();
+        }
+        self.palette_hash = Some(palette_hash);
+    }
+
+    /// Forget a region's clean state, so the next scan diffs it against its cache again.
+    pub fn mark_dirty(&mut self, rloc: &RegionPos) {
+        self.clean.remove(&to_key(rloc));
+    }
+}
@@ -0,0 +1,80 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::coords::{BlockPos, ChunkPos, RegionPos};
+use crate::dimension::Dimension;
+use crate::region_index::to_region_name;
+
+/// `--order`: which regions are rendered first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RenderOrder {
+    /// Regions with the most chunks to draw first, so the last ones keep every thread busy.
+    #[default]
+    DirtyFirst,
+    /// The biggest region files first, whose chunks are the slowest to decode.
+    Size,
+    /// Ring by ring outwards from the world spawn (0,0 outside the overworld).
+    Spiral,
+    /// Nearest first to a block position.
+    Nearest(BlockPos),
+}
+
+impl FromStr for RenderOrder {
+    type Err = String;
+
+    /// "spiral", "nearest:X,Z", "dirty-first" or "size".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "dirty-first" => Ok(RenderOrder::DirtyFirst),
+            "size" => Ok(RenderOrder::Size),
+            "spiral" => Ok(RenderOrder::Spiral),
+            s => match s.strip_prefix("nearest:").map(crate::cli::parse_location_val) {
+                Some(Ok(pos)) => Ok(RenderOrder::Nearest(BlockPos::from(pos))),
+                _ => Err(format!("invalid order \"{}\", expected spiral, nearest:X,Z, dirty-first or size", s)),
+            },
+        }
+    }
+}
+
+/// SplitMix64 of the region under `seed`: a stable, evenly spread rank.
+fn tie_break(seed: u64, rloc: &RegionPos) -> u64 {
+    let mut z = seed ^ (((rloc.x as u32 as u64) << 32) | rloc.z as u32 as u64);
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Angle of `d` around the centre, clockwise from north, as a sortable integer.
+fn bearing(dx: i32, dz: i32) -> i64 {
+    let angle = (dx as f64).atan2(-dz as f64);
+    let angle = if angle < 0.0 { angle + std::f64::consts::TAU } else { angle };
+    (angle * 1e6) as i64
+}
+
+impl RenderOrder {
+    /// Sort `regions` of `dim` into render order; `spawn` is the centre of
+    /// `Spiral`, and `seed` orders the regions the order ranks the same.
+    pub fn sort(&self, regions: &mut Vec<(&RegionPos, &HashSet<ChunkPos>)>, dim: &Dimension, spawn: BlockPos, seed: u64) {
+        match *self {
+            RenderOrder::DirtyFirst => regions.sort_by_key(|(rloc, clocs)| (Reverse(clocs.len() as u64), tie_break(seed, rloc))),
+            RenderOrder::Size => regions.sort_by_cached_key(|(rloc, _)| {
+                let size = std::fs::metadata(dim.dim_path.join(to_region_name(rloc))).map_or(0, |metadata| metadata.len());
+                (Reverse(size), tie_break(seed, rloc))
+            }),
+            RenderOrder::Spiral => {
+                let centre = spawn.region();
+                regions.sort_by_key(|(rloc, _)| {
+                    let (dx, dz) = (rloc.x - centre.x, rloc.z - centre.z);
+                    (dx.abs().max(dz.abs()) as u64, bearing(dx, dz) as u64, tie_break(seed, rloc))
+                });
+            },
+            RenderOrder::Nearest(pos) => regions.sort_by_key(|(rloc, _)| {
+                let nearest = rloc.nearest_block(&pos);
+                let (dx, dz) = ((nearest.x - pos.x) as i64, (nearest.z - pos.z) as i64);
+                ((dx * dx + dz * dz) as u64, tie_break(seed, rloc))
+            }),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+
+use crate::coords::{ChunkPos, RegionPos};
+use crate::durability::Durability;
+
+pub const REPORT_NAME: &str = "render-report.json";
+pub const MANIFEST_NAME: &str = "changed-regions.json";
+pub const MANIFEST_LIST_NAME: &str = "changed-regions.txt";
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionStatus {
+    Rendered,
+    /// Rendered, but the region file changed since the scan.
+    Changed,
+    /// Its image could not be written.
+    Failed,
+    /// The region file disappeared since the scan; not rendered.
+    Missing,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RegionReport {
+    pub x: i32,
+    pub z: i32,
+    pub status: RegionStatus,
+    /// Chunks drawn, the others were kept from the previous image.
+    pub chunks: usize,
+    pub seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Chunks that could not be read, as [x, z].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_chunks: Vec<[i32; 2]>,
+}
+
+impl RegionReport {
+    pub fn new(rloc: &RegionPos, status: RegionStatus, chunks: usize, time: Duration, error: Option<String>) -> Self {
+        RegionReport { x: rloc.x, z: rloc.z, status, chunks, seconds: time.as_secs_f64(), error, failed_chunks: vec![] }
+    }
+
+    pub fn with_failed_chunks(mut self, clocs: &[ChunkPos]) -> Self {
+        self.failed_chunks = clocs.iter().map(|cloc| [cloc.x, cloc.z]).collect();
+        self.failed_chunks.sort_by_key(|[x, z]| (*z, *x));
+        self
+    }
+}
+
+/// Summary of a `render_all`, written as render-report.json in the image
+/// path for monitoring and cron wrappers.
+#[derive(Serialize, Debug, Clone)]
+pub struct RenderReport {
+    pub started: String,
+    pub wall_seconds: f64,
+    pub regions_rendered: usize,
+    pub regions_failed: usize,
+    pub regions_missing: usize,
+    pub chunks_rendered: usize,
+    /// Chunks of the rendered regions kept from their previous image.
+    pub chunks_from_cache: usize,
+    /// Chunks that could not be read.
+    pub chunk_errors: usize,
+    pub regions: Vec<RegionReport>,
+}
+
+/// A region image rewritten by the run.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChangedRegion {
+    pub x: i32,
+    pub z: i32,
+    /// Relative to the image path.
+    pub file: String,
+    /// None when the image is new.
+    pub old_mtime: Option<String>,
+    pub new_mtime: Option<String>,
+    /// Chunks drawn again.
+    pub chunks: usize,
+}
+
+impl ChangedRegion {
+    pub fn new(rloc: &RegionPos, file: String, old_mtime: Option<SystemTime>, new_mtime: Option<SystemTime>, chunks: usize) -> Self {
+        let format = |time: SystemTime| chrono::DateTime::<chrono::Local>::from(time).to_rfc3339();
+        ChangedRegion { x: rloc.x, z: rloc.z, file, old_mtime: old_mtime.map(format), new_mtime: new_mtime.map(format), chunks }
+    }
+}
+
+/// Write `changed-regions.json` and `changed-regions.txt` (one file name per
+/// line) into `image_path`, for CDN purges and sync scripts that should only
+/// touch what the run rewrote.
+pub fn write_manifest(image_path: &Path, mut changed: Vec<ChangedRegion>) -> std::io::Result<()> {
+    changed.sort_by_key(|region| (region.x, region.z));
+    let list: String = changed.iter().map(|region| format!("{}\n", region.file)).collect();
+    Durability::Fast.write(&image_path.join(MANIFEST_NAME), &serde_json::to_vec_pretty(&changed)?)?;
+    Durability::Fast.write(&image_path.join(MANIFEST_LIST_NAME), list.as_bytes())
+}
+
+impl RenderReport {
+    pub fn new(started: chrono::DateTime<chrono::Local>, wall: Duration, mut regions: Vec<RegionReport>, chunks_from_cache: usize, chunk_errors: usize) -> Self {
+        regions.sort_by_key(|region| (region.x, region.z));
+        let count = |status: RegionStatus| regions.iter().filter(|region| region.status == status).count();
+        RenderReport {
+            started: started.to_rfc3339(),
+            wall_seconds: wall.as_secs_f64(),
+            regions_rendered: count(RegionStatus::Rendered) + count(RegionStatus::Changed),
+            regions_failed: count(RegionStatus::Failed),
+            regions_missing: count(RegionStatus::Missing),
+            chunks_rendered: regions.iter().filter(|region| region.status != RegionStatus::Missing).map(|region| region.chunks).sum(),
+            chunks_from_cache,
+            chunk_errors,
+            regions,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        Durability::Fast.write(path, &serde_json::to_vec_pretty(self)?)
+    }
+}
@@ -1,9 +1,12 @@
+use std::ops::Range;
 use std::path::PathBuf;
-use fastanvil::{RenderedPalette, Rgba} ;
+use fastanvil::{Block, Chunk, HeightMode, Palette, RenderedPalette, Rgba, TopShadeRenderer} ;
+use fastanvil::biome::Biome;
 
 use flate2::read::GzDecoder;
+use clap::ArgEnum;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use crate::error::Result;
 
 pub fn get_palette(path: &PathBuf) -> Result<RenderedPalette> {
     let f = std::fs::File::open(path)?;
@@ -50,3 +53,325 @@ pub fn get_palette(path: &PathBuf) -> Result<RenderedPalette> {
 
     Ok(p)
 }
+
+/// FNV-1a hash of a palette's block colours and colour maps, stored with
+/// the caches to notice a palette change.
+pub fn palette_hash(palette: &RenderedPalette) -> u64 {
+    let mut states: Vec<(&String, &Rgba)> = palette.blockstates.iter().collect();
+    states.sort();
+    let bytes = states.into_iter()
+        .flat_map(|(name, colour)| name.as_bytes().iter().chain(colour.iter()))
+        .chain(palette.grass.as_raw().iter())
+        .chain(palette.foliage.as_raw().iter());
+    bytes.fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn is_air(block: Option<&Block>) -> bool {
+    match block {
+        None => true,
+        Some(block) => matches!(block.name(), "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air"),
+    }
+}
+
+/// Chunk view that hides everything at and above a roof, for the Nether.
+/// The surface of a column is the first block below the first air gap under
+/// `cut_y`, so the renderer sees the cave floor instead of the bedrock ceiling.
+pub struct CeilingChunk<'a, C: Chunk> {
+    inner: &'a C,
+    cut_y: isize,
+}
+
+impl<'a, C: Chunk> CeilingChunk<'a, C> {
+    pub fn new(inner: &'a C, cut_y: isize) -> Self {
+        CeilingChunk { inner, cut_y }
+    }
+}
+
+impl<'a, C: Chunk> Chunk for CeilingChunk<'a, C> {
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+
+    fn surface_height(&self, x: usize, z: usize, _mode: HeightMode) -> isize {
+        let y_range = self.inner.y_range();
+        let mut y = self.cut_y.min(y_range.end - 1);
+        // Skip the roof down to the first air gap...
+        while y > y_range.start && !is_air(self.inner.block(x, y, z)) {
+            y -= 1;
+        }
+        // ...then down to the first block under it.
+        while y > y_range.start && is_air(self.inner.block(x, y, z)) {
+            y -= 1;
+        }
+        // Heights are one above the top block, like heightmaps.
+        y + 1
+    }
+
+    fn biome(&self, x: usize, y: isize, z: usize) -> Option<Biome> {
+        self.inner.biome(x, y, z)
+    }
+
+    fn block(&self, x: usize, y: isize, z: usize) -> Option<&Block> {
+        self.inner.block(x, y, z)
+    }
+
+    fn y_range(&self) -> Range<isize> {
+        self.inner.y_range()
+    }
+}
+
+/// Height the top shading follows on water columns.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum ShadeHeight {
+    /// The water surface: oceans and rivers are shaded flat.
+    #[default]
+    Water,
+    /// The terrain under the water, drawn through a water tint.
+    Seabed,
+}
+
+/// `--water`: how water columns are drawn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum WaterStyle {
+    /// The palette colour of the water surface, like any top block.
+    #[default]
+    Flat,
+    /// The water surface, darker the deeper the column.
+    Depth,
+    /// The floor through shallow water, fading to the water colour with depth.
+    Transparent,
+}
+
+/// `--error-style`: what the area of a chunk that cannot be read or decoded shows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum ErrorStyle {
+    /// Whatever the previous image had there.
+    #[default]
+    Keep,
+    /// Transparent, like a chunk that was never generated.
+    Blank,
+    /// A magenta and black checkerboard, hard to miss.
+    Pattern,
+}
+
+/// The 16x16 pixels of a chunk drawn with `--error-style pattern`.
+pub fn error_pattern() -> [fastanvil::Rgba; 16 * 16] {
+    let mut buf = [[0u8; 4]; 16 * 16];
+    for (i, px) in buf.iter_mut().enumerate() {
+        let (x, z) = (i % 16, i / 16);
+        *px = if (x / 4 + z / 4) % 2 == 0 { [255, 0, 255, 255] } else { [0, 0, 0, 255] };
+    }
+    buf
+}
+
+/// `--height-mode`: where the surface of a column comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum HeightSource {
+    /// The heightmaps stored in the chunks, unless they don't match the blocks.
+    #[default]
+    Trust,
+    /// Search every column from the top, for worlds whose heightmaps are all broken.
+    Calculate,
+}
+
+/// Height mode to draw `chunk` with. Chunks upgraded from old versions can
+/// carry a missing or stale heightmap (columns floating above the terrain, or
+/// empty over blocks); those are calculated even with `HeightSource::Trust`.
+pub fn chunk_height_mode<C: Chunk>(chunk: &C, source: HeightSource) -> HeightMode {
+    if source == HeightSource::Calculate {
+        return HeightMode::Calculate;
+    }
+    let bottom = chunk.y_range().start;
+    let broken = (0..16).flat_map(|z| (0..16).map(move |x| (x, z))).any(|(x, z)| {
+        let y = chunk.surface_height(x, z, HeightMode::Trust);
+        if y > bottom {
+            // One above the top block, so never above air.
+            is_air(chunk.block(x, y - 1, z))
+        } else {
+            chunk.surface_height(x, z, HeightMode::Calculate) > bottom
+        }
+    });
+    if broken { HeightMode::Calculate } else { HeightMode::Trust }
+}
+
+/// Relief shading of the map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum ShadeMode {
+    /// Flat palette colours, one per block, for pixel-exact analysis.
+    Off,
+    /// Lighter or darker by the height of the column to the north.
+    #[default]
+    North,
+    /// Also by the column to the west, as if lit from the north-west.
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Shading {
+    pub mode: ShadeMode,
+    /// 0 is flat, 1 the full shading.
+    pub strength: f32,
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Shading { mode: ShadeMode::North, strength: 1.0 }
+    }
+}
+
+/// Draw the top of `chunk` shaded as `shading` asks; `north` and `west` are
+/// its neighbours (in the same view), used for the columns on its edges.
+/// `mode` is the one `renderer` was made with.
+pub fn shade_chunk<C: Chunk>(renderer: &TopShadeRenderer<RenderedPalette>, palette: &RenderedPalette, mode: HeightMode, chunk: &C, north: Option<&C>, west: Option<&C>, shading: &Shading) -> [Rgba; 256] {
+    if shading.mode == ShadeMode::North && shading.strength >= 1.0 {
+        return renderer.render(chunk, north);
+    }
+    let flat = flat_chunk(palette, chunk, mode);
+    if shading.mode == ShadeMode::Off {
+        return flat;
+    }
+    let mut shaded = renderer.render(chunk, north);
+    if shading.mode == ShadeMode::Full {
+        for z in 0..16 {
+            let mut west_y = west.map(|west| west.surface_height(15, z, mode));
+            for x in 0..16 {
+                let y = chunk.surface_height(x, z, mode);
+                if let Some(west_y) = west_y {
+                    let factor = match y.cmp(&west_y) {
+                        std::cmp::Ordering::Greater => 1.1,
+                        std::cmp::Ordering::Less => 0.9,
+                        std::cmp::Ordering::Equal => 1.0,
+                    };
+                    for c in shaded[z * 16 + x].iter_mut().take(3) {
+                        *c = (*c as f32 * factor).min(255.0) as u8;
+                    }
+                }
+                west_y = Some(y);
+            }
+        }
+    }
+    let strength = shading.strength.clamp(0.0, 1.0);
+    for (px, flat) in shaded.iter_mut().zip(flat.iter()) {
+        for (c, f) in px.iter_mut().zip(flat.iter()).take(3) {
+            *c = (*f as f32 + (*c as f32 - *f as f32) * strength).round() as u8;
+        }
+    }
+    shaded
+}
+
+/// Palette colour of the top block of every column, unshaded.
+fn flat_chunk<C: Chunk>(palette: &RenderedPalette, chunk: &C, mode: HeightMode) -> [Rgba; 256] {
+    let mut buf = [[0u8; 4]; 256];
+    for z in 0..16 {
+        for x in 0..16 {
+            let top = chunk.surface_height(x, z, mode) - 1;
+            if let Some(block) = chunk.block(x, top, z) {
+                buf[z * 16 + x] = palette.pick(block, chunk.biome(x, top, z));
+            }
+        }
+    }
+    buf
+}
+
+fn is_water(block: Option<&Block>) -> bool {
+    match block {
+        None => false,
+        Some(block) => matches!(block.name(),
+            "minecraft:water" | "minecraft:bubble_column" | "minecraft:kelp" | "minecraft:kelp_plant"
+            | "minecraft:seagrass" | "minecraft:tall_seagrass"),
+    }
+}
+
+/// Water blocks between the surface of a column and its floor.
+pub fn water_depth<C: Chunk>(chunk: &C, x: usize, z: usize, mode: HeightMode) -> isize {
+    let bottom = chunk.y_range().start;
+    let surface = chunk.surface_height(x, z, mode);
+    let mut y = surface - 1;
+    while y > bottom && is_water(chunk.block(x, y, z)) {
+        y -= 1;
+    }
+    surface - (y + 1)
+}
+
+/// Palette colour of still water, for tinting seabed renders.
+pub fn water_colour(palette: &RenderedPalette) -> Rgba {
+    ["minecraft:water|level=0", "minecraft:water"].iter()
+        .find_map(|name| palette.blockstates.get(*name))
+        .copied()
+        .unwrap_or([63, 118, 228, 255])
+}
+
+/// Blend `water` over a seabed pixel, more opaque the deeper the column.
+pub fn tint_water(px: &mut Rgba, water: Rgba, depth: isize) {
+    if depth <= 0 {
+        return;
+    }
+    let alpha = (96 + depth as u32 * 8).min(200);
+    for (c, w) in px.iter_mut().zip(water.iter()).take(3) {
+        *c = ((*c as u32 * (255 - alpha) + *w as u32 * alpha) / 255) as u8;
+    }
+}
+
+/// Darken a water surface pixel by `depth`, down to 40% at 24 blocks.
+pub fn darken_water(px: &mut Rgba, depth: isize) {
+    if depth <= 1 {
+        return;
+    }
+    let factor = 1.0 - depth.min(24) as f32 / 24.0 * 0.6;
+    for c in px.iter_mut().take(3) {
+        *c = (*c as f32 * factor).round() as u8;
+    }
+}
+
+/// Blend `water` over a floor pixel: the floor shows through a block or
+/// two of water, and is gone under eight.
+pub fn clear_water(px: &mut Rgba, water: Rgba, depth: isize) {
+    if depth <= 0 {
+        return;
+    }
+    let alpha = (depth as u32 * 32).min(255);
+    for (c, w) in px.iter_mut().zip(water.iter()).take(3) {
+        *c = ((*c as u32 * (255 - alpha) + *w as u32 * alpha) / 255) as u8;
+    }
+}
+
+/// Chunk view whose surface is the terrain under any water, so the
+/// renderer shades the seabed's relief instead of the flat water surface.
+pub struct SeabedChunk<'a, C: Chunk> {
+    inner: &'a C,
+    /// Mode of the water surface in `water_depth`.
+    mode: HeightMode,
+}
+
+impl<'a, C: Chunk> SeabedChunk<'a, C> {
+    pub fn new(inner: &'a C, mode: HeightMode) -> Self {
+        SeabedChunk { inner, mode }
+    }
+
+    /// Water blocks above the seabed of a column.
+    pub fn water_depth(&self, x: usize, z: usize) -> isize {
+        water_depth(self.inner, x, z, self.mode)
+    }
+}
+
+impl<'a, C: Chunk> Chunk for SeabedChunk<'a, C> {
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+
+    fn surface_height(&self, x: usize, z: usize, mode: HeightMode) -> isize {
+        self.inner.surface_height(x, z, mode) - water_depth(self.inner, x, z, mode)
+    }
+
+    fn biome(&self, x: usize, y: isize, z: usize) -> Option<Biome> {
+        self.inner.biome(x, y, z)
+    }
+
+    fn block(&self, x: usize, y: isize, z: usize) -> Option<&Block> {
+        self.inner.block(x, y, z)
+    }
+
+    fn y_range(&self) -> Range<isize> {
+        self.inner.y_range()
+    }
+}
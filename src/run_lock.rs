@@ -0,0 +1,130 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::warn;
+
+/// The lock of a cache path, held by the run writing to it.
+pub const LOCK_NAME: &str = ".mcanvilrenderer.lock";
+
+/// How often `--wait` looks at the lock again.
+const WAIT_POLL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// The lock files this process holds, for `exit`.
+    static ref HELD: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+}
+
+/// Advisory lock on a cache path (and the images rendered with it), so an
+/// overlapping cron run doesn't write the same region images and caches as
+/// the one still running. A lock file holds the pid, host and start time
+/// of its run and is removed when the run ends; one left by a run that was
+/// killed is taken over when its pid is gone from this host.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another run holds the lock: what its lock file says.
+    Held(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LockError::Held(holder) => write!(f, "another run holds the lock ({}); --wait to wait for it, --force to take it over", holder),
+            LockError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname").map(|host| host.trim().to_string()).unwrap_or_default()
+}
+
+/// Whether the run that wrote `holder` ("pid host started") is gone: only
+/// known on the same host, and where /proc lists processes.
+fn stale(holder: &str) -> bool {
+    let mut fields = holder.split_whitespace();
+    let (pid, host) = match (fields.next().and_then(|pid| pid.parse::<u32>().ok()), fields.next()) {
+        (Some(pid), Some(host)) => (pid, host),
+        _ => return false,
+    };
+    host == hostname() && Path::new("/proc/self").exists() && !Path::new("/proc").join(pid.to_string()).exists()
+}
+
+impl RunLock {
+    /// Take the lock of `cache_path`. A held lock is an error, unless
+    /// `wait` (wait for it to be released) or `force` (take it anyway).
+    pub fn acquire(cache_path: &Path, wait: bool, force: bool) -> Result<Self, LockError> {
+        std::fs::create_dir_all(cache_path).map_err(LockError::Io)?;
+        let path = cache_path.join(LOCK_NAME);
+        let mut waiting = false;
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{} {} {}", std::process::id(), hostname(), chrono::Local::now().to_rfc3339()).map_err(LockError::Io)?;
+                    HELD.lock().unwrap().push(path.clone());
+                    return Ok(RunLock { path });
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(LockError::Io(e)),
+            }
+            // Empty while its run is still writing it.
+            let holder = std::fs::read_to_string(&path).unwrap_or_default().trim().to_string();
+            if force || stale(&holder) {
+                warn!("taking over the lock {} ({})", path.display(), holder);
+                // Moved aside rather than removed: of several runs taking over
+                // the same stale lock, one moves it, and another that comes
+                // after it has created its own lock moves that one instead,
+                // sees it isn't the lock it read, and puts it back.
+                let aside = path.with_file_name(format!("{}.{}", LOCK_NAME, std::process::id()));
+                if std::fs::rename(&path, &aside).is_ok() {
+                    let moved = std::fs::read_to_string(&aside).unwrap_or_default().trim().to_string();
+                    if moved != holder {
+                        // Fails if yet another run has locked it since.
+                        let _ = std::fs::hard_link(&aside, &path);
+                    }
+                    let _ = std::fs::remove_file(&aside);
+                }
+                continue;
+            }
+            if !wait {
+                return Err(LockError::Held(holder));
+            }
+            if !waiting {
+                note!("Waiting for the run holding {} ({})", path.display(), holder);
+                waiting = true;
+            }
+            std::thread::sleep(WAIT_POLL);
+        }
+    }
+}
+
+fn release(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("cannot remove the lock {}: {}", path.display(), e);
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap();
+        if let Some(index) = held.iter().position(|path| *path == self.path) {
+            held.remove(index);
+            release(&self.path);
+        }
+    }
+}
+
+/// `std::process::exit`, releasing the locks first: exiting skips the drop.
+pub fn exit(code: i32) -> ! {
+    for path in HELD.lock().unwrap().drain(..) {
+        release(&path);
+    }
+    std::process::exit(code)
+}
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+use fastanvil::Rgba;
+
+use crate::coords::{BlockPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+
+/// `--scale`: pixels per block of the region images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scale {
+    /// Every block drawn as n×n pixels.
+    Up(usize),
+    /// Every n×n blocks averaged into one pixel.
+    Down(usize),
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::Up(1)
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    /// "1", "2", "4", "1/2" or "1/4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "1" => Ok(Scale::Up(1)),
+            "2" => Ok(Scale::Up(2)),
+            "4" => Ok(Scale::Up(4)),
+            "1/2" => Ok(Scale::Down(2)),
+            "1/4" => Ok(Scale::Down(4)),
+            _ => Err(format!("invalid scale \"{}\", expected 1, 2, 4, 1/2 or 1/4", s)),
+        }
+    }
+}
+
+impl Scale {
+    /// Edge of a region image in pixels.
+    pub fn region_pixels(&self) -> usize {
+        match self {
+            Scale::Up(n) => REGION_SIZE as usize * n,
+            Scale::Down(n) => REGION_SIZE as usize / n,
+        }
+    }
+
+    /// Region-local block (north-west one when downscaled) of pixel `x`, `z`.
+    pub fn block_at(&self, x: usize, z: usize) -> (i32, i32) {
+        match self {
+            Scale::Up(n) => ((x / n) as i32, (z / n) as i32),
+            Scale::Down(n) => ((x * n) as i32, (z * n) as i32),
+        }
+    }
+
+    /// Draw the 16×16 pixels of a chunk into the region image `buf` at the
+    /// region-local chunk `cx`, `cz`, so chunks drawn over a cached image
+    /// land on the same pixels as the ones kept.
+    pub fn composite(&self, buf: &mut [Rgba], chunk: &[Rgba], cx: usize, cz: usize) {
+        let chunk_size = CHUNK_SIZE as usize;
+        let width = self.region_pixels();
+        match *self {
+            Scale::Up(1) => {
+                let (left, top) = (cx * chunk_size, cz * chunk_size);
+                for (z, row) in chunk.chunks_exact(chunk_size).enumerate() {
+                    let start = (top + z) * width + left;
+                    buf[start..start + chunk_size].copy_from_slice(row);
+                }
+            },
+            Scale::Up(n) => {
+                let (left, top) = (cx * chunk_size * n, cz * chunk_size * n);
+                let mut wide = [[0u8; 4]; CHUNK_SIZE as usize * 4];
+                let wide = &mut wide[..chunk_size * n];
+                for (z, row) in chunk.chunks_exact(chunk_size).enumerate() {
+                    for (x, px) in wide.iter_mut().enumerate() {
+                        *px = row[x / n];
+                    }
+                    // Each widened row is drawn n times.
+                    for dz in 0..n {
+                        let start = (top + z * n + dz) * width + left;
+                        buf[start..start + chunk_size * n].copy_from_slice(wide);
+                    }
+                }
+            },
+            Scale::Down(n) => {
+                let side = chunk_size / n;
+                let (left, top) = (cx * side, cz * side);
+                for z in 0..side {
+                    for x in 0..side {
+                        let block: Vec<Rgba> = (0..n * n).map(|i| chunk[(z * n + i / n) * chunk_size + x * n + i % n]).collect();
+                        buf[(top + z) * width + left + x] = average(&block);
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// `--far-scale`: regions farther than `distance` blocks from every centre
+/// (the spawn and the players) are drawn at `scale` instead.
+#[derive(Debug, Clone)]
+pub struct FarScale {
+    pub scale: Scale,
+    pub distance: i64,
+    pub centres: Vec<BlockPos>,
+}
+
+impl FarScale {
+    /// Whether no block of `rloc` is within `distance` of a centre.
+    pub fn is_far(&self, rloc: &RegionPos) -> bool {
+        self.centres.iter().all(|centre| {
+            let nearest = rloc.nearest_block(centre);
+            let (dx, dz) = ((nearest.x - centre.x) as i64, (nearest.z - centre.z) as i64);
+            dx * dx + dz * dz > self.distance * self.distance
+        })
+    }
+}
+
+/// Mean of `pixels`, colours weighted by their alpha.
+fn average(pixels: &[Rgba]) -> Rgba {
+    let alpha: u32 = pixels.iter().map(|px| px[3] as u32).sum();
+    if alpha == 0 {
+        return [0; 4];
+    }
+    let mut out = [0u8; 4];
+    for (c, channel) in out.iter_mut().enumerate().take(3) {
+        *channel = (pixels.iter().map(|px| px[c] as u32 * px[3] as u32).sum::<u32>() / alpha) as u8;
+    }
+    out[3] = (alpha / pixels.len() as u32) as u8;
+    out
+}
@@ -0,0 +1,128 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+
+use crate::coords::{RegionBounds, RegionPos};
+
+lazy_static! {
+    /// Held by a pass while it writes the files both lanes of serve share on
+    /// one image and cache path, so neither overwrites the other's:
+    /// - the region index, saved by the scan (`Dimension::from_dimdir`);
+    /// - the skip list, read, merged and written (`finish_skip_list`);
+    /// - render-report.json and the changed-regions manifest (`render_all`);
+    /// - the tiles and the stitched image (`render_pass`).
+    pub static ref PUBLISH: Mutex<()> = Mutex::new(());
+}
+
+/// Lets more urgent work run between the chunks of a region.
+pub trait Preemption: Send + Sync {
+    /// A worker starts drawing `rloc`.
+    fn region_started(&self, rloc: &RegionPos);
+    /// The worker is done with `rloc`, written or not.
+    fn region_done(&self, rloc: &RegionPos);
+    /// Between two chunks: block while more urgent work is waiting or running.
+    fn chunk_boundary(&self);
+}
+
+/// Regions a viewer asked for, of one layer (dimension).
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub layer: usize,
+    pub bounds: RegionBounds,
+    /// Render the regions in full whatever their caches say (--render-missing).
+    pub force: bool,
+}
+
+#[derive(Default)]
+struct State {
+    interactive: VecDeque<Job>,
+    /// Interactive jobs being rendered.
+    running: usize,
+    /// Regions (per layer) batch workers are in the middle of.
+    batch_busy: HashSet<(usize, RegionPos)>,
+}
+
+/// The two lanes of serve mode: scheduled passes over the whole world
+/// (batch) and regions a viewer is looking at (interactive). Batch workers
+/// pause at the next chunk boundary while interactive work is queued or
+/// running, so a viewport renders without waiting for the world.
+#[derive(Default)]
+pub struct Scheduler {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// Most regions one interactive job may cover; larger views are left to the batch lane.
+pub const MAX_JOB_REGIONS: usize = 64;
+
+impl Scheduler {
+    /// Queue a job unless the same one is already waiting, which is then
+    /// forced if this one is.
+    pub fn submit(&self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        match state.interactive.iter_mut().find(|queued| queued.layer == job.layer && queued.bounds == job.bounds) {
+            Some(queued) => queued.force |= job.force,
+            None => {
+                state.interactive.push_back(job);
+                self.changed.notify_all();
+            },
+        }
+    }
+
+    /// Wait up to `timeout` for the next interactive job; `finish` it when rendered.
+    pub fn next(&self, timeout: Duration) -> Option<Job> {
+        let until = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.interactive.pop_front() {
+                state.running += 1;
+                return Some(job);
+            }
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return None;
+            }
+            state = self.changed.wait_timeout(state, left).unwrap().0;
+        }
+    }
+
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Whether a batch worker has `rloc` of `layer` half drawn; the
+    /// interactive lane leaves it, the batch worker writes it when resumed.
+    pub fn batch_busy(&self, layer: usize, rloc: &RegionPos) -> bool {
+        self.state.lock().unwrap().batch_busy.contains(&(layer, *rloc))
+    }
+
+    /// The batch lane's side of the scheduler, for the passes over `layer`.
+    pub fn batch_lane(self: &Arc<Self>, layer: usize) -> Arc<dyn Preemption> {
+        Arc::new(BatchLane { scheduler: Arc::clone(self), layer })
+    }
+}
+
+struct BatchLane {
+    scheduler: Arc<Scheduler>,
+    layer: usize,
+}
+
+impl Preemption for BatchLane {
+    fn region_started(&self, rloc: &RegionPos) {
+        self.scheduler.state.lock().unwrap().batch_busy.insert((self.layer, *rloc));
+    }
+
+    fn region_done(&self, rloc: &RegionPos) {
+        self.scheduler.state.lock().unwrap().batch_busy.remove(&(self.layer, *rloc));
+    }
+
+    fn chunk_boundary(&self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        while !state.interactive.is_empty() || state.running > 0 {
+            state = self.scheduler.changed.wait(state).unwrap();
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::str::FromStr;
+
+use crate::cli::{parse_location_val, parse_range_val};
+use crate::coords::{BlockBounds, BlockPos, ChunkPos, RegionPos, CHUNK_SIZE, REGION_SIZE};
+
+/// One shape of a selection, in blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// Inclusive rectangle (min, max).
+    Rect(BlockBounds),
+    /// Blocks within `radius` of `center`.
+    Circle { center: BlockPos, radius: i32 },
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    /// "x1,z1:x2,z2" for a rectangle, "C<x>,<z>,<radius>" for a circle, both
+    /// in blocks. A circle written "C<x>,<z>,<radius>r" is in regions: around
+    /// the middle of region x,z, reaching `radius` regions from it. Circles
+    /// whose bounds don't fit in block coordinates are refused.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(circle) = s.strip_prefix('C').or_else(|| s.strip_prefix('c')) {
+            let (center, radius) = circle.rsplit_once(',').ok_or_else(|| format!("invalid circle \"{}\", expected C<x>,<z>,<radius>", s))?;
+            let center = parse_location_val(center).map_err(|e| e.to_string())?;
+            let in_regions = radius.ends_with('r');
+            let radius: i32 = radius.trim_end_matches('r').parse().map_err(|_| format!("invalid circle radius \"{}\"", radius))?;
+            if radius < 0 {
+                return Err(format!("invalid circle radius \"{}\"", radius));
+            }
+            let too_large = || format!("circle \"{}\" reaches beyond the block coordinates", s);
+            let (center, radius) = if in_regions {
+                let half = REGION_SIZE / 2;
+                let middle = |region: i32| region.checked_mul(REGION_SIZE).and_then(|block| block.checked_add(half));
+                let center = match (middle(center.0), middle(center.1)) {
+                    (Some(x), Some(z)) => BlockPos::new(x, z),
+                    _ => return Err(too_large()),
+                };
+                (center, radius.checked_mul(REGION_SIZE).ok_or_else(too_large)?)
+            } else {
+                (BlockPos::from(center), radius)
+            };
+            // So that `bounds` can offset the centre by the radius.
+            let fits = |c: i32| c.checked_sub(radius).is_some() && c.checked_add(radius).is_some();
+            if !fits(center.x) || !fits(center.z) {
+                return Err(too_large());
+            }
+            return Ok(Shape::Circle { center, radius });
+        }
+        let (a, b) = parse_range_val(s)
+            .map_err(|_| format!("invalid selection \"{}\", expected x1,z1:x2,z2 or C<x>,<z>,<radius>", s))?;
+        Ok(Shape::Rect((BlockPos::new(a.0.min(b.0), a.1.min(b.1)), BlockPos::new(a.0.max(b.0), a.1.max(b.1)))))
+    }
+}
+
+impl Shape {
+    fn contains(&self, block: &BlockPos) -> bool {
+        match self {
+            Shape::Rect(bounds) => block.in_bounds(bounds),
+            Shape::Circle { center, radius } => {
+                let (dx, dz) = ((block.x - center.x) as i64, (block.z - center.z) as i64);
+                dx * dx + dz * dz <= *radius as i64 * *radius as i64
+            },
+        }
+    }
+
+    /// Whether any block of the square `size` blocks wide at `origin` is inside.
+    fn overlaps(&self, origin: BlockPos, size: i32) -> bool {
+        match self {
+            Shape::Rect(bounds) => origin.x <= bounds.1.x && bounds.0.x < origin.x + size
+                && origin.z <= bounds.1.z && bounds.0.z < origin.z + size,
+            Shape::Circle { center, .. } => {
+                let nearest = BlockPos::new(
+                    center.x.clamp(origin.x, origin.x + size - 1),
+                    center.z.clamp(origin.z, origin.z + size - 1));
+                self.contains(&nearest)
+            },
+        }
+    }
+
+    fn bounds(&self) -> BlockBounds {
+        match self {
+            Shape::Rect(bounds) => *bounds,
+            Shape::Circle { center, radius } => (center.offset(-radius, -radius), center.offset(*radius, *radius)),
+        }
+    }
+}
+
+/// Area to render: the union of rectangles and circles. Regions and chunks
+/// are picked by overlap, and blocks outside are left transparent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    shapes: Vec<Shape>,
+}
+
+impl Selection {
+    pub fn new(shapes: Vec<Shape>) -> Option<Self> {
+        if shapes.is_empty() { None } else { Some(Selection { shapes }) }
+    }
+
+    pub fn contains(&self, block: &BlockPos) -> bool {
+        self.shapes.iter().any(|shape| shape.contains(block))
+    }
+
+    pub fn overlaps_chunk(&self, cloc: &ChunkPos) -> bool {
+        self.shapes.iter().any(|shape| shape.overlaps(cloc.block_origin(), CHUNK_SIZE))
+    }
+
+    pub fn overlaps_region(&self, rloc: &RegionPos) -> bool {
+        self.shapes.iter().any(|shape| shape.overlaps(rloc.block_origin(), REGION_SIZE))
+    }
+
+    /// Bounding rectangle of every shape.
+    pub fn bounds(&self) -> BlockBounds {
+        self.shapes.iter().map(Shape::bounds).reduce(|a, b| (
+            BlockPos::new(a.0.x.min(b.0.x), a.0.z.min(b.0.z)),
+            BlockPos::new(a.1.x.max(b.1.x), a.1.z.max(b.1.z)),
+        )).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circles_in_regions() {
+        let circle: Shape = "C1,-1,2r".parse().unwrap();
+        assert_eq!(circle, Shape::Circle { center: BlockPos::new(768, -256), radius: 1024 });
+        assert_eq!(circle.bounds(), (BlockPos::new(-256, -1280), BlockPos::new(1792, 768)));
+    }
+
+    #[test]
+    fn circles_beyond_the_coordinates_are_refused() {
+        assert!("C0,0,5000000r".parse::<Shape>().is_err());
+        assert!("C5000000,0,1r".parse::<Shape>().is_err());
+        assert!("C2147483000,0,1000".parse::<Shape>().is_err());
+        assert!("C0,0,2147483647".parse::<Shape>().is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use log::warn;
+
+use crate::run_lock;
+
+/// Exit code of a run stopped by SIGINT or SIGTERM (128 + SIGINT).
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch SIGINT and SIGTERM: the first one asks the run to stop once the
+/// regions being drawn are written, a second one exits at once.
+pub fn install() {
+    let installed = ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            eprintln!("interrupted again, exiting now");
+            run_lock::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("stopping after the regions in progress (interrupt again to exit now)");
+    });
+    if let Err(e) = installed {
+        warn!("cannot handle interrupts, Ctrl-C stops the run at once: {}", e);
+    }
+}
+
+/// Whether the run was asked to stop: no new region is started.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration`, or less when a stop is requested meanwhile.
+pub fn sleep(duration: Duration) {
+    let until = Instant::now() + duration;
+    while !requested() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(250)));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::coords::ChunkPos;
+
+pub const SKIP_LIST_NAME: &str = "broken-chunks.json";
+
+/// A chunk that failed to decode, as persisted in the cache directory.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BrokenChunk {
+    pub failures: u32,
+    pub first_seen: String,
+    /// Header timestamp of the failing version; a rewritten chunk is retried.
+    pub timestamp: u32,
+}
+
+/// Persisted list of chunks that keep failing to decode.
+/// Chunks are skipped once they failed `THRESHOLD` runs in a row with the
+/// same header timestamp, and within a run after their first failure.
+pub struct SkipList {
+    path: PathBuf,
+    /// Started empty (--retry-broken): the saved list replaces the file.
+    retry_broken: bool,
+    entries: Mutex<BTreeMap<String, BrokenChunk>>,
+    failed: Mutex<HashSet<ChunkPos>>,
+    /// Chunks of the list that decoded this run.
+    recovered: Mutex<HashSet<ChunkPos>>,
+    skipped: AtomicUsize,
+}
+
+pub struct SkipListSummary {
+    pub known: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+fn to_key(cloc: &ChunkPos) -> String {
+    format!("{},{}", cloc.x, cloc.z)
+}
+
+/// The list saved at `path`, empty if there is none or it cannot be read.
+fn read(path: &Path) -> BTreeMap<String, BrokenChunk> {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+            warn!("ignore unreadable {}: {}", path.to_str().unwrap(), e);
+            Default::default()
+        }),
+        Err(_) => Default::default(),
+    }
+}
+
+impl SkipList {
+    pub const THRESHOLD: u32 = 3;
+
+    /// Load the skip list of `cache_path`, or start empty when `retry_broken`.
+    pub fn load(cache_path: &Path, retry_broken: bool) -> Self {
+        let path = cache_path.join(SKIP_LIST_NAME);
+        let entries = if retry_broken { Default::default() } else { read(&path) };
+        SkipList {
+            path,
+            retry_broken,
+            entries: Mutex::new(entries),
+            failed: Default::default(),
+            recovered: Default::default(),
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn should_skip(&self, cloc: &ChunkPos, timestamp: u32) -> bool {
+        let skip = self.failed.lock().unwrap().contains(cloc) || match self.entries.lock().unwrap().get(&to_key(cloc)) {
+            Some(entry) => entry.failures >= Self::THRESHOLD && entry.timestamp == timestamp,
+            None => false,
+        };
+        if skip {
+            debug!("skip broken chunk {}", cloc);
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        skip
+    }
+
+    pub fn record_failure(&self, cloc: &ChunkPos, timestamp: u32) {
+        if !self.failed.lock().unwrap().insert(*cloc) {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(to_key(cloc)).or_insert_with(|| BrokenChunk {
+            failures: 0,
+            first_seen: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            timestamp,
+        });
+        if entry.timestamp != timestamp {
+            entry.failures = 0;
+            entry.timestamp = timestamp;
+        }
+        entry.failures += 1;
+    }
+
+    /// A chunk that decodes again is dropped from the list.
+    pub fn record_success(&self, cloc: &ChunkPos) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.is_empty() && entries.remove(&to_key(cloc)).is_some() {
+            self.recovered.lock().unwrap().insert(*cloc);
+        }
+    }
+
+    /// Write this run's failures and recoveries into the list on disk, which
+    /// another pass on the cache path may have saved since `load` (serve
+    /// renders in two lanes): hold `scheduler::PUBLISH` from the read to the
+    /// write. With --retry-broken the list is replaced.
+    pub fn save(&self) -> std::io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let merged = if self.retry_broken { entries.clone() } else {
+            let mut merged = read(&self.path);
+            for cloc in self.failed.lock().unwrap().iter() {
+                let key = to_key(cloc);
+                if let Some(entry) = entries.get(&key) {
+                    merged.insert(key, entry.clone());
+                }
+            }
+            for cloc in self.recovered.lock().unwrap().iter() {
+                merged.remove(&to_key(cloc));
+            }
+            merged
+        };
+        let file = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer_pretty(file, &merged)?;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> SkipListSummary {
+        SkipListSummary {
+            known: self.entries.lock().unwrap().len(),
+            failed: self.failed.lock().unwrap().len(),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
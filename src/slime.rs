@@ -0,0 +1,78 @@
+use image::RgbaImage;
+
+use crate::coords::{BlockPos, ChunkPos};
+use crate::grid::blend;
+
+/// `java.util.Random`, as far as slime chunks need it.
+struct JavaRandom {
+    seed: i64,
+}
+
+impl JavaRandom {
+    const MULTIPLIER: i64 = 0x5DEECE66D;
+    const MASK: i64 = (1 << 48) - 1;
+
+    fn new(seed: i64) -> Self {
+        JavaRandom { seed: (seed ^ Self::MULTIPLIER) & Self::MASK }
+    }
+
+    fn next(&mut self, bits: u32) -> i32 {
+        self.seed = self.seed.wrapping_mul(Self::MULTIPLIER).wrapping_add(0xB) & Self::MASK;
+        (self.seed >> (48 - bits)) as i32
+    }
+
+    /// `nextInt(bound)` for a bound that is not a power of two.
+    fn next_int(&mut self, bound: i32) -> i32 {
+        loop {
+            let bits = self.next(31);
+            let value = bits % bound;
+            // Java rejects the top of the range via int overflow.
+            if bits.wrapping_sub(value).wrapping_add(bound - 1) >= 0 {
+                return value;
+            }
+        }
+    }
+}
+
+/// Whether slimes spawn in `cloc` of an overworld with `seed`, as Java Edition decides it:
+/// the chunk's own `Random` gives 0 on `nextInt(10)`.
+pub fn is_slime_chunk(seed: i64, cloc: &ChunkPos) -> bool {
+    let (x, z) = (cloc.x, cloc.z);
+    // Same int/long mix as the game, overflow included.
+    let chunk_seed = seed
+        .wrapping_add(x.wrapping_mul(x).wrapping_mul(0x4c1906) as i64)
+        .wrapping_add(x.wrapping_mul(0x5ac0db) as i64)
+        .wrapping_add((z.wrapping_mul(z) as i64).wrapping_mul(0x4307a7))
+        .wrapping_add(z.wrapping_mul(0x5f24f) as i64)
+        ^ 0x3ad8025f;
+    JavaRandom::new(chunk_seed).next_int(10) == 0
+}
+
+/// Green tint over the slime chunks of published images (tiles, the
+/// stitched image); the region images stay untinted.
+#[derive(Debug, Clone)]
+pub struct SlimeOverlay {
+    pub seed: i64,
+}
+
+impl SlimeOverlay {
+    /// Tint `image`, whose top-left pixel is at block `origin` and whose
+    /// pixels each cover `scale` blocks. Ungenerated (transparent) pixels stay as they are.
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        let (width, height) = (image.width(), image.height());
+        let mut last: Option<(ChunkPos, bool)> = None;
+        for y in 0..height {
+            for x in 0..width {
+                let cloc = origin.offset(x as i32 * scale, y as i32 * scale).chunk();
+                let slime = match last {
+                    Some((last_cloc, slime)) if last_cloc == cloc => slime,
+                    _ => is_slime_chunk(self.seed, &cloc),
+                };
+                last = Some((cloc, slime));
+                if slime {
+                    blend(image, x, y, [60, 220, 60], 0.4);
+                }
+            }
+        }
+    }
+}
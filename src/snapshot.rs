@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use fastanvil::{Region, RegionFileLoader, RegionLoader};
+use log::debug;
+
+use crate::coords::{ChunkPos, RegionPos};
+use crate::dimension::Dimension;
+use crate::error::{Error, Result};
+use crate::region_index::to_region_name;
+
+pub type ShareRegion = Arc<RegionHandles>;
+
+/// Handles of one region file, so chunks of a region can be read by several
+/// workers at once: a reader takes a free handle, or opens another one
+/// when they are all in use, and hands it back after the read. There are
+/// never more handles than readers at one time.
+pub struct RegionHandles {
+    path: PathBuf,
+    free: Mutex<Vec<Region<File>>>,
+}
+
+impl RegionHandles {
+    fn new(path: PathBuf, first: Region<File>) -> Self {
+        RegionHandles { path, free: Mutex::new(vec![first]) }
+    }
+
+    /// The compressed NBT of a chunk, `x` and `z` local to the region.
+    pub fn read_chunk(&self, x: usize, z: usize) -> std::result::Result<Option<Vec<u8>>, String> {
+        let free = self.free.lock().unwrap().pop();
+        let mut region = match free {
+            Some(region) => region,
+            None => File::open(&self.path).map_err(|e| e.to_string())
+                .and_then(|file| Region::from_stream(file).map_err(|e| e.to_string()))?,
+        };
+        let data = region.read_chunk(x, z).map_err(|e| e.to_string());
+        self.free.lock().unwrap().push(region);
+        data
+    }
+}
+
+/// Pending work on a region across every renderer of a snapshot.
+#[derive(Default)]
+struct RegionUse {
+    /// Render jobs reading its chunks: its own, and the one of the region
+    /// south of it (whose top row is shaded against this region's bottom row).
+    readers: usize,
+    /// Render jobs of the region itself still to finish.
+    renders: usize,
+    /// Chunks that failed in any of them, left stale in the cache.
+    failed: Vec<ChunkPos>,
+    /// Whether a renderer could not write its image; the cache is then kept.
+    unsaved: bool,
+}
+
+/// One scan of a dimension, shared by every renderer drawing it (map,
+/// heightmap, isometric, ...) so they can run concurrently against the same
+/// region list and timestamps. Region files are opened once for all of them
+/// and closed when no renderer still needs them; a region's cache is saved
+/// once every renderer is done with it, so none of them races the others on it.
+pub struct WorldSnapshot {
+    pub dimension: Dimension,
+    loader: RegionFileLoader,
+    regions: Mutex<HashMap<RegionPos, ShareRegion>>,
+    uses: Mutex<HashMap<RegionPos, RegionUse>>,
+}
+
+impl WorldSnapshot {
+    pub fn new(dimension: Dimension) -> Arc<Self> {
+        Arc::new(WorldSnapshot {
+            loader: RegionFileLoader::new(dimension.dim_path.clone()),
+            dimension,
+            regions: Default::default(),
+            uses: Default::default(),
+        })
+    }
+
+    /// Register a renderer that will render every region of the snapshot.
+    /// All renderers must be registered before the first one starts.
+    pub(crate) fn claim(&self) {
+        let mut uses_l = self.uses.lock().unwrap();
+        for rloc in self.dimension.render_regions.keys() {
+            uses_l.entry(*rloc).or_default().renders += 1;
+            for read in [*rloc, rloc.offset(0, -1)] {
+                uses_l.entry(read).or_default().readers += 1;
+            }
+        }
+    }
+
+    /// The open region file, opening it on first use.
+    pub(crate) fn region(&self, rloc: &RegionPos) -> Result<Option<ShareRegion>> {
+        let mut regions_l = self.regions.lock().unwrap();
+        if let Some(region) = regions_l.get(rloc) {
+            return Ok(Some(Arc::clone(region)));
+        }
+        debug!("region: {:?}", rloc);
+        let (x, z) = rloc.to_anvil();
+        let region = match self.loader.region(x, z) {
+            Ok(Some(region)) => region,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(Error::OpenRegion { rloc: *rloc, message: e.to_string() }),
+        };
+        let region = Arc::new(RegionHandles::new(self.dimension.dim_path.join(to_region_name(rloc)), region));
+        regions_l.insert(*rloc, Arc::clone(&region));
+        Ok(Some(region))
+    }
+
+    /// A renderer finished region `rloc`, with `failed` chunks, and `saved`
+    /// its image or not. Region files no render still reads are closed, and
+    /// once the last renderer is done with the region its cache is saved,
+    /// unless one of them could not write its image.
+    pub(crate) fn finish_region(&self, rloc: &RegionPos, failed: &[ChunkPos], saved: bool) {
+        let mut released: Vec<RegionPos> = vec![];
+        let mut save = None;
+        {
+            let mut uses_l = self.uses.lock().unwrap();
+            for read in [*rloc, rloc.offset(0, -1)] {
+                let region_use = match uses_l.get_mut(&read) {
+                    Some(region_use) => region_use,
+                    None => continue,
+                };
+                region_use.readers -= 1;
+                if read == *rloc {
+                    region_use.renders -= 1;
+                    region_use.failed.extend_from_slice(failed);
+                    region_use.unsaved |= !saved;
+                    if region_use.renders == 0 && !region_use.unsaved {
+                        save = Some(std::mem::take(&mut region_use.failed));
+                    }
+                }
+                if region_use.readers == 0 && region_use.renders == 0 {
+                    uses_l.remove(&read);
+                    released.push(read);
+                }
+            }
+        }
+        if !released.is_empty() {
+            debug!("release regions {:?}", released);
+            for released_rloc in &released {
+                trace_region!(released_rloc, "region file closed after region {} finished", rloc);
+            }
+            self.regions.lock().unwrap().retain(|r, _| !released.contains(r));
+        }
+        if let Some(failed) = save {
+            self.dimension.save_cache_or_degrade(rloc, &failed);
+        }
+    }
+}
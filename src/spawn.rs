@@ -0,0 +1,37 @@
+use image::{Rgba, RgbaImage};
+
+use crate::coords::BlockPos;
+
+/// Pixels from the centre of the spawn mark to its tips, whatever the scale.
+const MARK_RADIUS: i32 = 5;
+
+/// A yellow diamond at the world spawn, drawn onto published images (tiles,
+/// the stitched image); the region images stay unmarked.
+#[derive(Debug, Clone)]
+pub struct SpawnOverlay {
+    pub spawn: BlockPos,
+}
+
+impl SpawnOverlay {
+    /// Draw onto `image`, whose top-left pixel is at block `origin` and whose
+    /// pixels each cover `scale` blocks. Unlike the tints, the mark is drawn
+    /// over ungenerated pixels too: a fresh world's spawn may not be rendered yet.
+    pub fn apply(&self, image: &mut RgbaImage, origin: BlockPos, scale: i32) {
+        let (cx, cy) = ((self.spawn.x - origin.x).div_euclid(scale), (self.spawn.z - origin.z).div_euclid(scale));
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        for dy in -MARK_RADIUS - 1..=MARK_RADIUS + 1 {
+            for dx in -MARK_RADIUS - 1..=MARK_RADIUS + 1 {
+                let (x, y) = (cx + dx, cy + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                let distance = dx.abs() + dy.abs();
+                if distance <= MARK_RADIUS {
+                    image.put_pixel(x as u32, y as u32, Rgba([240, 200, 40, 255]));
+                } else if distance == MARK_RADIUS + 1 {
+                    image.put_pixel(x as u32, y as u32, Rgba([40, 30, 0, 255]));
+                }
+            }
+        }
+    }
+}
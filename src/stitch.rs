@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use image::RgbaImage;
+use log::{info, warn};
+use regex::Regex;
+
+use crate::coords::{RegionPos, RegionBounds, REGION_SIZE};
+use crate::error::Result;
+use crate::tiles::{average_block, open_region_image};
+use crate::watermark::Watermark;
+use crate::overlay::{self, Overlay};
+
+/// Region images (`r.X.Z.png`) found in the image path.
+fn scan_region_images(image_path: &Path, bounds: Option<&RegionBounds>) -> Result<HashMap<RegionPos, PathBuf>> {
+    let mut images: HashMap<RegionPos, PathBuf> = Default::default();
+    let image_re = Regex::new(r"^r\.(-?\d+)\.(-?\d+)\.png$").unwrap();
+    for entry in image_path.read_dir()? {
+        let file = entry?;
+        if file.path().is_dir() { continue; }
+
+        let filestr = file.file_name().into_string().unwrap();
+        let caps = match image_re.captures(&filestr) {
+            Some(caps) => caps,
+            None => continue,
+        };
+        let x: i32 = caps.get(1).unwrap().as_str().parse().unwrap();
+        let z: i32 = caps.get(2).unwrap().as_str().parse().unwrap();
+        let rloc = RegionPos::new(x, z);
+        let is_target = if let Some(bounds) = bounds {
+            rloc.in_bounds(bounds)
+        } else { true };
+        if is_target {
+            images.insert(rloc, file.path());
+        }
+    }
+    Ok(images)
+}
+
+/// Composite every region image of `image_path` into one image at `out_path`,
+/// placed by region position and downscaled by `scale` (a power of two up to 512).
+/// Regions without an image stay transparent, and `overlays` and `watermark`
+/// go on top. Returns the number of regions placed.
+pub fn stitch(image_path: &Path, out_path: &Path, scale: u32, bounds: Option<&RegionBounds>, overlays: &[Overlay], watermark: Option<&Watermark>) -> Result<usize> {
+    if !scale.is_power_of_two() || scale > REGION_SIZE as u32 {
+        return Err(format!("stitch scale must be a power of two up to {}", REGION_SIZE).into());
+    }
+    let images = scan_region_images(image_path, bounds)?;
+    if images.is_empty() {
+        return Err("no region images to stitch".into());
+    }
+    let min = RegionPos::new(
+        images.keys().map(|rloc| rloc.x).min().unwrap(),
+        images.keys().map(|rloc| rloc.z).min().unwrap());
+    let max = RegionPos::new(
+        images.keys().map(|rloc| rloc.x).max().unwrap(),
+        images.keys().map(|rloc| rloc.z).max().unwrap());
+    let region_pixels = REGION_SIZE as u32 / scale;
+    let width = (max.x - min.x + 1) as u32 * region_pixels;
+    let height = (max.z - min.z + 1) as u32 * region_pixels;
+    info!("stitch {} regions from {} to {} into {}x{}", images.len(), min, max, width, height);
+
+    let mut out = RgbaImage::new(width, height);
+    let mut placed = 0;
+    for (rloc, path) in &images {
+        let image = match open_region_image(path) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("skip unreadable region image {}: {}", path.to_str().unwrap(), e);
+                continue;
+            }
+        };
+        let offset = *rloc - min;
+        let (ox, oy) = (offset.x as u32 * region_pixels, offset.z as u32 * region_pixels);
+        for y in 0..region_pixels.min(image.height() / scale) {
+            for x in 0..region_pixels.min(image.width() / scale) {
+                out.put_pixel(ox + x, oy + y, average_block(&image, x * scale, y * scale, scale));
+            }
+        }
+        placed += 1;
+    }
+    overlay::apply_all(overlays, &mut out, min.block_origin(), scale as i32);
+    if let Some(watermark) = watermark {
+        watermark.apply(&mut out);
+    }
+    out.save(out_path)?;
+    Ok(placed)
+}
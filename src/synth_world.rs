@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use crate::coords::{ChunkPos, RegionPos, REGION_CHUNKS};
+use crate::durability::Durability;
+use crate::error::Result;
+use crate::selection::Selection;
+
+/// Data version written into the chunks (1.19.2).
+const DATA_VERSION: i32 = 3120;
+const MIN_SECTION: i32 = -4;
+const SECTOR: usize = 4096;
+
+/// Biomes of the checkerboard, alternating chunk by chunk.
+const BIOMES: [&str; 2] = ["minecraft:plains", "minecraft:desert"];
+
+/// Minimal but valid region files for fixtures: flat stone under a grass
+/// surface, chunk-sized checkerboard biomes and header timestamps chosen by
+/// the caller, so detector, cache and renderer runs are reproducible
+/// without real world data. In memory (`region_bytes`) or on disk (`write`).
+#[derive(Debug, Clone)]
+pub struct SynthWorld {
+    surface_y: i32,
+    timestamp: u32,
+    touched: HashMap<ChunkPos, u32>,
+}
+
+#[derive(Serialize)]
+struct ChunkNbt {
+    #[serde(rename = "DataVersion")]
+    data_version: i32,
+    #[serde(rename = "xPos")]
+    x_pos: i32,
+    #[serde(rename = "zPos")]
+    z_pos: i32,
+    #[serde(rename = "yPos")]
+    y_pos: i32,
+    #[serde(rename = "Status")]
+    status: &'static str,
+    sections: Vec<SectionNbt>,
+}
+
+#[derive(Serialize)]
+struct SectionNbt {
+    #[serde(rename = "Y")]
+    y: i8,
+    block_states: BlockStatesNbt,
+    biomes: BiomesNbt,
+}
+
+#[derive(Serialize)]
+struct BlockStatesNbt {
+    palette: Vec<BlockNbt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<fastnbt::LongArray>,
+}
+
+#[derive(Serialize)]
+struct BlockNbt {
+    #[serde(rename = "Name")]
+    name: &'static str,
+    #[serde(rename = "Properties", skip_serializing_if = "Option::is_none")]
+    properties: Option<HashMap<&'static str, &'static str>>,
+}
+
+#[derive(Serialize)]
+struct BiomesNbt {
+    palette: Vec<&'static str>,
+}
+
+impl SynthWorld {
+    /// Every chunk gets `timestamp` in the region header.
+    pub fn new(timestamp: u32) -> Self {
+        SynthWorld { surface_y: 64, timestamp, touched: Default::default() }
+    }
+
+    /// Y of the grass surface (default 64).
+    pub fn with_surface_y(mut self, surface_y: i32) -> Self {
+        self.surface_y = surface_y.clamp(MIN_SECTION * 16, 319);
+        self
+    }
+
+    /// Give one chunk its own header timestamp, as if saved later.
+    pub fn touch(&mut self, cloc: ChunkPos, timestamp: u32) {
+        self.touched.insert(cloc, timestamp);
+    }
+
+    pub fn chunk_timestamp(&self, cloc: &ChunkPos) -> u32 {
+        self.touched.get(cloc).copied().unwrap_or(self.timestamp)
+    }
+
+    /// NBT of one chunk, uncompressed.
+    pub fn chunk_nbt(&self, cloc: &ChunkPos) -> Result<Vec<u8>> {
+        let biome = BIOMES[(cloc.x + cloc.z).rem_euclid(2) as usize];
+        let top = self.surface_y.div_euclid(16);
+        let sections = (MIN_SECTION..=top).map(|y| SectionNbt {
+            y: y as i8,
+            block_states: if y < top { fill_section() } else { surface_section(self.surface_y.rem_euclid(16)) },
+            biomes: BiomesNbt { palette: vec![biome] },
+        }).collect();
+        let chunk = ChunkNbt {
+            data_version: DATA_VERSION,
+            x_pos: cloc.x,
+            z_pos: cloc.z,
+            y_pos: MIN_SECTION,
+            status: "minecraft:full",
+            sections,
+        };
+        Ok(fastnbt::to_bytes(&chunk)?)
+    }
+
+    /// The region file `r.X.Z.mca` holding `chunks` (those of other regions are ignored).
+    pub fn region_bytes(&self, rloc: &RegionPos, chunks: &[ChunkPos]) -> Result<Vec<u8>> {
+        let mut header = vec![0u8; SECTOR * 2];
+        let mut body = vec![];
+        for cloc in chunks.iter().filter(|cloc| rloc.contains_chunk(cloc)) {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(&self.chunk_nbt(cloc)?)?;
+            let compressed = encoder.finish()?;
+            let start = body.len();
+            body.extend_from_slice(&(compressed.len() as u32 + 1).to_be_bytes());
+            body.push(2); // zlib
+            body.extend_from_slice(&compressed);
+            body.resize((body.len() + SECTOR - 1) / SECTOR * SECTOR, 0);
+
+            let (x, z) = cloc.local();
+            let index = z * REGION_CHUNKS as usize + x;
+            let offset = (2 + start / SECTOR) as u32;
+            let sectors = ((body.len() - start) / SECTOR) as u32;
+            header[index * 4..index * 4 + 4].copy_from_slice(&(offset << 8 | sectors).to_be_bytes());
+            header[SECTOR + index * 4..SECTOR + index * 4 + 4].copy_from_slice(&self.chunk_timestamp(cloc).to_be_bytes());
+        }
+        header.extend_from_slice(&body);
+        Ok(header)
+    }
+
+    /// Write the region files of every chunk overlapping `area` into `dir`;
+    /// returns the number of regions written.
+    pub fn write(&self, dir: &Path, area: &Selection) -> Result<usize> {
+        std::fs::create_dir_all(dir)?;
+        let (min, max) = area.bounds();
+        let (min, max) = (min.chunk(), max.chunk());
+        let mut regions: HashMap<RegionPos, Vec<ChunkPos>> = HashMap::new();
+        for z in min.z..=max.z {
+            for x in min.x..=max.x {
+                let cloc = ChunkPos::new(x, z);
+                if area.overlaps_chunk(&cloc) {
+                    regions.entry(cloc.region()).or_default().push(cloc);
+                }
+            }
+        }
+        for (rloc, chunks) in &regions {
+            let path = dir.join(format!("r.{}.{}.mca", rloc.x, rloc.z));
+            Durability::Fast.write(&path, &self.region_bytes(rloc, chunks)?)?;
+        }
+        Ok(regions.len())
+    }
+}
+
+fn fill_section() -> BlockStatesNbt {
+    BlockStatesNbt { palette: vec![block("minecraft:stone")], data: None }
+}
+
+/// Stone up to `surface` (section-local Y), grass on it, air above.
+fn surface_section(surface: i32) -> BlockStatesNbt {
+    // 4 bits per block (the minimum), 16 blocks per long, indices in YZX order.
+    let mut data = vec![0i64; 4096 / 16];
+    for index in 0..4096usize {
+        let y = (index / 256) as i32;
+        let state: i64 = if y < surface { 0 } else if y == surface { 1 } else { 2 };
+        data[index / 16] |= state << (index % 16 * 4);
+    }
+    let mut grass = block("minecraft:grass_block");
+    grass.properties = Some(vec![("snowy", "false")].into_iter().collect());
+    BlockStatesNbt {
+        palette: vec![block("minecraft:stone"), grass, block("minecraft:air")],
+        data: Some(fastnbt::LongArray::new(data)),
+    }
+}
+
+fn block(name: &'static str) -> BlockNbt {
+    BlockNbt { name, properties: None }
+}
@@ -0,0 +1,54 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Reads ahead of the limit allowed without waiting, so short bursts
+/// (a region's first chunks) don't stall every worker.
+const IO_BURST: Duration = Duration::from_millis(250);
+
+/// `--throttle` and `--io-limit`: slow the workers down so a render can run
+/// next to a live server without taking its CPU time and disk bandwidth.
+pub struct Throttle {
+    per_chunk: Duration,
+    /// Bytes per second read from region files, shared by every worker.
+    io_limit: Option<f64>,
+    /// When the reads so far have used up the IO budget.
+    io_due: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(per_chunk_ms: u64, io_limit_mb: Option<f64>) -> Self {
+        Throttle {
+            per_chunk: Duration::from_millis(per_chunk_ms),
+            io_limit: io_limit_mb.filter(|mb| *mb > 0.0).map(|mb| mb * (1 << 20) as f64),
+            io_due: Mutex::new(None),
+        }
+    }
+
+    /// Pause a worker between chunks.
+    pub fn chunk_done(&self) {
+        if !self.per_chunk.is_zero() {
+            std::thread::sleep(self.per_chunk);
+        }
+    }
+
+    /// Account for `bytes` read from a region file, waiting until the read
+    /// fits the limit. Chunks are counted as decoded, which is more than
+    /// their compressed size on disk, so the disk is never read faster.
+    pub fn read(&self, bytes: usize) {
+        let limit = match self.io_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let wait = {
+            let mut due = self.io_due.lock().unwrap();
+            let now = Instant::now();
+            let start = due.filter(|due| *due > now).unwrap_or(now);
+            let next = start + Duration::from_secs_f64(bytes as f64 / limit);
+            *due = Some(next);
+            next.saturating_duration_since(now + IO_BURST)
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use clap::ArgEnum;
+use image::RgbaImage;
+use log::debug;
+
+use crate::coords::{BlockPos, RegionPos, REGION_CHUNKS, REGION_SIZE};
+
+/// `--layout` of the tile tree of another renderer, at its most detailed zoom
+/// (one block per pixel, seen from the top).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum TileLayout {
+    /// `<x>/<y>.png` with y growing south: one zoom directory of a Leaflet
+    /// XYZ tree, like this tool's `tiles/<z>`.
+    Xyz,
+    /// Dynmap's flat map: `<x/32>_<y/32>/<x>_<y>.png`, 128 pixel tiles with y
+    /// growing north.
+    DynmapFlat,
+}
+
+impl TileLayout {
+    pub fn default_tile_size(&self) -> i32 {
+        match self {
+            TileLayout::Xyz => 256,
+            TileLayout::DynmapFlat => 128,
+        }
+    }
+}
+
+struct Tile {
+    image: RgbaImage,
+    /// Modification time, seconds since the epoch.
+    mtime: u32,
+}
+
+/// Tiles of a tree, loaded once each as regions are assembled from them.
+pub struct TileTree {
+    root: PathBuf,
+    layout: TileLayout,
+    size: i32,
+    /// Block shown at the north-west pixel of tile 0,0.
+    origin: BlockPos,
+    tiles: HashMap<(i32, i32), Option<Tile>>,
+}
+
+impl TileTree {
+    pub fn new(root: &Path, layout: TileLayout, size: i32, origin: BlockPos) -> Self {
+        TileTree { root: root.to_path_buf(), layout, size, origin, tiles: Default::default() }
+    }
+
+    /// Tile holding `block`, and the pixel of the block in it.
+    fn locate(&self, block: &BlockPos) -> ((i32, i32), (u32, u32)) {
+        let (x, z) = (block.x - self.origin.x, block.z - self.origin.z);
+        let (tx, px) = (x.div_euclid(self.size), x.rem_euclid(self.size) as u32);
+        let (tz, pz) = (z.div_euclid(self.size), z.rem_euclid(self.size) as u32);
+        match self.layout {
+            TileLayout::Xyz => ((tx, tz), (px, pz)),
+            // Row 0 is still the north edge of the tile.
+            TileLayout::DynmapFlat => ((tx, -tz - 1), (px, pz)),
+        }
+    }
+
+    fn path(&self, (x, y): (i32, i32)) -> PathBuf {
+        match self.layout {
+            TileLayout::Xyz => self.root.join(x.to_string()).join(format!("{}.png", y)),
+            TileLayout::DynmapFlat => self.root.join(format!("{}_{}", x >> 5, y >> 5)).join(format!("{}_{}.png", x, y)),
+        }
+    }
+
+    fn tile(&mut self, pos: (i32, i32)) -> Option<&Tile> {
+        if !self.tiles.contains_key(&pos) {
+            let path = self.path(pos);
+            let tile = image::open(&path).map_err(|e| debug!("no tile {}: {}", path.display(), e)).ok()
+                .map(|image| image.into_rgba8())
+                .filter(|image| image.dimensions() == (self.size as u32, self.size as u32))
+                .map(|image| {
+                    let mtime = std::fs::metadata(&path).and_then(|m| m.modified()).ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs() as u32);
+                    Tile { image, mtime }
+                });
+            self.tiles.insert(pos, tile);
+        }
+        self.tiles[&pos].as_ref()
+    }
+
+    /// The image of `rloc` put together from its tiles, and the modification
+    /// time of the oldest tile under each chunk (region-local, z-major).
+    /// None unless every tile the region needs is there.
+    pub fn region(&mut self, rloc: &RegionPos) -> Option<(RgbaImage, Vec<u32>)> {
+        let origin = rloc.block_origin();
+        let mut image = RgbaImage::new(REGION_SIZE as u32, REGION_SIZE as u32);
+        let mut chunk_mtimes = vec![u32::MAX; (REGION_CHUNKS * REGION_CHUNKS) as usize];
+        for z in 0..REGION_SIZE {
+            for x in 0..REGION_SIZE {
+                let block = origin.offset(x, z);
+                let (pos, (px, pz)) = self.locate(&block);
+                let tile = self.tile(pos)?;
+                image.put_pixel(x as u32, z as u32, *tile.image.get_pixel(px, pz));
+                let (cx, cz) = block.chunk().local();
+                let mtime = &mut chunk_mtimes[cz * REGION_CHUNKS as usize + cx];
+                *mtime = (*mtime).min(tile.mtime);
+            }
+        }
+        Some((image, chunk_mtimes))
+    }
+
+    /// Drop the loaded tiles lying wholly north of block row `z`, once the
+    /// regions are assembled row by row past them.
+    pub fn forget_north_of(&mut self, z: i32) {
+        let (size, origin_z, layout) = (self.size, self.origin.z, self.layout);
+        self.tiles.retain(|(_, y), _| {
+            let row = match layout {
+                TileLayout::Xyz => *y,
+                TileLayout::DynmapFlat => -y - 1,
+            };
+            origin_z + (row + 1) * size > z
+        });
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use image::{DynamicImage, Rgba, RgbaImage};
+use log::{info, debug};
+
+use crate::coords::{BlockPos, RegionPos, TilePos, REGION_SIZE, TILE_SIZE};
+use crate::dim_renderer::to_image_name;
+use crate::error::Result;
+use crate::indexed_png::PngEncoding;
+use crate::mbtiles::MbTiles;
+use crate::utfgrid;
+use crate::watermark::Watermark;
+use crate::overlay::{self, Overlay};
+
+/// z/x/y tile pyramid in `<image_path>/tiles`, laid out for Leaflet/OpenLayers.
+/// Zoom `levels - 1` is 1 block per pixel; each zoom below halves the resolution.
+/// Only zooms `min_zoom..=max_zoom` are written.
+pub struct TilePyramid {
+    root: PathBuf,
+    levels: u32,
+    min_zoom: u32,
+    max_zoom: u32,
+    prune: bool,
+    markers: Vec<(BlockPos, Rgba<u8>)>,
+    png_encoding: PngEncoding,
+    grid: bool,
+    watermark: Option<Arc<Watermark>>,
+    overlays: Vec<Overlay>,
+    archive: Option<MbTiles>,
+}
+
+/// Radius in pixels of the marker icons drawn on the most detailed tiles.
+pub const ICON_RADIUS: u32 = 3;
+
+impl TilePyramid {
+    pub fn new(image_path: &Path, levels: u32) -> Self {
+        let levels = levels.max(1);
+        TilePyramid {
+            root: image_path.join("tiles"),
+            levels,
+            min_zoom: 0,
+            max_zoom: levels - 1,
+            prune: false,
+            markers: vec![],
+            png_encoding: PngEncoding::Plain,
+            grid: false,
+            watermark: None,
+            overlays: vec![],
+            archive: None,
+        }
+    }
+
+    /// Limit the written zooms; `max_zoom` is clamped to the full resolution zoom.
+    pub fn with_zoom_range(mut self, min_zoom: u32, max_zoom: Option<u32>) -> Self {
+        self.max_zoom = max_zoom.unwrap_or(self.levels - 1).min(self.levels - 1);
+        self.min_zoom = min_zoom.min(self.max_zoom);
+        self
+    }
+
+    /// Draw an icon at each of `markers` on the most detailed tiles written,
+    /// from where it shrinks into the zooms below. Tiles only get the icons
+    /// when they are regenerated, so the regions around the markers of this and
+    /// the previous run should be passed to `update`.
+    pub fn with_markers(mut self, markers: Vec<(BlockPos, Rgba<u8>)>) -> Self {
+        self.markers = markers;
+        self
+    }
+
+    /// Don't keep tiles that are fully transparent (nothing explored there).
+    pub fn with_pruning(mut self, prune: bool) -> Self {
+        self.prune = prune;
+        self
+    }
+
+    pub fn with_png_encoding(mut self, png_encoding: PngEncoding) -> Self {
+        self.png_encoding = png_encoding;
+        self
+    }
+
+    /// Write a UTFGrid (`y.grid.json`) next to each tile mapping its pixels to chunks.
+    pub fn with_grid(mut self, grid: bool) -> Self {
+        self.grid = grid;
+        self
+    }
+
+    /// Composite `watermark` onto every tile written. Zooms are then
+    /// downscaled from the region images instead of the tiles below, which
+    /// carry the logo already.
+    pub fn with_watermark(mut self, watermark: Option<Arc<Watermark>>) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// Draw `overlays` onto every tile written; like the watermark, zooms are
+    /// then downscaled from the region images so lines don't blur together.
+    pub fn with_overlays(mut self, overlays: Vec<Overlay>) -> Self {
+        self.overlays = overlays;
+        self
+    }
+
+    /// Write the tiles into an MBTiles archive instead of the tiles directory.
+    /// UTFGrids are not written there.
+    pub fn with_archive(mut self, archive: Option<MbTiles>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Leaflet zoom of a `TilePos::level`.
+    fn zoom(&self, level: u32) -> u32 {
+        self.levels - 1 - level
+    }
+
+    pub fn tile_path(&self, tile: &TilePos) -> PathBuf {
+        self.root
+            .join(self.zoom(tile.level).to_string())
+            .join(tile.x.to_string())
+            .join(format!("{}.png", tile.z))
+    }
+
+    /// Regenerate every tile that depends on the region images of `regions`,
+    /// leaving the rest of the pyramid as it is. Returns the tiles written.
+    pub fn update(&self, image_path: &Path, regions: &[RegionPos]) -> Result<Vec<TilePos>> {
+        let base_level = self.zoom(self.max_zoom);
+        let top_level = self.zoom(self.min_zoom);
+        let mut written = vec![];
+        let mut dirty: HashSet<TilePos> = Default::default();
+        if let Some(archive) = &self.archive {
+            archive.set_metadata("mcanvilrenderer", self.min_zoom, self.max_zoom)?;
+            archive.begin()?;
+        }
+        if base_level == 0 {
+            for rloc in regions {
+                let image = match open_region_image(&image_path.join(to_image_name(rloc))) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        debug!("no region image for {}: {}", rloc, e);
+                        continue;
+                    }
+                };
+                let origin = rloc.block_origin();
+                for tile in rloc.tiles(0) {
+                    let offset = tile.block_origin() - origin;
+                    let mut tile_image = image::imageops::crop_imm(
+                        &image, offset.x as u32, offset.z as u32, TILE_SIZE as u32, TILE_SIZE as u32).to_image();
+                    self.draw_markers(&tile, &mut tile_image);
+                    if self.save(&tile, &tile_image)? {
+                        written.push(tile);
+                    }
+                    dirty.insert(tile);
+                }
+            }
+        } else {
+            // Zooms above max_zoom are not written, so the first level is
+            // downscaled from the region images directly.
+            dirty = regions.iter().flat_map(|rloc| rloc.tiles(base_level)).collect();
+            for tile in &dirty {
+                let mut tile_image = self.from_regions(image_path, tile);
+                self.draw_markers(tile, &mut tile_image);
+                if self.save(tile, &tile_image)? {
+                    written.push(*tile);
+                }
+            }
+        }
+
+        for _ in base_level + 1..=top_level {
+            dirty = dirty.iter().map(TilePos::parent).collect();
+            for tile in &dirty {
+                let tile_image = if self.watermark.is_some() || !self.overlays.is_empty() { self.from_regions(image_path, tile) } else { self.compose(tile) };
+                if self.save(tile, &tile_image)? {
+                    written.push(*tile);
+                }
+            }
+        }
+        if let Some(archive) = &self.archive {
+            archive.commit()?;
+        }
+        info!("tiles written: {}", written.len());
+        Ok(written)
+    }
+
+    fn draw_markers(&self, tile: &TilePos, image: &mut RgbaImage) {
+        let origin = tile.block_origin();
+        let size = TILE_SIZE as i32;
+        let radius = ICON_RADIUS as i32;
+        for (block, colour) in &self.markers {
+            let offset = *block - origin;
+            let (cx, cy) = (offset.x >> tile.level, offset.z >> tile.level);
+            for dy in -radius - 1..=radius + 1 {
+                for dx in -radius - 1..=radius + 1 {
+                    let (x, y) = (cx + dx, cy + dy);
+                    if x < 0 || y < 0 || x >= size || y >= size {
+                        continue;
+                    }
+                    let distance = dx * dx + dy * dy;
+                    if distance <= radius * radius {
+                        image.put_pixel(x as u32, y as u32, *colour);
+                    } else if distance <= (radius + 1) * (radius + 1) {
+                        image.put_pixel(x as u32, y as u32, Rgba([255, 255, 255, 255]));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Downscale the region images overlapping `tile` into it.
+    fn from_regions(&self, image_path: &Path, tile: &TilePos) -> RgbaImage {
+        let size = TILE_SIZE as u32;
+        let factor = 1u32 << tile.level;
+        let origin = tile.block_origin();
+        let mut out = RgbaImage::new(size, size);
+        for rloc in tile.regions() {
+            let image = match open_region_image(&image_path.join(to_image_name(&rloc))) {
+                Ok(image) => image,
+                Err(_) => continue,
+            };
+            let offset = rloc.block_origin() - origin;
+            let (ox, oy) = (offset.x as u32 / factor, offset.z as u32 / factor);
+            let pixels = (REGION_SIZE as u32 / factor).min(size);
+            for y in 0..pixels {
+                for x in 0..pixels {
+                    out.put_pixel(ox + x, oy + y, average_block(&image, x * factor, y * factor, factor));
+                }
+            }
+        }
+        out
+    }
+
+    /// Downscale the four children of `tile` into one image.
+    fn compose(&self, tile: &TilePos) -> RgbaImage {
+        let size = TILE_SIZE as u32;
+        let half = size / 2;
+        let mut out = RgbaImage::new(size, size);
+        for (i, child) in tile.children().iter().enumerate() {
+            let child_image = match self.load(child) {
+                Some(image) => image,
+                None => continue,
+            };
+            let (ox, oy) = ((i as u32 % 2) * half, (i as u32 / 2) * half);
+            for y in 0..half {
+                for x in 0..half {
+                    out.put_pixel(ox + x, oy + y, average_block(&child_image, x * 2, y * 2, 2));
+                }
+            }
+        }
+        out
+    }
+
+    /// A tile written before, from the directory or the archive.
+    fn load(&self, tile: &TilePos) -> Option<RgbaImage> {
+        let image = match &self.archive {
+            Some(archive) => image::load_from_memory(&archive.get(self.zoom(tile.level), tile).ok()??).ok()?,
+            None => image::open(self.tile_path(tile)).ok()?,
+        };
+        Some(image.into_rgba8())
+    }
+
+    /// Write a tile, or with pruning remove it if it is fully transparent.
+    /// Returns whether a tile was written.
+    fn save(&self, tile: &TilePos, image: &RgbaImage) -> Result<bool> {
+        if let Some(archive) = &self.archive {
+            let zoom = self.zoom(tile.level);
+            if self.prune && image.pixels().all(|p| p.0[3] == 0) {
+                archive.remove(zoom, tile)?;
+                return Ok(false);
+            }
+            archive.put(zoom, tile, &self.publish(tile, image)?)?;
+            return Ok(true);
+        }
+        let path = self.tile_path(tile);
+        let grid_path = path.with_extension("grid.json");
+        if self.prune && image.pixels().all(|p| p.0[3] == 0) {
+            for path in [&path, &grid_path] {
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            return Ok(false);
+        }
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, self.publish(tile, image)?)?;
+        if self.grid {
+            std::fs::write(grid_path, serde_json::to_vec(&utfgrid::tile_grid(tile, image))?)?;
+        }
+        Ok(true)
+    }
+
+    /// PNG of a tile as published, with the overlays and the watermark.
+    fn publish(&self, tile: &TilePos, image: &RgbaImage) -> Result<Vec<u8>> {
+        let mut published = image.clone();
+        overlay::apply_all(&self.overlays, &mut published, tile.block_origin(), 1 << tile.level);
+        if let Some(watermark) = &self.watermark {
+            watermark.apply(&mut published);
+        }
+        Ok(self.png_encoding.encode(&DynamicImage::ImageRgba8(published))?)
+    }
+}
+
+/// A region image at one pixel per block: those drawn smaller by
+/// --far-scale are scaled up, block by block.
+pub fn open_region_image(path: &Path) -> image::ImageResult<RgbaImage> {
+    let image = image::open(path)?.into_rgba8();
+    if image.width() < REGION_SIZE as u32 {
+        return Ok(image::imageops::resize(&image, REGION_SIZE as u32, REGION_SIZE as u32, image::imageops::FilterType::Nearest));
+    }
+    Ok(image)
+}
+
+/// Alpha-weighted average of the `size`×`size` block at (x, y), so
+/// transparent (ungenerated) pixels don't darken edges.
+pub fn average_block(image: &RgbaImage, x: u32, y: u32, size: u32) -> Rgba<u8> {
+    if size == 1 {
+        return *image.get_pixel(x, y);
+    }
+    let mut sum = [0u64; 4];
+    for dy in 0..size {
+        for dx in 0..size {
+            let p = image.get_pixel(x + dx, y + dy).0;
+            let a = p[3] as u64;
+            sum[0] += p[0] as u64 * a;
+            sum[1] += p[1] as u64 * a;
+            sum[2] += p[2] as u64 * a;
+            sum[3] += a;
+        }
+    }
+    if sum[3] == 0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (sum[0] / sum[3]) as u8,
+        (sum[1] / sum[3]) as u8,
+        (sum[2] / sum[3]) as u8,
+        (sum[3] / (size * size) as u64) as u8,
+    ])
+}
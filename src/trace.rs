@@ -0,0 +1,28 @@
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+
+use crate::coords::RegionPos;
+
+/// Log target for `--trace-region` messages; enabled regardless of RUST_LOG.
+pub const TARGET: &str = "mcanvilrenderer::trace";
+
+lazy_static! {
+    static ref TRACE_REGION: RwLock<Option<RegionPos>> = RwLock::new(None);
+}
+
+pub fn set_trace_region(rloc: Option<RegionPos>) {
+    *TRACE_REGION.write().unwrap() = rloc;
+}
+
+pub fn is_traced(rloc: &RegionPos) -> bool {
+    *TRACE_REGION.read().unwrap() == Some(*rloc)
+}
+
+/// Log a decision about a region when it is the one selected with `--trace-region`.
+macro_rules! trace_region {
+    ($rloc:expr, $($arg:tt)+) => {
+        if crate::trace::is_traced(&$rloc) {
+            log::info!(target: crate::trace::TARGET, "[{}] {}", $rloc, format_args!($($arg)+));
+        }
+    };
+}
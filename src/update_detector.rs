@@ -1,62 +1,58 @@
-use core::fmt;
-use std::error::Error;
 use std::io::{Read, Write, Seek, SeekFrom, Cursor};
 use std::cmp::PartialEq;
-use std::clone::Clone;
-use std::convert::TryFrom;
-use fmt::Formatter;
+use std::convert::TryInto;
 
-pub type RCoord = i32;
-pub type CCoord = usize;
-pub fn r2r(r: RCoord) -> fastanvil::RCoord { fastanvil::RCoord(r as isize) }
-// pub fn c2c(c: CCoord) -> fastanvil::CCoord { fastanvil::CCoord(c as isize) }
+/// Start of a cache file in the versioned format. As a timestamp of the
+/// first chunk of a headerless cache it would be early 2011, before region
+/// files had timestamps, so the two formats cannot be mistaken.
+const CACHE_MAGIC: &[u8; 4] = b"MCAC";
+/// Bumped when the layout after the header changes; newer caches are
+/// refused rather than misread by older renderers.
+pub const CACHE_FORMAT: u8 = 1;
+const FLAG_ZSTD: u8 = 1;
+const FLAG_PALETTE: u8 = 2;
+const FLAG_HASHES: u8 = 4;
 
-#[derive(Hash, Eq, PartialEq, Clone, Debug)]
-pub struct CLoc(pub CCoord, pub CCoord);
-
-#[derive(Debug, Clone)]
-pub struct InvalidOffsetError;
-
-impl Error for InvalidOffsetError { }
-
-impl fmt::Display for InvalidOffsetError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Chunk coord will be out of bounds.")
-    }
+/// A region cache as read from disk.
+///
+/// The format: `MCAC`, the format version (u8), flags (u8), the palette hash
+/// (u64, big endian, 0 without the palette flag), the renderer version (u8
+/// length and UTF-8), then the 4096 byte timestamp table, followed with the
+/// hashes flag by the payload hash of every chunk (1024 u64, big endian, 0
+/// when unknown), all zstd compressed with the zstd flag.
+pub struct CacheData {
+    pub timestamps: RegionTimestamps,
+    pub palette_hash: Option<u64>,
+    /// Version of the renderer that wrote it, None for headerless caches.
+    pub renderer: Option<String>,
+    /// `--verify-chunk-hash`: `payload_hash` of each chunk when it was
+    /// saved, by region-local index (`z * 32 + x`).
+    pub hashes: Option<Vec<u64>>,
+    /// Written in the headerless format, before versioned caches.
+    pub legacy: bool,
 }
 
-#[derive(Hash, Eq, PartialEq, Clone, Debug)]
-pub struct RLoc(pub RCoord, pub RCoord);
-
-pub type RegionBounds = (RLoc, RLoc);
-
-impl From<(CCoord, CCoord)> for CLoc {
-    fn from(tuple: (CCoord, CCoord)) -> Self {
-        Self(tuple.0, tuple.1)
-    }
+/// FNV-1a hash of a chunk's uncompressed NBT, to tell a chunk whose
+/// timestamp changed with it from one only copied or recompressed.
+pub fn payload_hash(data: &[u8]) -> u64 {
+    // 0 stands for no hash in the cache.
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3)).max(1)
 }
-impl From<(RCoord, RCoord)> for RLoc {
-    fn from(tuple: (RCoord, RCoord)) -> Self {
-        Self(tuple.0, tuple.1)
-    }
-}
-
-impl CLoc {
-    pub fn offset(&self, x: i32, z: i32) -> Result<CLoc, InvalidOffsetError> {
-        let new_x = i32::try_from(self.0).unwrap() + x;
-        let new_z = i32::try_from(self.1).unwrap() + z;
-
-        if new_x < 0 || new_x >= 32 { return Err(InvalidOffsetError); }
-        if new_z < 0 || new_z >= 32 { return Err(InvalidOffsetError); }
 
-        Ok(CLoc(usize::try_from(new_x).unwrap(), usize::try_from(new_z).unwrap()))
-    }
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
 }
 
-impl RLoc {
-    pub fn offset(&self, x: RCoord, z: RCoord) -> Self {
-        Self(self.0 + x, self.1 + z)
-    }
+/// How a chunk of a region header differs from an older header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkChange {
+    /// New, or saved again since.
+    Updated,
+    /// Older than before: reset or regenerated (a backup restored, or
+    /// trimmed with an editor and generated again).
+    Reset,
+    /// Gone from the header.
+    Removed,
 }
 
 pub struct RegionTimestamps {
@@ -64,8 +60,8 @@ pub struct RegionTimestamps {
 }
 #[derive(Debug)]
 pub struct ChunkTimestamp {
-    pub x: CCoord,
-    pub z: CCoord,
+    pub x: usize,
+    pub z: usize,
     pub timestamp: u32,
 }
 impl std::fmt::Display for ChunkTimestamp {
@@ -80,8 +76,57 @@ impl RegionTimestamps {
         region_data.seek(SeekFrom::Start(4096))?;
         Self::new(region_data)
     }
-    pub fn from_cachedata<T: Read>(cache_data: &mut T) -> std::io::Result<Self> {
-        Self::new(cache_data)
+    /// Read a cache in the current format or the headerless one written
+    /// before it (the timestamp table, then the palette hash if any).
+    pub fn from_cachedata<T: Read>(cache_data: &mut T) -> std::io::Result<CacheData> {
+        let mut magic: [u8; 4] = [0; 4];
+        cache_data.read_exact(&mut magic)?;
+        if &magic != CACHE_MAGIC {
+            return Self::from_legacy_cachedata(magic, cache_data);
+        }
+        let mut head: [u8; 11] = [0; 11];
+        cache_data.read_exact(&mut head)?;
+        let (format, flags) = (head[0], head[1]);
+        if format > CACHE_FORMAT {
+            return Err(invalid_data(format!("cache format {} is newer than this renderer reads ({})", format, CACHE_FORMAT)));
+        }
+        let hash = u64::from_be_bytes(head[2..10].try_into().unwrap());
+        let mut renderer = vec![0; head[10] as usize];
+        cache_data.read_exact(&mut renderer)?;
+        let mut payload = vec![];
+        cache_data.read_to_end(&mut payload)?;
+        if flags & FLAG_ZSTD != 0 {
+            payload = zstd::bulk::decompress(&payload, 4096 + 1024 * 8)?;
+        }
+        let mut payload = payload.as_slice();
+        let timestamps = Self::new(&mut payload)?;
+        let hashes = if flags & FLAG_HASHES != 0 {
+            if payload.len() < 1024 * 8 {
+                return Err(invalid_data("cache hashes are truncated".to_string()));
+            }
+            Some(payload.chunks_exact(8).take(1024).map(|hash| u64::from_be_bytes(hash.try_into().unwrap())).collect())
+        } else {
+            None
+        };
+        Ok(CacheData {
+            timestamps,
+            palette_hash: Some(hash).filter(|_| flags & FLAG_PALETTE != 0),
+            renderer: Some(String::from_utf8_lossy(&renderer).into_owned()),
+            hashes,
+            legacy: false,
+        })
+    }
+    fn from_legacy_cachedata<T: Read>(start: [u8; 4], cache_data: &mut T) -> std::io::Result<CacheData> {
+        let mut rawdata: [u8; 4096] = [0; 4096];
+        rawdata[..4].copy_from_slice(&start);
+        cache_data.read_exact(&mut rawdata[4..])?;
+        let mut hash: [u8; 8] = [0; 8];
+        let palette_hash = match cache_data.read_exact(&mut hash) {
+            Ok(()) => Some(u64::from_be_bytes(hash)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+        Ok(CacheData { timestamps: RegionTimestamps { rawdata }, palette_hash, renderer: None, hashes: None, legacy: true })
     }
     pub fn new<T: Read>(region_data: &mut T) -> std::io::Result<Self> {
         let mut rawdata: [u8; 4096] = [0; 4096];
@@ -90,8 +135,33 @@ impl RegionTimestamps {
             rawdata: rawdata
         })
     }
-    pub fn save_cache<T: Write>(&self, writable: &mut T) -> std::io::Result<()> {
-        writable.write_all(&self.rawdata)
+    /// Write the cache in the current format, with the chunk hashes if any.
+    /// The tables are stored zstd compressed when that is smaller, which it
+    /// is unless most chunks exist.
+    pub fn save_cache<T: Write>(&self, writable: &mut T, palette_hash: Option<u64>, hashes: Option<&[u64]>) -> std::io::Result<()> {
+        let mut tables = self.rawdata.to_vec();
+        if let Some(hashes) = hashes {
+            tables.extend(hashes.iter().flat_map(|hash| hash.to_be_bytes()));
+        }
+        let compressed = zstd::bulk::compress(&tables, 3)?;
+        let (payload, mut flags) = if compressed.len() < tables.len() {
+            (&compressed[..], FLAG_ZSTD)
+        } else {
+            (&tables[..], 0)
+        };
+        if palette_hash.is_some() {
+            flags |= FLAG_PALETTE;
+        }
+        if hashes.is_some() {
+            flags |= FLAG_HASHES;
+        }
+        let renderer = env!("CARGO_PKG_VERSION").as_bytes();
+        writable.write_all(CACHE_MAGIC)?;
+        writable.write_all(&[CACHE_FORMAT, flags])?;
+        writable.write_all(&palette_hash.unwrap_or(0).to_be_bytes())?;
+        writable.write_all(&[renderer.len() as u8])?;
+        writable.write_all(renderer)?;
+        writable.write_all(payload)
     }
     #[allow(dead_code)]
     pub fn list_timestamps(&self) -> std::io::Result<Box<Vec<ChunkTimestamp>>> {
@@ -109,6 +179,33 @@ impl RegionTimestamps {
         }
         Ok(Box::new(timestamps))
     }
+    /// Timestamp of one chunk, by region-local position.
+    pub fn timestamp(&self, x: usize, z: usize) -> u32 {
+        let index = (z * 32 + x) * 4;
+        u32::from_be_bytes([self.rawdata[index], self.rawdata[index + 1], self.rawdata[index + 2], self.rawdata[index + 3]])
+    }
+    /// Copy with the timestamps of the given region-local chunks zeroed.
+    pub fn with_cleared<I: IntoIterator<Item = (usize, usize)>>(&self, chunks: I) -> Self {
+        let mut rawdata = self.rawdata;
+        for (x, z) in chunks {
+            let index = (z * 32 + x) * 4;
+            rawdata[index..index + 4].copy_from_slice(&[0; 4]);
+        }
+        RegionTimestamps { rawdata }
+    }
+    /// Copy with the timestamps of the given region-local chunks taken from `other`.
+    pub fn with_chunks_from<I: IntoIterator<Item = (usize, usize)>>(&self, other: &Self, chunks: I) -> Self {
+        let mut rawdata = self.rawdata;
+        for (x, z) in chunks {
+            let index = (z * 32 + x) * 4;
+            rawdata[index..index + 4].copy_from_slice(&other.rawdata[index..index + 4]);
+        }
+        RegionTimestamps { rawdata }
+    }
+    /// FNV-1a hash of the timestamp table, to tell cheaply whether a header changed.
+    pub fn header_hash(&self) -> u64 {
+        self.rawdata.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+    }
     pub fn to_tsarray(&self) -> std::io::Result<[u32; 1024]> {
         let mut cursor = Cursor::new(&self.rawdata);
         let mut ar: [u32; 1024] = [0; 1024];
@@ -120,22 +217,32 @@ impl RegionTimestamps {
         }
         Ok(ar)
     }
-    pub fn diffs(&self, other: Option<&Self>) -> std::io::Result<Vec<(CCoord, CCoord)>> {
+    /// Region-local (x, z) of chunks whose timestamp differs from `other`, and how.
+    pub fn changes(&self, other: Option<&Self>) -> std::io::Result<Vec<(usize, usize, ChunkChange)>> {
         let me_ar = self.to_tsarray()?;
         let other_ar = match other {
             Some(other) => other.to_tsarray()?,
             None => [0; 1024],
         };
-        let mut diffs = Vec::new();
+        let mut changes = Vec::new();
         for index in 0..1024 {
-            if me_ar[index] > 0 && (me_ar[index] != other_ar[index]) {
-                diffs.push((
-                    index % 32, // x
-                    index / 32  // z
-                ))
-            }
+            let change = match (me_ar[index], other_ar[index]) {
+                (me, other) if me == other => continue,
+                (0, _) => ChunkChange::Removed,
+                (me, other) if me < other => ChunkChange::Reset,
+                _ => ChunkChange::Updated,
+            };
+            changes.push((
+                index % 32, // x
+                index / 32, // z
+                change,
+            ));
         }
-        Ok(diffs)
+        Ok(changes)
+    }
+    /// Region-local (x, z) of chunks that changed since `other`, whichever way.
+    pub fn diffs(&self, other: Option<&Self>) -> std::io::Result<Vec<(usize, usize)>> {
+        Ok(self.changes(other)?.into_iter().map(|(x, z, _)| (x, z)).collect())
     }
 }
 
@@ -143,4 +250,97 @@ impl PartialEq for RegionTimestamps {
     fn eq(&self, other: &Self) -> bool {
         self.rawdata == other.rawdata
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table with `count` chunks saved at increasing times.
+    fn timestamps(count: usize) -> RegionTimestamps {
+        let mut rawdata = [0u8; 4096];
+        for index in 0..count {
+            let timestamp = 1_600_000_000u32 + index as u32 * 7919;
+            rawdata[index * 4..index * 4 + 4].copy_from_slice(&timestamp.to_be_bytes());
+        }
+        RegionTimestamps { rawdata }
+    }
+
+    fn saved(region: &RegionTimestamps, palette_hash: Option<u64>, hashes: Option<&[u64]>) -> Vec<u8> {
+        let mut data = vec![];
+        region.save_cache(&mut data, palette_hash, hashes).unwrap();
+        data
+    }
+
+    /// The header of a cache in the current format, before its tables.
+    fn header(format: u8, flags: u8) -> Vec<u8> {
+        let mut data = CACHE_MAGIC.to_vec();
+        data.extend([format, flags]);
+        data.extend(0u64.to_be_bytes());
+        data.push(0);
+        data
+    }
+
+    #[test]
+    fn round_trip_without_hashes() {
+        let region = timestamps(3);
+        let cache = RegionTimestamps::from_cachedata(&mut saved(&region, None, None).as_slice()).unwrap();
+        assert!(cache.timestamps == region);
+        assert_eq!(cache.palette_hash, None);
+        assert_eq!(cache.hashes, None);
+        assert_eq!(cache.renderer.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert!(!cache.legacy);
+    }
+
+    #[test]
+    fn round_trip_with_hashes_and_palette() {
+        let region = timestamps(1024);
+        let hashes: Vec<u64> = (0..1024).map(|i| payload_hash(&[i as u8, (i >> 8) as u8])).collect();
+        let cache = RegionTimestamps::from_cachedata(&mut saved(&region, Some(0xfeed), Some(&hashes)).as_slice()).unwrap();
+        assert!(cache.timestamps == region);
+        assert_eq!(cache.palette_hash, Some(0xfeed));
+        assert_eq!(cache.hashes, Some(hashes));
+    }
+
+    #[test]
+    fn sparse_tables_are_compressed() {
+        let region = timestamps(2);
+        let data = saved(&region, None, Some(&[0; 1024]));
+        assert_eq!(data[5] & FLAG_ZSTD, FLAG_ZSTD);
+        assert!(data.len() < 4096);
+        let cache = RegionTimestamps::from_cachedata(&mut data.as_slice()).unwrap();
+        assert!(cache.timestamps == region);
+        assert_eq!(cache.hashes, Some(vec![0; 1024]));
+    }
+
+    #[test]
+    fn reads_headerless_caches() {
+        let region = timestamps(5);
+        let cache = RegionTimestamps::from_cachedata(&mut region.rawdata.as_slice()).unwrap();
+        assert!(cache.timestamps == region);
+        assert!(cache.legacy);
+        assert_eq!((cache.palette_hash, cache.renderer), (None, None));
+
+        let mut data = region.rawdata.to_vec();
+        data.extend(42u64.to_be_bytes());
+        let cache = RegionTimestamps::from_cachedata(&mut data.as_slice()).unwrap();
+        assert!(cache.timestamps == region);
+        assert_eq!(cache.palette_hash, Some(42));
+    }
+
+    #[test]
+    fn refuses_newer_formats() {
+        let mut data = header(CACHE_FORMAT + 1, 0);
+        data.extend([0; 4096]);
+        let e = RegionTimestamps::from_cachedata(&mut data.as_slice()).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_hashes_are_an_error() {
+        let mut data = header(CACHE_FORMAT, FLAG_HASHES);
+        data.extend([0; 4096 + 100]);
+        let e = RegionTimestamps::from_cachedata(&mut data.as_slice()).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "cache hashes are truncated");
+    }
+}
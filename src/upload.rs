@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::{info, warn};
+use regex::Regex;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+
+use crate::coords::RegionPos;
+use crate::error::Result;
+
+/// `--upload` as given: "s3://bucket/prefix".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadTarget {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl FromStr for UploadTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let rest = s.strip_prefix("s3://").ok_or_else(|| format!("invalid upload target \"{}\", expected s3://bucket/prefix", s))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!("invalid upload target \"{}\", no bucket", s));
+        }
+        Ok(UploadTarget { bucket: bucket.to_string(), prefix: prefix.trim_matches('/').to_string() })
+    }
+}
+
+impl UploadTarget {
+    /// The target of a subdirectory, for the dimensions of `--world-path`.
+    pub fn join(&self, name: &str) -> Self {
+        let prefix = if self.prefix.is_empty() { name.to_string() } else { format!("{}/{}", self.prefix, name) };
+        UploadTarget { bucket: self.bucket.clone(), prefix }
+    }
+}
+
+/// Uploads the outputs of a render pass to S3 or a compatible store (MinIO,
+/// R2 with `endpoint`). Credentials come from the AWS_ACCESS_KEY_ID /
+/// AWS_SECRET_ACCESS_KEY environment or the AWS profile.
+pub struct Uploader {
+    bucket: Bucket,
+    prefix: String,
+}
+
+impl Uploader {
+    pub fn new(target: &UploadTarget, endpoint: Option<&str>, region: &str) -> Result<Self> {
+        let region = match endpoint {
+            Some(endpoint) => Region::Custom { region: region.to_string(), endpoint: endpoint.to_string() },
+            None => region.parse().map_err(|e| format!("invalid --upload-region \"{}\": {}", region, e))?,
+        };
+        let credentials = Credentials::default().map_err(|e| format!("no bucket credentials: {}", e))?;
+        let mut bucket = Bucket::new(&target.bucket, region, credentials)?;
+        if endpoint.is_some() {
+            // MinIO and most self-hosted stores don't resolve bucket subdomains.
+            bucket = bucket.with_path_style();
+        }
+        Ok(Uploader { bucket, prefix: target.prefix.clone() })
+    }
+
+    /// Upload `files`, keyed by their path under `root` (files outside it by
+    /// their name). Failures are reported and skipped; returns the number of
+    /// files uploaded.
+    pub fn upload(&self, root: &Path, files: &[PathBuf]) -> usize {
+        let mut uploaded = 0;
+        for path in files {
+            let relative = path.strip_prefix(root).ok()
+                .or_else(|| path.file_name().map(Path::new))
+                .unwrap_or(path);
+            let name = relative.components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>().join("/");
+            let key = if self.prefix.is_empty() { name } else { format!("{}/{}", self.prefix, name) };
+            let content = match std::fs::read(path) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("cannot read {} for upload: {}", path.display(), e);
+                    continue;
+                }
+            };
+            match self.bucket.put_object_with_content_type(&key, &content, content_type(path)) {
+                Ok(response) if response.status_code() / 100 == 2 => uploaded += 1,
+                Ok(response) => warn!("upload of {} failed with status {}", key, response.status_code()),
+                Err(e) => warn!("upload of {} failed: {}", key, e),
+            }
+        }
+        info!("uploaded {} of {} files to s3://{}/{}", uploaded, files.len(), self.bucket.name(), self.prefix);
+        uploaded
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => "image/png",
+        Some("json") => "application/json",
+        Some("mbtiles") => "application/vnd.sqlite3",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The per-region files (`r.X.Z.*`: images, sidecars, heights) of `regions` in `dir`.
+pub fn region_files(dir: &Path, regions: &HashSet<RegionPos>) -> Vec<PathBuf> {
+    let file_re = Regex::new(r"^r\.(-?\d+)\.(-?\d+)\.[a-z.]+$").unwrap();
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files: Vec<PathBuf> = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        let caps = file_re.captures(&name)?;
+        let rloc = RegionPos::new(caps[1].parse().ok()?, caps[2].parse().ok()?);
+        regions.contains(&rloc).then(|| entry.path())
+    }).collect();
+    files.sort();
+    files
+}
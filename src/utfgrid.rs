@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use image::RgbaImage;
+
+use crate::coords::{TilePos, TILE_SIZE};
+
+/// Tile pixels per grid cell.
+const RESOLUTION: u32 = 4;
+
+/// Character of the `id`th key, skipping '"' and '\' as the UTFGrid spec asks.
+fn encode_id(id: usize) -> char {
+    let mut code = id as u32 + 32;
+    if code >= 34 {
+        code += 1;
+    }
+    if code >= 92 {
+        code += 1;
+    }
+    char::from_u32(code).unwrap()
+}
+
+/// UTFGrid interaction layer of a tile, keyed by chunk: hovering a cell gives
+/// the chunk, its north-west block and its region file. Cells over
+/// transparent (unexplored) pixels have no key.
+pub fn tile_grid(tile: &TilePos, image: &RgbaImage) -> serde_json::Value {
+    let cells = TILE_SIZE as u32 / RESOLUTION;
+    let origin = tile.block_origin();
+    let mut keys: Vec<String> = vec!["".to_string()];
+    let mut ids: HashMap<String, usize> = Default::default();
+    let mut data = serde_json::Map::new();
+    let mut grid: Vec<String> = Vec::with_capacity(cells as usize);
+    for cy in 0..cells {
+        let mut row = String::with_capacity(cells as usize);
+        for cx in 0..cells {
+            let (px, py) = (cx * RESOLUTION + RESOLUTION / 2, cy * RESOLUTION + RESOLUTION / 2);
+            if image.get_pixel(px, py).0[3] == 0 {
+                row.push(encode_id(0));
+                continue;
+            }
+            let block = origin.offset((px << tile.level) as i32, (py << tile.level) as i32);
+            let chunk = block.chunk();
+            let key = format!("{},{}", chunk.x, chunk.z);
+            let id = *ids.entry(key.clone()).or_insert_with(|| {
+                let region = chunk.region();
+                let chunk_origin = chunk.block_origin();
+                data.insert(key.clone(), serde_json::json!({
+                    "chunk": { "x": chunk.x, "z": chunk.z },
+                    "block": { "x": chunk_origin.x, "z": chunk_origin.z },
+                    "region": format!("r.{}.{}", region.x, region.z),
+                }));
+                keys.push(key);
+                keys.len() - 1
+            });
+            row.push(encode_id(id));
+        }
+        grid.push(row);
+    }
+    serde_json::json!({ "grid": grid, "keys": keys, "data": data })
+}
@@ -0,0 +1,67 @@
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use log::LevelFilter;
+
+/// `--quiet` and `--verbose`: how much of the logs, the progress display and
+/// the summaries reaches the terminal, from one setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Errors and the final summary line only: for cron.
+    Quiet,
+    /// Progress and summaries; logs as RUST_LOG says (errors by default).
+    Normal,
+    /// Also info logs, with the progress as text lines so they don't fight the bars.
+    Verbose,
+    Debug,
+    Trace,
+}
+
+lazy_static! {
+    static ref VERBOSITY: RwLock<Verbosity> = RwLock::new(Verbosity::Normal);
+}
+
+impl Verbosity {
+    /// From `--quiet` and the number of `-v`.
+    pub fn from_flags(quiet: bool, verbose: u64) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, 2) => Verbosity::Debug,
+            (false, _) => Verbosity::Trace,
+        }
+    }
+
+    /// Log level replacing RUST_LOG's default one (module directives of
+    /// RUST_LOG still apply), None to leave RUST_LOG alone.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        match self {
+            Verbosity::Quiet => Some(LevelFilter::Error),
+            Verbosity::Normal => None,
+            Verbosity::Verbose => Some(LevelFilter::Info),
+            Verbosity::Debug => Some(LevelFilter::Debug),
+            Verbosity::Trace => Some(LevelFilter::Trace),
+        }
+    }
+}
+
+pub fn set(verbosity: Verbosity) {
+    *VERBOSITY.write().unwrap() = verbosity;
+}
+
+pub fn get() -> Verbosity {
+    *VERBOSITY.read().unwrap()
+}
+
+pub fn quiet() -> bool {
+    get() == Verbosity::Quiet
+}
+
+/// `println!` of a progress or summary message, left out with --quiet.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if !crate::verbosity::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::ArgEnum;
+use image::RgbaImage;
+
+use crate::error::Result;
+
+/// Distance in pixels between the logo and the image edges.
+const MARGIN: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Which published images get the watermark.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ArgEnum)]
+pub enum WatermarkTarget {
+    #[default]
+    All,
+    Tiles,
+    Stitch,
+}
+
+/// `--watermark` as given: the logo file, its corner and its opacity.
+#[derive(Debug, Clone)]
+pub struct WatermarkSpec {
+    pub path: PathBuf,
+    pub corner: Corner,
+    pub alpha: f32,
+}
+
+impl FromStr for WatermarkSpec {
+    type Err = String;
+
+    /// "logo.png:corner:alpha", corner one of top-left, top-right, bottom-left,
+    /// bottom-right and alpha from 0 to 1. Both can be left out
+    /// (bottom-right, 1).
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut spec = WatermarkSpec { path: PathBuf::from(s), corner: Corner::BottomRight, alpha: 1.0 };
+        // Split from the right, the path itself may contain ':'.
+        let mut rest = s;
+        if let Some((head, alpha)) = rest.rsplit_once(':') {
+            if let Ok(alpha) = alpha.parse::<f32>() {
+                if !(0.0..=1.0).contains(&alpha) {
+                    return Err(format!("watermark alpha {} is not between 0 and 1", alpha));
+                }
+                spec.alpha = alpha;
+                rest = head;
+            }
+        }
+        if let Some((head, corner)) = rest.rsplit_once(':') {
+            let corner = match corner {
+                "top-left" => Some(Corner::TopLeft),
+                "top-right" => Some(Corner::TopRight),
+                "bottom-left" => Some(Corner::BottomLeft),
+                "bottom-right" => Some(Corner::BottomRight),
+                _ => None,
+            };
+            if let Some(corner) = corner {
+                spec.corner = corner;
+                rest = head;
+            }
+        }
+        if rest.is_empty() {
+            return Err("watermark needs a logo file".to_string());
+        }
+        spec.path = PathBuf::from(rest);
+        Ok(spec)
+    }
+}
+
+/// A loaded logo, composited into a corner of published images.
+pub struct Watermark {
+    logo: RgbaImage,
+    corner: Corner,
+    alpha: f32,
+}
+
+impl Watermark {
+    pub fn load(spec: &WatermarkSpec) -> Result<Self> {
+        let logo = image::open(&spec.path)
+            .map_err(|e| format!("cannot read watermark {}: {}", spec.path.to_str().unwrap(), e))?
+            .into_rgba8();
+        Ok(Watermark { logo, corner: spec.corner, alpha: spec.alpha })
+    }
+
+    /// Blend the logo over `image`, shrunk to at most half its width and height.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let (max_width, max_height) = (image.width() / 2, image.height() / 2);
+        let resized;
+        let logo = if self.logo.width() > max_width || self.logo.height() > max_height {
+            let scale = (max_width as f32 / self.logo.width() as f32).min(max_height as f32 / self.logo.height() as f32);
+            let (width, height) = ((self.logo.width() as f32 * scale) as u32, (self.logo.height() as f32 * scale) as u32);
+            if width == 0 || height == 0 {
+                return;
+            }
+            resized = image::imageops::resize(&self.logo, width, height, image::imageops::FilterType::Triangle);
+            &resized
+        } else {
+            &self.logo
+        };
+        let margin_x = MARGIN.min(image.width() - logo.width());
+        let margin_y = MARGIN.min(image.height() - logo.height());
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin_x,
+            Corner::TopRight | Corner::BottomRight => image.width() - logo.width() - margin_x,
+        };
+        let y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin_y,
+            Corner::BottomLeft | Corner::BottomRight => image.height() - logo.height() - margin_y,
+        };
+        for (lx, ly, logo_px) in logo.enumerate_pixels() {
+            let alpha = logo_px[3] as f32 / 255.0 * self.alpha;
+            if alpha <= 0.0 {
+                continue;
+            }
+            let px = image.get_pixel_mut(x + lx, y + ly);
+            for (c, l) in px.0.iter_mut().zip(logo_px.0.iter()).take(3) {
+                *c = (*c as f32 * (1.0 - alpha) + *l as f32 * alpha).round() as u8;
+            }
+            px[3] = (px[3] as f32 + (255.0 - px[3] as f32) * alpha).round() as u8;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Renders of `synth-world` fixtures through the binary: change detection
+//! against the timestamp caches, and the pixels of the region images.
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Chunks 0..=2, 0..=1 of region 0,0.
+const AREA: &str = "0,0:47,31";
+const TIMESTAMP: &str = "1600000000";
+
+/// A directory of its own for each test, removed when it ends.
+struct Work {
+    dir: tempfile::TempDir,
+}
+
+impl Work {
+    fn new(name: &str) -> Self {
+        let dir = tempfile::Builder::new().prefix(&format!("mcanvilrenderer-test-{}-", name)).tempdir().unwrap();
+        for sub in ["world", "cache", "images"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        write_palette(&dir.path().join("palette.tar.gz"));
+        Work { dir }
+    }
+
+    fn path(&self, sub: &str) -> PathBuf {
+        self.dir.path().join(sub)
+    }
+
+    fn synth(&self, touch: &[&str]) {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mcanvilrenderer"));
+        command.args(["synth-world", "--area", AREA, "--timestamp", TIMESTAMP]).arg("-o").arg(self.path("world"));
+        for touch in touch {
+            command.args(["--touch", touch]);
+        }
+        assert!(command.output().unwrap().status.success());
+    }
+
+    fn render(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_mcanvilrenderer"))
+            .args(["render", "--progress-format", "json", "--threads", "1"])
+            .args(args)
+            .arg("-d").arg(self.path("world"))
+            .arg("-p").arg(self.path("palette.tar.gz"))
+            .arg("-c").arg(self.path("cache"))
+            .arg("-i").arg(self.path("images"))
+            .output()
+            .unwrap()
+    }
+
+    /// `diff --format json` against the caches of the last render.
+    fn diff(&self) -> serde_json::Value {
+        let output = Command::new(env!("CARGO_BIN_EXE_mcanvilrenderer"))
+            .args(["diff", "--format", "json", "--threads", "1"])
+            .arg("-d").arg(self.path("world"))
+            .arg("-c").arg(self.path("cache"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+}
+
+/// A palette of one grey for every block and flat colour maps.
+fn write_palette(path: &Path) {
+    let colour = [120u8, 120, 120, 255];
+    let blockstates = serde_json::json!({
+        "minecraft:stone": colour,
+        "minecraft:grass_block": colour,
+        "minecraft:grass_block|snowy=false": colour,
+    });
+    let mut map = vec![];
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(256, 256, image::Rgba(colour)))
+        .write_to(&mut map, image::ImageOutputFormat::Png)
+        .unwrap();
+
+    let encoder = flate2::write::GzEncoder::new(std::fs::File::create(path).unwrap(), flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let json = serde_json::to_vec(&blockstates).unwrap();
+    for (name, data) in [("blockstates.json", &json), ("grass-colourmap.png", &map), ("foliage-colourmap.png", &map)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, &data[..]).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+}
+
+fn changed_chunks(diff: &serde_json::Value) -> Vec<(i32, i32)> {
+    let mut chunks: Vec<(i32, i32)> = diff["regions"].as_array().unwrap().iter()
+        .flat_map(|region| region["chunks"].as_array().unwrap().iter())
+        .map(|cloc| (cloc[0].as_i64().unwrap() as i32, cloc[1].as_i64().unwrap() as i32))
+        .collect();
+    chunks.sort();
+    chunks
+}
+
+#[test]
+fn first_render_draws_every_chunk() {
+    let work = Work::new("first");
+    work.synth(&[]);
+    // Every generated chunk, and the south neighbours shaded by them.
+    let chunks = changed_chunks(&work.diff());
+    assert!((0..=2).all(|x| (0..=1).all(|z| chunks.contains(&(x, z)))));
+    assert_eq!(chunks.len(), 9);
+    assert!(work.render(&[]).status.success());
+    assert!(work.path("images").join("r.0.0.png").is_file());
+}
+
+#[test]
+fn caches_round_trip_the_timestamps() {
+    let work = Work::new("round-trip");
+    work.synth(&[]);
+    assert!(work.render(&[]).status.success());
+    assert!(changed_chunks(&work.diff()).is_empty());
+    // Nothing changed: the second pass renders nothing and says so.
+    assert_eq!(work.render(&["--skip-if-unchanged"]).status.code(), Some(3));
+}
+
+#[test]
+fn touched_chunks_are_detected() {
+    let work = Work::new("touch");
+    work.synth(&[]);
+    assert!(work.render(&[]).status.success());
+    work.synth(&["1,0:1600000100"]);
+    // The touched chunk and its south neighbour, whose shading depends on it.
+    assert_eq!(changed_chunks(&work.diff()), vec![(1, 0), (1, 1)]);
+    assert!(work.render(&["--skip-if-unchanged"]).status.success());
+    assert!(changed_chunks(&work.diff()).is_empty());
+}
+
+#[test]
+fn rendered_pixels_cover_the_generated_chunks() {
+    let work = Work::new("pixels");
+    work.synth(&[]);
+    assert!(work.render(&[]).status.success());
+    let image = image::open(work.path("images").join("r.0.0.png")).unwrap().into_rgba8();
+    assert_eq!(image.dimensions(), (512, 512));
+    for (x, z) in [(0, 0), (47, 31), (20, 5)] {
+        assert_eq!(image.get_pixel(x, z)[3], 255, "block {},{} is drawn", x, z);
+    }
+    for (x, z) in [(48, 0), (0, 32), (300, 300)] {
+        assert_eq!(image.get_pixel(x, z)[3], 0, "block {},{} is outside the generated area", x, z);
+    }
+}